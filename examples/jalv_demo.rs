@@ -0,0 +1,132 @@
+//! A tiny plugin and its UI in one binary, run under jalv by `tests/jalv.rs`
+//!
+//! The plugin has a single control input `gain` and does nothing
+//! with it. The UI has no window, it provides `ui:showInterface`, so
+//! the console jalv can show it without a toolkit.
+
+use lv2_sys as sys;
+
+use std::ffi::c_void;
+use std::os::raw::c_char;
+
+use lv2_ui::lv2_ui_descriptors;
+use lv2_ui::prelude::*;
+
+struct DemoPlugin {
+    gain: *const f32,
+}
+
+unsafe extern "C" fn instantiate(
+    _descriptor: *const sys::LV2_Descriptor,
+    _sample_rate: f64,
+    _bundle_path: *const c_char,
+    _features: *const *const sys::LV2_Feature,
+) -> sys::LV2_Handle {
+    Box::into_raw(Box::new(DemoPlugin {
+        gain: std::ptr::null(),
+    })) as sys::LV2_Handle
+}
+
+unsafe extern "C" fn connect_port(instance: sys::LV2_Handle, port: u32, data: *mut c_void) {
+    if port == 0 {
+        (*(instance as *mut DemoPlugin)).gain = data as *const f32;
+    }
+}
+
+unsafe extern "C" fn run(_instance: sys::LV2_Handle, _sample_count: u32) {}
+
+unsafe extern "C" fn cleanup(instance: sys::LV2_Handle) {
+    drop(Box::from_raw(instance as *mut DemoPlugin));
+}
+
+unsafe extern "C" fn extension_data(_uri: *const c_char) -> *const c_void {
+    std::ptr::null()
+}
+
+struct PluginDescriptor(sys::LV2_Descriptor);
+
+// The descriptor only points to static data
+unsafe impl Sync for PluginDescriptor {}
+
+static PLUGIN_DESCRIPTOR: PluginDescriptor = PluginDescriptor(sys::LV2_Descriptor {
+    URI: b"urn:lv2-ui:jalv-demo\0".as_ptr() as *const c_char,
+    instantiate: Some(instantiate),
+    connect_port: Some(connect_port),
+    activate: None,
+    run: Some(run),
+    deactivate: None,
+    cleanup: Some(cleanup),
+    extension_data: Some(extension_data),
+});
+
+#[no_mangle]
+pub extern "C" fn lv2_descriptor(index: u32) -> *const sys::LV2_Descriptor {
+    match index {
+        0 => &PLUGIN_DESCRIPTOR.0,
+        _ => std::ptr::null(),
+    }
+}
+
+pub struct DemoPorts {
+    gain: UIControlPort,
+}
+
+impl UIPortsTrait for DemoPorts {
+    fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+        match port_index {
+            0 => Some(&mut self.gain),
+            _ => None,
+        }
+    }
+
+    fn map_atom_port(&mut self, _port_index: u32) -> Option<&mut UIAtomPort> {
+        None
+    }
+
+    fn visit_ports(&mut self, mut f: impl FnMut(PortRef)) {
+        f(PortRef::Control(&mut self.gain));
+    }
+}
+
+#[uri("urn:lv2-ui:jalv-demo#ui")]
+pub struct DemoUI {
+    ports: DemoPorts,
+}
+
+impl PluginUI for DemoUI {
+    type InitFeatures = ();
+    type UIPorts = DemoPorts;
+
+    const SHOW_INTERFACE: bool = true;
+
+    fn new(
+        _plugin_ui_info: &PluginUIInfo,
+        _features: &mut (),
+        _parent_window: *mut c_void,
+        _write_handle: PluginPortWriteHandle,
+    ) -> Result<Self, PluginUIError> {
+        Ok(Self {
+            ports: DemoPorts {
+                gain: UIControlPort::new(0),
+            },
+        })
+    }
+
+    fn cleanup(&mut self) {}
+
+    fn ports(&mut self) -> &mut DemoPorts {
+        &mut self.ports
+    }
+
+    fn update(&mut self, _reason: UpdateReason) {}
+
+    fn idle(&mut self) -> i32 {
+        0
+    }
+
+    fn widget(&self) -> sys::LV2UI_Widget {
+        std::ptr::null_mut()
+    }
+}
+
+lv2_ui_descriptors!(DemoUI);
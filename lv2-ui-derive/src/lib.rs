@@ -0,0 +1,665 @@
+//! Derive macros for lv2-ui
+//!
+//! Use them through the `derive` feature of lv2-ui, see
+//! `lv2_ui::object` and `lv2_ui::port_collection` for documentation.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, Lit, Meta, NestedMeta, Type};
+
+struct ObjectField {
+    name: Ident,
+    property: Ident,
+    ty: Type,
+    optional: bool,
+}
+
+struct ObjectDescription {
+    name: Ident,
+    urids: Type,
+    otype: Ident,
+    fields: Vec<ObjectField>,
+}
+
+/// Reads the `name = "value"` pairs of the `#[<attribute>(…)]` attributes
+fn args(attrs: &[syn::Attribute], attribute: &str) -> syn::Result<Vec<(Ident, syn::LitStr)>> {
+    let mut args = Vec::new();
+    for attr in attrs.iter().filter(|a| a.path.is_ident(attribute)) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => {
+                return Err(syn::Error::new_spanned(
+                    meta,
+                    format!("expected {}(…)", attribute),
+                ))
+            }
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(pair)) => {
+                    match (pair.path.get_ident(), pair.lit) {
+                        (Some(name), Lit::Str(value)) => args.push((name.clone(), value)),
+                        (_, lit) => {
+                            return Err(syn::Error::new_spanned(lit, "expected name = \"value\""))
+                        }
+                    }
+                }
+                nested => return Err(syn::Error::new_spanned(nested, "expected name = \"value\"")),
+            }
+        }
+    }
+    Ok(args)
+}
+
+fn atom_object_args(attrs: &[syn::Attribute]) -> syn::Result<Vec<(Ident, syn::LitStr)>> {
+    args(attrs, "atom_object")
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last()?,
+        _ => return None,
+    };
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) if args.args.len() == 1 => match &args.args[0] {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn named_fields(input: &DeriveInput) -> syn::Result<&syn::FieldsNamed> {
+    match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(named) => Ok(named),
+            _ => Err(syn::Error::new_spanned(
+                &input.ident,
+                "only structs with named fields are supported",
+            )),
+        },
+        _ => Err(syn::Error::new_spanned(
+            &input.ident,
+            "only structs are supported",
+        )),
+    }
+}
+
+fn describe(input: &DeriveInput) -> syn::Result<ObjectDescription> {
+    let mut urids = None;
+    let mut otype = None;
+    for (name, value) in atom_object_args(&input.attrs)? {
+        if name == "urids" {
+            urids = Some(value.parse::<Type>()?);
+        } else if name == "otype" {
+            otype = Some(value.parse::<Ident>()?);
+        } else {
+            return Err(syn::Error::new_spanned(name, "unknown argument"));
+        }
+    }
+    let missing = |what| {
+        syn::Error::new(
+            Span::call_site(),
+            format!("missing #[atom_object({} = \"…\")]", what),
+        )
+    };
+    let urids = urids.ok_or_else(|| missing("urids"))?;
+    let otype = otype.ok_or_else(|| missing("otype"))?;
+
+    let named = named_fields(input)?;
+
+    let mut fields = Vec::new();
+    for field in named.named.iter() {
+        let name = field.ident.clone().unwrap();
+        let mut property = name.clone();
+        for (arg, value) in atom_object_args(&field.attrs)? {
+            if arg == "property" {
+                property = value.parse()?;
+            } else {
+                return Err(syn::Error::new_spanned(arg, "unknown argument"));
+            }
+        }
+        let (ty, optional) = match option_inner(&field.ty) {
+            Some(inner) => (inner.clone(), true),
+            None => (field.ty.clone(), false),
+        };
+        fields.push(ObjectField {
+            name,
+            property,
+            ty,
+            optional,
+        });
+    }
+
+    Ok(ObjectDescription {
+        name: input.ident.clone(),
+        urids,
+        otype,
+        fields,
+    })
+}
+
+fn from_atom_object(description: &ObjectDescription) -> TokenStream2 {
+    let ObjectDescription {
+        name,
+        urids,
+        otype,
+        fields,
+    } = description;
+    let declarations = fields.iter().map(|field| {
+        let name = &field.name;
+        quote! { let mut #name = None; }
+    });
+    let matches = fields.iter().map(|field| {
+        let ObjectField {
+            name, property, ty, ..
+        } = field;
+        quote! {
+            if __property.key == urids.#property {
+                #name = <#ty as ::lv2_ui::patch::ParamValue>::read_atom(__atom, &urids.atom);
+                continue;
+            }
+        }
+    });
+    let values = fields.iter().map(|field| {
+        let name = &field.name;
+        match field.optional {
+            true => quote! { #name },
+            false => quote! { #name: #name? },
+        }
+    });
+    quote! {
+        impl ::lv2_ui::object::FromAtomObject for #name {
+            type URIDs = #urids;
+
+            fn atom_urids(urids: &Self::URIDs) -> &::lv2_ui::object::__private::AtomURIDCollection {
+                &urids.atom
+            }
+
+            fn from_object(
+                header: ::lv2_ui::object::__private::ObjectHeader,
+                reader: ::lv2_ui::object::__private::ObjectReader,
+                urids: &Self::URIDs,
+            ) -> Option<Self> {
+                if header.otype != urids.#otype {
+                    return None;
+                }
+                #(#declarations)*
+                for (__property, __atom) in reader {
+                    #(#matches)*
+                }
+                Some(Self { #(#values),* })
+            }
+        }
+    }
+}
+
+fn to_atom_object(description: &ObjectDescription) -> TokenStream2 {
+    let ObjectDescription {
+        name,
+        urids,
+        otype,
+        fields,
+    } = description;
+    let writes = fields.iter().map(|field| {
+        let ObjectField {
+            name,
+            property,
+            ty,
+            optional,
+        } = field;
+        let write = quote! {
+            <#ty as ::lv2_ui::patch::ParamValue>::write_property(
+                __value,
+                object,
+                urids.#property.into_general(),
+                &urids.atom,
+            )?;
+        };
+        match optional {
+            true => quote! {
+                if let Some(__value) = &self.#name {
+                    #write
+                }
+            },
+            false => quote! {
+                let __value = &self.#name;
+                #write
+            },
+        }
+    });
+    quote! {
+        impl ::lv2_ui::object::ToAtomObject for #name {
+            type URIDs = #urids;
+
+            fn atom_urids(urids: &Self::URIDs) -> &::lv2_ui::object::__private::AtomURIDCollection {
+                &urids.atom
+            }
+
+            fn otype(urids: &Self::URIDs) -> ::lv2_ui::object::__private::URID {
+                urids.#otype.into_general()
+            }
+
+            fn write_properties(
+                &self,
+                object: &mut ::lv2_ui::object::__private::ObjectWriter,
+                urids: &Self::URIDs,
+            ) -> Option<()> {
+                #(#writes)*
+                Some(())
+            }
+        }
+    }
+}
+
+/// The kind of a port in a `lv2_core` port collection
+#[derive(Clone, Copy, PartialEq)]
+enum PortKind {
+    Control,
+    Atom,
+    /// Audio and CV ports, not seen by the UI but counted for the indices
+    Skipped,
+}
+
+struct CollectionPort {
+    name: Ident,
+    index: u32,
+    kind: PortKind,
+}
+
+/// A field holding a sub-collection, `#[nested(offset = N)]`
+struct NestedCollection {
+    name: Ident,
+    ty: Type,
+    offset: u32,
+}
+
+struct CollectionDescription {
+    vis: syn::Visibility,
+    source: Ident,
+    name: Ident,
+    ports: Vec<CollectionPort>,
+    nested: Vec<NestedCollection>,
+}
+
+fn last_segment(ty: &Type) -> Option<&syn::PathSegment> {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => path.path.segments.last(),
+        _ => None,
+    }
+}
+
+fn first_type_argument(segment: &syn::PathSegment) -> Option<&Type> {
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(args) => args.args.iter().find_map(|arg| match arg {
+            syn::GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        }),
+        _ => None,
+    }
+}
+
+/// The kind of `InputPort<T>`, `OutputPort<T>` or an `Option` of them
+fn port_kind(ty: &Type) -> Option<PortKind> {
+    let ty = option_inner(ty).unwrap_or(ty);
+    let segment = last_segment(ty)?;
+    if segment.ident != "InputPort" && segment.ident != "OutputPort" {
+        return None;
+    }
+    let port_type = last_segment(first_type_argument(segment)?)?;
+    match port_type.ident.to_string().as_str() {
+        "Control" | "InPlaceControl" => Some(PortKind::Control),
+        "AtomPort" => Some(PortKind::Atom),
+        "Audio" | "InPlaceAudio" | "CV" | "InPlaceCV" => Some(PortKind::Skipped),
+        _ => None,
+    }
+}
+
+/// Reads the offset of `#[nested(offset = N)]`, None if the field is no sub-collection
+fn nested_offset(attrs: &[syn::Attribute]) -> syn::Result<Option<u32>> {
+    let mut offset = None;
+    for attr in attrs.iter().filter(|a| a.path.is_ident("nested")) {
+        let list = match attr.parse_meta()? {
+            Meta::List(list) => list,
+            meta => return Err(syn::Error::new_spanned(meta, "expected nested(offset = N)")),
+        };
+        for nested in list.nested {
+            match nested {
+                NestedMeta::Meta(Meta::NameValue(pair)) if pair.path.is_ident("offset") => {
+                    match pair.lit {
+                        Lit::Int(value) => offset = Some(value.base10_parse::<u32>()?),
+                        lit => return Err(syn::Error::new_spanned(lit, "expected an integer")),
+                    }
+                }
+                nested => return Err(syn::Error::new_spanned(nested, "expected offset = N")),
+            }
+        }
+        if offset.is_none() {
+            return Err(syn::Error::new_spanned(attr, "missing offset = N"));
+        }
+    }
+    Ok(offset)
+}
+
+fn describe_collection(input: &DeriveInput) -> syn::Result<CollectionDescription> {
+    let mut name = None;
+    for (arg, value) in args(&input.attrs, "ui_ports")? {
+        if arg == "name" {
+            name = Some(value.parse::<Ident>()?);
+        } else {
+            return Err(syn::Error::new_spanned(arg, "unknown argument"));
+        }
+    }
+    let name = name
+        .ok_or_else(|| syn::Error::new(Span::call_site(), "missing #[ui_ports(name = \"…\")]"))?;
+
+    let mut ports = Vec::new();
+    let mut nested = Vec::new();
+    for (index, field) in named_fields(input)?.named.iter().enumerate() {
+        if let Some(offset) = nested_offset(&field.attrs)? {
+            if !args(&field.attrs, "ui_ports")?.is_empty() {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "#[ui_ports(…)] is not allowed on nested collections",
+                ));
+            }
+            if offset < index as u32 {
+                return Err(syn::Error::new_spanned(
+                    field,
+                    "the offset overlaps the ports before",
+                ));
+            }
+            nested.push(NestedCollection {
+                name: field.ident.clone().unwrap(),
+                ty: field.ty.clone(),
+                offset,
+            });
+            continue;
+        }
+        if !nested.is_empty() {
+            return Err(syn::Error::new_spanned(
+                field,
+                "ports need to come before the nested collections",
+            ));
+        }
+        let mut kind = port_kind(&field.ty);
+        for (arg, value) in args(&field.attrs, "ui_ports")? {
+            if arg != "kind" {
+                return Err(syn::Error::new_spanned(arg, "unknown argument"));
+            }
+            kind = match value.value().as_str() {
+                "control" => Some(PortKind::Control),
+                "atom" => Some(PortKind::Atom),
+                "skip" => Some(PortKind::Skipped),
+                _ => {
+                    return Err(syn::Error::new_spanned(
+                        value,
+                        "expected \"control\", \"atom\" or \"skip\"",
+                    ))
+                }
+            };
+        }
+        let kind = kind.ok_or_else(|| {
+            syn::Error::new_spanned(
+                &field.ty,
+                "unknown port type, set it by #[ui_ports(kind = \"…\")]",
+            )
+        })?;
+        ports.push(CollectionPort {
+            name: field.ident.clone().unwrap(),
+            index: index as u32,
+            kind,
+        });
+    }
+
+    Ok(CollectionDescription {
+        vis: input.vis.clone(),
+        source: input.ident.clone(),
+        name,
+        ports,
+        nested,
+    })
+}
+
+fn ui_ports(description: &CollectionDescription) -> TokenStream2 {
+    let CollectionDescription {
+        vis,
+        source,
+        name,
+        ports,
+        nested,
+    } = description;
+    let seen = || ports.iter().filter(|port| port.kind != PortKind::Skipped);
+    let of_kind = move |kind| ports.iter().filter(move |port| port.kind == kind);
+    // Sub-collections may have atom ports, so they get the URID as well
+    let needs_urid = of_kind(PortKind::Atom).next().is_some() || !nested.is_empty();
+    let nested_ty = |ty: &Type| {
+        quote! {
+            ::lv2_ui::port::NestedPorts<
+                <#ty as ::lv2_ui::port_collection::DerivedUIPorts>::UIPorts,
+            >
+        }
+    };
+
+    let fields = seen().map(|port| {
+        let CollectionPort { name, index, kind } = port;
+        let doc = format!("Port {}", index);
+        let ty = match kind {
+            PortKind::Control => quote! { ::lv2_ui::port::UIControlPort },
+            _ => quote! { ::lv2_ui::port::UIAtomPort },
+        };
+        quote! {
+            #[doc = #doc]
+            pub #name: #ty
+        }
+    });
+    let nested_fields = nested.iter().map(|nested| {
+        let NestedCollection { name, ty, offset } = nested;
+        let doc = format!("Ports from {} on", offset);
+        let ty = nested_ty(ty);
+        quote! {
+            #[doc = #doc]
+            pub #name: #ty
+        }
+    });
+    let initializers = seen().map(|port| {
+        let CollectionPort { name, index, kind } = port;
+        match kind {
+            PortKind::Control => quote! { #name: ::lv2_ui::port::UIControlPort::new(#index) },
+            _ => quote! { #name: ::lv2_ui::port::UIAtomPort::new(event_transfer, #index) },
+        }
+    });
+    let nested_initializers = nested.iter().map(|nested| {
+        let NestedCollection { name, ty, offset } = nested;
+        quote! {
+            #name: ::lv2_ui::port::NestedPorts::new(
+                <#ty as ::lv2_ui::port_collection::DerivedUIPorts>::ui_ports(event_transfer),
+                #offset,
+            )
+        }
+    });
+    let new_args = match needs_urid {
+        true => quote! {
+            event_transfer: ::lv2_ui::port_collection::__private::URID<
+                ::lv2_ui::port_collection::__private::EventTransfer,
+            >
+        },
+        false => quote! {},
+    };
+    let expectations = seen().map(|port| {
+        let CollectionPort { name, index, kind } = port;
+        let symbol = name.to_string();
+        match kind {
+            PortKind::Control => {
+                quote! { ::lv2_ui::description::PortExpectation::control(#index, #symbol) }
+            }
+            _ => quote! { ::lv2_ui::description::PortExpectation::atom(#index, #symbol) },
+        }
+    });
+    let map_arms = |kind| {
+        of_kind(kind).map(|port| {
+            let CollectionPort { name, index, .. } = port;
+            quote! { #index => return Some(&mut self.#name), }
+        })
+    };
+    let control_arms = map_arms(PortKind::Control);
+    let atom_arms = map_arms(PortKind::Atom);
+    let nested_names = nested.iter().map(|nested| &nested.name).collect::<Vec<_>>();
+    let nested_maps = match nested.is_empty() {
+        true => quote! {},
+        false => quote! {
+            fn map_modulation_port(
+                &mut self,
+                port_index: u32,
+            ) -> Option<&mut ::lv2_ui::port::UIControlPort> {
+                #(if let Some(port) = ::lv2_ui::port::UIPortsTrait::map_modulation_port(
+                    &mut self.#nested_names,
+                    port_index,
+                ) {
+                    return Some(port);
+                })*
+                None
+            }
+
+            fn map_peak_port(
+                &mut self,
+                port_index: u32,
+            ) -> Option<&mut ::lv2_ui::meter::PeakMeter> {
+                #(if let Some(port) = ::lv2_ui::port::UIPortsTrait::map_peak_port(
+                    &mut self.#nested_names,
+                    port_index,
+                ) {
+                    return Some(port);
+                })*
+                None
+            }
+        },
+    };
+    let ui_ports_args = match needs_urid {
+        true => quote! { event_transfer },
+        false => quote! {},
+    };
+    let visits = seen().map(|port| {
+        let name = &port.name;
+        match port.kind {
+            PortKind::Control => {
+                quote! { f(::lv2_ui::port::PortRef::Control(&mut self.#name)); }
+            }
+            _ => quote! { f(::lv2_ui::port::PortRef::Atom(&mut self.#name)); },
+        }
+    });
+
+    quote! {
+        #vis struct #name {
+            #(#fields,)*
+            #(#nested_fields,)*
+        }
+
+        impl #name {
+            /// The expected ports, their symbols being the field names
+            pub const PORTS: &'static [::lv2_ui::description::PortExpectation] = &[
+                #(#expectations),*
+            ];
+
+            pub fn new(#new_args) -> Self {
+                Self {
+                    #(#initializers,)*
+                    #(#nested_initializers,)*
+                }
+            }
+        }
+
+        impl ::lv2_ui::port_collection::DerivedUIPorts for #source {
+            type UIPorts = #name;
+
+            #[allow(unused_variables)]
+            fn ui_ports(
+                event_transfer: ::lv2_ui::port_collection::__private::URID<
+                    ::lv2_ui::port_collection::__private::EventTransfer,
+                >,
+            ) -> #name {
+                #name::new(#ui_ports_args)
+            }
+        }
+
+        impl ::lv2_ui::port::UIPortsTrait for #name {
+            fn map_control_port(
+                &mut self,
+                port_index: u32,
+            ) -> Option<&mut ::lv2_ui::port::UIControlPort> {
+                match port_index {
+                    #(#control_arms)*
+                    _ => {}
+                }
+                #(if let Some(port) = ::lv2_ui::port::UIPortsTrait::map_control_port(
+                    &mut self.#nested_names,
+                    port_index,
+                ) {
+                    return Some(port);
+                })*
+                None
+            }
+
+            fn map_atom_port(
+                &mut self,
+                port_index: u32,
+            ) -> Option<&mut ::lv2_ui::port::UIAtomPort> {
+                match port_index {
+                    #(#atom_arms)*
+                    _ => {}
+                }
+                #(if let Some(port) = ::lv2_ui::port::UIPortsTrait::map_atom_port(
+                    &mut self.#nested_names,
+                    port_index,
+                ) {
+                    return Some(port);
+                })*
+                None
+            }
+
+            #nested_maps
+
+            #[allow(unused_mut, unused_variables)]
+            fn visit_ports(&mut self, mut f: impl FnMut(::lv2_ui::port::PortRef)) {
+                #(#visits)*
+                #(::lv2_ui::port::UIPortsTrait::visit_ports(&mut self.#nested_names, &mut f);)*
+            }
+        }
+    }
+}
+
+/// Derives `FromAtomObject`, see `lv2_ui::object`
+#[proc_macro_derive(FromAtomObject, attributes(atom_object))]
+pub fn derive_from_atom_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match describe(&input) {
+        Ok(description) => from_atom_object(&description).into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Derives `ToAtomObject`, see `lv2_ui::object`
+#[proc_macro_derive(ToAtomObject, attributes(atom_object))]
+pub fn derive_to_atom_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match describe(&input) {
+        Ok(description) => to_atom_object(&description).into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+/// Derives the UI ports of a `lv2_core` port collection, see `lv2_ui::port_collection`
+#[proc_macro_derive(UIPorts, attributes(ui_ports, nested))]
+pub fn derive_ui_ports(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match describe_collection(&input) {
+        Ok(description) => ui_ports(&description).into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
@@ -0,0 +1,240 @@
+//! Helpers for spectrum analyzer UIs
+//!
+//! Analyzer UIs receive chunks of audio frames from the plugin,
+//! usually as `atom:Vector`s of floats. `SpectrumAnalyzer` collects
+//! them and computes magnitude spectra, `LogBinning` reduces the
+//! linear FFT bins to logarithmically spaced bands for display, and
+//! the dB functions scale the result.
+//!
+//! Only available with the `fft` feature.
+
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+/// Converts a linear amplitude to dB, limited to `floor_db`
+pub fn amplitude_to_db(amplitude: f32, floor_db: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return floor_db;
+    }
+    (20.0 * amplitude.log10()).max(floor_db)
+}
+
+/// Converts a dB value to linear amplitude
+pub fn db_to_amplitude(db: f32) -> f32 {
+    10.0f32.powf(db / 20.0)
+}
+
+/// Maps a dB value to 0.0..=1.0 between `min_db` and `max_db`, e.g. for drawing
+pub fn normalize_db(db: f32, min_db: f32, max_db: f32) -> f32 {
+    ((db - min_db) / (max_db - min_db)).clamp(0.0, 1.0)
+}
+
+/// Computes magnitude spectra of the most recently received samples
+///
+/// The samples are Hann windowed. The magnitudes are normalized, so
+/// that a full scale sine results in a magnitude of 1.0.
+pub struct SpectrumAnalyzer {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    window_gain: f32,
+    input: Vec<f32>,
+    write_position: usize,
+    buffer: Vec<Complex<f32>>,
+    scratch: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+}
+
+impl SpectrumAnalyzer {
+    /// Creates an analyzer with an FFT of `size` samples
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is 0.
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0, "the FFT size of a SpectrumAnalyzer must not be 0");
+        let fft = FftPlanner::new().plan_fft_forward(size);
+        let window: Vec<f32> = (0..size)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / size as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        let window_gain = window.iter().sum::<f32>() / 2.0;
+        let scratch = vec![Complex::new(0.0, 0.0); fft.get_inplace_scratch_len()];
+        Self {
+            fft,
+            window,
+            window_gain,
+            input: vec![0.0; size],
+            write_position: 0,
+            buffer: vec![Complex::new(0.0, 0.0); size],
+            scratch,
+            magnitudes: vec![0.0; size / 2 + 1],
+        }
+    }
+
+    /// The FFT size
+    pub fn size(&self) -> usize {
+        self.input.len()
+    }
+
+    /// The center frequency of FFT bin `bin`
+    pub fn bin_frequency(&self, bin: usize, sample_rate: f32) -> f32 {
+        bin as f32 * sample_rate / self.size() as f32
+    }
+
+    /// Appends received samples, dropping the oldest ones
+    pub fn push(&mut self, samples: &[f32]) {
+        let size = self.size();
+        let samples = &samples[samples.len().saturating_sub(size)..];
+        for &sample in samples {
+            self.input[self.write_position] = sample;
+            self.write_position = (self.write_position + 1) % size;
+        }
+    }
+
+    /// Computes the magnitude spectrum of the last `size()` samples pushed
+    ///
+    /// Returns `size() / 2 + 1` magnitudes, from DC to Nyquist.
+    pub fn compute(&mut self) -> &[f32] {
+        let size = self.size();
+        for i in 0..size {
+            let sample = self.input[(self.write_position + i) % size];
+            self.buffer[i] = Complex::new(sample * self.window[i], 0.0);
+        }
+        self.fft
+            .process_with_scratch(&mut self.buffer, &mut self.scratch);
+        for (magnitude, bin) in self.magnitudes.iter_mut().zip(self.buffer.iter()) {
+            *magnitude = bin.norm() / self.window_gain;
+        }
+        &self.magnitudes
+    }
+
+    /// The spectrum computed by the last call of `compute()`
+    pub fn magnitudes(&self) -> &[f32] {
+        &self.magnitudes
+    }
+}
+
+/// Reduces linear FFT bins to logarithmically spaced frequency bands
+pub struct LogBinning {
+    bands: Vec<(usize, usize)>,
+    frequencies: Vec<f32>,
+}
+
+impl LogBinning {
+    /// Creates `band_count` bands between `min_frequency` and `max_frequency`
+    ///
+    /// Bands narrower than one FFT bin get the nearest bin.
+    pub fn new(
+        fft_size: usize,
+        sample_rate: f32,
+        min_frequency: f32,
+        max_frequency: f32,
+        band_count: usize,
+    ) -> Self {
+        let bin_width = sample_rate / fft_size as f32;
+        let last_bin = fft_size / 2;
+        let ratio = (max_frequency / min_frequency).powf(1.0 / band_count as f32);
+
+        let mut bands = Vec::with_capacity(band_count);
+        let mut frequencies = Vec::with_capacity(band_count);
+        let mut lower = min_frequency;
+        for _ in 0..band_count {
+            let upper = lower * ratio;
+            let first = ((lower / bin_width).round() as usize).min(last_bin);
+            let end = ((upper / bin_width).round() as usize)
+                .max(first + 1)
+                .min(last_bin + 1);
+            bands.push((first, end));
+            frequencies.push((lower * upper).sqrt());
+            lower = upper;
+        }
+        Self { bands, frequencies }
+    }
+
+    /// The center frequencies of the bands
+    pub fn frequencies(&self) -> &[f32] {
+        &self.frequencies
+    }
+
+    /// Writes the peak magnitude of each band to `output`
+    pub fn apply(&self, magnitudes: &[f32], output: &mut Vec<f32>) {
+        output.clear();
+        output.extend(self.bands.iter().map(|&(first, end)| {
+            magnitudes
+                .get(first..end.min(magnitudes.len()))
+                .unwrap_or(&[])
+                .iter()
+                .fold(0.0f32, |peak, m| peak.max(*m))
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn sine(frequency: f32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f32::consts::PI * frequency * i as f32 / SAMPLE_RATE).sin())
+            .collect()
+    }
+
+    #[test]
+    fn sine_lands_in_its_bin() {
+        let mut analyzer = SpectrumAnalyzer::new(1024);
+        let frequency = analyzer.bin_frequency(64, SAMPLE_RATE);
+        assert_eq!(frequency, 3000.0);
+        analyzer.push(&sine(frequency, 1500));
+
+        let magnitudes = analyzer.compute();
+        assert_eq!(magnitudes.len(), 513);
+        let (peak_bin, peak) =
+            magnitudes
+                .iter()
+                .enumerate()
+                .fold((0, 0.0f32), |(bin, peak), (i, m)| match *m > peak {
+                    true => (i, *m),
+                    false => (bin, peak),
+                });
+        assert_eq!(peak_bin, 64);
+        assert!((peak - 1.0).abs() < 0.01, "peak magnitude {}", peak);
+        assert!(magnitudes[..62].iter().all(|m| *m < 0.01));
+        assert!(magnitudes[67..].iter().all(|m| *m < 0.01));
+    }
+
+    #[test]
+    #[should_panic]
+    fn size_0_is_rejected() {
+        SpectrumAnalyzer::new(0);
+    }
+
+    #[test]
+    fn log_binning_edges_are_monotonic() {
+        let binning = LogBinning::new(1024, SAMPLE_RATE, 20.0, 20000.0, 48);
+        assert_eq!(binning.bands.len(), 48);
+        for &(first, end) in binning.bands.iter() {
+            assert!(first < end && end <= 513);
+        }
+        for (lower, upper) in binning.bands.iter().zip(binning.bands.iter().skip(1)) {
+            assert!(lower.0 <= upper.0 && lower.1 <= upper.1);
+        }
+        let frequencies = binning.frequencies();
+        assert!(frequencies.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(frequencies[0] > 20.0 && frequencies[47] < 20000.0);
+    }
+
+    #[test]
+    fn log_binning_takes_the_peak_of_each_band() {
+        let binning = LogBinning::new(16, 16.0, 1.0, 8.0, 3);
+        let magnitudes = [0.0, 0.1, 0.2, 0.3, 0.9, 0.5, 0.6, 0.7, 0.8];
+        let mut output = Vec::new();
+        binning.apply(&magnitudes, &mut output);
+        assert_eq!(output, vec![0.1, 0.3, 0.9]);
+    }
+}
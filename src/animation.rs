@@ -0,0 +1,164 @@
+//! Frame timing for animated UIs
+//!
+//! Hosts call `PluginUI::idle()` as often as they like, some at a
+//! steady rate, some whenever their own event loop is idle. Meters
+//! and scopes animated per idle call would run at different speeds
+//! in different hosts. `AnimationClock` steps at the rate the host
+//! declares by the `ui:updateRate` option instead:
+//!
+//! ```ignore
+//! fn new(plugin_ui_info: &PluginUIInfo, ...) -> Result<Self, PluginUIError> {
+//!     let clock = AnimationClock::from_options(plugin_ui_info.host_options());
+//!     ...
+//! }
+//!
+//! fn idle(&mut self) -> i32 {
+//!     if let Some(frame) = self.clock.tick() {
+//!         self.meter.decay(frame.delta);
+//!         self.redraw();
+//!     }
+//!     0
+//! }
+//! ```
+//!
+//! Steps are not made up for: if idle is called too seldom, the next
+//! step is due right away and `AnimationFrame::delta` is longer.
+
+use std::time::{Duration, Instant};
+
+use crate::options::HostOptions;
+use crate::plugin_ui::IdleContext;
+
+/// The rate in Hz if the host does not declare `ui:updateRate`
+pub const DEFAULT_UPDATE_RATE: f32 = 30.0;
+
+/// An animation step reported by `AnimationClock::tick()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AnimationFrame {
+    /// The number of the step, counting from 0
+    pub number: u64,
+    /// The time since the first step
+    pub elapsed: Duration,
+    /// The time since the previous step, zero for the first one
+    pub delta: Duration,
+}
+
+/// Tells from within `idle()` when an animation step is due
+#[derive(Clone, Debug)]
+pub struct AnimationClock {
+    interval: Duration,
+    start: Option<Instant>,
+    last_step: Option<Instant>,
+    next_step: Option<Instant>,
+    frames: u64,
+}
+
+impl AnimationClock {
+    /// A clock stepping `update_rate` times per second
+    ///
+    /// Rates that are not positive fall back to `DEFAULT_UPDATE_RATE`.
+    pub fn new(update_rate: f32) -> Self {
+        Self {
+            interval: interval(update_rate),
+            start: None,
+            last_step: None,
+            next_step: None,
+            frames: 0,
+        }
+    }
+
+    /// A clock stepping at the host's `ui:updateRate`
+    pub fn from_options(options: &HostOptions) -> Self {
+        Self::new(options.update_rate().unwrap_or(DEFAULT_UPDATE_RATE))
+    }
+
+    /// The rate in Hz the clock steps at
+    pub fn update_rate(&self) -> f32 {
+        (1.0 / self.interval.as_secs_f64()) as f32
+    }
+
+    /// Changes the rate, e.g. when the host changed `ui:updateRate`
+    pub fn set_update_rate(&mut self, update_rate: f32) {
+        self.interval = interval(update_rate);
+        self.next_step = self.last_step.map(|last| last + self.interval);
+    }
+
+    /// The time between two steps
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// The number of steps made so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The time since the first step, zero before it
+    pub fn elapsed(&self) -> Duration {
+        self.start.map_or(Duration::ZERO, |start| start.elapsed())
+    }
+
+    /// True if a step would be made by `tick()` now
+    pub fn is_due(&self) -> bool {
+        self.next_step.is_none_or(|next| Instant::now() >= next)
+    }
+
+    /// To be called in `idle()`, returns the frame if a step is due
+    ///
+    /// The first call always makes a step.
+    pub fn tick(&mut self) -> Option<AnimationFrame> {
+        self.tick_at(Instant::now())
+    }
+
+    /// Like `tick()` in `PluginUI::idle_with()`, following changes of the update rate
+    pub fn tick_with(&mut self, context: &IdleContext) -> Option<AnimationFrame> {
+        if let Some(update_rate) = context.update_rate() {
+            if interval(update_rate) != self.interval {
+                self.set_update_rate(update_rate);
+            }
+        }
+        self.tick()
+    }
+
+    /// Like `tick()` with the time given, e.g. for deterministic tests
+    pub fn tick_at(&mut self, now: Instant) -> Option<AnimationFrame> {
+        if self.next_step.is_some_and(|next| now < next) {
+            return None;
+        }
+        let start = *self.start.get_or_insert(now);
+        let delta = self
+            .last_step
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        // Keep the steps on the grid unless a whole interval was missed
+        self.next_step = Some(match self.next_step {
+            Some(next) if now.saturating_duration_since(next) < self.interval => {
+                next + self.interval
+            }
+            _ => now + self.interval,
+        });
+        self.last_step = Some(now);
+        let frame = AnimationFrame {
+            number: self.frames,
+            elapsed: now.saturating_duration_since(start),
+            delta,
+        };
+        self.frames += 1;
+        Some(frame)
+    }
+
+    /// Starts over at frame 0 with the next `tick()`
+    pub fn reset(&mut self) {
+        self.start = None;
+        self.last_step = None;
+        self.next_step = None;
+        self.frames = 0;
+    }
+}
+
+fn interval(update_rate: f32) -> Duration {
+    let update_rate = match update_rate.is_finite() && update_rate > 0.0 {
+        true => update_rate,
+        false => DEFAULT_UPDATE_RATE,
+    };
+    Duration::from_secs_f64(1.0 / f64::from(update_rate))
+}
@@ -0,0 +1,202 @@
+//! Vector drawing by femtovg on a GL window
+//!
+//! `CanvasWindow` puts a femtovg `Canvas` on a `GlWindow`, ready to
+//! draw knobs and meters by paths, gradients and text. Frames are
+//! drawn from `PluginUI::idle()`, e.g. paced by an `AnimationClock`:
+//!
+//! ```ignore
+//! fn idle(&mut self) -> i32 {
+//!     if self.canvas.pump_events(&mut self.input).is_err() {
+//!         return 1;
+//!     }
+//!     if self.clock.tick().is_some() {
+//!         let mut frame = match self.canvas.begin_frame() {
+//!             Ok(frame) => frame,
+//!             Err(_) => return 1,
+//!         };
+//!         let mut path = Path::new();
+//!         path.circle(50.0, 50.0, 40.0);
+//!         frame.fill_path(&path, &Paint::color(Color::rgb(200, 80, 40)));
+//!         if frame.end().is_err() {
+//!             return 1;
+//!         }
+//!     }
+//!     0
+//! }
+//! ```
+//!
+//! The canvas draws in logical coordinates: `begin_frame()` scales by
+//! the scale factor, so a UI of 200 × 100 logical pixels fills a
+//! window of 400 × 200 pixels at a scale factor of 2. Resizes of the
+//! window are picked up by the next frame.
+
+use std::ops::{Deref, DerefMut};
+
+use femtovg::renderer::OpenGl;
+use femtovg::{Canvas, Color, ErrorKind};
+
+use crate::backend::gl::{GlContext, GlError, GlWindow};
+use crate::backend::winit::{WinitHandler, WinitWindowError};
+use crate::plugin_ui::WindowMode;
+
+#[derive(Debug)]
+pub enum CanvasError {
+    Gl(GlError),
+    Canvas(ErrorKind),
+}
+
+impl From<GlError> for CanvasError {
+    fn from(e: GlError) -> Self {
+        CanvasError::Gl(e)
+    }
+}
+
+impl From<WinitWindowError> for CanvasError {
+    fn from(e: WinitWindowError) -> Self {
+        CanvasError::Gl(GlError::Window(e))
+    }
+}
+
+impl From<ErrorKind> for CanvasError {
+    fn from(e: ErrorKind) -> Self {
+        CanvasError::Canvas(e)
+    }
+}
+
+/// A `GlWindow` drawn on by a femtovg `Canvas`
+pub struct CanvasWindow {
+    // Dropped while the GL context still exists
+    canvas: Canvas<OpenGl>,
+    window: GlWindow,
+    scale_factor: f32,
+    background: Color,
+    surface_size: (u32, u32),
+}
+
+impl CanvasWindow {
+    /// Opens the window according to `mode`, see `GlWindow::open()`
+    ///
+    /// `width` and `height` are logical pixels, the window is opened
+    /// `scale_factor` times as large, e.g. by
+    /// `HostOptions::scale_factor()`.
+    pub fn open(
+        mode: WindowMode,
+        width: u32,
+        height: u32,
+        title: &str,
+        scale_factor: f32,
+    ) -> Result<Self, CanvasError> {
+        let scale_factor = valid_scale(scale_factor);
+        let physical = |size: u32| (size as f32 * scale_factor).round() as u32;
+        let window = GlWindow::open(mode, physical(width), physical(height), title)?;
+        let context = window.context();
+        let renderer =
+            unsafe { OpenGl::new_from_function_cstr(|name| context.get_proc_address(name))? };
+        let canvas = Canvas::new(renderer)?;
+        Ok(Self {
+            canvas,
+            window,
+            scale_factor,
+            background: Color::black(),
+            surface_size: (0, 0),
+        })
+    }
+
+    pub fn window(&self) -> &GlWindow {
+        &self.window
+    }
+
+    pub fn window_mut(&mut self) -> &mut GlWindow {
+        &mut self.window
+    }
+
+    /// The canvas, e.g. to load fonts and images outside of frames
+    pub fn canvas(&mut self) -> &mut Canvas<OpenGl> {
+        &mut self.canvas
+    }
+
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    /// Changes the scale factor, e.g. in `PluginUI::scale_factor_changed()`
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = valid_scale(scale_factor);
+    }
+
+    /// The color every frame starts with
+    pub fn set_background(&mut self, background: Color) {
+        self.background = background;
+    }
+
+    /// The size of the window in logical pixels
+    pub fn logical_size(&self) -> (f32, f32) {
+        let (width, height) = self.window.window().size();
+        (
+            width as f32 / self.scale_factor,
+            height as f32 / self.scale_factor,
+        )
+    }
+
+    /// Pumps the events of the window, see `WinitWindow::pump_events()`
+    pub fn pump_events(&mut self, handler: &mut impl WinitHandler) -> Result<(), CanvasError> {
+        self.window.window_mut().pump_events(handler)?;
+        Ok(())
+    }
+
+    /// Starts a frame cleared to the background, to be finished by `CanvasFrame::end()`
+    pub fn begin_frame(&mut self) -> Result<CanvasFrame<'_>, CanvasError> {
+        let context = self.window.context();
+        context.make_current()?;
+        let size = self.window.window().size();
+        if size != self.surface_size {
+            context.resize(size.0, size.1);
+            self.surface_size = size;
+        }
+        let (width, height) = size;
+        self.canvas.set_size(width, height, self.scale_factor);
+        self.canvas.reset();
+        self.canvas.clear_rect(0, 0, width, height, self.background);
+        self.canvas.scale(self.scale_factor, self.scale_factor);
+        Ok(CanvasFrame {
+            canvas: &mut self.canvas,
+            context,
+        })
+    }
+}
+
+/// A frame being drawn, dereferences to the `Canvas`
+pub struct CanvasFrame<'a> {
+    canvas: &'a mut Canvas<OpenGl>,
+    context: &'a GlContext,
+}
+
+impl<'a> CanvasFrame<'a> {
+    /// Renders what has been drawn and shows it
+    pub fn end(self) -> Result<(), CanvasError> {
+        self.canvas.flush();
+        self.context.swap_buffers()?;
+        Ok(())
+    }
+}
+
+impl<'a> Deref for CanvasFrame<'a> {
+    type Target = Canvas<OpenGl>;
+
+    fn deref(&self) -> &Self::Target {
+        self.canvas
+    }
+}
+
+impl<'a> DerefMut for CanvasFrame<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.canvas
+    }
+}
+
+fn valid_scale(scale_factor: f32) -> f32 {
+    match scale_factor.is_finite() && scale_factor > 0.0 {
+        true => scale_factor,
+        false => 1.0,
+    }
+}
@@ -0,0 +1,150 @@
+//! Files dropped onto the UI's window
+//!
+//! Sample based UIs let the user drop audio files onto them. Hosts
+//! don't take part in drag and drop, the windowing backends receive
+//! the drops themselves: `X11Window` speaks the XDND protocol and
+//! `WinitWindow` the one of its platform. Both collect the drops as
+//! `DroppedFile`s, which the UI forwards to
+//! `PluginUI::file_dropped()`:
+//!
+//! ```ignore
+//! fn idle(&mut self) -> i32 {
+//!     while let Ok(Some(event)) = self.window.poll_event() {
+//!         self.handle_event(event);
+//!     }
+//!     for file in self.window.take_dropped_files() {
+//!         file.forward(self);
+//!     }
+//!     0
+//! }
+//! ```
+//!
+//! Most drag sources only look for drop targets among top-level
+//! windows. An embedded `X11Window` therefore sets `XdndProxy` on the
+//! host's top-level window, unless the host takes drops itself. Then
+//! only sources looking below the top-level window, as Qt and winit
+//! do, reach an embedded window.
+
+use std::path::PathBuf;
+
+use crate::plugin_ui::PluginUI;
+
+/// A file dropped onto a window
+#[derive(Clone, Debug, PartialEq)]
+pub struct DroppedFile {
+    pub path: PathBuf,
+    /// The position of the drop in pixels relative to the window
+    pub x: f64,
+    pub y: f64,
+}
+
+impl DroppedFile {
+    /// Passes the file to `PluginUI::file_dropped()`
+    pub fn forward(&self, ui: &mut impl PluginUI) {
+        ui.file_dropped(&self.path, self.x, self.y);
+    }
+}
+
+/// The local paths of a `text/uri-list`, as dropped by file managers
+///
+/// URIs other than `file:` ones of the local host are skipped.
+pub fn paths_from_uri_list(uri_list: &[u8]) -> Vec<PathBuf> {
+    uri_list
+        .split(|byte| *byte == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty() && !line.starts_with(b"#"))
+        .filter_map(path_from_file_uri)
+        .collect()
+}
+
+fn path_from_file_uri(uri: &[u8]) -> Option<PathBuf> {
+    let rest = uri.strip_prefix(b"file://")?;
+    let path = match rest.iter().position(|byte| *byte == b'/')? {
+        0 => rest,
+        host_end => match &rest[..host_end] {
+            b"localhost" => &rest[host_end..],
+            _ => return None,
+        },
+    };
+    path_from_bytes(percent_decode(path))
+}
+
+fn percent_decode(bytes: &[u8]) -> Vec<u8> {
+    let hex = |byte: u8| (byte as char).to_digit(16).map(|digit| digit as u8);
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i..i + 3) {
+            Some([b'%', high, low]) => hex(*high).zip(hex(*low)),
+            _ => None,
+        };
+        match escaped {
+            Some((high, low)) => {
+                decoded.push(high << 4 | low);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::ffi::OsString;
+    use std::os::unix::ffi::OsStringExt;
+    Some(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_list_is_split_at_crlf() {
+        let paths = paths_from_uri_list(b"file:///tmp/a.wav\r\nfile:///tmp/b.wav\r\n");
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("/tmp/a.wav"), PathBuf::from("/tmp/b.wav")]
+        );
+    }
+
+    #[test]
+    fn comments_and_empty_lines_are_skipped() {
+        let paths = paths_from_uri_list(b"# dropped\r\n\r\nfile:///tmp/a.wav\n");
+        assert_eq!(paths, vec![PathBuf::from("/tmp/a.wav")]);
+    }
+
+    #[test]
+    fn paths_are_percent_decoded() {
+        let paths = paths_from_uri_list(b"file:///tmp/my%20loop%2541.wav");
+        assert_eq!(paths, vec![PathBuf::from("/tmp/my loop%41.wav")]);
+    }
+
+    #[test]
+    fn invalid_escapes_are_kept() {
+        let paths = paths_from_uri_list(b"file:///tmp/100%.wav\nfile:///tmp/%zz");
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("/tmp/100%.wav"), PathBuf::from("/tmp/%zz")]
+        );
+    }
+
+    #[test]
+    fn only_local_files_are_taken() {
+        let uri_list = b"file://localhost/tmp/a.wav\r\n\
+            file://studio/tmp/b.wav\r\n\
+            https://example.org/c.wav\r\n\
+            file:/tmp/d.wav";
+        let paths = paths_from_uri_list(uri_list);
+        assert_eq!(paths, vec![PathBuf::from("/tmp/a.wav")]);
+    }
+}
@@ -0,0 +1,133 @@
+//! One scale factor from the host, the toolkit and the backend
+//!
+//! The scale factor for HiDPI displays comes from several places:
+//! the host's `ui:scaleFactor` option, the toolkit the UI draws with,
+//! e.g. winit's per monitor scale, and the backend, e.g. the `Xft.dpi`
+//! resource read by `X11Window`. They may disagree or only some of
+//! them may be known. `DpiPolicy` keeps what the sources report and
+//! takes the effective scale from the first source of its precedence
+//! that has one:
+//!
+//! ```ignore
+//! // in new()
+//! let mut dpi = DpiPolicy::new();
+//! dpi.update(ScaleSource::Host, plugin_ui_info.host_options().scale_factor());
+//! dpi.update(ScaleSource::Backend, window.scale_factor());
+//!
+//! // in idle()
+//! if let Some(scale) = window.take_scale_change() {
+//!     if let Some(effective) = self.dpi.update(ScaleSource::Backend, Some(scale)) {
+//!         self.relayout(effective);
+//!     }
+//! }
+//! ```
+//!
+//! `PluginUI::scale_factor_changed()` reports the host's changes by
+//! `PluginUI::OPTIONS_INTERFACE`, to be passed as `ScaleSource::Host`.
+//! The UI lays out in logical pixels and converts by `to_physical()`
+//! and `to_logical()`.
+
+/// Where a scale factor comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScaleSource {
+    /// The host's `ui:scaleFactor` option
+    Host,
+    /// The toolkit the UI draws with
+    Toolkit,
+    /// The windowing backend, e.g. the `Xft.dpi` resource on X11
+    Backend,
+}
+
+impl ScaleSource {
+    fn slot(self) -> usize {
+        match self {
+            ScaleSource::Host => 0,
+            ScaleSource::Toolkit => 1,
+            ScaleSource::Backend => 2,
+        }
+    }
+}
+
+/// Reconciles the scale factors of the sources into one
+#[derive(Clone, Debug, PartialEq)]
+pub struct DpiPolicy {
+    precedence: [ScaleSource; 3],
+    scales: [Option<f32>; 3],
+}
+
+impl Default for DpiPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DpiPolicy {
+    /// Prefers the host, which knows the monitor its window is on, then the toolkit
+    pub fn new() -> Self {
+        Self::with_precedence([
+            ScaleSource::Host,
+            ScaleSource::Toolkit,
+            ScaleSource::Backend,
+        ])
+    }
+
+    /// Takes the scale of the first source in `precedence` that has one
+    pub fn with_precedence(precedence: [ScaleSource; 3]) -> Self {
+        Self {
+            precedence,
+            scales: [None; 3],
+        }
+    }
+
+    /// Sets the scale `source` reports, returns the effective scale if it has changed
+    ///
+    /// Scales that are not positive are taken as unknown.
+    pub fn update(&mut self, source: ScaleSource, scale: Option<f32>) -> Option<f32> {
+        let before = self.scale_factor();
+        self.scales[source.slot()] = scale.filter(|scale| scale.is_finite() && *scale > 0.0);
+        let after = self.scale_factor();
+        (after != before).then_some(after)
+    }
+
+    /// The scale `source` has reported
+    pub fn reported(&self, source: ScaleSource) -> Option<f32> {
+        self.scales[source.slot()]
+    }
+
+    /// The source the effective scale is taken from, None if no source knows one
+    pub fn source(&self) -> Option<ScaleSource> {
+        self.precedence
+            .iter()
+            .copied()
+            .find(|source| self.scales[source.slot()].is_some())
+    }
+
+    /// The effective scale factor, 1.0 if no source knows one
+    pub fn scale_factor(&self) -> f32 {
+        self.source()
+            .and_then(|source| self.scales[source.slot()])
+            .unwrap_or(1.0)
+    }
+
+    /// Converts a logical coordinate into pixels
+    pub fn to_physical(&self, logical: f32) -> f32 {
+        logical * self.scale_factor()
+    }
+
+    /// Converts pixels into a logical coordinate
+    pub fn to_logical(&self, physical: f32) -> f32 {
+        physical / self.scale_factor()
+    }
+
+    /// Converts a logical size into pixels, rounded
+    pub fn to_physical_size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let physical = |size: u32| self.to_physical(size as f32).round() as u32;
+        (physical(width), physical(height))
+    }
+
+    /// Converts a size in pixels into a logical one, rounded
+    pub fn to_logical_size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        let logical = |size: u32| self.to_logical(size as f32).round() as u32;
+        (logical(width), logical(height))
+    }
+}
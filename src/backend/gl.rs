@@ -0,0 +1,250 @@
+//! OpenGL rendering on the UI's window
+//!
+//! `GlContext` creates an OpenGL context and a surface on a window by
+//! glutin, using GLX on X11, WGL on Windows and CGL on macOS, and
+//! hands out a `glow::Context` to render with. `GlWindow` combines it
+//! with a `WinitWindow`, embedded into the host's window like the
+//! window alone:
+//!
+//! ```ignore
+//! impl GlHandler for AmpView {
+//!     fn draw(&mut self, gl: &glow::Context) {
+//!         unsafe {
+//!             gl.clear_color(0.1, 0.1, 0.1, 1.0);
+//!             gl.clear(glow::COLOR_BUFFER_BIT);
+//!         }
+//!     }
+//! }
+//!
+//! fn idle(&mut self) -> i32 {
+//!     if let Err(e) = self.window.pump_events(&mut self.view) {
+//!         ui_log!(Error, "GL window failed: {:?}", e);
+//!         return 1;
+//!     }
+//!     0
+//! }
+//! ```
+//!
+//! The surface follows the size of the window. The handler draws on
+//! `RedrawRequested`, UIs animating from `idle()` call
+//! `Window::request_redraw()` or draw right away by
+//! `GlContext::draw()`. The context is made current before every
+//! drawing, as hosts may show several GL UIs in one thread.
+
+use std::ffi::{c_void, CStr};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use glutin::config::ConfigTemplateBuilder;
+use glutin::context::{
+    ContextAttributesBuilder, NotCurrentGlContext, PossiblyCurrentContext, PossiblyCurrentGlContext,
+};
+use glutin::display::{Display, DisplayApiPreference, GetGlDisplay, GlDisplay};
+use glutin::surface::{GlSurface, Surface, SurfaceAttributesBuilder, WindowSurface};
+use winit::event::WindowEvent;
+use winit::raw_window_handle::{HandleError, HasDisplayHandle, HasWindowHandle, RawWindowHandle};
+use winit::window::Window;
+
+use crate::backend::winit::{WinitWindow, WinitWindowError};
+use crate::plugin_ui::WindowMode;
+
+#[derive(Debug)]
+pub enum GlError {
+    Glutin(glutin::error::Error),
+    Handle(HandleError),
+    Window(WinitWindowError),
+    /// No framebuffer configuration fits the window
+    NoConfig,
+}
+
+impl From<glutin::error::Error> for GlError {
+    fn from(e: glutin::error::Error) -> Self {
+        GlError::Glutin(e)
+    }
+}
+
+impl From<HandleError> for GlError {
+    fn from(e: HandleError) -> Self {
+        GlError::Handle(e)
+    }
+}
+
+impl From<WinitWindowError> for GlError {
+    fn from(e: WinitWindowError) -> Self {
+        GlError::Window(e)
+    }
+}
+
+/// The platform's native GL API
+#[cfg(all(unix, not(target_os = "macos")))]
+fn api_preference(_window: RawWindowHandle) -> DisplayApiPreference {
+    // winit's Xlib connection reports the errors of GLX
+    DisplayApiPreference::Glx(Box::new(winit::platform::x11::register_xlib_error_hook))
+}
+
+/// The platform's native GL API
+#[cfg(target_os = "windows")]
+fn api_preference(window: RawWindowHandle) -> DisplayApiPreference {
+    DisplayApiPreference::Wgl(Some(window))
+}
+
+/// The platform's native GL API
+#[cfg(target_os = "macos")]
+fn api_preference(_window: RawWindowHandle) -> DisplayApiPreference {
+    DisplayApiPreference::Cgl
+}
+
+// Surfaces can't be empty, windows can
+fn surface_size(size: u32) -> NonZeroU32 {
+    NonZeroU32::new(size).unwrap_or(NonZeroU32::MIN)
+}
+
+/// An OpenGL context with a surface on a window
+pub struct GlContext {
+    gl: Arc<glow::Context>,
+    surface: Surface<WindowSurface>,
+    context: PossiblyCurrentContext,
+}
+
+impl GlContext {
+    /// Creates the context on `window` of the size `width` × `height`
+    ///
+    /// The context is current afterwards.
+    ///
+    /// # Safety
+    ///
+    /// `window` has to outlive the context.
+    pub unsafe fn new(
+        window: &(impl HasWindowHandle + HasDisplayHandle),
+        width: u32,
+        height: u32,
+    ) -> Result<Self, GlError> {
+        let raw_window = window.window_handle()?.as_raw();
+        let display = Display::new(
+            window.display_handle()?.as_raw(),
+            api_preference(raw_window),
+        )?;
+
+        // On X11 the window's visual needs to match the configuration
+        let template = ConfigTemplateBuilder::new()
+            .compatible_with_native_window(raw_window)
+            .build();
+        let config = display
+            .find_configs(template)?
+            .next()
+            .ok_or(GlError::NoConfig)?;
+
+        let context_attributes = ContextAttributesBuilder::new().build(Some(raw_window));
+        let context = display.create_context(&config, &context_attributes)?;
+        let surface_attributes = SurfaceAttributesBuilder::<WindowSurface>::new().build(
+            raw_window,
+            surface_size(width),
+            surface_size(height),
+        );
+        let surface = display.create_window_surface(&config, &surface_attributes)?;
+        let context = context.make_current(&surface)?;
+
+        let gl = glow::Context::from_loader_function_cstr(|name| display.get_proc_address(name));
+        Ok(Self {
+            gl: Arc::new(gl),
+            surface,
+            context,
+        })
+    }
+
+    /// The functions to render with, shared e.g. with a GUI library
+    pub fn gl(&self) -> &Arc<glow::Context> {
+        &self.gl
+    }
+
+    /// The address of the GL function `name`, for loaders other than glow
+    pub fn get_proc_address(&self, name: &CStr) -> *const c_void {
+        self.context.display().get_proc_address(name)
+    }
+
+    /// Makes the context current in the calling thread
+    pub fn make_current(&self) -> Result<(), GlError> {
+        self.context.make_current(&self.surface)?;
+        Ok(())
+    }
+
+    /// Shows what has been rendered
+    pub fn swap_buffers(&self) -> Result<(), GlError> {
+        self.surface.swap_buffers(&self.context)?;
+        Ok(())
+    }
+
+    /// Resizes the surface, to be called when the window has been resized
+    pub fn resize(&self, width: u32, height: u32) {
+        self.surface
+            .resize(&self.context, surface_size(width), surface_size(height));
+    }
+
+    /// Makes the context current, calls `render` and swaps the buffers
+    pub fn draw(&self, render: impl FnOnce(&glow::Context)) -> Result<(), GlError> {
+        self.make_current()?;
+        render(&self.gl);
+        self.swap_buffers()
+    }
+}
+
+/// Renders the content of a `GlWindow`
+pub trait GlHandler {
+    /// Draws the window, the context is current and the buffers are swapped afterwards
+    fn draw(&mut self, gl: &glow::Context);
+
+    /// Receives the events of the window, after the surface has followed a resize
+    fn window_event(&mut self, _window: &Window, _event: &WindowEvent) {}
+}
+
+/// A `WinitWindow` rendered by OpenGL
+pub struct GlWindow {
+    // Dropped before the window it renders on
+    context: GlContext,
+    window: WinitWindow,
+}
+
+impl GlWindow {
+    /// Opens the window according to `mode`, see `WinitWindow::open()`
+    pub fn open(mode: WindowMode, width: u32, height: u32, title: &str) -> Result<Self, GlError> {
+        let window = WinitWindow::open(mode, width, height, title)?;
+        // The window is kept as long as the context
+        let context = unsafe { GlContext::new(window.window(), width, height)? };
+        Ok(Self { context, window })
+    }
+
+    pub fn window(&self) -> &WinitWindow {
+        &self.window
+    }
+
+    pub fn window_mut(&mut self) -> &mut WinitWindow {
+        &mut self.window
+    }
+
+    pub fn context(&self) -> &GlContext {
+        &self.context
+    }
+
+    /// The functions to render with, see `GlContext::gl()`
+    pub fn gl(&self) -> &Arc<glow::Context> {
+        self.context.gl()
+    }
+
+    /// Pumps the events like `WinitWindow::pump_events()` and draws on `RedrawRequested`
+    pub fn pump_events(&mut self, handler: &mut impl GlHandler) -> Result<(), GlError> {
+        let context = &self.context;
+        let mut drawn = Ok(());
+        self.window
+            .pump_events(&mut |window: &Window, event: WindowEvent| {
+                match &event {
+                    WindowEvent::Resized(size) => context.resize(size.width, size.height),
+                    WindowEvent::RedrawRequested => {
+                        drawn = context.draw(|gl| handler.draw(gl));
+                    }
+                    _ => {}
+                }
+                handler.window_event(window, &event);
+            })?;
+        drawn
+    }
+}
@@ -0,0 +1,25 @@
+//! Optional windowing backends
+//!
+//! The backends take care of the platform specific window handling,
+//! so that the same UI code works for hosts embedding the UI into
+//! their window as well as for hosts expecting the UI to open its
+//! own top-level window. Every backend is behind a cargo feature.
+//! `session` picks the window mode for the session the UI runs in,
+//! `dpi` reconciles the scale factors of host, toolkit and backend,
+//! `dnd` delivers the files dropped onto the window.
+
+#[cfg(feature = "canvas")]
+pub mod canvas;
+pub mod dnd;
+pub mod dpi;
+#[cfg(feature = "gl")]
+pub mod gl;
+pub mod session;
+#[cfg(feature = "winit")]
+pub mod winit;
+#[cfg(feature = "x11")]
+pub mod x11;
+#[cfg(feature = "x11-calloop")]
+pub mod x11_calloop;
+#[cfg(feature = "x11")]
+pub mod x11_software;
@@ -0,0 +1,106 @@
+//! Embedding on X11 and Wayland sessions
+//!
+//! Hosts pass the `ui:parent` as X11 window id on Linux. On a
+//! Wayland session that only works through XWayland: an X11 host
+//! running there passes an XWayland window, into which the UI can
+//! embed its X11 window as on X11. Without XWayland no X11 window
+//! can be opened at all and Wayland has no embedding of foreign
+//! windows, so the UI needs to open a top-level window.
+//!
+//! `Session` detects the session type by the environment and picks
+//! the window mode accordingly:
+//!
+//! ```ignore
+//! let mode = Session::detect().window_mode(parent_window);
+//! ```
+//!
+//! The backends use it instead of `WindowMode::from_parent()`. UIs
+//! that may end up in a top-level window should provide
+//! `PluginUI::SHOW_INTERFACE`, so hosts expecting to embed them can
+//! still show them.
+
+use std::ffi::{OsStr, OsString};
+
+use crate::logging::ui_log;
+use crate::plugin_ui::WindowMode;
+
+/// The kind of the desktop session
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionType {
+    X11,
+    Wayland,
+    /// Not a Linux desktop session, e.g. Windows or macOS
+    Other,
+}
+
+/// The desktop session the UI runs in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Session {
+    session_type: SessionType,
+    x_display: bool,
+}
+
+impl Session {
+    /// Detects the session by the environment of the process
+    pub fn detect() -> Self {
+        Self::from_env(|name| std::env::var_os(name))
+    }
+
+    /// Detects the session by the environment variables `var` returns
+    ///
+    /// `XDG_SESSION_TYPE` takes precedence, then `WAYLAND_DISPLAY`
+    /// and `DISPLAY` are looked at.
+    pub fn from_env(var: impl Fn(&str) -> Option<OsString>) -> Self {
+        let is_set = |name: &str| var(name).is_some_and(|value| !value.is_empty());
+        let session_type = match var("XDG_SESSION_TYPE").as_deref().and_then(OsStr::to_str) {
+            Some("wayland") => SessionType::Wayland,
+            Some("x11") => SessionType::X11,
+            _ if is_set("WAYLAND_DISPLAY") => SessionType::Wayland,
+            _ if is_set("DISPLAY") => SessionType::X11,
+            _ => SessionType::Other,
+        };
+        Self {
+            session_type,
+            x_display: is_set("DISPLAY"),
+        }
+    }
+
+    pub fn session_type(&self) -> SessionType {
+        self.session_type
+    }
+
+    /// True if X11 windows can be opened, natively or by XWayland
+    pub fn has_x_display(&self) -> bool {
+        self.x_display
+    }
+
+    /// True if X11 windows are opened by XWayland
+    pub fn is_xwayland(&self) -> bool {
+        self.session_type == SessionType::Wayland && self.x_display
+    }
+
+    /// True if the UI can embed its window into the host's `ui:parent`
+    pub fn can_embed(&self) -> bool {
+        match self.session_type {
+            SessionType::X11 | SessionType::Wayland => self.x_display,
+            SessionType::Other => true,
+        }
+    }
+
+    /// The window mode for the `parent_window` passed to `PluginUI::new()`
+    ///
+    /// Like `WindowMode::from_parent()`, but falls back to a
+    /// top-level window if the session can't embed.
+    pub fn window_mode(&self, parent_window: *mut std::ffi::c_void) -> WindowMode {
+        match WindowMode::from_parent(parent_window) {
+            WindowMode::Embedded(_) if !self.can_embed() => {
+                ui_log!(
+                    Warning,
+                    "cannot embed into the host's window without XWayland, opening a top-level window"
+                );
+                WindowMode::TopLevel
+            }
+            mode => mode,
+        }
+    }
+}
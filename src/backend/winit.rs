@@ -0,0 +1,390 @@
+//! winit window backend
+//!
+//! `WinitWindow` opens a winit window either as child of the window
+//! the host provided by the `ui:parent` feature, or, if the host did
+//! not provide one, as top-level window. This way UIs can use the
+//! libraries built on winit for rendering and widgets.
+//!
+//! winit expects to own the event loop of the application, which is
+//! the host's in a plugin UI. The loop is therefore pumped from
+//! `PluginUI::idle()` without blocking, and the events of the window
+//! are passed to a `WinitHandler`:
+//!
+//! ```ignore
+//! impl WinitHandler for AmpView {
+//!     fn window_event(&mut self, window: &Window, event: WindowEvent) {
+//!         if let WindowEvent::RedrawRequested = event {
+//!             self.draw(window);
+//!         }
+//!     }
+//! }
+//!
+//! fn idle(&mut self) -> i32 {
+//!     if let Err(e) = self.window.pump_events(&mut self.view) {
+//!         ui_log!(Error, "winit event loop failed: {:?}", e);
+//!         return 1;
+//!     }
+//!     if let Some((width, height)) = self.window.take_resize() {
+//!         self.resized(width, height);
+//!     }
+//!     self.window.close_requested() as i32
+//! }
+//! ```
+//!
+//! winit allows only one event loop per process. It is created by
+//! the first window and kept by its thread, the windows opened in
+//! that thread share it like the ones of the X11 backend share the
+//! connection. Windows in other threads fail with
+//! `WinitWindowError::EventLoop`. On Linux the loop uses X11 if
+//! possible, as hosts pass X11 windows as parents, and Wayland else.
+//!
+//! Files dropped onto the window are collected as `DroppedFile`s,
+//! see `WinitWindow::take_dropped_files()`.
+
+use lv2_sys as sys;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use winit::application::ApplicationHandler;
+use winit::dpi::PhysicalSize;
+use winit::error::{EventLoopError, OsError};
+use winit::event::WindowEvent;
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::platform::pump_events::{EventLoopExtPumpEvents, PumpStatus};
+use winit::raw_window_handle::{HandleError, HasWindowHandle, RawWindowHandle};
+use winit::window::{Window, WindowId};
+
+use crate::backend::dnd::DroppedFile;
+use crate::plugin_ui::WindowMode;
+
+#[derive(Debug)]
+pub enum WinitWindowError {
+    EventLoop(EventLoopError),
+    Os(OsError),
+    Handle(HandleError),
+    /// The parent window is null or not of the platform's kind
+    InvalidParent,
+    /// The event loop has been exited with the code
+    Exited(i32),
+}
+
+impl From<EventLoopError> for WinitWindowError {
+    fn from(e: EventLoopError) -> Self {
+        WinitWindowError::EventLoop(e)
+    }
+}
+
+impl From<OsError> for WinitWindowError {
+    fn from(e: OsError) -> Self {
+        WinitWindowError::Os(e)
+    }
+}
+
+impl From<HandleError> for WinitWindowError {
+    fn from(e: HandleError) -> Self {
+        WinitWindowError::Handle(e)
+    }
+}
+
+/// Receives the events of a `WinitWindow`
+pub trait WinitHandler {
+    fn window_event(&mut self, window: &Window, event: WindowEvent);
+}
+
+impl<F: FnMut(&Window, WindowEvent)> WinitHandler for F {
+    fn window_event(&mut self, window: &Window, event: WindowEvent) {
+        (*self)(window, event)
+    }
+}
+
+thread_local! {
+    // Never dropped, as winit does not allow to create another one
+    static EVENT_LOOP: RefCell<Option<EventLoop<()>>> = const { RefCell::new(None) };
+    static QUEUES: RefCell<HashMap<WindowId, VecDeque<WindowEvent>>> =
+        RefCell::new(HashMap::new());
+}
+
+fn build_event_loop() -> Result<EventLoop<()>, EventLoopError> {
+    #[allow(unused_mut)]
+    let mut builder = EventLoop::builder();
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        use winit::platform::x11::EventLoopBuilderExtX11;
+        // Without X11 only top-level windows are possible, see `session`
+        if crate::backend::session::Session::detect().has_x_display() {
+            builder.with_x11();
+        }
+        builder.with_any_thread(true);
+    }
+    #[cfg(target_os = "windows")]
+    {
+        use winit::platform::windows::EventLoopBuilderExtWindows;
+        builder.with_any_thread(true);
+    }
+    builder.build()
+}
+
+/// Runs `f` with the event loop of the thread, creating it if there is none
+fn with_event_loop<R>(
+    f: impl FnOnce(&mut EventLoop<()>) -> Result<R, WinitWindowError>,
+) -> Result<R, WinitWindowError> {
+    EVENT_LOOP.with(|slot| {
+        let mut event_loop = match slot.borrow_mut().take() {
+            Some(event_loop) => event_loop,
+            None => build_event_loop()?,
+        };
+        let result = f(&mut event_loop);
+        *slot.borrow_mut() = Some(event_loop);
+        result
+    })
+}
+
+/// Sorts the events of the pumped loop into the queues of the windows
+struct Dispatcher;
+
+impl ApplicationHandler for Dispatcher {
+    fn resumed(&mut self, _event_loop: &ActiveEventLoop) {}
+
+    fn window_event(&mut self, _event_loop: &ActiveEventLoop, id: WindowId, event: WindowEvent) {
+        QUEUES.with(|queues| {
+            if let Some(queue) = queues.borrow_mut().get_mut(&id) {
+                queue.push_back(event);
+            }
+        });
+    }
+}
+
+/// The parent window as the platform's window handle
+#[cfg(all(unix, not(target_os = "macos")))]
+fn parent_handle(parent: *mut std::ffi::c_void) -> Option<RawWindowHandle> {
+    use winit::raw_window_handle::XlibWindowHandle;
+    match parent as usize {
+        0 => None,
+        window => Some(XlibWindowHandle::new(window as std::os::raw::c_ulong).into()),
+    }
+}
+
+/// The parent window as the platform's window handle
+#[cfg(target_os = "windows")]
+fn parent_handle(parent: *mut std::ffi::c_void) -> Option<RawWindowHandle> {
+    use winit::raw_window_handle::Win32WindowHandle;
+    let hwnd = std::num::NonZeroIsize::new(parent as isize)?;
+    Some(Win32WindowHandle::new(hwnd).into())
+}
+
+/// The parent view as the platform's window handle
+#[cfg(target_os = "macos")]
+fn parent_handle(parent: *mut std::ffi::c_void) -> Option<RawWindowHandle> {
+    use winit::raw_window_handle::AppKitWindowHandle;
+    let ns_view = std::ptr::NonNull::new(parent)?;
+    Some(AppKitWindowHandle::new(ns_view).into())
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+fn parent_handle(_parent: *mut std::ffi::c_void) -> Option<RawWindowHandle> {
+    None
+}
+
+/// The window as LV2UI_Widget, the window id or the view
+fn widget_of(window: &Window) -> Result<sys::LV2UI_Widget, WinitWindowError> {
+    match window.window_handle()?.as_raw() {
+        RawWindowHandle::Xlib(handle) => Ok(handle.window as usize as sys::LV2UI_Widget),
+        RawWindowHandle::Xcb(handle) => Ok(handle.window.get() as usize as sys::LV2UI_Widget),
+        RawWindowHandle::Win32(handle) => Ok(handle.hwnd.get() as sys::LV2UI_Widget),
+        RawWindowHandle::AppKit(handle) => Ok(handle.ns_view.as_ptr()),
+        _ => Err(WinitWindowError::Handle(HandleError::NotSupported)),
+    }
+}
+
+/// The winit window of a plugin UI
+///
+/// The window is closed when the `WinitWindow` is dropped.
+pub struct WinitWindow {
+    window: Window,
+    widget: sys::LV2UI_Widget,
+    top_level: bool,
+    close_requested: bool,
+    size: (u32, u32),
+    resized: bool,
+    scale_factor: f64,
+    scale_changed: bool,
+    cursor_position: (f64, f64),
+    dropped_files: Vec<DroppedFile>,
+}
+
+impl WinitWindow {
+    /// Opens the window according to `mode`
+    ///
+    /// An embedded window is visible right away. A top-level window
+    /// gets `title` as window title and needs to be shown by `show()`.
+    pub fn open(
+        mode: WindowMode,
+        width: u32,
+        height: u32,
+        title: &str,
+    ) -> Result<Self, WinitWindowError> {
+        let mut attributes = Window::default_attributes()
+            .with_inner_size(PhysicalSize::new(width, height))
+            .with_title(title);
+        let top_level = match mode {
+            WindowMode::Embedded(parent) => {
+                let parent = parent_handle(parent).ok_or(WinitWindowError::InvalidParent)?;
+                // The host keeps its window alive as long as the UI
+                attributes = unsafe { attributes.with_parent_window(Some(parent)) };
+                false
+            }
+            WindowMode::TopLevel => {
+                attributes = attributes.with_visible(false);
+                true
+            }
+        };
+        // The host drives the loop, so the window is created outside of an `ApplicationHandler`
+        #[allow(deprecated)]
+        let window = with_event_loop(|event_loop| Ok(event_loop.create_window(attributes)?))?;
+        let widget = widget_of(&window)?;
+        QUEUES.with(|queues| queues.borrow_mut().insert(window.id(), VecDeque::new()));
+        let size = window.inner_size();
+        let scale_factor = window.scale_factor();
+        Ok(Self {
+            window,
+            widget,
+            top_level,
+            close_requested: false,
+            size: (size.width, size.height),
+            resized: false,
+            scale_factor,
+            scale_changed: false,
+            cursor_position: (0.0, 0.0),
+            dropped_files: Vec::new(),
+        })
+    }
+
+    /// The winit window, e.g. to create a rendering surface on
+    pub fn window(&self) -> &Window {
+        &self.window
+    }
+
+    /// The window as LV2UI_Widget, to be returned by `PluginUI::widget()`
+    pub fn widget(&self) -> sys::LV2UI_Widget {
+        self.widget
+    }
+
+    /// True if the window is a top-level window rather than embedded
+    pub fn is_top_level(&self) -> bool {
+        self.top_level
+    }
+
+    /// The current size of the window in pixels
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// Returns the new size if the window has been resized
+    ///
+    /// To be forwarded to `PluginUI::resized()`.
+    pub fn take_resize(&mut self) -> Option<(u32, u32)> {
+        match std::mem::replace(&mut self.resized, false) {
+            true => Some(self.size),
+            false => None,
+        }
+    }
+
+    /// The scale factor winit reports for the window's monitor
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    /// Returns the new scale factor if it has changed
+    ///
+    /// To be forwarded to `PluginUI::scale_factor_changed()`.
+    pub fn take_scale_change(&mut self) -> Option<f32> {
+        match std::mem::replace(&mut self.scale_changed, false) {
+            true => Some(self.scale_factor as f32),
+            false => None,
+        }
+    }
+
+    /// Returns the files dropped onto the window since the last call
+    ///
+    /// To be forwarded to `PluginUI::file_dropped()`. winit reports
+    /// no position with the drop, so the last position of the cursor
+    /// within the window is taken.
+    pub fn take_dropped_files(&mut self) -> Vec<DroppedFile> {
+        std::mem::take(&mut self.dropped_files)
+    }
+
+    pub fn show(&self) {
+        self.window.set_visible(true);
+    }
+
+    pub fn hide(&self) {
+        self.window.set_visible(false);
+    }
+
+    /// True if the user asked to close the top-level window
+    pub fn close_requested(&self) -> bool {
+        self.close_requested
+    }
+
+    /// Pumps the event loop and passes the pending events of the window to `handler`
+    ///
+    /// Does not block, to be called in `PluginUI::idle()`. Size and
+    /// scale changes, dropped files and close requests are tracked by
+    /// the window before the event is passed on.
+    pub fn pump_events(&mut self, handler: &mut impl WinitHandler) -> Result<(), WinitWindowError> {
+        let status = with_event_loop(|event_loop| {
+            Ok(event_loop.pump_app_events(Some(Duration::ZERO), &mut Dispatcher))
+        })?;
+        if let PumpStatus::Exit(code) = status {
+            return Err(WinitWindowError::Exited(code));
+        }
+        while let Some(event) = self.next_event() {
+            match &event {
+                WindowEvent::Resized(size) if (size.width, size.height) != self.size => {
+                    self.size = (size.width, size.height);
+                    self.resized = true;
+                }
+                WindowEvent::ScaleFactorChanged { scale_factor, .. }
+                    if *scale_factor != self.scale_factor =>
+                {
+                    self.scale_factor = *scale_factor;
+                    self.scale_changed = true;
+                }
+                WindowEvent::CloseRequested => {
+                    self.close_requested = true;
+                }
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.cursor_position = (position.x, position.y);
+                }
+                WindowEvent::DroppedFile(path) => {
+                    let (x, y) = self.cursor_position;
+                    self.dropped_files.push(DroppedFile {
+                        path: path.clone(),
+                        x,
+                        y,
+                    });
+                }
+                _ => {}
+            }
+            handler.window_event(&self.window, event);
+        }
+        Ok(())
+    }
+
+    fn next_event(&self) -> Option<WindowEvent> {
+        QUEUES.with(|queues| {
+            queues
+                .borrow_mut()
+                .get_mut(&self.window.id())
+                .and_then(VecDeque::pop_front)
+        })
+    }
+}
+
+impl Drop for WinitWindow {
+    fn drop(&mut self) {
+        QUEUES.with(|queues| queues.borrow_mut().remove(&self.window.id()));
+    }
+}
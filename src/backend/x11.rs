@@ -0,0 +1,918 @@
+//! X11 window backend based on x11rb
+//!
+//! `X11Window` opens the UI's window either as child of the window
+//! the host provided by the `ui:parent` feature, or, if the host
+//! did not provide one, as top-level window.
+//!
+//! Hosts often show several plugin UIs in one process. The windows
+//! opened in one thread share the connection to the X server, an
+//! `X11Display`, which is closed when the last window is dropped.
+//! Every window only gets the events of its own window, the events
+//! of the others are kept for them.
+//!
+//! The scale factor for HiDPI displays is taken from the `Xft.dpi`
+//! resource. The display watches the resources of the root window,
+//! so changes by the desktop are reported by
+//! `X11Window::take_scale_change()`.
+//!
+//! Embedded windows don't get the keyboard focus from the window
+//! manager, the host decides. `FocusPolicy` lets the window take it
+//! on a click or while the pointer is inside, and
+//! `X11Window::grab_keyboard()` holds it during text entry.
+//!
+//! Files dropped onto the window by the XDND protocol are collected
+//! as `DroppedFile`s, see `X11Window::take_dropped_files()`.
+
+use lv2_sys as sys;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::rc::{Rc, Weak};
+
+use x11rb::atom_manager;
+
+use x11rb::connection::Connection;
+use x11rb::errors::{ConnectError, ConnectionError, ReplyError, ReplyOrIdError};
+use x11rb::protocol::xproto::{self, ConnectionExt as _};
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+use x11rb::wrapper::ConnectionExt as _;
+
+use crate::backend::dnd::{paths_from_uri_list, DroppedFile};
+use crate::plugin_ui::WindowMode;
+
+#[derive(Debug)]
+pub enum X11WindowError {
+    Connect(ConnectError),
+    Connection(ConnectionError),
+    Reply(ReplyError),
+    ReplyOrId(ReplyOrIdError),
+}
+
+impl From<ConnectError> for X11WindowError {
+    fn from(e: ConnectError) -> Self {
+        X11WindowError::Connect(e)
+    }
+}
+
+impl From<ConnectionError> for X11WindowError {
+    fn from(e: ConnectionError) -> Self {
+        X11WindowError::Connection(e)
+    }
+}
+
+impl From<ReplyError> for X11WindowError {
+    fn from(e: ReplyError) -> Self {
+        X11WindowError::Reply(e)
+    }
+}
+
+impl From<ReplyOrIdError> for X11WindowError {
+    fn from(e: ReplyOrIdError) -> Self {
+        X11WindowError::ReplyOrId(e)
+    }
+}
+
+/// The XDND protocol version spoken
+const XDND_VERSION: u32 = 5;
+
+atom_manager! {
+    XdndAtoms: XdndAtomsCookie {
+        XdndAware,
+        XdndProxy,
+        XdndEnter,
+        XdndPosition,
+        XdndStatus,
+        XdndLeave,
+        XdndDrop,
+        XdndFinished,
+        XdndSelection,
+        XdndTypeList,
+        XdndActionCopy,
+        TEXT_URI_LIST: b"text/uri-list",
+    }
+}
+
+thread_local! {
+    static DISPLAY: RefCell<Weak<X11Display>> = const { RefCell::new(Weak::new()) };
+}
+
+/// The connection to the X server shared by the windows of a thread
+pub struct X11Display {
+    connection: RustConnection,
+    screen_num: usize,
+    wm_protocols: xproto::Atom,
+    wm_delete_window: xproto::Atom,
+    xdnd: XdndAtoms,
+    queues: RefCell<HashMap<xproto::Window, VecDeque<Event>>>,
+    /// The windows receiving the drops onto a top-level window
+    xdnd_proxies: RefCell<HashMap<xproto::Window, xproto::Window>>,
+    resources_generation: Cell<u64>,
+}
+
+impl X11Display {
+    /// The display of the current thread, connecting if there is none
+    pub fn shared() -> Result<Rc<Self>, X11WindowError> {
+        if let Some(display) = DISPLAY.with(|display| display.borrow().upgrade()) {
+            return Ok(display);
+        }
+        let display = Rc::new(Self::connect()?);
+        DISPLAY.with(|shared| *shared.borrow_mut() = Rc::downgrade(&display));
+        Ok(display)
+    }
+
+    fn connect() -> Result<Self, X11WindowError> {
+        let (connection, screen_num) = x11rb::connect(None)?;
+        let wm_protocols = connection.intern_atom(false, b"WM_PROTOCOLS")?;
+        let wm_delete_window = connection.intern_atom(false, b"WM_DELETE_WINDOW")?;
+        let xdnd = XdndAtoms::new(&connection)?;
+        let wm_protocols = wm_protocols.reply()?.atom;
+        let wm_delete_window = wm_delete_window.reply()?.atom;
+        let xdnd = xdnd.reply()?;
+        // Report changes of the resources, see `scale_factor()`
+        let root = connection.setup().roots[screen_num].root;
+        let aux =
+            xproto::ChangeWindowAttributesAux::new().event_mask(xproto::EventMask::PROPERTY_CHANGE);
+        connection.change_window_attributes(root, &aux)?;
+        Ok(Self {
+            connection,
+            screen_num,
+            wm_protocols,
+            wm_delete_window,
+            xdnd,
+            queues: RefCell::new(HashMap::new()),
+            xdnd_proxies: RefCell::new(HashMap::new()),
+            resources_generation: Cell::new(0),
+        })
+    }
+
+    pub fn connection(&self) -> &RustConnection {
+        &self.connection
+    }
+
+    pub fn screen(&self) -> &xproto::Screen {
+        &self.connection.setup().roots[self.screen_num]
+    }
+
+    /// The scale factor by the `Xft.dpi` resource, 96 dpi being 1.0
+    ///
+    /// None if the resource is not set.
+    pub fn scale_factor(&self) -> Result<Option<f32>, X11WindowError> {
+        let resources = self
+            .connection
+            .get_property(
+                false,
+                self.screen().root,
+                xproto::AtomEnum::RESOURCE_MANAGER,
+                xproto::AtomEnum::STRING,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+        let dpi = std::string::String::from_utf8_lossy(&resources.value)
+            .lines()
+            .filter_map(|line| line.strip_prefix("Xft.dpi:"))
+            .find_map(|value| value.trim().parse::<f32>().ok());
+        Ok(dpi.filter(|dpi| *dpi > 0.0).map(|dpi| dpi / 96.0))
+    }
+
+    /// Returns the next pending event of `window` without blocking
+    ///
+    /// Events of the other windows opened on the display are queued
+    /// for them. All other events, e.g. of subwindows created by the
+    /// UI, are returned to any window.
+    fn poll_event_for(&self, window: xproto::Window) -> Result<Option<Event>, X11WindowError> {
+        if let Some(event) = self
+            .queues
+            .borrow_mut()
+            .get_mut(&window)
+            .and_then(VecDeque::pop_front)
+        {
+            return Ok(Some(event));
+        }
+        while let Some(event) = self.connection.poll_for_event()? {
+            if let Event::PropertyNotify(e) = &event {
+                if e.window == self.screen().root
+                    && e.atom == u32::from(xproto::AtomEnum::RESOURCE_MANAGER)
+                {
+                    self.resources_generation
+                        .set(self.resources_generation.get() + 1);
+                    continue;
+                }
+            }
+            // XDND messages name the top-level window, not the proxy
+            let target = event_window(&event)
+                .map(|target| {
+                    let proxies = self.xdnd_proxies.borrow();
+                    proxies.get(&target).copied().unwrap_or(target)
+                })
+                .filter(|target| *target != window);
+            let mut queues = self.queues.borrow_mut();
+            match target.and_then(|target| queues.get_mut(&target)) {
+                Some(queue) => queue.push_back(event),
+                None => return Ok(Some(event)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn register(&self, window: xproto::Window) {
+        self.queues.borrow_mut().insert(window, VecDeque::new());
+    }
+
+    fn unregister(&self, window: xproto::Window) {
+        self.queues.borrow_mut().remove(&window);
+    }
+}
+
+/// The window an event is reported to
+fn event_window(event: &Event) -> Option<xproto::Window> {
+    match event {
+        Event::Expose(e) => Some(e.window),
+        Event::ConfigureNotify(e) => Some(e.event),
+        Event::ReparentNotify(e) => Some(e.event),
+        Event::MapNotify(e) => Some(e.event),
+        Event::UnmapNotify(e) => Some(e.event),
+        Event::DestroyNotify(e) => Some(e.event),
+        Event::ClientMessage(e) => Some(e.window),
+        Event::ButtonPress(e) | Event::ButtonRelease(e) => Some(e.event),
+        Event::MotionNotify(e) => Some(e.event),
+        Event::KeyPress(e) | Event::KeyRelease(e) => Some(e.event),
+        Event::EnterNotify(e) | Event::LeaveNotify(e) => Some(e.event),
+        Event::FocusIn(e) | Event::FocusOut(e) => Some(e.event),
+        Event::SelectionNotify(e) => Some(e.requestor),
+        _ => None,
+    }
+}
+
+/// When the window takes the keyboard focus
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FocusPolicy {
+    /// Never, the host or the window manager sets the focus
+    Host,
+    /// When the window is clicked
+    ClickToFocus,
+    /// While the pointer is inside the window, giving it back when it leaves
+    FollowsMouse,
+}
+
+/// A drag by the XDND protocol over the window
+#[derive(Clone, Copy, Debug)]
+struct XdndDrag {
+    source: xproto::Window,
+    version: u32,
+    /// True if the source offers a `text/uri-list`
+    accepted: bool,
+    position: (f64, f64),
+    /// True if the position is inside the window, see `XdndProxy`
+    inside: bool,
+    /// True once dropped, while waiting for the data
+    dropped: bool,
+}
+
+/// The X11 window of a plugin UI
+///
+/// The window is destroyed when the `X11Window` is dropped.
+pub struct X11Window {
+    display: Rc<X11Display>,
+    window: xproto::Window,
+    parent: xproto::Window,
+    parent_changed: bool,
+    top_level: bool,
+    close_requested: bool,
+    size: (u16, u16),
+    resized: bool,
+    mapped: bool,
+    visibility_changed: bool,
+    scale_factor: Option<f32>,
+    resources_generation: u64,
+    focus_policy: FocusPolicy,
+    has_focus: bool,
+    previous_focus: Option<xproto::Window>,
+    keyboard_grabbed: bool,
+    drag: Option<XdndDrag>,
+    /// The top-level window forwarding its drops to the window
+    xdnd_proxy: Option<xproto::Window>,
+    dropped_files: Vec<DroppedFile>,
+}
+
+impl X11Window {
+    /// Opens the window according to `mode`
+    ///
+    /// An embedded window is mapped right away. A top-level window
+    /// gets `title` as window name and needs to be shown by `show()`.
+    /// The window uses the shared display of the thread, see
+    /// `X11Display::shared()`.
+    pub fn open(
+        mode: WindowMode,
+        width: u16,
+        height: u16,
+        title: &str,
+    ) -> Result<Self, X11WindowError> {
+        Self::open_on(X11Display::shared()?, mode, width, height, title)
+    }
+
+    /// Opens the window on `display`
+    pub fn open_on(
+        display: Rc<X11Display>,
+        mode: WindowMode,
+        width: u16,
+        height: u16,
+        title: &str,
+    ) -> Result<Self, X11WindowError> {
+        let connection = display.connection();
+        let screen = display.screen();
+
+        let (parent, top_level) = match mode {
+            WindowMode::Embedded(parent) => (parent as usize as xproto::Window, false),
+            WindowMode::TopLevel => (screen.root, true),
+        };
+
+        let window = connection.generate_id()?;
+        let aux = xproto::CreateWindowAux::new()
+            .background_pixel(screen.black_pixel)
+            .event_mask(
+                xproto::EventMask::EXPOSURE
+                    | xproto::EventMask::STRUCTURE_NOTIFY
+                    | xproto::EventMask::BUTTON_PRESS
+                    | xproto::EventMask::BUTTON_RELEASE
+                    | xproto::EventMask::POINTER_MOTION
+                    | xproto::EventMask::KEY_PRESS
+                    | xproto::EventMask::KEY_RELEASE
+                    | xproto::EventMask::ENTER_WINDOW
+                    | xproto::EventMask::LEAVE_WINDOW
+                    | xproto::EventMask::FOCUS_CHANGE,
+            );
+        connection.create_window(
+            x11rb::COPY_DEPTH_FROM_PARENT,
+            window,
+            parent,
+            0,
+            0,
+            width,
+            height,
+            0,
+            xproto::WindowClass::INPUT_OUTPUT,
+            0,
+            &aux,
+        )?;
+
+        if top_level {
+            connection.change_property8(
+                xproto::PropMode::REPLACE,
+                window,
+                xproto::AtomEnum::WM_NAME,
+                xproto::AtomEnum::STRING,
+                title.as_bytes(),
+            )?;
+            connection.change_property32(
+                xproto::PropMode::REPLACE,
+                window,
+                display.wm_protocols,
+                xproto::AtomEnum::ATOM,
+                &[display.wm_delete_window],
+            )?;
+        } else {
+            connection.map_window(window)?;
+        }
+        connection.change_property32(
+            xproto::PropMode::REPLACE,
+            window,
+            display.xdnd.XdndAware,
+            xproto::AtomEnum::ATOM,
+            &[XDND_VERSION],
+        )?;
+        connection.flush()?;
+        display.register(window);
+        let scale_factor = display.scale_factor()?;
+        let resources_generation = display.resources_generation.get();
+
+        let mut x11_window = Self {
+            display,
+            window,
+            parent,
+            parent_changed: false,
+            top_level,
+            close_requested: false,
+            size: (width, height),
+            resized: false,
+            mapped: false,
+            visibility_changed: false,
+            scale_factor,
+            resources_generation,
+            focus_policy: FocusPolicy::Host,
+            has_focus: false,
+            previous_focus: None,
+            keyboard_grabbed: false,
+            drag: None,
+            xdnd_proxy: None,
+            dropped_files: Vec::new(),
+        };
+        if !top_level {
+            x11_window.set_xdnd_proxy()?;
+        }
+        Ok(x11_window)
+    }
+
+    /// The X11 id of the window
+    pub fn id(&self) -> xproto::Window {
+        self.window
+    }
+
+    /// The window as LV2UI_Widget, to be returned by `PluginUI::widget()`
+    pub fn widget(&self) -> sys::LV2UI_Widget {
+        self.window as usize as sys::LV2UI_Widget
+    }
+
+    pub fn connection(&self) -> &RustConnection {
+        self.display.connection()
+    }
+
+    /// The display shared with the other windows of the thread
+    pub fn display(&self) -> &Rc<X11Display> {
+        &self.display
+    }
+
+    /// True if the window is a top-level window rather than embedded
+    pub fn is_top_level(&self) -> bool {
+        self.top_level
+    }
+
+    /// The current window mode, following reparenting by the host
+    pub fn mode(&self) -> WindowMode {
+        match self.top_level {
+            true => WindowMode::TopLevel,
+            false => WindowMode::Embedded(self.parent as usize as *mut std::ffi::c_void),
+        }
+    }
+
+    /// Returns the new parent window if the host has reparented the window
+    ///
+    /// To be forwarded to `PluginUI::parent_changed()`. Only embedded
+    /// windows are tracked, as window managers reparent top-level
+    /// windows into their frames.
+    pub fn take_parent_change(&mut self) -> Option<*mut std::ffi::c_void> {
+        match std::mem::replace(&mut self.parent_changed, false) {
+            true => Some(self.parent as usize as *mut std::ffi::c_void),
+            false => None,
+        }
+    }
+
+    /// The current size of the window
+    pub fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    /// Resizes the window, e.g. in `PluginUI::resized()` for a host driven resize
+    pub fn set_size(&self, width: u16, height: u16) -> Result<(), X11WindowError> {
+        let aux = xproto::ConfigureWindowAux::new()
+            .width(u32::from(width))
+            .height(u32::from(height));
+        self.connection().configure_window(self.window, &aux)?;
+        self.connection().flush()?;
+        Ok(())
+    }
+
+    /// Returns the new size if the window has been resized
+    ///
+    /// To be forwarded to `PluginUI::resized()`.
+    pub fn take_resize(&mut self) -> Option<(u16, u16)> {
+        match std::mem::replace(&mut self.resized, false) {
+            true => Some(self.size),
+            false => None,
+        }
+    }
+
+    /// True if the window is mapped
+    ///
+    /// An embedded window is mapped while the host has not hidden it
+    /// by unmapping it, even if the host's window is not visible.
+    pub fn is_mapped(&self) -> bool {
+        self.mapped
+    }
+
+    /// Returns true if the window has been mapped, false if unmapped
+    ///
+    /// To be forwarded to `PluginUI::on_show()` and
+    /// `PluginUI::on_hide()`. Reported once the window has been
+    /// mapped after opening, too.
+    pub fn take_visibility_change(&mut self) -> Option<bool> {
+        match std::mem::replace(&mut self.visibility_changed, false) {
+            true => Some(self.mapped),
+            false => None,
+        }
+    }
+
+    /// The scale factor for HiDPI displays, see `X11Display::scale_factor()`
+    pub fn scale_factor(&self) -> Option<f32> {
+        self.scale_factor
+    }
+
+    /// Returns the new scale factor if it has changed
+    ///
+    /// To be forwarded to `PluginUI::scale_factor_changed()`. A
+    /// removed `Xft.dpi` resource is reported as 1.0.
+    pub fn take_scale_change(&mut self) -> Option<f32> {
+        let generation = self.display.resources_generation.get();
+        if generation == self.resources_generation {
+            return None;
+        }
+        self.resources_generation = generation;
+        let scale_factor = self.display.scale_factor().ok()?;
+        if scale_factor == self.scale_factor {
+            return None;
+        }
+        self.scale_factor = scale_factor;
+        Some(scale_factor.unwrap_or(1.0))
+    }
+
+    /// When the window takes the keyboard focus, `FocusPolicy::Host` by default
+    pub fn set_focus_policy(&mut self, policy: FocusPolicy) {
+        self.focus_policy = policy;
+    }
+
+    pub fn focus_policy(&self) -> FocusPolicy {
+        self.focus_policy
+    }
+
+    /// True if the window has the keyboard focus
+    pub fn has_focus(&self) -> bool {
+        self.has_focus
+    }
+
+    /// Takes the keyboard focus, remembering who had it
+    pub fn take_focus(&mut self, time: xproto::Timestamp) -> Result<(), X11WindowError> {
+        let focus = self.connection().get_input_focus()?.reply()?.focus;
+        if focus != self.window {
+            self.previous_focus = Some(focus);
+        }
+        self.connection()
+            .set_input_focus(xproto::InputFocus::PARENT, self.window, time)?;
+        self.connection().flush()?;
+        Ok(())
+    }
+
+    /// Gives the keyboard focus back to the window that had it before `take_focus()`
+    pub fn restore_focus(&mut self, time: xproto::Timestamp) -> Result<(), X11WindowError> {
+        if let Some(previous) = self.previous_focus.take() {
+            self.connection()
+                .set_input_focus(xproto::InputFocus::PARENT, previous, time)?;
+            self.connection().flush()?;
+        }
+        Ok(())
+    }
+
+    /// Grabs the keyboard for text entry, returns false if another client holds it
+    ///
+    /// All key events go to the window until `ungrab_keyboard()`,
+    /// also while the pointer is outside. The grab is released when
+    /// the window is unmapped or dropped, so the host is never left
+    /// without keyboard.
+    pub fn grab_keyboard(&mut self) -> Result<bool, X11WindowError> {
+        let reply = self
+            .connection()
+            .grab_keyboard(
+                true,
+                self.window,
+                x11rb::CURRENT_TIME,
+                xproto::GrabMode::ASYNC,
+                xproto::GrabMode::ASYNC,
+            )?
+            .reply()?;
+        self.keyboard_grabbed = reply.status == xproto::GrabStatus::SUCCESS;
+        Ok(self.keyboard_grabbed)
+    }
+
+    /// Releases the keyboard grabbed by `grab_keyboard()`
+    pub fn ungrab_keyboard(&mut self) -> Result<(), X11WindowError> {
+        if std::mem::replace(&mut self.keyboard_grabbed, false) {
+            self.connection().ungrab_keyboard(x11rb::CURRENT_TIME)?;
+            self.connection().flush()?;
+        }
+        Ok(())
+    }
+
+    /// True while the keyboard is grabbed by `grab_keyboard()`
+    pub fn is_keyboard_grabbed(&self) -> bool {
+        self.keyboard_grabbed
+    }
+
+    /// Returns the files dropped onto the window since the last call
+    ///
+    /// To be forwarded to `PluginUI::file_dropped()`.
+    pub fn take_dropped_files(&mut self) -> Vec<DroppedFile> {
+        std::mem::take(&mut self.dropped_files)
+    }
+
+    pub fn show(&self) -> Result<(), X11WindowError> {
+        self.connection().map_window(self.window)?;
+        self.connection().flush()?;
+        Ok(())
+    }
+
+    pub fn hide(&self) -> Result<(), X11WindowError> {
+        self.connection().unmap_window(self.window)?;
+        self.connection().flush()?;
+        Ok(())
+    }
+
+    /// True if the user asked the window manager to close the top-level window
+    pub fn close_requested(&self) -> bool {
+        self.close_requested
+    }
+
+    /// Returns the next pending event of the window without blocking
+    ///
+    /// Size changes, reparenting, mapping, the focus, dropped files
+    /// and close requests are tracked by the window before the event is returned.
+    pub fn poll_event(&mut self) -> Result<Option<Event>, X11WindowError> {
+        let event = self.display.poll_event_for(self.window)?;
+        match &event {
+            Some(Event::ConfigureNotify(e))
+                if e.window == self.window && (e.width, e.height) != self.size =>
+            {
+                self.size = (e.width, e.height);
+                self.resized = true;
+            }
+            Some(Event::ReparentNotify(e))
+                if e.window == self.window && !self.top_level && e.parent != self.parent =>
+            {
+                self.parent = e.parent;
+                self.parent_changed = true;
+                self.set_xdnd_proxy()?;
+            }
+            Some(Event::ButtonPress(e))
+                if e.event == self.window
+                    && !self.has_focus
+                    && self.focus_policy != FocusPolicy::Host =>
+            {
+                self.take_focus(e.time)?;
+            }
+            Some(Event::EnterNotify(e))
+                if e.event == self.window && self.focus_policy == FocusPolicy::FollowsMouse =>
+            {
+                self.take_focus(e.time)?;
+            }
+            // Pointer moves into subwindows are no leaving
+            Some(Event::LeaveNotify(e))
+                if e.event == self.window
+                    && e.detail != xproto::NotifyDetail::INFERIOR
+                    && self.focus_policy == FocusPolicy::FollowsMouse
+                    && !self.keyboard_grabbed =>
+            {
+                self.restore_focus(e.time)?;
+            }
+            Some(Event::FocusIn(e)) if e.event == self.window => self.has_focus = true,
+            Some(Event::FocusOut(e)) if e.event == self.window => self.has_focus = false,
+            Some(Event::MapNotify(e)) if e.window == self.window => self.set_mapped(true),
+            Some(Event::UnmapNotify(e)) if e.window == self.window => {
+                self.set_mapped(false);
+                self.ungrab_keyboard()?;
+            }
+            Some(Event::ClientMessage(e))
+                if e.type_ == self.display.wm_protocols
+                    && e.format == 32
+                    && e.data.as_data32()[0] == self.display.wm_delete_window =>
+            {
+                self.close_requested = true;
+            }
+            Some(Event::ClientMessage(e)) if e.format == 32 => self.handle_xdnd_message(e)?,
+            Some(Event::SelectionNotify(e))
+                if e.requestor == self.window && e.selection == self.display.xdnd.XdndSelection =>
+            {
+                self.receive_drop(e)?;
+            }
+            _ => {}
+        }
+        Ok(event)
+    }
+
+    fn set_mapped(&mut self, mapped: bool) {
+        if mapped != self.mapped {
+            self.mapped = mapped;
+            self.visibility_changed = true;
+        }
+    }
+
+    /// Lets the top-level window of the host forward drops to the window
+    ///
+    /// Most drag sources only look for `XdndAware` on top-level
+    /// windows, so an embedded window would never see their drops.
+    /// Unless the host's top-level window takes drops itself, it gets
+    /// `XdndProxy` pointing to the window. Drops onto other parts of
+    /// the host are then refused by `handle_xdnd_message()`.
+    fn set_xdnd_proxy(&mut self) -> Result<(), X11WindowError> {
+        self.remove_xdnd_proxy()?;
+        let connection = self.display.connection();
+        let xdnd = &self.display.xdnd;
+        let mut top_level = self.window;
+        loop {
+            let tree = connection.query_tree(top_level)?.reply()?;
+            if tree.parent == tree.root || tree.parent == x11rb::NONE {
+                break;
+            }
+            top_level = tree.parent;
+        }
+        if top_level == self.window {
+            return Ok(());
+        }
+        let has_property = |property| -> Result<bool, X11WindowError> {
+            let reply = connection
+                .get_property(false, top_level, property, xproto::AtomEnum::ANY, 0, 1)?
+                .reply()?;
+            Ok(reply.type_ != x11rb::NONE)
+        };
+        if has_property(xdnd.XdndAware)? || has_property(xdnd.XdndProxy)? {
+            return Ok(());
+        }
+        // The proxy window needs to point to itself
+        for window in [self.window, top_level].iter() {
+            connection.change_property32(
+                xproto::PropMode::REPLACE,
+                *window,
+                xdnd.XdndProxy,
+                xproto::AtomEnum::WINDOW,
+                &[self.window],
+            )?;
+        }
+        connection.change_property32(
+            xproto::PropMode::REPLACE,
+            top_level,
+            xdnd.XdndAware,
+            xproto::AtomEnum::ATOM,
+            &[XDND_VERSION],
+        )?;
+        connection.flush()?;
+        self.display
+            .xdnd_proxies
+            .borrow_mut()
+            .insert(top_level, self.window);
+        self.xdnd_proxy = Some(top_level);
+        Ok(())
+    }
+
+    fn remove_xdnd_proxy(&mut self) -> Result<(), X11WindowError> {
+        if let Some(top_level) = self.xdnd_proxy.take() {
+            self.display.xdnd_proxies.borrow_mut().remove(&top_level);
+            let connection = self.display.connection();
+            connection.delete_property(top_level, self.display.xdnd.XdndProxy)?;
+            connection.delete_property(top_level, self.display.xdnd.XdndAware)?;
+            connection.flush()?;
+        }
+        Ok(())
+    }
+
+    fn handle_xdnd_message(
+        &mut self,
+        e: &xproto::ClientMessageEvent,
+    ) -> Result<(), X11WindowError> {
+        let xdnd = &self.display.xdnd;
+        let data = e.data.as_data32();
+        let source = data[0];
+        if e.type_ == xdnd.XdndEnter {
+            let version = data[1] >> 24;
+            // More than three types are listed by the source
+            let types = match data[1] & 1 {
+                1 => self
+                    .connection()
+                    .get_property(
+                        false,
+                        source,
+                        xdnd.XdndTypeList,
+                        xproto::AtomEnum::ATOM,
+                        0,
+                        u32::MAX,
+                    )?
+                    .reply()?
+                    .value32()
+                    .map(Iterator::collect)
+                    .unwrap_or_default(),
+                _ => data[2..].to_vec(),
+            };
+            self.drag = Some(XdndDrag {
+                source,
+                version,
+                accepted: types.contains(&xdnd.TEXT_URI_LIST),
+                position: (0.0, 0.0),
+                inside: false,
+                dropped: false,
+            });
+        } else if e.type_ == xdnd.XdndPosition {
+            let drag = match self.drag.as_mut().filter(|drag| drag.source == source) {
+                Some(drag) => drag,
+                None => return Ok(()),
+            };
+            let root = self.display.screen().root;
+            let (x, y) = ((data[2] >> 16) as i16, (data[2] & 0xffff) as i16);
+            let translated = self
+                .display
+                .connection()
+                .translate_coordinates(root, self.window, x, y)?
+                .reply()?;
+            drag.position = (f64::from(translated.dst_x), f64::from(translated.dst_y));
+            let (width, height) = self.size;
+            drag.inside = translated.same_screen
+                && (0..width as i16).contains(&translated.dst_x)
+                && (0..height as i16).contains(&translated.dst_y);
+            let (accepted, action) = match drag.accepted && drag.inside {
+                true => (1, xdnd.XdndActionCopy),
+                false => (0, x11rb::NONE),
+            };
+            self.send_xdnd(
+                source,
+                xdnd.XdndStatus,
+                [self.window, accepted, 0, 0, action],
+            )?;
+        } else if e.type_ == xdnd.XdndLeave {
+            self.drag = None;
+        } else if e.type_ == xdnd.XdndDrop {
+            let drag = match self.drag.as_mut().filter(|drag| drag.source == source) {
+                Some(drag) => drag,
+                None => return Ok(()),
+            };
+            if !drag.accepted || !drag.inside {
+                self.finish_drop(false)?;
+                return Ok(());
+            }
+            drag.dropped = true;
+            let time = match drag.version {
+                0 => x11rb::CURRENT_TIME,
+                _ => data[2],
+            };
+            self.connection().convert_selection(
+                self.window,
+                xdnd.XdndSelection,
+                xdnd.TEXT_URI_LIST,
+                xdnd.XdndSelection,
+                time,
+            )?;
+            self.connection().flush()?;
+        }
+        Ok(())
+    }
+
+    /// Reads the dropped `text/uri-list` converted to the window
+    fn receive_drop(&mut self, e: &xproto::SelectionNotifyEvent) -> Result<(), X11WindowError> {
+        let drag = match self.drag.filter(|drag| drag.dropped) {
+            Some(drag) => drag,
+            None => return Ok(()),
+        };
+        if e.property == x11rb::NONE {
+            return self.finish_drop(false);
+        }
+        let uri_list = self
+            .connection()
+            .get_property(
+                true,
+                self.window,
+                e.property,
+                xproto::AtomEnum::ANY,
+                0,
+                u32::MAX,
+            )?
+            .reply()?
+            .value;
+        let (x, y) = drag.position;
+        let paths = paths_from_uri_list(&uri_list);
+        let accepted = !paths.is_empty();
+        self.dropped_files
+            .extend(paths.into_iter().map(|path| DroppedFile { path, x, y }));
+        self.finish_drop(accepted)
+    }
+
+    /// Tells the source that the drop is done and ends the drag
+    fn finish_drop(&mut self, accepted: bool) -> Result<(), X11WindowError> {
+        if let Some(drag) = self.drag.take() {
+            let (accepted, action) = match accepted {
+                true => (1, self.display.xdnd.XdndActionCopy),
+                false => (0, x11rb::NONE),
+            };
+            let finished = self.display.xdnd.XdndFinished;
+            self.send_xdnd(drag.source, finished, [self.window, accepted, action, 0, 0])?;
+        }
+        Ok(())
+    }
+
+    fn send_xdnd(
+        &self,
+        target: xproto::Window,
+        message: xproto::Atom,
+        data: [u32; 5],
+    ) -> Result<(), X11WindowError> {
+        let event = xproto::ClientMessageEvent::new(32, target, message, data);
+        self.connection()
+            .send_event(false, target, xproto::EventMask::NO_EVENT, event)?;
+        self.connection().flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for X11Window {
+    fn drop(&mut self) {
+        let _ = self.ungrab_keyboard();
+        let _ = self.remove_xdnd_proxy();
+        self.display.unregister(self.window);
+        let _ = self.connection().destroy_window(self.window);
+        let _ = self.connection().flush();
+    }
+}
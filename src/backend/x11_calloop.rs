@@ -0,0 +1,224 @@
+//! calloop integration of the X11 backend
+//!
+//! UIs built around a calloop event loop, e.g. for timers or channels
+//! from worker threads, can't run the loop themselves, as the host
+//! owns the thread. `X11Loop` owns the loop together with an
+//! `X11Window`, registers the window's connection as event source
+//! and dispatches both without blocking from `PluginUI::idle()`:
+//!
+//! ```ignore
+//! let x11 = X11Loop::open(WindowMode::from_parent(parent_window), 400, 300, "Amp")?;
+//! x11.handle().insert_source(Timer::from_duration(FADE), |_, _, state| { ... })?;
+//!
+//! fn idle(&mut self) -> i32 {
+//!     let events = match self.x11.dispatch(Some(Duration::ZERO), &mut self.state) {
+//!         Ok(events) => events,
+//!         Err(_) => return 1,
+//!     };
+//!     for event in events {
+//!         match event.forward(self) {
+//!             Some(X11UIEvent::CloseRequested) => return 1,
+//!             Some(X11UIEvent::Other(event)) => self.handle_event(event),
+//!             _ => {}
+//!         }
+//!     }
+//!     0
+//! }
+//! ```
+//!
+//! The X events are translated into `X11UIEvent`s, which
+//! `X11UIEvent::forward()` passes to the matching `PluginUI` hooks.
+
+use std::io;
+use std::os::fd::AsFd;
+use std::time::Duration;
+
+use calloop::generic::Generic;
+use calloop::{EventLoop, Interest, LoopHandle, Mode, PostAction};
+use x11rb::protocol::Event;
+
+use crate::backend::dnd::DroppedFile;
+use crate::backend::x11::{X11Window, X11WindowError};
+use crate::plugin_ui::{PluginUI, UpdateReason, WindowMode};
+
+#[derive(Debug)]
+pub enum X11LoopError {
+    Calloop(calloop::Error),
+    Io(io::Error),
+    X11(X11WindowError),
+}
+
+impl From<calloop::Error> for X11LoopError {
+    fn from(e: calloop::Error) -> Self {
+        X11LoopError::Calloop(e)
+    }
+}
+
+impl From<io::Error> for X11LoopError {
+    fn from(e: io::Error) -> Self {
+        X11LoopError::Io(e)
+    }
+}
+
+impl From<X11WindowError> for X11LoopError {
+    fn from(e: X11WindowError) -> Self {
+        X11LoopError::X11(e)
+    }
+}
+
+/// An X event translated for the UI
+#[derive(Debug)]
+pub enum X11UIEvent {
+    /// The window needs to be redrawn, once per series of `Expose` events
+    Expose,
+    /// The window has been resized, see `X11Window::take_resize()`
+    Resized(u16, u16),
+    /// The host has reparented the window, see `X11Window::take_parent_change()`
+    ParentChanged(*mut std::ffi::c_void),
+    /// The scale factor has changed, see `X11Window::take_scale_change()`
+    ScaleFactorChanged(f32),
+    /// The window has been mapped, see `X11Window::take_visibility_change()`
+    Shown,
+    /// The window has been unmapped
+    Hidden,
+    /// A file has been dropped onto the window, see `X11Window::take_dropped_files()`
+    FileDropped(DroppedFile),
+    /// The user asked the window manager to close the top-level window
+    CloseRequested,
+    /// Any other event, e.g. of the mouse or the keyboard
+    Other(Event),
+}
+
+impl X11UIEvent {
+    /// Calls the `PluginUI` hook of the event
+    ///
+    /// `Expose` becomes `update(UpdateReason::Expose)`. The events
+    /// without hook, `CloseRequested` and `Other`, are returned.
+    pub fn forward(self, ui: &mut impl PluginUI) -> Option<Self> {
+        match self {
+            X11UIEvent::Expose => ui.update(UpdateReason::Expose),
+            X11UIEvent::Resized(width, height) => ui.resized(u32::from(width), u32::from(height)),
+            X11UIEvent::ParentChanged(new_parent) => ui.parent_changed(new_parent),
+            X11UIEvent::ScaleFactorChanged(scale_factor) => ui.scale_factor_changed(scale_factor),
+            X11UIEvent::Shown => ui.on_show(),
+            X11UIEvent::Hidden => ui.on_hide(),
+            X11UIEvent::FileDropped(file) => file.forward(ui),
+            event => return Some(event),
+        }
+        None
+    }
+}
+
+/// A calloop event loop with an `X11Window` as event source
+///
+/// `D` is the data passed to the callbacks of the sources.
+pub struct X11Loop<D: 'static = ()> {
+    event_loop: EventLoop<'static, D>,
+    window: X11Window,
+    close_reported: bool,
+}
+
+impl<D: 'static> X11Loop<D> {
+    /// Opens an `X11Window`, see `X11Window::open()`
+    pub fn open(
+        mode: WindowMode,
+        width: u16,
+        height: u16,
+        title: &str,
+    ) -> Result<Self, X11LoopError> {
+        Self::new(X11Window::open(mode, width, height, title)?)
+    }
+
+    /// A new loop with the connection of `window` as event source
+    pub fn new(window: X11Window) -> Result<Self, X11LoopError> {
+        let event_loop = EventLoop::try_new()?;
+        // The events are read by the connection, the source only wakes the loop
+        let fd = window.connection().stream().as_fd().try_clone_to_owned()?;
+        event_loop
+            .handle()
+            .insert_source(Generic::new(fd, Interest::READ, Mode::Level), |_, _, _| {
+                Ok(PostAction::Continue)
+            })
+            .map_err(|e| X11LoopError::Calloop(e.error))?;
+        Ok(Self {
+            event_loop,
+            window,
+            close_reported: false,
+        })
+    }
+
+    /// The handle to insert the UI's own event sources
+    pub fn handle(&self) -> LoopHandle<'static, D> {
+        self.event_loop.handle()
+    }
+
+    pub fn window(&self) -> &X11Window {
+        &self.window
+    }
+
+    pub fn window_mut(&mut self) -> &mut X11Window {
+        &mut self.window
+    }
+
+    /// Dispatches the sources and returns the X events of the window
+    ///
+    /// Waits at most `timeout` for a source or the X server, None
+    /// waits without limit. In `PluginUI::idle()` the timeout needs
+    /// to be zero, as the host waits for `idle()` to return. Events
+    /// the connection has already read don't wait at all.
+    pub fn dispatch(
+        &mut self,
+        timeout: Option<Duration>,
+        data: &mut D,
+    ) -> Result<Vec<X11UIEvent>, X11LoopError> {
+        let mut events = Vec::new();
+        self.drain_into(&mut events)?;
+        let timeout = match events.is_empty() {
+            true => timeout,
+            false => Some(Duration::ZERO),
+        };
+        self.event_loop.dispatch(timeout, data)?;
+        self.drain_into(&mut events)?;
+
+        if let Some((width, height)) = self.window.take_resize() {
+            events.push(X11UIEvent::Resized(width, height));
+        }
+        if let Some(new_parent) = self.window.take_parent_change() {
+            events.push(X11UIEvent::ParentChanged(new_parent));
+        }
+        if let Some(scale_factor) = self.window.take_scale_change() {
+            events.push(X11UIEvent::ScaleFactorChanged(scale_factor));
+        }
+        match self.window.take_visibility_change() {
+            Some(true) => events.push(X11UIEvent::Shown),
+            Some(false) => events.push(X11UIEvent::Hidden),
+            None => {}
+        }
+        events.extend(
+            self.window
+                .take_dropped_files()
+                .into_iter()
+                .map(X11UIEvent::FileDropped),
+        );
+        if self.window.close_requested() && !self.close_reported {
+            self.close_reported = true;
+            events.push(X11UIEvent::CloseRequested);
+        }
+        Ok(events)
+    }
+
+    /// Translates the pending events of the window
+    ///
+    /// Size and parent changes are tracked by the window and reported
+    /// once per dispatch.
+    fn drain_into(&mut self, events: &mut Vec<X11UIEvent>) -> Result<(), X11LoopError> {
+        while let Some(event) = self.window.poll_event()? {
+            match event {
+                Event::Expose(e) if e.count == 0 => events.push(X11UIEvent::Expose),
+                Event::Expose(_) | Event::ConfigureNotify(_) | Event::ReparentNotify(_) => {}
+                event => events.push(X11UIEvent::Other(event)),
+            }
+        }
+        Ok(())
+    }
+}
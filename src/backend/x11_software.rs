@@ -0,0 +1,336 @@
+//! Software rendering into an X11 window
+//!
+//! UIs not wanting GL draw into a pixel buffer, which
+//! `X11SoftwareWindow` copies to its `X11Window` by `PutImage`. The
+//! buffer keeps the content, so areas uncovered by `Expose` events
+//! are restored without the UI drawing again. Only the damaged parts
+//! are sent to the X server:
+//!
+//! ```ignore
+//! fn idle(&mut self) -> i32 {
+//!     while let Ok(Some(event)) = self.window.poll_event() {
+//!         self.handle_event(event);
+//!     }
+//!     if self.needs_redraw {
+//!         self.window.draw(|buffer| {
+//!             buffer.fill_rect(0, 0, buffer.width(), buffer.height(), 0x202020);
+//!             buffer.fill_rect(10, 10, self.level_width(), 8, 0x40c040);
+//!         });
+//!     }
+//!     match self.window.present() {
+//!         Ok(()) => 0,
+//!         Err(_) => 1,
+//!     }
+//! }
+//! ```
+//!
+//! Pixels are `0x00RRGGBB`. The window needs a true color visual of
+//! 24 or 32 bits depth, which is what hosts use nowadays.
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::errors::{ConnectionError, ReplyError, ReplyOrIdError};
+use x11rb::protocol::xproto::{self, ConnectionExt as _};
+use x11rb::protocol::Event;
+
+use crate::backend::x11::{X11Window, X11WindowError};
+use crate::plugin_ui::WindowMode;
+
+#[derive(Debug)]
+pub enum X11SoftwareError {
+    X11(X11WindowError),
+    /// The window's visual is no 24 bit true color one stored in 32 bits per pixel
+    UnsupportedVisual,
+}
+
+impl From<X11WindowError> for X11SoftwareError {
+    fn from(e: X11WindowError) -> Self {
+        X11SoftwareError::X11(e)
+    }
+}
+
+impl From<ConnectionError> for X11SoftwareError {
+    fn from(e: ConnectionError) -> Self {
+        X11SoftwareError::X11(e.into())
+    }
+}
+
+impl From<ReplyError> for X11SoftwareError {
+    fn from(e: ReplyError) -> Self {
+        X11SoftwareError::X11(e.into())
+    }
+}
+
+impl From<ReplyOrIdError> for X11SoftwareError {
+    fn from(e: ReplyOrIdError) -> Self {
+        X11SoftwareError::X11(e.into())
+    }
+}
+
+/// The pixels of an `X11SoftwareWindow` to draw into
+pub struct PixelBuffer<'a> {
+    pixels: &'a mut [u32],
+    width: u16,
+    height: u16,
+}
+
+impl<'a> PixelBuffer<'a> {
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// All pixels, row by row
+    pub fn pixels_mut(&mut self) -> &mut [u32] {
+        self.pixels
+    }
+
+    /// The pixels of the row `y`
+    pub fn row_mut(&mut self, y: u16) -> &mut [u32] {
+        let width = usize::from(self.width);
+        let start = usize::from(y) * width;
+        &mut self.pixels[start..start + width]
+    }
+
+    /// Sets the pixel, if it is within the buffer
+    pub fn set_pixel(&mut self, x: u16, y: u16, color: u32) {
+        if x < self.width && y < self.height {
+            self.pixels[usize::from(y) * usize::from(self.width) + usize::from(x)] = color;
+        }
+    }
+
+    /// Fills the rectangle, clipped to the buffer
+    pub fn fill_rect(&mut self, x: u16, y: u16, width: u16, height: u16, color: u32) {
+        let x_end = x.saturating_add(width).min(self.width);
+        for row in y..y.saturating_add(height).min(self.height) {
+            if x < x_end {
+                self.row_mut(row)[usize::from(x)..usize::from(x_end)].fill(color);
+            }
+        }
+    }
+}
+
+/// The bounding box of the damaged areas, the ends exclusive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Damage {
+    x0: u16,
+    y0: u16,
+    x1: u16,
+    y1: u16,
+}
+
+impl Damage {
+    fn union(self, other: Damage) -> Damage {
+        Damage {
+            x0: self.x0.min(other.x0),
+            y0: self.y0.min(other.y0),
+            x1: self.x1.max(other.x1),
+            y1: self.y1.max(other.y1),
+        }
+    }
+
+    fn clip(self, width: u16, height: u16) -> Option<Damage> {
+        let damage = Damage {
+            x0: self.x0,
+            y0: self.y0,
+            x1: self.x1.min(width),
+            y1: self.y1.min(height),
+        };
+        (damage.x0 < damage.x1 && damage.y0 < damage.y1).then_some(damage)
+    }
+}
+
+/// An `X11Window` showing a pixel buffer
+pub struct X11SoftwareWindow {
+    window: X11Window,
+    gc: xproto::Gcontext,
+    depth: u8,
+    lsb_first: bool,
+    pixels: Vec<u32>,
+    size: (u16, u16),
+    damage: Option<Damage>,
+}
+
+impl X11SoftwareWindow {
+    /// Opens the window, see `X11Window::open()`
+    pub fn open(
+        mode: WindowMode,
+        width: u16,
+        height: u16,
+        title: &str,
+    ) -> Result<Self, X11SoftwareError> {
+        Self::new(X11Window::open(mode, width, height, title)?)
+    }
+
+    /// Shows a pixel buffer in `window`
+    pub fn new(window: X11Window) -> Result<Self, X11SoftwareError> {
+        let connection = window.connection();
+        let geometry = connection.get_geometry(window.id())?.reply()?;
+        let visual = connection
+            .get_window_attributes(window.id())?
+            .reply()?
+            .visual;
+        let setup = connection.setup();
+        let format = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == geometry.depth);
+        let true_color = setup
+            .roots
+            .iter()
+            .flat_map(|screen| screen.allowed_depths.iter())
+            .flat_map(|depth| depth.visuals.iter())
+            .find(|candidate| candidate.visual_id == visual)
+            .is_some_and(|visual| {
+                visual.class == xproto::VisualClass::TRUE_COLOR
+                    && visual.red_mask == 0xff0000
+                    && visual.green_mask == 0x00ff00
+                    && visual.blue_mask == 0x0000ff
+            });
+        if !true_color || format.is_none_or(|format| format.bits_per_pixel != 32) {
+            return Err(X11SoftwareError::UnsupportedVisual);
+        }
+        let lsb_first = setup.image_byte_order == xproto::ImageOrder::LSB_FIRST;
+
+        let gc = connection.generate_id()?;
+        connection.create_gc(gc, window.id(), &xproto::CreateGCAux::new())?;
+        let size = window.size();
+        Ok(Self {
+            window,
+            gc,
+            depth: geometry.depth,
+            lsb_first,
+            pixels: vec![0; usize::from(size.0) * usize::from(size.1)],
+            size,
+            damage: None,
+        })
+    }
+
+    pub fn window(&self) -> &X11Window {
+        &self.window
+    }
+
+    pub fn window_mut(&mut self) -> &mut X11Window {
+        &mut self.window
+    }
+
+    /// Returns the next pending event of the window without blocking
+    ///
+    /// `Expose` events damage the exposed areas, so `present()`
+    /// restores them. After a resize the buffer has the new size,
+    /// keeping the overlapping pixels.
+    pub fn poll_event(&mut self) -> Result<Option<Event>, X11WindowError> {
+        let event = self.window.poll_event()?;
+        if let Some(Event::Expose(e)) = &event {
+            self.damage(e.x, e.y, e.width, e.height);
+        }
+        if self.window.size() != self.size {
+            self.resize_buffer(self.window.size());
+        }
+        Ok(event)
+    }
+
+    fn resize_buffer(&mut self, (width, height): (u16, u16)) {
+        let mut pixels = vec![0; usize::from(width) * usize::from(height)];
+        let copied = usize::from(width.min(self.size.0));
+        for y in 0..usize::from(height.min(self.size.1)) {
+            let old = y * usize::from(self.size.0);
+            let new = y * usize::from(width);
+            pixels[new..new + copied].copy_from_slice(&self.pixels[old..old + copied]);
+        }
+        self.pixels = pixels;
+        self.size = (width, height);
+        self.damage(0, 0, width, height);
+    }
+
+    /// The size of the buffer
+    pub fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    /// Marks an area to be sent by the next `present()`
+    pub fn damage(&mut self, x: u16, y: u16, width: u16, height: u16) {
+        let damage = Damage {
+            x0: x,
+            y0: y,
+            x1: x.saturating_add(width),
+            y1: y.saturating_add(height),
+        };
+        self.damage = Some(match self.damage {
+            Some(previous) => previous.union(damage),
+            None => damage,
+        });
+    }
+
+    /// Draws into the buffer, damaging all of it
+    pub fn draw(&mut self, f: impl FnOnce(&mut PixelBuffer)) {
+        let (width, height) = self.size;
+        f(&mut self.buffer());
+        self.damage(0, 0, width, height);
+    }
+
+    /// The buffer to draw into, the areas changed need to be marked by `damage()`
+    pub fn buffer(&mut self) -> PixelBuffer<'_> {
+        PixelBuffer {
+            pixels: &mut self.pixels,
+            width: self.size.0,
+            height: self.size.1,
+        }
+    }
+
+    /// Sends the damaged area to the X server
+    ///
+    /// Large areas are sent in bands fitting the maximum request size.
+    pub fn present(&mut self) -> Result<(), X11WindowError> {
+        let damage = match self.damage.take() {
+            Some(damage) => damage,
+            None => return Ok(()),
+        };
+        let Damage { x0, y0, x1, y1 } = match damage.clip(self.size.0, self.size.1) {
+            Some(damage) => damage,
+            None => return Ok(()),
+        };
+        let connection = self.window.connection();
+        let row_bytes = usize::from(x1 - x0) * 4;
+        // The request header of `PutImage` takes 24 bytes
+        let band = ((connection.maximum_request_bytes() - 24) / row_bytes).max(1);
+        let mut data = Vec::with_capacity(row_bytes * band.min(usize::from(y1 - y0)));
+        let mut y = y0;
+        while y < y1 {
+            let rows = (usize::from(y1 - y)).min(band) as u16;
+            data.clear();
+            for row in y..y + rows {
+                let start = usize::from(row) * usize::from(self.size.0);
+                for pixel in &self.pixels[start + usize::from(x0)..start + usize::from(x1)] {
+                    data.extend_from_slice(&match self.lsb_first {
+                        true => pixel.to_le_bytes(),
+                        false => pixel.to_be_bytes(),
+                    });
+                }
+            }
+            connection.put_image(
+                xproto::ImageFormat::Z_PIXMAP,
+                self.window.id(),
+                self.gc,
+                x1 - x0,
+                rows,
+                x0 as i16,
+                y as i16,
+                0,
+                self.depth,
+                &data,
+            )?;
+            y += rows;
+        }
+        connection.flush()?;
+        Ok(())
+    }
+}
+
+impl Drop for X11SoftwareWindow {
+    fn drop(&mut self) {
+        let _ = self.window.connection().free_gc(self.gc);
+    }
+}
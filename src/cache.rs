@@ -0,0 +1,212 @@
+//! Cache for decoded resources like images and thumbnails
+//!
+//! Decoding images or computing waveform overviews of samples is
+//! too expensive to be repeated in every `idle()` call. The
+//! `ResourceCache` keeps the decoded results keyed by their file
+//! path, so that widgets can share them. Relative paths are resolved
+//! against the bundle path, so resources shipped with the UI can be
+//! referred to by their name in the bundle.
+//!
+//! The cache holds a limited number of entries and evicts the least
+//! recently used one when full, or when its resources exceed the
+//! memory budget given by `with_memory_budget()`. It is supposed to
+//! be cleared in `PluginUI::cleanup()`.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use crate::memory::*;
+
+/// Key of a cached resource: the resolved path and a variant, e.g. a thumbnail size
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceKey {
+    pub path: PathBuf,
+    pub variant: u32,
+}
+
+struct CacheEntry<T> {
+    value: Rc<T>,
+    size: usize,
+    last_used: u64,
+}
+
+/// LRU cache of decoded resources
+pub struct ResourceCache<T> {
+    bundle_path: PathBuf,
+    capacity: usize,
+    entries: HashMap<ResourceKey, CacheEntry<T>>,
+    clock: u64,
+    memory: Option<MemoryAccount>,
+    size_of: fn(&T) -> usize,
+}
+
+impl<T> ResourceCache<T> {
+    /// Creates a cache holding up to `capacity` resources
+    ///
+    /// `bundle_path` is usually `PluginUIInfo::bundle_path()`.
+    pub fn new(bundle_path: &Path, capacity: usize) -> Self {
+        Self {
+            bundle_path: bundle_path.to_path_buf(),
+            capacity,
+            entries: HashMap::new(),
+            clock: 0,
+            memory: None,
+            size_of: |_| 0,
+        }
+    }
+
+    /// Accounts the resources to `budget`, their sizes in bytes given by `size`
+    ///
+    /// Least recently used resources are evicted until a new one
+    /// fits into the budget. A resource larger than the budget is
+    /// still cached on its own.
+    pub fn with_memory_budget(mut self, budget: &MemoryBudget, size: fn(&T) -> usize) -> Self {
+        self.memory = Some(budget.account(MemoryCategory::Caches));
+        self.size_of = size;
+        self
+    }
+
+    /// Resolves a path relative to the bundle, absolute paths are kept
+    pub fn resolve(&self, path: &Path) -> PathBuf {
+        self.bundle_path.join(path)
+    }
+
+    /// Returns the resource at `path`, calling `load` to decode it if not cached
+    pub fn get_or_load<E, F>(&mut self, path: impl AsRef<Path>, load: F) -> Result<Rc<T>, E>
+    where
+        F: FnOnce(&Path) -> Result<T, E>,
+    {
+        self.get_or_load_variant(path, 0, load)
+    }
+
+    /// Like `get_or_load()`, but for a variant of the resource, e.g. a thumbnail size
+    pub fn get_or_load_variant<E, F>(
+        &mut self,
+        path: impl AsRef<Path>,
+        variant: u32,
+        load: F,
+    ) -> Result<Rc<T>, E>
+    where
+        F: FnOnce(&Path) -> Result<T, E>,
+    {
+        let key = ResourceKey {
+            path: self.resolve(path.as_ref()),
+            variant,
+        };
+        if let Some(value) = self.touch(&key) {
+            return Ok(value);
+        }
+        let value = load(&key.path)?;
+        Ok(self.insert_key(key, value))
+    }
+
+    /// Returns the cached resource at `path` without loading it
+    pub fn get(&mut self, path: impl AsRef<Path>, variant: u32) -> Option<Rc<T>> {
+        let key = ResourceKey {
+            path: self.resolve(path.as_ref()),
+            variant,
+        };
+        self.touch(&key)
+    }
+
+    /// Puts a resource into the cache, replacing a previous one
+    pub fn insert(&mut self, path: impl AsRef<Path>, variant: u32, value: T) -> Rc<T> {
+        let key = ResourceKey {
+            path: self.resolve(path.as_ref()),
+            variant,
+        };
+        self.insert_key(key, value)
+    }
+
+    /// Removes all variants of the resource at `path`, e.g. when the file has changed
+    pub fn invalidate(&mut self, path: impl AsRef<Path>) {
+        let path = self.resolve(path.as_ref());
+        self.entries.retain(|key, _| key.path != path);
+        self.account();
+    }
+
+    /// Removes all resources
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.account();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn touch(&mut self, key: &ResourceKey) -> Option<Rc<T>> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.entries.get_mut(key).map(|entry| {
+            entry.last_used = clock;
+            entry.value.clone()
+        })
+    }
+
+    fn insert_key(&mut self, key: ResourceKey, value: T) -> Rc<T> {
+        if self.entries.remove(&key).is_some() {
+            self.account();
+        }
+        while !self.entries.is_empty() && self.entries.len() >= self.capacity {
+            self.evict_least_recently_used();
+        }
+        let size = (self.size_of)(&value);
+        while !self.entries.is_empty()
+            && self
+                .memory
+                .as_ref()
+                .is_some_and(|memory| !memory.fits(size))
+        {
+            if let Some(memory) = &self.memory {
+                memory.record_eviction();
+            }
+            self.evict_least_recently_used();
+        }
+        self.clock += 1;
+        let value = Rc::new(value);
+        self.entries.insert(
+            key,
+            CacheEntry {
+                value: value.clone(),
+                size,
+                last_used: self.clock,
+            },
+        );
+        self.account();
+        value
+    }
+
+    /// The bytes of the cached resources, 0 without a memory budget
+    pub fn memory_used(&self) -> usize {
+        self.entries.values().map(|entry| entry.size).sum()
+    }
+
+    fn account(&mut self) {
+        let used = self.memory_used();
+        if let Some(memory) = &mut self.memory {
+            memory.set(used);
+        }
+    }
+
+    fn evict_least_recently_used(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(key) = oldest {
+            self.entries.remove(&key);
+            self.account();
+        }
+    }
+}
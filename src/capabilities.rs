@@ -0,0 +1,115 @@
+//! Which features the host supplied
+//!
+//! Features in `PluginUI::InitFeatures` are either required, failing
+//! the instantiation if missing, or `Option`s, which the UI checks
+//! one by one. `HostCapabilities` summarizes all features the host
+//! passed on instantiation, so the UI can adapt its behavior up
+//! front, e.g. drawing its own resize handle:
+//!
+//! ```ignore
+//! fn new(plugin_ui_info: &PluginUIInfo, ...) -> Result<Self, PluginUIError> {
+//!     let host = plugin_ui_info.host_capabilities();
+//!     let resize_handle = !host.resize() && !host.has_feature(lv2_sys::LV2_UI__noUserResize);
+//!     ...
+//! }
+//! ```
+
+use lv2_sys as sys;
+
+use std::ffi::CStr;
+
+use urid::*;
+
+/// The features the host supplied on instantiation
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HostCapabilities {
+    uris: Vec<UriBuf>,
+}
+
+impl HostCapabilities {
+    /// Collects the URIs of the null terminated feature list `features`
+    pub(crate) unsafe fn from_raw(features: *const *const sys::LV2_Feature) -> Self {
+        let mut uris = Vec::new();
+        let mut fptr = features;
+        while !fptr.is_null() && !(*fptr).is_null() {
+            if !(**fptr).URI.is_null() {
+                uris.push(CStr::from_ptr((**fptr).URI).to_owned());
+            }
+            fptr = fptr.add(1);
+        }
+        Self { uris }
+    }
+
+    /// True if the host supplied the feature `uri`
+    ///
+    /// The URI may be null terminated like the ones of `lv2_sys`.
+    pub fn has_feature(&self, uri: &[u8]) -> bool {
+        let uri = uri.strip_suffix(&[0]).unwrap_or(uri);
+        self.uris.iter().any(|supplied| supplied.as_bytes() == uri)
+    }
+
+    /// The URIs of all features supplied
+    pub fn uris(&self) -> impl Iterator<Item = &Uri> {
+        self.uris.iter().map(UriBuf::as_c_str)
+    }
+
+    /// A parent widget, `ui:parent`
+    pub fn parent(&self) -> bool {
+        self.has_feature(sys::LV2_UI__parent)
+    }
+
+    /// Resizing by the UI, `ui:resize`
+    pub fn resize(&self) -> bool {
+        self.has_feature(sys::LV2_UI__resize)
+    }
+
+    /// Gestures, `ui:touch`, see `touch`
+    pub fn touch(&self) -> bool {
+        self.has_feature(sys::LV2_UI__touch)
+    }
+
+    /// Subscribing to port notifications, `ui:portSubscribe`, see `notification`
+    pub fn port_subscribe(&self) -> bool {
+        self.has_feature(sys::LV2_UI__portSubscribe)
+    }
+
+    /// Asking the host for a value, `ui:requestValue`, see `request_value`
+    pub fn request_value(&self) -> bool {
+        self.has_feature(sys::LV2_UI__requestValue)
+    }
+
+    /// Looking up ports by symbol, `ui:portMap`, see `port_map`
+    pub fn port_map(&self) -> bool {
+        self.has_feature(sys::LV2_UI__portMap)
+    }
+
+    /// Options like the scale factor, `opts:options`
+    pub fn options(&self) -> bool {
+        self.has_feature(sys::LV2_OPTIONS__options)
+    }
+
+    /// Mapping URIs, `urid:map`
+    pub fn map(&self) -> bool {
+        self.has_feature(sys::LV2_URID__map)
+    }
+
+    /// Unmapping URIDs, `urid:unmap`
+    pub fn unmap(&self) -> bool {
+        self.has_feature(sys::LV2_URID__unmap)
+    }
+
+    /// Logging to the host, `log:log`
+    pub fn log(&self) -> bool {
+        self.has_feature(sys::LV2_LOG__log)
+    }
+
+    /// Accessing the plugin instance, `instance-access`, see `shared`
+    pub fn instance_access(&self) -> bool {
+        self.has_feature(sys::LV2_INSTANCE_ACCESS_URI)
+    }
+
+    /// Accessing the plugin's extension data, `data-access`
+    pub fn data_access(&self) -> bool {
+        self.has_feature(sys::LV2_DATA_ACCESS_URI)
+    }
+}
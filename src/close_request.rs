@@ -0,0 +1,48 @@
+//! Telling the host that the UI has been closed
+//!
+//! When the user closes the UI's own window, e.g. the top-level
+//! window of an external UI or of a UI shown by `ui:showInterface`,
+//! or presses a "close" button of the UI, the host needs to know.
+//! Every instance has a `CloseRequest`, see
+//! `PluginUIInfo::close_request()`, which the UI keeps and triggers:
+//!
+//! ```ignore
+//! // in new()
+//! let close_request = plugin_ui_info.close_request();
+//!
+//! // in idle()
+//! if self.window.close_requested() {
+//!     self.close_request.request_close();
+//! }
+//! ```
+//!
+//! The instance then returns non-zero from the idle interface and
+//! calls the `ui_closed` callback of the external-ui extension, once,
+//! after `PluginUI::run()`. The host cleans up the UI afterwards, so
+//! it should not write to the ports anymore. The request can be
+//! triggered from any thread, e.g. from a `UIWorker`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A handle to ask the host to close the UI
+#[derive(Clone, Debug, Default)]
+pub struct CloseRequest {
+    requested: Arc<AtomicBool>,
+}
+
+impl CloseRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the host to close the UI, the next idle call reports it
+    pub fn request_close(&self) {
+        self.requested.store(true, Ordering::Release);
+    }
+
+    /// True if `request_close()` has been called on this or a clone of it
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::Acquire)
+    }
+}
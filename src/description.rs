@@ -0,0 +1,225 @@
+//! Static description of a UI type
+//!
+//! Tools around a UI need to know about it without a host
+//! instantiating it: a build script writing the bundle's TTL, a
+//! bundler checking the UI against the plugin or a Rust host
+//! selecting a UI. `UIDescription::of()` collects what the UI type
+//! declares: its URI, `PluginUI::PLUGIN_URI`, the features in
+//! `PluginUI::REQUIRED_FEATURES` and `PluginUI::OPTIONAL_FEATURES`
+//! and the ports in `PluginUI::PORTS`:
+//!
+//! ```ignore
+//! impl PluginUI for AmpUI {
+//!     const PLUGIN_URI: Option<&'static [u8]> = Some(b"https://example.org/amp\0");
+//!     const REQUIRED_FEATURES: &'static [&'static [u8]] = &[lv2_sys::LV2_URID__map];
+//!     const PORTS: &'static [PortExpectation] = &[
+//!         PortExpectation::control(0, "gain"),
+//!         PortExpectation::atom(3, "notify"),
+//!     ];
+//!     ...
+//! }
+//!
+//! // in build.rs
+//! let ttl = UIDescription::of::<AmpUI>().ttl(UIClass::native(), "amp_ui.so");
+//! ```
+//!
+//! The feature list is a declaration for tools, the features are
+//! still retrieved by `PluginUI::InitFeatures`.
+
+use lv2_sys as sys;
+
+use urid::UriBound;
+
+use crate::plugin_ui::PluginUI;
+use crate::ui_class::UIClass;
+
+/// What kind of port a UI expects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortKind {
+    Control,
+    Atom,
+    /// A control port delivered by `ui:peakProtocol`, see `meter`
+    Peak,
+}
+
+/// A port the UI expects the plugin to have
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortExpectation {
+    pub index: u32,
+    /// The `lv2:symbol` of the port
+    pub symbol: &'static str,
+    pub kind: PortKind,
+}
+
+impl PortExpectation {
+    pub const fn control(index: u32, symbol: &'static str) -> Self {
+        Self {
+            index,
+            symbol,
+            kind: PortKind::Control,
+        }
+    }
+
+    pub const fn atom(index: u32, symbol: &'static str) -> Self {
+        Self {
+            index,
+            symbol,
+            kind: PortKind::Atom,
+        }
+    }
+
+    pub const fn peak(index: u32, symbol: &'static str) -> Self {
+        Self {
+            index,
+            symbol,
+            kind: PortKind::Peak,
+        }
+    }
+
+    /// The mismatch if the plugin has the port at `actual`
+    pub(crate) fn mismatch(&self, actual: Option<u32>) -> Option<PortMismatch> {
+        match actual {
+            None => Some(PortMismatch::Missing {
+                symbol: self.symbol,
+            }),
+            Some(actual) if actual != self.index => Some(PortMismatch::Moved {
+                symbol: self.symbol,
+                expected: self.index,
+                actual,
+            }),
+            Some(_) => None,
+        }
+    }
+}
+
+/// A difference between the ports a UI expects and the ones of the plugin
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PortMismatch {
+    /// The plugin has no port with the symbol
+    Missing { symbol: &'static str },
+    /// The plugin has the port at another index
+    Moved {
+        symbol: &'static str,
+        expected: u32,
+        actual: u32,
+    },
+}
+
+/// What a UI type declares about itself, see the module documentation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UIDescription {
+    pub uri: &'static str,
+    pub plugin_uri: Option<&'static str>,
+    pub required_features: Vec<&'static str>,
+    pub optional_features: Vec<&'static str>,
+    /// The interfaces the UI provides, `PluginUI::IDLE_INTERFACE` and the like
+    pub extension_data: Vec<&'static str>,
+    pub ports: &'static [PortExpectation],
+}
+
+// The URIs are declared null terminated, like the ones of `lv2_sys`
+fn uri_str(uri: &'static [u8]) -> &'static str {
+    let uri = uri.strip_suffix(&[0]).unwrap_or(uri);
+    std::str::from_utf8(uri).expect("URI is not UTF-8")
+}
+
+impl UIDescription {
+    /// The description of the UI type `T`
+    pub fn of<T: PluginUI + UriBound>() -> Self {
+        let interfaces = [
+            (T::IDLE_INTERFACE, sys::LV2_UI__idleInterface),
+            (T::SHOW_INTERFACE, sys::LV2_UI__showInterface),
+            (T::RESIZE_INTERFACE, sys::LV2_UI__resize),
+            (T::OPTIONS_INTERFACE, sys::LV2_OPTIONS__interface),
+        ];
+        Self {
+            uri: uri_str(T::URI),
+            plugin_uri: T::PLUGIN_URI.map(uri_str),
+            required_features: T::REQUIRED_FEATURES.iter().copied().map(uri_str).collect(),
+            optional_features: T::OPTIONAL_FEATURES.iter().copied().map(uri_str).collect(),
+            extension_data: interfaces
+                .iter()
+                .filter(|(provided, _)| *provided)
+                .map(|(_, uri)| uri_str(uri))
+                .collect(),
+            ports: T::PORTS,
+        }
+    }
+
+    /// The expected port with the symbol `symbol`
+    pub fn port(&self, symbol: &str) -> Option<&PortExpectation> {
+        self.ports.iter().find(|port| port.symbol == symbol)
+    }
+
+    /// Compares the expected ports with the `(index, symbol)` pairs of the plugin's ports
+    ///
+    /// Returns an empty list if all expected ports are where the UI
+    /// expects them.
+    pub fn check_ports<'s>(
+        &self,
+        plugin_ports: impl IntoIterator<Item = (u32, &'s str)> + Clone,
+    ) -> Vec<PortMismatch> {
+        self.ports
+            .iter()
+            .filter_map(|port| {
+                let actual = plugin_ports
+                    .clone()
+                    .into_iter()
+                    .find(|(_, symbol)| *symbol == port.symbol)
+                    .map(|(index, _)| index);
+                port.mismatch(actual)
+            })
+            .collect()
+    }
+
+    /// The TTL describing the UI of the class `class` in `binary`
+    ///
+    /// Extends `UIClass::manifest_entry()` by the features, the
+    /// interfaces and, if the plugin URI is known, a
+    /// `ui:portNotification` for every expected control and peak
+    /// port. The `ui:` and `lv2:` prefixes need to be declared.
+    /// Returns None if the class is not valid for the compile target.
+    pub fn ttl(&self, class: UIClass, binary: &str) -> Option<std::string::String> {
+        let entry = class.manifest_entry(self.uri, binary)?;
+        let mut lines: Vec<std::string::String> = entry
+            .trim_end()
+            .trim_end_matches('.')
+            .trim_end()
+            .lines()
+            .map(Into::into)
+            .collect();
+        if let Some(last) = lines.last_mut() {
+            last.push_str(" ;");
+        }
+        for feature in self.required_features.iter() {
+            lines.push(format!("    lv2:requiredFeature <{}> ;", feature));
+        }
+        for feature in self.optional_features.iter() {
+            lines.push(format!("    lv2:optionalFeature <{}> ;", feature));
+        }
+        for interface in self.extension_data.iter() {
+            lines.push(format!("    lv2:extensionData <{}> ;", interface));
+        }
+        if let Some(plugin_uri) = self.plugin_uri {
+            for port in self.ports.iter() {
+                let protocol = match port.kind {
+                    PortKind::Control => "ui:floatProtocol",
+                    PortKind::Peak => "ui:peakProtocol",
+                    PortKind::Atom => continue,
+                };
+                lines.push(format!(
+                    "    ui:portNotification [\n        ui:plugin <{}> ;\n        \
+                     lv2:symbol \"{}\" ;\n        ui:protocol {}\n    ] ;",
+                    plugin_uri, port.symbol, protocol
+                ));
+            }
+        }
+        // Every line but the first ends with a `;`, the last one ends the entry
+        let mut ttl = lines.join("\n");
+        if ttl.ends_with(" ;") {
+            ttl.truncate(ttl.len() - 2);
+        }
+        ttl.push_str(" .\n");
+        Some(ttl)
+    }
+}
@@ -0,0 +1,529 @@
+//! Standalone runner to develop plugin UIs without a DAW
+//!
+//! The `DevRunner` loads the companion DSP plugin from its bundle,
+//! instantiates the plugin and the UI and runs them connected like a
+//! host would do. The UI does not get a parent window, so it opens a
+//! top-level window. Audio is processed by a dummy backend, which
+//! runs the plugin in real time with silent audio inputs.
+//!
+//! The plugin's binary and ports are taken from the bundle's Turtle
+//! files by lilv, which is loaded at runtime, so it needs to be
+//! installed:
+//!
+//! ```ignore
+//! fn main() {
+//!     DevRunner::new("target/amp.lv2", "https://example.org/amp")
+//!         .run::<AmpUI>()
+//!         .unwrap();
+//! }
+//! ```
+//!
+//! The runner stops when the UI's `idle()` returns non-zero. A UI
+//! without the idle interface cannot tell it is closed, so the
+//! runner does not run it.
+//!
+//! Only available with the `dev-runner` feature.
+
+use lv2_sys as sys;
+
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use lv2_atom as atom;
+use urid::*;
+
+use crate::logging::ui_log;
+use crate::plugin_ui::PluginUI;
+use crate::testing::*;
+
+const ATOM_BUFFER_SIZE: usize = 8192;
+
+// The kind of a plugin port
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PortKind {
+    AudioInput,
+    AudioOutput,
+    /// A control input port with its initial value
+    ControlInput(f32),
+    ControlOutput,
+    AtomInput,
+    AtomOutput,
+}
+
+#[derive(Debug)]
+pub enum DevRunnerError {
+    Library(libloading::Error),
+    /// lilv could not be loaded, probably it is not installed
+    Lilv(libloading::Error),
+    PluginNotFound,
+    /// The port with this index is of a kind the runner cannot connect
+    UnsupportedPort(u32),
+    InvalidPath,
+    PluginInstantiation,
+    UIInstantiation,
+    /// The UI does not provide the idle interface
+    NoIdleInterface,
+}
+
+impl From<libloading::Error> for DevRunnerError {
+    fn from(e: libloading::Error) -> Self {
+        DevRunnerError::Library(e)
+    }
+}
+
+enum PortBuffer {
+    Audio(Vec<f32>),
+    Control(Box<f32>, f32),
+    Atom(Vec<u64>, Vec<Vec<u8>>),
+}
+
+struct Port {
+    index: u32,
+    kind: PortKind,
+    buffer: PortBuffer,
+}
+
+/// Runs a plugin and its UI
+pub struct DevRunner {
+    bundle_path: PathBuf,
+    plugin_uri: String,
+    sample_rate: f64,
+    block_size: usize,
+}
+
+impl DevRunner {
+    /// Creates a runner for the plugin `plugin_uri` in the bundle `bundle_path`
+    pub fn new(bundle_path: impl Into<PathBuf>, plugin_uri: &str) -> Self {
+        Self {
+            bundle_path: bundle_path.into(),
+            plugin_uri: plugin_uri.to_owned(),
+            sample_rate: 48000.0,
+            block_size: 256,
+        }
+    }
+
+    pub fn sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Runs the plugin and the UI `T` until the UI's `idle()` returns non-zero
+    pub fn run<T: PluginUI>(&self) -> Result<(), DevRunnerError> {
+        let plugin_uri =
+            CString::new(self.plugin_uri.as_str()).map_err(|_| DevRunnerError::InvalidPath)?;
+        let mut bundle_path = self
+            .bundle_path
+            .to_str()
+            .ok_or(DevRunnerError::InvalidPath)?
+            .to_owned();
+        if !bundle_path.ends_with('/') {
+            bundle_path.push('/');
+        }
+        let bundle_path_c =
+            CString::new(bundle_path.as_str()).map_err(|_| DevRunnerError::InvalidPath)?;
+
+        let lilv = Lilv::load().map_err(DevRunnerError::Lilv)?;
+        let (binary, plugin_ports) = unsafe { lilv.discover(&bundle_path_c, &plugin_uri)? };
+
+        let library = unsafe { libloading::Library::new(&binary)? };
+        let lv2_descriptor: libloading::Symbol<
+            unsafe extern "C" fn(u32) -> *const sys::LV2_Descriptor,
+        > = unsafe { library.get(b"lv2_descriptor\0")? };
+
+        let descriptor = (0..)
+            .map(|index| unsafe { lv2_descriptor(index).as_ref() })
+            .take_while(Option::is_some)
+            .flatten()
+            .find(|descriptor| unsafe { CStr::from_ptr(descriptor.URI) } == plugin_uri.as_c_str())
+            .ok_or(DevRunnerError::PluginNotFound)?;
+
+        let mut host = MockUIHost::new()
+            .with_parent(std::ptr::null_mut())
+            .with_plugin_uri(&self.plugin_uri)
+            .with_bundle_path(&bundle_path);
+
+        let plugin_features = host.features();
+
+        let plugin = unsafe {
+            (descriptor
+                .instantiate
+                .ok_or(DevRunnerError::PluginInstantiation)?)(
+                descriptor,
+                self.sample_rate,
+                bundle_path_c.as_ptr(),
+                plugin_features.as_ptr(),
+            )
+        };
+        if plugin.is_null() {
+            return Err(DevRunnerError::PluginInstantiation);
+        }
+
+        let sequence_urid = map_urid(host.map(), sys::LV2_ATOM__Sequence);
+        let chunk_urid = map_urid(host.map(), sys::LV2_ATOM__Chunk);
+        let event_transfer = host
+            .map()
+            .map_type::<atom::uris::EventTransfer>()
+            .map(URID::get)
+            .unwrap_or(0);
+
+        let mut ports: Vec<Port> = plugin_ports
+            .iter()
+            .map(|&(index, kind)| Port {
+                index,
+                kind,
+                buffer: match kind {
+                    PortKind::AudioInput | PortKind::AudioOutput => {
+                        PortBuffer::Audio(vec![0.0; self.block_size])
+                    }
+                    PortKind::ControlInput(value) => PortBuffer::Control(Box::new(value), value),
+                    PortKind::ControlOutput => PortBuffer::Control(Box::new(0.0), f32::NAN),
+                    PortKind::AtomInput | PortKind::AtomOutput => {
+                        PortBuffer::Atom(vec![0; ATOM_BUFFER_SIZE / 8], Vec::new())
+                    }
+                },
+            })
+            .collect();
+
+        for port in ports.iter_mut() {
+            let data = match &mut port.buffer {
+                PortBuffer::Audio(buffer) => buffer.as_mut_ptr() as *mut c_void,
+                PortBuffer::Control(value, _) => value.as_mut() as *mut f32 as *mut c_void,
+                PortBuffer::Atom(buffer, _) => buffer.as_mut_ptr() as *mut c_void,
+            };
+            if let Some(connect_port) = descriptor.connect_port {
+                unsafe { connect_port(plugin, port.index, data) };
+            }
+        }
+
+        let result = match host.instantiate::<T>() {
+            Some(mut ui) => {
+                for port in ports.iter() {
+                    if let PortKind::ControlInput(value) = port.kind {
+                        ui.control_event(port.index, value);
+                    }
+                }
+                if let Some(activate) = descriptor.activate {
+                    unsafe { activate(plugin) };
+                }
+
+                let period = Duration::from_secs_f64(self.block_size as f64 / self.sample_rate);
+                let mut next_cycle = Instant::now();
+                let result = loop {
+                    for write in ui.take_writes() {
+                        apply_write(&mut ports, write, event_transfer);
+                    }
+                    for port in ports.iter_mut() {
+                        prepare_atom_port(port, sequence_urid, chunk_urid);
+                    }
+                    if let Some(run) = descriptor.run {
+                        unsafe { run(plugin, self.block_size as u32) };
+                    }
+                    for port in ports.iter_mut() {
+                        match (&port.kind, &mut port.buffer) {
+                            (PortKind::ControlOutput, PortBuffer::Control(value, last))
+                                if **value != *last =>
+                            {
+                                *last = **value;
+                                ui.control_event(port.index, *last);
+                            }
+                            (PortKind::AtomOutput, PortBuffer::Atom(buffer, _)) => {
+                                for event in sequence_events(buffer, sequence_urid) {
+                                    ui.atom_event(port.index, &event);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    match ui.idle() {
+                        Some(0) => {}
+                        Some(_) => break Ok(()),
+                        None => break Err(DevRunnerError::NoIdleInterface),
+                    }
+
+                    next_cycle += period;
+                    let now = Instant::now();
+                    if next_cycle > now {
+                        std::thread::sleep(next_cycle - now);
+                    } else {
+                        next_cycle = now;
+                    }
+                };
+
+                if let Some(deactivate) = descriptor.deactivate {
+                    unsafe { deactivate(plugin) };
+                }
+                result
+            }
+            None => Err(DevRunnerError::UIInstantiation),
+        };
+
+        if let Some(cleanup) = descriptor.cleanup {
+            unsafe { cleanup(plugin) };
+        }
+        result
+    }
+}
+
+// The part of the lilv C API used to find the plugin's binary and ports
+struct Lilv {
+    world_new: unsafe extern "C" fn() -> *mut c_void,
+    world_free: unsafe extern "C" fn(*mut c_void),
+    world_load_bundle: unsafe extern "C" fn(*mut c_void, *const c_void),
+    world_get_all_plugins: unsafe extern "C" fn(*const c_void) -> *const c_void,
+    plugins_get_by_uri: unsafe extern "C" fn(*const c_void, *const c_void) -> *const c_void,
+    new_uri: unsafe extern "C" fn(*mut c_void, *const c_char) -> *mut c_void,
+    new_file_uri: unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char) -> *mut c_void,
+    node_free: unsafe extern "C" fn(*mut c_void),
+    node_as_uri: unsafe extern "C" fn(*const c_void) -> *const c_char,
+    file_uri_parse: unsafe extern "C" fn(*const c_char, *mut *mut c_char) -> *mut c_char,
+    free: unsafe extern "C" fn(*mut c_void),
+    plugin_get_library_uri: unsafe extern "C" fn(*const c_void) -> *const c_void,
+    plugin_get_num_ports: unsafe extern "C" fn(*const c_void) -> u32,
+    plugin_get_port_by_index: unsafe extern "C" fn(*const c_void, u32) -> *const c_void,
+    plugin_get_port_ranges_float: unsafe extern "C" fn(*const c_void, *mut f32, *mut f32, *mut f32),
+    port_is_a: unsafe extern "C" fn(*const c_void, *const c_void, *const c_void) -> bool,
+    // Keeps the functions above loaded
+    _library: libloading::Library,
+}
+
+#[cfg(target_os = "linux")]
+const LILV_LIBRARIES: &[&str] = &["liblilv-0.so.0", "liblilv-0.so"];
+#[cfg(target_os = "macos")]
+const LILV_LIBRARIES: &[&str] = &["liblilv-0.0.dylib", "liblilv-0.dylib"];
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+const LILV_LIBRARIES: &[&str] = &["lilv-0.dll", "liblilv-0.so.0"];
+
+impl Lilv {
+    fn load() -> Result<Self, libloading::Error> {
+        let mut libraries = LILV_LIBRARIES
+            .iter()
+            .map(|name| unsafe { libloading::Library::new(name) });
+        let mut library = libraries.next().expect("no lilv library name");
+        for next in libraries {
+            if library.is_ok() {
+                break;
+            }
+            library = next;
+        }
+        let library = library?;
+        unsafe {
+            Ok(Self {
+                world_new: *library.get(b"lilv_world_new\0")?,
+                world_free: *library.get(b"lilv_world_free\0")?,
+                world_load_bundle: *library.get(b"lilv_world_load_bundle\0")?,
+                world_get_all_plugins: *library.get(b"lilv_world_get_all_plugins\0")?,
+                plugins_get_by_uri: *library.get(b"lilv_plugins_get_by_uri\0")?,
+                new_uri: *library.get(b"lilv_new_uri\0")?,
+                new_file_uri: *library.get(b"lilv_new_file_uri\0")?,
+                node_free: *library.get(b"lilv_node_free\0")?,
+                node_as_uri: *library.get(b"lilv_node_as_uri\0")?,
+                file_uri_parse: *library.get(b"lilv_file_uri_parse\0")?,
+                free: *library.get(b"lilv_free\0")?,
+                plugin_get_library_uri: *library.get(b"lilv_plugin_get_library_uri\0")?,
+                plugin_get_num_ports: *library.get(b"lilv_plugin_get_num_ports\0")?,
+                plugin_get_port_by_index: *library.get(b"lilv_plugin_get_port_by_index\0")?,
+                plugin_get_port_ranges_float: *library
+                    .get(b"lilv_plugin_get_port_ranges_float\0")?,
+                port_is_a: *library.get(b"lilv_port_is_a\0")?,
+                _library: library,
+            })
+        }
+    }
+
+    /// The binary and the ports of the plugin `plugin_uri` in the bundle
+    unsafe fn discover(
+        &self,
+        bundle_path: &CStr,
+        plugin_uri: &CStr,
+    ) -> Result<(PathBuf, Vec<(u32, PortKind)>), DevRunnerError> {
+        let world = (self.world_new)();
+        let bundle_uri = (self.new_file_uri)(world, std::ptr::null(), bundle_path.as_ptr());
+        (self.world_load_bundle)(world, bundle_uri);
+        let uri = (self.new_uri)(world, plugin_uri.as_ptr());
+        let plugin = (self.plugins_get_by_uri)((self.world_get_all_plugins)(world), uri);
+        let result = if plugin.is_null() {
+            Err(DevRunnerError::PluginNotFound)
+        } else {
+            self.binary(plugin)
+                .ok_or(DevRunnerError::InvalidPath)
+                .and_then(|binary| Ok((binary, self.ports(world, plugin)?)))
+        };
+        (self.node_free)(uri);
+        (self.node_free)(bundle_uri);
+        (self.world_free)(world);
+        result
+    }
+
+    unsafe fn binary(&self, plugin: *const c_void) -> Option<PathBuf> {
+        let library_uri = (self.plugin_get_library_uri)(plugin);
+        if library_uri.is_null() {
+            return None;
+        }
+        let path = (self.file_uri_parse)((self.node_as_uri)(library_uri), std::ptr::null_mut());
+        if path.is_null() {
+            return None;
+        }
+        let binary = CStr::from_ptr(path).to_str().ok().map(PathBuf::from);
+        (self.free)(path as *mut c_void);
+        binary
+    }
+
+    unsafe fn ports(
+        &self,
+        world: *mut c_void,
+        plugin: *const c_void,
+    ) -> Result<Vec<(u32, PortKind)>, DevRunnerError> {
+        let classes = [
+            sys::LV2_CORE__InputPort,
+            sys::LV2_CORE__OutputPort,
+            sys::LV2_CORE__AudioPort,
+            sys::LV2_CORE__CVPort,
+            sys::LV2_CORE__ControlPort,
+            sys::LV2_ATOM__AtomPort,
+        ]
+        .map(|uri| (self.new_uri)(world, uri.as_ptr() as *const c_char));
+        let [input, output, audio, cv, control, atom] = classes;
+
+        let count = (self.plugin_get_num_ports)(plugin);
+        let mut defaults = vec![f32::NAN; count as usize];
+        (self.plugin_get_port_ranges_float)(
+            plugin,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            defaults.as_mut_ptr(),
+        );
+
+        let ports = (0..count)
+            .map(|index| {
+                let port = (self.plugin_get_port_by_index)(plugin, index);
+                let is_a = |class: *mut c_void| (self.port_is_a)(plugin, port, class);
+                let kind = match (is_a(input), is_a(output)) {
+                    (true, _) if is_a(audio) || is_a(cv) => PortKind::AudioInput,
+                    (_, true) if is_a(audio) || is_a(cv) => PortKind::AudioOutput,
+                    (true, _) if is_a(control) => {
+                        let default = defaults[index as usize];
+                        PortKind::ControlInput(if default.is_nan() { 0.0 } else { default })
+                    }
+                    (_, true) if is_a(control) => PortKind::ControlOutput,
+                    (true, _) if is_a(atom) => PortKind::AtomInput,
+                    (_, true) if is_a(atom) => PortKind::AtomOutput,
+                    _ => return Err(DevRunnerError::UnsupportedPort(index)),
+                };
+                Ok((index, kind))
+            })
+            .collect();
+
+        for class in classes.iter() {
+            (self.node_free)(*class);
+        }
+        ports
+    }
+}
+
+fn map_urid(map: &HashURIDMapper, uri: &[u8]) -> u32 {
+    let uri = unsafe { CStr::from_bytes_with_nul_unchecked(uri) };
+    map.map_uri(uri).map(URID::get).unwrap_or(0)
+}
+
+fn apply_write(ports: &mut [Port], write: PortWrite, event_transfer: u32) {
+    let port = match ports.iter_mut().find(|port| port.index == write.port_index) {
+        Some(port) => port,
+        None => {
+            ui_log!(Warning, "UI wrote to undeclared port {}", write.port_index);
+            return;
+        }
+    };
+    match (&port.kind, &mut port.buffer) {
+        (PortKind::ControlInput(_), PortBuffer::Control(value, _)) if write.protocol == 0 => {
+            if let Some(new_value) = write.control_value() {
+                **value = new_value;
+            }
+        }
+        (PortKind::AtomInput, PortBuffer::Atom(_, pending)) if write.protocol == event_transfer => {
+            pending.push(write.data);
+        }
+        _ => ui_log!(
+            Warning,
+            "UI wrote with protocol {} to incompatible port {}",
+            write.protocol,
+            write.port_index
+        ),
+    }
+}
+
+fn buffer_bytes(buffer: &mut [u64]) -> &mut [u8] {
+    unsafe { std::slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut u8, buffer.len() * 8) }
+}
+
+fn write_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_ne_bytes());
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut value = [0u8; 4];
+    value.copy_from_slice(&bytes[offset..offset + 4]);
+    u32::from_ne_bytes(value)
+}
+
+// Fills atom inputs with a sequence of the pending events, sets up
+// atom outputs for the plugin to write a sequence into.
+fn prepare_atom_port(port: &mut Port, sequence_urid: u32, chunk_urid: u32) {
+    let (buffer, pending) = match &mut port.buffer {
+        PortBuffer::Atom(buffer, pending) => (buffer, pending),
+        _ => return,
+    };
+    let bytes = buffer_bytes(buffer);
+    match port.kind {
+        PortKind::AtomInput => {
+            // Sequence header: atom size and type, unit and pad
+            let mut offset = 16;
+            for event in pending.drain(..) {
+                let padded = (event.len() + 7) & !7;
+                if offset + 8 + padded > bytes.len() {
+                    ui_log!(Warning, "dropping event, atom input buffer full");
+                    continue;
+                }
+                bytes[offset..offset + 8].copy_from_slice(&0i64.to_ne_bytes());
+                bytes[offset + 8..offset + 8 + event.len()].copy_from_slice(&event);
+                offset += 8 + padded;
+            }
+            write_u32(bytes, 0, offset as u32 - 8);
+            write_u32(bytes, 4, sequence_urid);
+            write_u32(bytes, 8, 0);
+            write_u32(bytes, 12, 0);
+        }
+        PortKind::AtomOutput => {
+            let capacity = bytes.len() as u32 - 8;
+            write_u32(bytes, 0, capacity);
+            write_u32(bytes, 4, chunk_urid);
+        }
+        _ => {}
+    }
+}
+
+// Extracts the atoms of the events of a sequence the plugin has written.
+fn sequence_events(buffer: &mut [u64], sequence_urid: u32) -> Vec<Vec<u8>> {
+    let bytes = buffer_bytes(buffer);
+    let mut events = Vec::new();
+    if read_u32(bytes, 4) != sequence_urid {
+        return events;
+    }
+    let end = (8 + read_u32(bytes, 0) as usize).min(bytes.len());
+    let mut offset = 16;
+    while offset + 16 <= end {
+        let atom_size = 8 + read_u32(bytes, offset + 8) as usize;
+        if offset + 8 + atom_size > end {
+            break;
+        }
+        events.push(bytes[offset + 8..offset + 8 + atom_size].to_vec());
+        offset += 8 + ((atom_size + 7) & !7);
+    }
+    events
+}
@@ -0,0 +1,548 @@
+//! Diagnostics of the communication with the host
+//!
+//! Every port write of the UI calls the host's write function in the
+//! UI thread. Most hosts just queue the value, but some do expensive
+//! work there, which makes dragging a knob stutter. The
+//! `PluginPortWriteHandle` measures how long the write function
+//! takes, see `PluginPortWriteHandle::diagnostics()`.
+//!
+//! With a slow write function the handle can defer the writes and
+//! pass them to the host in one batch at the end of `idle()`. Writes
+//! to the same control port in between are merged, so the host only
+//! gets the latest value:
+//!
+//! ```ignore
+//! write_handle.set_write_mode(WriteMode::Auto {
+//!     threshold: Duration::from_micros(500),
+//! });
+//! ```
+//!
+//! Plugins doing expensive work per received value, e.g. recomputing
+//! filter tables, are better off with fewer writes regardless of the
+//! host. `WriteMode::Throttled` merges the writes the same way, but
+//! passes them at most once per interval:
+//!
+//! ```ignore
+//! write_handle.set_write_mode(WriteMode::Throttled {
+//!     interval: Duration::from_millis(50),
+//! });
+//! ```
+//!
+//! Deferred writes rely on the host calling the idle interface. UIs
+//! driven by their own event loop call `PluginPortWriteHandle::flush()`
+//! themselves.
+//!
+//! For error reports from the field, `Diagnostics::capture()` takes a
+//! snapshot of the port values, the atom queues, the last port events
+//! from the host and the write measurements. `Diagnostics::dump()`
+//! serializes it as JSON to attach to the report:
+//!
+//! ```ignore
+//! let report = Diagnostics::capture(&mut self.ports, &self.write_handle)
+//!     .with_memory(&self.memory_budget)
+//!     .dump();
+//! ```
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::logging::ui_log;
+use crate::memory::{MemoryBudget, MemoryUsage};
+use crate::metrics::PortMetrics;
+use crate::plugin_ui::PluginPortWriteHandle;
+use crate::port::{PortRef, UIPort, UIPortsTrait};
+
+/// The number of writes measured before `WriteMode::Auto` decides
+const AUTO_MIN_WRITES: u64 = 8;
+
+/// The number of port events from the host kept for `Diagnostics`
+const RECENT_EVENTS: usize = 64;
+
+/// When port writes are passed to the host
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Every write calls the host's write function right away
+    #[default]
+    Immediate,
+    /// Writes are passed to the host at the end of `idle()`
+    Deferred,
+    /// Immediate until the host's write function takes longer than
+    /// `threshold` on average, deferred from then on
+    Auto { threshold: Duration },
+    /// Deferred, but passed at the end of `idle()` only if `interval`
+    /// has passed since the last batch
+    Throttled { interval: Duration },
+}
+
+/// Measurements of the host's write function
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteDiagnostics {
+    /// The number of calls of the write function
+    pub writes: u64,
+    /// The time spent in the write function
+    pub total: Duration,
+    /// The longest call of the write function
+    pub max: Duration,
+    /// The last call of the write function
+    pub last: Option<Duration>,
+    /// The number of writes deferred
+    pub deferred: u64,
+    /// The number of deferred writes replaced by a later one to the same port
+    pub merged: u64,
+    /// The number of batches of deferred writes passed to the host
+    pub batches: u64,
+    /// True if writes are currently deferred
+    pub deferring: bool,
+}
+
+impl WriteDiagnostics {
+    /// The average time of a call of the write function
+    pub fn mean(&self) -> Option<Duration> {
+        match self.writes {
+            0 => None,
+            writes => Some(Duration::from_secs_f64(
+                self.total.as_secs_f64() / writes as f64,
+            )),
+        }
+    }
+}
+
+/// A port event the host has delivered
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortEventRecord {
+    pub port_index: u32,
+    /// 0 for control values, otherwise the URID of the port protocol
+    pub format: u32,
+    /// The size of the buffer in bytes
+    pub size: u32,
+    pub received: Instant,
+}
+
+/// The value of a control port at the time of the snapshot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ControlSnapshot {
+    pub index: u32,
+    pub value: f32,
+    pub modulated_value: Option<f32>,
+}
+
+/// The queue of an atom port at the time of the snapshot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtomQueueSnapshot {
+    pub index: u32,
+    /// The number of atoms not read yet
+    pub queued: usize,
+    /// The number of atoms dropped by the overflow policy
+    pub dropped: usize,
+}
+
+/// A snapshot of the UI's communication with host and plugin
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostics {
+    pub captured: Instant,
+    pub controls: Vec<ControlSnapshot>,
+    pub atom_queues: Vec<AtomQueueSnapshot>,
+    /// The last port events, oldest first
+    pub recent_events: Vec<PortEventRecord>,
+    pub writes: WriteDiagnostics,
+    /// The accounting of the memory budget, if added by `with_memory()`
+    pub memory: Option<MemoryUsage>,
+}
+
+impl Diagnostics {
+    /// Takes a snapshot of the ports and the write handle
+    ///
+    /// The ports are enumerated by `UIPortsTrait::visit_ports()`.
+    pub fn capture(ports: &mut impl UIPortsTrait, write_handle: &PluginPortWriteHandle) -> Self {
+        let mut controls = Vec::new();
+        let mut atom_queues = Vec::new();
+        ports.visit_ports(|port| match port {
+            PortRef::Control(port) => controls.push(ControlSnapshot {
+                index: port.index(),
+                value: port.value(),
+                modulated_value: port.modulated_value(),
+            }),
+            PortRef::Atom(port) => atom_queues.push(AtomQueueSnapshot {
+                index: port.index(),
+                queued: port.queued(),
+                dropped: port.dropped(),
+            }),
+            PortRef::Peak(_) => {}
+        });
+        Self {
+            captured: Instant::now(),
+            controls,
+            atom_queues,
+            recent_events: write_handle.recent_port_events(),
+            writes: write_handle.diagnostics(),
+            memory: None,
+        }
+    }
+
+    /// Adds the accounting of `budget` to the snapshot
+    pub fn with_memory(mut self, budget: &MemoryBudget) -> Self {
+        self.memory = Some(budget.usage());
+        self
+    }
+
+    /// The snapshot as JSON
+    ///
+    /// Event times are given in milliseconds before the snapshot,
+    /// durations in microseconds.
+    pub fn dump(&self) -> std::string::String {
+        let mut json = std::string::String::new();
+        let controls = self
+            .controls
+            .iter()
+            .map(|control| {
+                format!(
+                    "{{\"index\":{},\"value\":{},\"modulated_value\":{}}}",
+                    control.index,
+                    json_number(control.value),
+                    control.modulated_value.map_or("null".into(), json_number)
+                )
+            })
+            .collect::<Vec<_>>();
+        let atom_queues = self
+            .atom_queues
+            .iter()
+            .map(|queue| {
+                format!(
+                    "{{\"index\":{},\"queued\":{},\"dropped\":{}}}",
+                    queue.index, queue.queued, queue.dropped
+                )
+            })
+            .collect::<Vec<_>>();
+        let events = self
+            .recent_events
+            .iter()
+            .map(|event| {
+                let age = self.captured.saturating_duration_since(event.received);
+                format!(
+                    "{{\"port_index\":{},\"format\":{},\"size\":{},\"age_ms\":{:.3}}}",
+                    event.port_index,
+                    event.format,
+                    event.size,
+                    age.as_secs_f64() * 1000.0
+                )
+            })
+            .collect::<Vec<_>>();
+        let memory = match &self.memory {
+            Some(memory) => {
+                let limit = |limit: Option<usize>| limit.map_or("null".into(), |l| l.to_string());
+                let categories = memory
+                    .categories
+                    .iter()
+                    .map(|category| {
+                        format!(
+                            "{{\"category\":\"{:?}\",\"limit\":{},\"used\":{},\"evicted\":{}}}",
+                            category.category,
+                            limit(category.limit),
+                            category.used,
+                            category.evicted
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                format!(
+                    "{{\"limit\":{},\"used\":{},\"categories\":[{}]}}",
+                    limit(memory.limit),
+                    memory.used(),
+                    categories.join(",")
+                )
+            }
+            None => "null".into(),
+        };
+        let writes = &self.writes;
+        let micros = |duration: Duration| duration.as_micros().to_string();
+        let _ = write!(
+            json,
+            "{{\"controls\":[{}],\"atom_queues\":[{}],\"recent_events\":[{}],\
+             \"writes\":{{\"writes\":{},\"total_us\":{},\"max_us\":{},\"mean_us\":{},\
+             \"deferred\":{},\"merged\":{},\"batches\":{},\"deferring\":{}}},\"memory\":{}}}",
+            controls.join(","),
+            atom_queues.join(","),
+            events.join(","),
+            writes.writes,
+            micros(writes.total),
+            micros(writes.max),
+            writes.mean().map_or("null".into(), micros),
+            writes.deferred,
+            writes.merged,
+            writes.batches,
+            writes.deferring,
+            memory
+        );
+        json
+    }
+}
+
+/// JSON has no representation of NaN and infinity
+fn json_number(value: f32) -> std::string::String {
+    match value.is_finite() {
+        true => value.to_string(),
+        false => "null".into(),
+    }
+}
+
+pub(crate) struct PendingWrite {
+    pub(crate) port_index: u32,
+    pub(crate) protocol: u32,
+    pub(crate) data: Vec<u8>,
+}
+
+/// The state shared by the write handle of the UI and the instance
+#[derive(Default)]
+pub(crate) struct WriteState {
+    mode: Cell<WriteMode>,
+    diagnostics: Cell<WriteDiagnostics>,
+    pending: RefCell<Vec<PendingWrite>>,
+    events: RefCell<VecDeque<PortEventRecord>>,
+    last_batch: Cell<Option<Instant>>,
+    remote: Arc<Mutex<Vec<PendingWrite>>>,
+    metrics: RefCell<Option<Rc<RefCell<dyn PortMetrics>>>>,
+}
+
+impl WriteState {
+    pub(crate) fn mode(&self) -> WriteMode {
+        self.mode.get()
+    }
+
+    pub(crate) fn set_mode(&self, mode: WriteMode) {
+        self.mode.set(mode);
+        let mut diagnostics = self.diagnostics.get();
+        diagnostics.deferring = match mode {
+            WriteMode::Immediate => false,
+            WriteMode::Deferred | WriteMode::Throttled { .. } => true,
+            WriteMode::Auto { threshold } => Self::too_slow(&diagnostics, threshold),
+        };
+        self.diagnostics.set(diagnostics);
+    }
+
+    pub(crate) fn diagnostics(&self) -> WriteDiagnostics {
+        self.diagnostics.get()
+    }
+
+    pub(crate) fn reset_diagnostics(&self) {
+        self.diagnostics.set(WriteDiagnostics {
+            deferring: self.diagnostics.get().deferring,
+            ..WriteDiagnostics::default()
+        });
+    }
+
+    pub(crate) fn is_deferring(&self) -> bool {
+        self.diagnostics.get().deferring
+    }
+
+    /// Queues a write, replacing a queued control value of the same port
+    pub(crate) fn defer(&self, port_index: u32, protocol: u32, data: &[u8]) {
+        let mut diagnostics = self.diagnostics.get();
+        diagnostics.deferred += 1;
+        let mut pending = self.pending.borrow_mut();
+        // Protocol 0 is `ui:floatProtocol`, only the latest value counts
+        let queued = pending
+            .iter_mut()
+            .find(|write| protocol == 0 && write.protocol == 0 && write.port_index == port_index);
+        match queued {
+            Some(write) => {
+                write.data.clear();
+                write.data.extend_from_slice(data);
+                diagnostics.merged += 1;
+            }
+            None => pending.push(PendingWrite {
+                port_index,
+                protocol,
+                data: data.to_vec(),
+            }),
+        }
+        self.diagnostics.set(diagnostics);
+    }
+
+    /// The queue of the writes from other threads
+    pub(crate) fn remote(&self) -> Arc<Mutex<Vec<PendingWrite>>> {
+        self.remote.clone()
+    }
+
+    pub(crate) fn take_remote(&self) -> Vec<PendingWrite> {
+        match self.remote.lock() {
+            Ok(mut remote) => std::mem::take(&mut *remote),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    pub(crate) fn take_pending(&self) -> Vec<PendingWrite> {
+        std::mem::take(&mut *self.pending.borrow_mut())
+    }
+
+    pub(crate) fn record(&self, elapsed: Duration) {
+        let mut diagnostics = self.diagnostics.get();
+        diagnostics.writes += 1;
+        diagnostics.total += elapsed;
+        diagnostics.max = diagnostics.max.max(elapsed);
+        diagnostics.last = Some(elapsed);
+        if let WriteMode::Auto { threshold } = self.mode.get() {
+            if !diagnostics.deferring && Self::too_slow(&diagnostics, threshold) {
+                ui_log!(
+                    Note,
+                    "host write function takes {:?} on average, deferring writes",
+                    diagnostics.mean().unwrap_or_default()
+                );
+                diagnostics.deferring = true;
+            }
+        }
+        self.diagnostics.set(diagnostics);
+    }
+
+    /// True if the pending writes are to be passed at the end of `idle()`
+    pub(crate) fn is_batch_due(&self) -> bool {
+        match (self.mode.get(), self.last_batch.get()) {
+            (WriteMode::Throttled { interval }, Some(last_batch)) => {
+                last_batch.elapsed() >= interval
+            }
+            _ => true,
+        }
+    }
+
+    pub(crate) fn record_batch(&self) {
+        self.last_batch.set(Some(Instant::now()));
+        let mut diagnostics = self.diagnostics.get();
+        diagnostics.batches += 1;
+        self.diagnostics.set(diagnostics);
+    }
+
+    pub(crate) fn set_metrics(&self, metrics: Option<Rc<RefCell<dyn PortMetrics>>>) {
+        *self.metrics.borrow_mut() = metrics;
+    }
+
+    pub(crate) fn has_metrics(&self) -> bool {
+        self.metrics.borrow().is_some()
+    }
+
+    /// Calls `f` with the metrics, unless there are none or they are borrowed
+    pub(crate) fn with_metrics(&self, f: impl FnOnce(&mut dyn PortMetrics)) {
+        let metrics = match self.metrics.borrow().clone() {
+            Some(metrics) => metrics,
+            None => return,
+        };
+        if let Ok(mut metrics) = metrics.try_borrow_mut() {
+            f(&mut *metrics);
+        };
+    }
+
+    pub(crate) fn record_event(&self, port_index: u32, format: u32, size: u32) {
+        self.with_metrics(|metrics| metrics.event_received(port_index, format, size));
+        let mut events = self.events.borrow_mut();
+        if events.len() == RECENT_EVENTS {
+            events.pop_front();
+        }
+        events.push_back(PortEventRecord {
+            port_index,
+            format,
+            size,
+            received: Instant::now(),
+        });
+    }
+
+    pub(crate) fn recent_events(&self) -> Vec<PortEventRecord> {
+        self.events.borrow().iter().copied().collect()
+    }
+
+    fn too_slow(diagnostics: &WriteDiagnostics, threshold: Duration) -> bool {
+        diagnostics.writes >= AUTO_MIN_WRITES
+            && diagnostics.mean().is_some_and(|mean| mean > threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::MemoryCategory;
+
+    fn diagnostics() -> Diagnostics {
+        let received = Instant::now();
+        Diagnostics {
+            captured: received + Duration::from_micros(1500),
+            controls: vec![
+                ControlSnapshot {
+                    index: 0,
+                    value: 0.5,
+                    modulated_value: Some(0.25),
+                },
+                ControlSnapshot {
+                    index: 1,
+                    value: f32::NAN,
+                    modulated_value: Some(f32::INFINITY),
+                },
+            ],
+            atom_queues: vec![AtomQueueSnapshot {
+                index: 2,
+                queued: 3,
+                dropped: 1,
+            }],
+            recent_events: vec![PortEventRecord {
+                port_index: 0,
+                format: 0,
+                size: 4,
+                received,
+            }],
+            writes: WriteDiagnostics {
+                writes: 2,
+                total: Duration::from_micros(300),
+                max: Duration::from_micros(200),
+                ..WriteDiagnostics::default()
+            },
+            memory: None,
+        }
+    }
+
+    #[test]
+    fn dump_is_json() {
+        assert_eq!(
+            diagnostics().dump(),
+            "{\"controls\":[{\"index\":0,\"value\":0.5,\"modulated_value\":0.25},\
+             {\"index\":1,\"value\":null,\"modulated_value\":null}],\
+             \"atom_queues\":[{\"index\":2,\"queued\":3,\"dropped\":1}],\
+             \"recent_events\":[{\"port_index\":0,\"format\":0,\"size\":4,\"age_ms\":1.500}],\
+             \"writes\":{\"writes\":2,\"total_us\":300,\"max_us\":200,\"mean_us\":150,\
+             \"deferred\":0,\"merged\":0,\"batches\":0,\"deferring\":false},\"memory\":null}"
+        );
+    }
+
+    #[test]
+    fn dump_of_an_empty_snapshot() {
+        let diagnostics = Diagnostics {
+            controls: Vec::new(),
+            atom_queues: Vec::new(),
+            recent_events: Vec::new(),
+            writes: WriteDiagnostics::default(),
+            ..diagnostics()
+        };
+        assert_eq!(
+            diagnostics.dump(),
+            "{\"controls\":[],\"atom_queues\":[],\"recent_events\":[],\
+             \"writes\":{\"writes\":0,\"total_us\":0,\"max_us\":0,\"mean_us\":null,\
+             \"deferred\":0,\"merged\":0,\"batches\":0,\"deferring\":false},\"memory\":null}"
+        );
+    }
+
+    #[test]
+    fn dump_includes_the_memory() {
+        let budget = MemoryBudget::new(1024).with_category_limit(MemoryCategory::History, 256);
+        let mut account = budget.account(MemoryCategory::AtomQueues);
+        account.set(100);
+        account.record_eviction();
+        let json = diagnostics().with_memory(&budget).dump();
+        assert!(json.ends_with(
+            "\"memory\":{\"limit\":1024,\"used\":100,\"categories\":[\
+             {\"category\":\"AtomQueues\",\"limit\":null,\"used\":100,\"evicted\":1},\
+             {\"category\":\"History\",\"limit\":256,\"used\":0,\"evicted\":0},\
+             {\"category\":\"Caches\",\"limit\":null,\"used\":0,\"evicted\":0}]}}"
+        ));
+
+        let json = diagnostics().with_memory(&MemoryBudget::unlimited()).dump();
+        assert!(json.contains("\"memory\":{\"limit\":null,\"used\":0,"));
+    }
+}
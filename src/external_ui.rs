@@ -0,0 +1,193 @@
+//! Support for the KXStudio external-ui extension
+//!
+//! Some hosts, like Carla or older versions of Qtractor, do not embed
+//! the plugin UI into their own window. Instead they let the UI open
+//! its own window and only tell it when to show or hide it. To
+//! support these hosts a `PluginUI` returns `true` in
+//! `is_external()` and implements `run()`, `show()` and `hide()`.
+//!
+//! The host passes the `ExternalUIHost` feature, which provides the
+//! human readable id of the plugin (useful as window title) and a
+//! callback to notify the host that the user closed the window.
+//!
+//! ```ignore
+//! fn run(&mut self) {
+//!     while let Ok(Some(event)) = self.window.poll_event() {
+//!         self.handle_event(event);
+//!     }
+//!     self.host.check_closed(&self.window, &self.close_request);
+//! }
+//! ```
+//!
+//! The callback is not called directly. Closing goes through the
+//! instance's `CloseRequest`, which calls it once after `run()`, see
+//! `close_request`.
+//!
+//! With the `x11` feature, `ExternalUIHost::open_window()` opens the
+//! top-level window titled with the plugin's human readable id.
+
+use lv2_sys as sys;
+
+use lv2_core::feature::*;
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_char;
+use urid::*;
+
+#[cfg(feature = "x11")]
+use crate::backend::x11::{X11Window, X11WindowError};
+use crate::close_request::CloseRequest;
+use crate::plugin_ui::retrieve_feature;
+#[cfg(feature = "x11")]
+use crate::plugin_ui::WindowMode;
+
+/// URI of the external UI class, to be used as `rdf:type` of the UI.
+pub const EXTERNAL_UI_WIDGET_URI: &[u8] = b"http://kxstudio.sf.net/ns/lv2ext/external-ui#Widget\0";
+
+/// The widget struct of the external-ui extension
+///
+/// A pointer to this struct is handed to the host instead of a window
+/// handle. Not to be used manually.
+#[repr(C)]
+pub struct ExternalUIWidget {
+    pub run: Option<unsafe extern "C" fn(widget: *mut ExternalUIWidget)>,
+    pub show: Option<unsafe extern "C" fn(widget: *mut ExternalUIWidget)>,
+    pub hide: Option<unsafe extern "C" fn(widget: *mut ExternalUIWidget)>,
+}
+
+#[repr(C)]
+struct ExternalUIHostRaw {
+    ui_closed: Option<unsafe extern "C" fn(controller: sys::LV2UI_Controller)>,
+    plugin_human_id: *const c_char,
+}
+
+/// The host feature of the external-ui extension
+pub struct ExternalUIHost<'a> {
+    internal: &'a ExternalUIHostRaw,
+}
+
+unsafe impl<'a> UriBound for ExternalUIHost<'a> {
+    const URI: &'static [u8] = b"http://kxstudio.sf.net/ns/lv2ext/external-ui#Host\0";
+}
+
+unsafe impl<'a> Feature for ExternalUIHost<'a> {
+    unsafe fn from_feature_ptr(feature: *const c_void, _class: ThreadingClass) -> Option<Self> {
+        (feature as *const ExternalUIHostRaw)
+            .as_ref()
+            .map(|internal| Self { internal })
+    }
+}
+
+impl<'a> ExternalUIHost<'a> {
+    /// The human readable id of the plugin instance, if the host provides one.
+    ///
+    /// Supposed to be used as title of the UI window.
+    pub fn plugin_human_id(&self) -> Option<&str> {
+        if self.internal.plugin_human_id.is_null() {
+            return None;
+        }
+        unsafe { CStr::from_ptr(self.internal.plugin_human_id) }
+            .to_str()
+            .ok()
+    }
+
+    /// The title for the UI window
+    ///
+    /// The plugin's human readable id, or `fallback` if the host
+    /// does not provide one.
+    pub fn window_title<'b>(&'b self, fallback: &'b str) -> &'b str {
+        self.plugin_human_id()
+            .filter(|id| !id.is_empty())
+            .unwrap_or(fallback)
+    }
+
+    /// Tells the host that the user closed the UI window.
+    ///
+    /// Triggers `close_request`, the instance's `CloseRequest`, which
+    /// notifies the host once after `run()`. The host will then clean
+    /// up the UI, so the UI must not write to the ports afterwards.
+    pub fn ui_closed(&self, close_request: &CloseRequest) {
+        close_request.request_close();
+    }
+}
+
+/// The host's callback telling it that the UI has been closed
+pub(crate) type UIClosedCallback = unsafe extern "C" fn(controller: sys::LV2UI_Controller);
+
+/// The `ui_closed` callback of the host feature, for a `CloseRequest`
+pub(crate) fn ui_closed_callback(
+    features: *const *const sys::LV2_Feature,
+) -> Option<UIClosedCallback> {
+    let host = retrieve_feature(features, ExternalUIHost::URI);
+    #[cfg(feature = "kxstudio")]
+    let host = match host.is_null() {
+        true => retrieve_feature(features, crate::kxstudio::LegacyExternalUIHost::URI),
+        false => host,
+    };
+    unsafe { (host as *const ExternalUIHostRaw).as_ref()?.ui_closed }
+}
+
+#[cfg(feature = "x11")]
+impl<'a> ExternalUIHost<'a> {
+    /// Opens the top-level window of the external UI
+    ///
+    /// The window is titled by `window_title()` and needs to be
+    /// shown by `show()` when the host asks for it.
+    pub fn open_window(
+        &self,
+        width: u16,
+        height: u16,
+        fallback_title: &str,
+    ) -> Result<X11Window, X11WindowError> {
+        X11Window::open(
+            WindowMode::TopLevel,
+            width,
+            height,
+            self.window_title(fallback_title),
+        )
+    }
+
+    /// Notifies the host if the user asked to close `window`
+    ///
+    /// To be called after polling the events of the window. Returns
+    /// true if the UI has been closed, by the window or otherwise.
+    pub fn check_closed(&self, window: &X11Window, close_request: &CloseRequest) -> bool {
+        if window.close_requested() {
+            self.ui_closed(close_request);
+        }
+        close_request.is_requested()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn count_ui_closed(_controller: sys::LV2UI_Controller) {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn ui_closed_goes_through_the_close_request() {
+        let raw = ExternalUIHostRaw {
+            ui_closed: Some(count_ui_closed),
+            plugin_human_id: std::ptr::null(),
+        };
+        let host = unsafe {
+            ExternalUIHost::from_feature_ptr(
+                &raw as *const ExternalUIHostRaw as *const c_void,
+                ThreadingClass::Instantiation,
+            )
+        }
+        .unwrap();
+        let close_request = CloseRequest::new();
+
+        host.ui_closed(&close_request);
+        host.ui_closed(&close_request);
+        assert!(close_request.is_requested());
+        // The instance calls the host, once, after `run()`
+        assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+    }
+}
@@ -0,0 +1,447 @@
+//! Generic fallback UI rendering a control panel from port metadata
+//!
+//! `GenericUI` shows one row per control port: a slider for ranged
+//! ports, a check box for toggled ports. It needs no drawing code
+//! at all, the plugin UI crate only describes the control ports:
+//!
+//! ```ignore
+//! #[uri("https://example.org/amp#generic-ui")]
+//! struct AmpPorts;
+//!
+//! impl GenericUIDescription for AmpPorts {
+//!     fn ports() -> Vec<ControlPortInfo> {
+//!         vec![
+//!             ControlPortInfo::new(0, "Gain", -90.0, 24.0, 0.0),
+//!             ControlPortInfo::new(3, "Enabled", 0.0, 1.0, 1.0).toggled(),
+//!         ]
+//!     }
+//! }
+//!
+//! lv2_ui_descriptors!(GenericUI<AmpPorts>);
+//! ```
+//!
+//! Dependencies between the ports are declared by `rules()`, see
+//! `crate::rules`. Rows of disabled ports are greyed out and ignore
+//! the pointer, rows of hidden ports are left empty.
+//!
+//! As the crate does not parse Turtle files, the metadata has to
+//! match the one in the plugin's TTL. The panel is drawn by the X11
+//! backend using core X11 requests, assuming a TrueColor visual.
+//!
+//! Only available with the `generic-ui` feature.
+
+use lv2_sys as sys;
+
+use std::marker::PhantomData;
+
+use urid::*;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{self, ConnectionExt as _};
+use x11rb::protocol::Event;
+
+use crate::backend::session::Session;
+use crate::backend::x11::*;
+use crate::logging::ui_log;
+use crate::plugin_ui::*;
+use crate::port::*;
+use crate::rules::PortRules;
+use crate::units::Unit;
+
+const ROW_HEIGHT: u16 = 28;
+const LABEL_WIDTH: u16 = 140;
+const CONTROL_WIDTH: u16 = 200;
+const VALUE_WIDTH: u16 = 80;
+const MARGIN: u16 = 6;
+
+const BACKGROUND: u32 = 0x20_22_26;
+const TROUGH: u32 = 0x40_44_4a;
+const FILL: u32 = 0x4a_9e_d6;
+const TEXT: u32 = 0xe0_e0_e0;
+const DISABLED_FILL: u32 = 0x5a_5e_66;
+const DISABLED_TEXT: u32 = 0x80_80_80;
+
+/// A control port shown by `GenericUI`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ControlPortInfo {
+    pub index: u32,
+    pub name: &'static str,
+    pub metadata: ControlMetadata,
+}
+
+impl ControlPortInfo {
+    pub fn new(index: u32, name: &'static str, min: f32, max: f32, default: f32) -> Self {
+        Self {
+            index,
+            name,
+            metadata: ControlMetadata::new(min, max, default),
+        }
+    }
+
+    pub fn toggled(mut self) -> Self {
+        self.metadata = self.metadata.toggled();
+        self
+    }
+
+    pub fn integer(mut self) -> Self {
+        self.metadata = self.metadata.integer();
+        self
+    }
+
+    pub fn logarithmic(mut self) -> Self {
+        self.metadata = self.metadata.logarithmic();
+        self
+    }
+
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.metadata = self.metadata.unit(unit);
+        self
+    }
+}
+
+/// Describes the plugin the `GenericUI` is for
+///
+/// The URI of the implementing type is the URI of the UI.
+pub trait GenericUIDescription: UriBound + 'static {
+    /// The control ports to be shown, from top to bottom
+    fn ports() -> Vec<ControlPortInfo>;
+
+    /// The dependencies between the ports, none by default
+    fn rules() -> PortRules {
+        PortRules::new()
+    }
+}
+
+/// The port collection of a `GenericUI`
+pub struct GenericUIPorts {
+    ports: Vec<UIControlPort>,
+}
+
+impl UIPortsTrait for GenericUIPorts {
+    fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+        self.ports.iter_mut().find(|p| p.index() == port_index)
+    }
+
+    fn map_atom_port(&mut self, _port_index: u32) -> Option<&mut UIAtomPort> {
+        None
+    }
+
+    // The ports are in the order they are shown
+    fn visit_ports(&mut self, mut f: impl FnMut(PortRef)) {
+        let mut ports: Vec<_> = self.ports.iter_mut().collect();
+        ports.sort_by_key(|port| port.index());
+        for port in ports {
+            f(PortRef::Control(port));
+        }
+    }
+}
+
+/// Plugin UI showing a control panel for the ports described by `D`
+pub struct GenericUI<D: GenericUIDescription> {
+    window: Option<X11Window>,
+    gc: xproto::Gcontext,
+    infos: Vec<ControlPortInfo>,
+    ports: GenericUIPorts,
+    rules: PortRules,
+    write_handle: PluginPortWriteHandle,
+    dragging: Option<usize>,
+    needs_redraw: bool,
+    description: PhantomData<D>,
+}
+
+unsafe impl<D: GenericUIDescription> UriBound for GenericUI<D> {
+    const URI: &'static [u8] = D::URI;
+}
+
+impl<D: GenericUIDescription> GenericUI<D> {
+    fn open_window(
+        mode: WindowMode,
+        rows: usize,
+    ) -> Result<(X11Window, xproto::Gcontext), X11WindowError> {
+        let (width, height) = Self::size_for(rows);
+        let window = X11Window::open(mode, width, height, "Generic UI")?;
+        let gc = window.connection().generate_id()?;
+        window.connection().create_gc(
+            gc,
+            window.id(),
+            &xproto::CreateGCAux::new()
+                .foreground(TEXT)
+                .background(BACKGROUND)
+                .graphics_exposures(0),
+        )?;
+        if window.is_top_level() {
+            window.show()?;
+        }
+        Ok((window, gc))
+    }
+
+    fn size_for(rows: usize) -> (u16, u16) {
+        let width = LABEL_WIDTH + CONTROL_WIDTH + VALUE_WIDTH + 4 * MARGIN;
+        let height = ROW_HEIGHT * rows.max(1) as u16 + 2 * MARGIN;
+        (width, height)
+    }
+
+    /// Returns the row under the pointer
+    fn row_at(&self, y: i16) -> Option<usize> {
+        let row = (y - MARGIN as i16).div_euclid(ROW_HEIGHT as i16);
+        if row < 0 || row as usize >= self.infos.len() {
+            return None;
+        }
+        Some(row as usize)
+    }
+
+    /// Returns the slider position of the horizontal pointer position
+    fn slider_position(x: i16) -> f32 {
+        let left = f32::from(LABEL_WIDTH + 2 * MARGIN);
+        (f32::from(x) - left) / f32::from(CONTROL_WIDTH)
+    }
+
+    fn set_from_pointer(&mut self, row: usize, position: f32) {
+        let port = &mut self.ports.ports[row];
+        port.set_normalized(position);
+        self.write_handle.write_port(port);
+        self.rules.apply(&mut self.ports);
+        self.needs_redraw = true;
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            Event::Expose(e) if e.count == 0 => self.needs_redraw = true,
+            Event::ButtonPress(e) if e.detail == 1 => {
+                let row = self.row_at(e.event_y).filter(|row| {
+                    let port = &self.ports.ports[*row];
+                    port.is_enabled() && port.is_visible()
+                });
+                if let Some(row) = row {
+                    if self.infos[row].metadata.toggled {
+                        let on = self.ports.ports[row].normalized() > 0.5;
+                        self.set_from_pointer(row, if on { 0.0 } else { 1.0 });
+                    } else {
+                        self.dragging = Some(row);
+                        self.set_from_pointer(row, Self::slider_position(e.event_x));
+                    }
+                }
+            }
+            Event::MotionNotify(e) => {
+                if let Some(row) = self.dragging {
+                    self.set_from_pointer(row, Self::slider_position(e.event_x));
+                }
+            }
+            Event::ButtonRelease(e) if e.detail == 1 => self.dragging = None,
+            _ => {}
+        }
+    }
+
+    fn draw(&self) -> Result<(), X11WindowError> {
+        let window = match &self.window {
+            Some(window) => window,
+            None => return Ok(()),
+        };
+        let connection = window.connection();
+        let (width, height) = window.size();
+
+        let fill = |color: u32, x: u16, y: u16, width: u16, height: u16| {
+            connection.change_gc(self.gc, &xproto::ChangeGCAux::new().foreground(color))?;
+            connection.poly_fill_rectangle(
+                window.id(),
+                self.gc,
+                &[xproto::Rectangle {
+                    x: x as i16,
+                    y: y as i16,
+                    width,
+                    height,
+                }],
+            )?;
+            Ok::<_, X11WindowError>(())
+        };
+
+        fill(BACKGROUND, 0, 0, width, height)?;
+        for (row, (info, port)) in self.infos.iter().zip(self.ports.ports.iter()).enumerate() {
+            if !port.is_visible() {
+                continue;
+            }
+            let (fill_color, text_color) = match port.is_enabled() {
+                true => (FILL, TEXT),
+                false => (DISABLED_FILL, DISABLED_TEXT),
+            };
+            let value = port.value();
+            let top = MARGIN + row as u16 * ROW_HEIGHT;
+            let control_left = LABEL_WIDTH + 2 * MARGIN;
+            let bar_top = top + ROW_HEIGHT / 4;
+            let bar_height = ROW_HEIGHT / 2;
+
+            if info.metadata.toggled {
+                fill(TROUGH, control_left, bar_top, bar_height, bar_height)?;
+                if value > 0.0 {
+                    fill(
+                        fill_color,
+                        control_left + 3,
+                        bar_top + 3,
+                        bar_height - 6,
+                        bar_height - 6,
+                    )?;
+                }
+            } else {
+                let filled = (port.normalized() * f32::from(CONTROL_WIDTH)) as u16;
+                fill(TROUGH, control_left, bar_top, CONTROL_WIDTH, bar_height)?;
+                fill(fill_color, control_left, bar_top, filled, bar_height)?;
+            }
+
+            connection.change_gc(self.gc, &xproto::ChangeGCAux::new().foreground(text_color))?;
+            let baseline = (top + ROW_HEIGHT / 2 + 4) as i16;
+            connection.image_text8(
+                window.id(),
+                self.gc,
+                MARGIN as i16,
+                baseline,
+                &latin1(info.name),
+            )?;
+            let value_text = port.display_value();
+            connection.image_text8(
+                window.id(),
+                self.gc,
+                (control_left + CONTROL_WIDTH + MARGIN) as i16,
+                baseline,
+                &latin1(&value_text),
+            )?;
+        }
+        connection.flush()?;
+        Ok(())
+    }
+}
+
+// ImageText8 takes at most 255 bytes of the font's encoding, which
+// is Latin-1 for the core fonts
+fn latin1(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| match c {
+            '\u{2212}' => b'-',
+            c if (c as u32) < 256 => c as u8,
+            _ => b'?',
+        })
+        .take(255)
+        .collect()
+}
+
+impl<D: GenericUIDescription> PluginUI for GenericUI<D> {
+    type UIPorts = GenericUIPorts;
+    type InitFeatures = ();
+
+    // Redrawing happens in `idle()` anyway
+    const PORT_EVENT_BATCHING: PortEventBatching = PortEventBatching::UntilIdle;
+
+    const RESIZE_INTERFACE: bool = true;
+    const OPTIONS_INTERFACE: bool = true;
+
+    fn new(
+        _plugin_ui_info: &PluginUIInfo,
+        _features: &mut Self::InitFeatures,
+        parent_window: *mut std::ffi::c_void,
+        write_handle: PluginPortWriteHandle,
+    ) -> Result<Self, PluginUIError> {
+        let infos = D::ports();
+        let (window, gc) =
+            Self::open_window(Session::detect().window_mode(parent_window), infos.len())?;
+        let mut ports = GenericUIPorts {
+            ports: infos
+                .iter()
+                .map(|info| UIControlPort::new(info.index).with_metadata(info.metadata))
+                .collect(),
+        };
+        let rules = D::rules();
+        rules.apply(&mut ports);
+        Ok(Self {
+            window: Some(window),
+            gc,
+            infos,
+            ports,
+            rules,
+            write_handle,
+            dragging: None,
+            needs_redraw: true,
+            description: PhantomData,
+        })
+    }
+
+    fn cleanup(&mut self) {
+        self.window = None;
+    }
+
+    fn ports(&mut self) -> &mut Self::UIPorts {
+        &mut self.ports
+    }
+
+    fn update(&mut self, reason: UpdateReason) {
+        if reason == UpdateReason::Resize {
+            self.needs_redraw = true;
+            return;
+        }
+        for port in self.ports.ports.iter_mut() {
+            if port.changed_value().is_some() {
+                self.needs_redraw = true;
+            }
+        }
+        if self.rules.apply(&mut self.ports) {
+            self.needs_redraw = true;
+        }
+    }
+
+    fn idle(&mut self) -> i32 {
+        loop {
+            let event = match self.window.as_mut().map(X11Window::poll_event) {
+                Some(Ok(Some(event))) => event,
+                Some(Ok(None)) => break,
+                Some(Err(e)) => {
+                    ui_log!(Error, "generic UI lost its X11 connection: {:?}", e);
+                    return 1;
+                }
+                None => return 1,
+            };
+            self.handle_event(event);
+        }
+        if let Some(new_parent) = self.window.as_mut().and_then(X11Window::take_parent_change) {
+            self.parent_changed(new_parent);
+        }
+        if let Some((width, height)) = self.window.as_mut().and_then(X11Window::take_resize) {
+            self.resized(u32::from(width), u32::from(height));
+        }
+        if let Some(scale_factor) = self.window.as_mut().and_then(X11Window::take_scale_change) {
+            self.scale_factor_changed(scale_factor);
+        }
+        if self.window.as_ref().is_some_and(X11Window::close_requested) {
+            return 1;
+        }
+        if self.needs_redraw {
+            self.needs_redraw = false;
+            if let Err(e) = self.draw() {
+                ui_log!(Error, "failed to draw generic UI: {:?}", e);
+            }
+        }
+        0
+    }
+
+    fn parent_changed(&mut self, _new_parent: *mut std::ffi::c_void) {
+        self.needs_redraw = true;
+    }
+
+    fn size_hint(&self) -> (u32, u32) {
+        let (width, height) = Self::size_for(self.infos.len());
+        (u32::from(width), u32::from(height))
+    }
+
+    fn resized(&mut self, width: u32, height: u32) {
+        let length = |length: u32| length.min(u32::from(u16::MAX)) as u16;
+        let size = (length(width), length(height));
+        if let Some(window) = self.window.as_ref().filter(|window| window.size() != size) {
+            if let Err(e) = window.set_size(size.0, size.1) {
+                ui_log!(Error, "failed to resize generic UI: {:?}", e);
+            }
+        }
+        self.update(UpdateReason::Resize);
+    }
+
+    fn widget(&self) -> sys::LV2UI_Widget {
+        self.window
+            .as_ref()
+            .map_or(std::ptr::null_mut(), X11Window::widget)
+    }
+}
@@ -0,0 +1,148 @@
+//! Instrumentation of the host's calls by `log` or `tracing`
+//!
+//! UIs that stutter in one host but not in another are hard to
+//! debug inside the DAW. With the `log` or the `tracing` feature the
+//! instance reports the calls of the host as events of the target
+//! `lv2_ui::instrument`, with the time they took:
+//!
+//! * `instantiate` at debug level, with the `ui` URI and `success`
+//! * `port_event` at trace level, with `port`, `format` and `size`
+//! * `idle` at trace level, with the `result` of the UI
+//! * `write` at trace level, for every write passed to the host,
+//!   with `port`, `protocol` and `size`
+//!
+//! Each has the duration as `duration_us`. `tracing` gets them as
+//! fields, `log` as `key=value` pairs in the message. A subscriber
+//! writing to a file is set up once, e.g. in `PluginUI::new()`:
+//!
+//! ```ignore
+//! let file = std::fs::File::create("/tmp/amp-ui.trace")?;
+//! let _ = tracing_subscriber::fmt()
+//!     .with_writer(std::sync::Mutex::new(file))
+//!     .with_env_filter("lv2_ui::instrument=trace")
+//!     .try_init();
+//! ```
+//!
+//! Without the features nothing is measured.
+
+use std::ffi::CStr;
+use std::time::Duration;
+#[cfg(any(feature = "log", feature = "tracing"))]
+use std::time::Instant;
+
+/// The target of the events
+pub const TARGET: &str = "lv2_ui::instrument";
+
+/// Measures a call, only if there is someone to report to
+pub(crate) struct Timer {
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    start: Instant,
+}
+
+impl Timer {
+    #[inline]
+    pub(crate) fn start() -> Self {
+        Self {
+            #[cfg(any(feature = "log", feature = "tracing"))]
+            start: Instant::now(),
+        }
+    }
+
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    fn micros(&self) -> u64 {
+        micros(self.start.elapsed())
+    }
+}
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+fn micros(duration: Duration) -> u64 {
+    duration.as_micros().min(u128::from(u64::MAX)) as u64
+}
+
+#[inline]
+#[allow(unused_variables)]
+pub(crate) fn instantiated(ui_uri: Option<&CStr>, success: bool, timer: Timer) {
+    #[cfg(any(feature = "log", feature = "tracing"))]
+    let ui_uri = ui_uri.map(CStr::to_string_lossy).unwrap_or_default();
+    #[cfg(feature = "log")]
+    log::debug!(
+        target: TARGET,
+        "instantiate ui={} success={} duration_us={}",
+        ui_uri,
+        success,
+        timer.micros()
+    );
+    #[cfg(feature = "tracing")]
+    tracing::debug!(
+        target: TARGET,
+        ui = %ui_uri,
+        success,
+        duration_us = timer.micros(),
+        "instantiate"
+    );
+}
+
+#[inline]
+#[allow(unused_variables)]
+pub(crate) fn port_event(port_index: u32, format: u32, buffer_size: u32, timer: Timer) {
+    #[cfg(feature = "log")]
+    log::trace!(
+        target: TARGET,
+        "port_event port={} format={} size={} duration_us={}",
+        port_index,
+        format,
+        buffer_size,
+        timer.micros()
+    );
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        target: TARGET,
+        port = port_index,
+        format,
+        size = buffer_size,
+        duration_us = timer.micros(),
+        "port_event"
+    );
+}
+
+#[inline]
+#[allow(unused_variables)]
+pub(crate) fn idle(result: i32, timer: Timer) {
+    #[cfg(feature = "log")]
+    log::trace!(
+        target: TARGET,
+        "idle result={} duration_us={}",
+        result,
+        timer.micros()
+    );
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        target: TARGET,
+        result,
+        duration_us = timer.micros(),
+        "idle"
+    );
+}
+
+#[inline]
+#[allow(unused_variables)]
+pub(crate) fn write(port_index: u32, protocol: u32, size: usize, duration: Duration) {
+    #[cfg(feature = "log")]
+    log::trace!(
+        target: TARGET,
+        "write port={} protocol={} size={} duration_us={}",
+        port_index,
+        protocol,
+        size,
+        micros(duration)
+    );
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        target: TARGET,
+        port = port_index,
+        protocol,
+        size,
+        duration_us = micros(duration),
+        "write"
+    );
+}
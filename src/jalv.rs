@@ -0,0 +1,342 @@
+//! Integration test harness running plugin UIs under jalv
+//!
+//! `MockUIHost` calls the UI the way this crate expects hosts to do.
+//! To check that assumption against a real host, the harness writes
+//! a bundle for a tiny demo plugin and its UI, runs it under jalv as
+//! a subprocess and records the callbacks the UI received:
+//!
+//! ```ignore
+//! #[test]
+//! fn jalv_sequencing() {
+//!     let dir = std::env::temp_dir().join("amp.lv2");
+//!     DemoBundle::new(&dir, "https://example.org/amp", Path::new("target/debug/libamp.so"))
+//!         .ui("https://example.org/amp#ui", Path::new("target/debug/libamp_ui.so"))
+//!         .control_input("gain", -90.0, 24.0, 0.0)
+//!         .write()
+//!         .unwrap();
+//!     let trace = Jalv::new(&dir, "https://example.org/amp")
+//!         .program("jalv.gtk3")
+//!         .run()
+//!         .unwrap();
+//!     trace.check_sequence().unwrap();
+//!     assert!(trace.count("port_event") > 0);
+//! }
+//! ```
+//!
+//! The UI binary needs to be built with the `jalv` feature, which
+//! makes `PluginUIInstance` append every callback to the file named
+//! by the environment variable `LV2_UI_CALL_TRACE`. As jalv has to be
+//! installed and needs a display, the harness is opt-in.
+//!
+//! The crate checks itself this way with the demo of
+//! `examples/jalv_demo.rs`, which has a windowless UI providing
+//! `ui:showInterface`, so the console jalv runs it with `-s`. The
+//! test is ignored by default:
+//!
+//! ```text
+//! cargo test --features jalv --test jalv -- --ignored
+//! ```
+//!
+//! Only available with the `jalv` feature.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::logging::ui_log;
+use crate::ui_class::UIClass;
+
+/// The environment variable naming the file the callbacks are recorded to
+pub const CALL_TRACE_ENV: &str = "LV2_UI_CALL_TRACE";
+
+/// Appends `call` to the call trace, if one is requested
+pub(crate) fn trace(call: &str) {
+    let path = match std::env::var_os(CALL_TRACE_ENV) {
+        Some(path) => path,
+        None => return,
+    };
+    let written = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", call));
+    if let Err(e) = written {
+        ui_log!(Error, "failed to write call trace: {}", e);
+    }
+}
+
+#[derive(Debug)]
+pub enum JalvError {
+    Io(io::Error),
+    /// jalv could not be started, probably it is not installed
+    Spawn(io::Error),
+    /// jalv exited before the run time was over
+    Exited(std::process::ExitStatus),
+}
+
+impl From<io::Error> for JalvError {
+    fn from(e: io::Error) -> Self {
+        JalvError::Io(e)
+    }
+}
+
+/// A violation of the expected callback sequence
+#[derive(Debug, PartialEq)]
+pub enum SequenceError {
+    /// The UI has not been instantiated
+    NotInstantiated,
+    /// The first callback was not the instantiation
+    CallBeforeInstantiate(String),
+    /// The UI has not been cleaned up
+    NotCleanedUp,
+    /// A callback arrived after the cleanup
+    CallAfterCleanup(String),
+}
+
+/// The callbacks a UI received, in the order they arrived
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CallTrace {
+    calls: Vec<String>,
+}
+
+impl CallTrace {
+    /// Reads a trace written by a UI built with the `jalv` feature
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let calls = match fs::read_to_string(path) {
+            Ok(text) => text.lines().map(String::from).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { calls })
+    }
+
+    pub fn calls(&self) -> &[String] {
+        &self.calls
+    }
+
+    /// How often the callback `call` has been received
+    pub fn count(&self, call: &str) -> usize {
+        self.calls.iter().filter(|c| *c == call).count()
+    }
+
+    /// Checks that the UI was instantiated first and cleaned up last
+    pub fn check_sequence(&self) -> Result<(), SequenceError> {
+        let first = self.calls.first().ok_or(SequenceError::NotInstantiated)?;
+        if first != "instantiate" {
+            return Err(SequenceError::CallBeforeInstantiate(first.clone()));
+        }
+        let cleanup = self
+            .calls
+            .iter()
+            .position(|c| c == "cleanup")
+            .ok_or(SequenceError::NotCleanedUp)?;
+        match self.calls.get(cleanup + 1) {
+            Some(call) => Err(SequenceError::CallAfterCleanup(call.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// A bundle of a demo plugin with control input ports and its UI
+///
+/// The plugin and UI binaries are built beforehand, e.g. by cargo.
+/// `write()` copies them into the bundle directory and writes the
+/// `manifest.ttl` describing them. The control ports get the port
+/// indices in the order they are added.
+pub struct DemoBundle {
+    dir: PathBuf,
+    plugin_uri: String,
+    plugin_binary: PathBuf,
+    ui: Option<(String, PathBuf)>,
+    ui_class: &'static str,
+    show_interface: bool,
+    control_inputs: Vec<(String, f32, f32, f32)>,
+}
+
+impl DemoBundle {
+    pub fn new(dir: &Path, plugin_uri: &str, plugin_binary: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            plugin_uri: plugin_uri.to_string(),
+            plugin_binary: plugin_binary.to_path_buf(),
+            ui: None,
+            ui_class: UIClass::native().name(),
+            show_interface: false,
+            control_inputs: Vec::new(),
+        }
+    }
+
+    /// Adds the UI to the bundle
+    pub fn ui(mut self, ui_uri: &str, ui_binary: &Path) -> Self {
+        self.ui = Some((ui_uri.to_string(), ui_binary.to_path_buf()));
+        self
+    }
+
+    /// Sets the class of the UI in the `ui:` namespace
+    ///
+    /// By default the native class of the compile target, see
+    /// `UIClass::native()`.
+    pub fn ui_class(mut self, ui_class: &'static str) -> Self {
+        self.ui_class = ui_class;
+        self
+    }
+
+    /// Declares that the UI provides `ui:showInterface`
+    ///
+    /// The console jalv only shows UIs with this interface, when run
+    /// with `-s`. The UI needs to set `PluginUI::SHOW_INTERFACE`.
+    pub fn show_interface(mut self) -> Self {
+        self.show_interface = true;
+        self
+    }
+
+    /// Adds a control input port
+    pub fn control_input(mut self, symbol: &str, min: f32, max: f32, default: f32) -> Self {
+        self.control_inputs
+            .push((symbol.to_string(), min, max, default));
+        self
+    }
+
+    /// Writes the bundle
+    pub fn write(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let plugin_binary = self.copy_binary(&self.plugin_binary)?;
+
+        let mut ttl = String::from(
+            "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+             @prefix ui: <http://lv2plug.in/ns/extensions/ui#> .\n\n",
+        );
+        ttl += &format!(
+            "<{}>\n    a lv2:Plugin ;\n    lv2:binary <{}> ;\n    lv2:name \"lv2-ui demo\"",
+            self.plugin_uri, plugin_binary
+        );
+        if let Some((ui_uri, _)) = &self.ui {
+            ttl += &format!(" ;\n    ui:ui <{}>", ui_uri);
+        }
+        for (index, (symbol, min, max, default)) in self.control_inputs.iter().enumerate() {
+            ttl += &format!(
+                " ;\n    lv2:port [\n        a lv2:InputPort, lv2:ControlPort ;\n        \
+                 lv2:index {} ;\n        lv2:symbol \"{}\" ;\n        lv2:name \"{}\" ;\n        \
+                 lv2:minimum {:?} ;\n        lv2:maximum {:?} ;\n        lv2:default {:?}\n    ]",
+                index, symbol, symbol, min, max, default
+            );
+        }
+        ttl += " .\n";
+
+        if let Some((ui_uri, ui_binary)) = &self.ui {
+            let ui_binary = self.copy_binary(ui_binary)?;
+            let extension_data = if self.show_interface {
+                "ui:idleInterface, ui:showInterface"
+            } else {
+                "ui:idleInterface"
+            };
+            ttl += &format!(
+                "\n<{}>\n    a ui:{} ;\n    ui:binary <{}> ;\n    \
+                 lv2:optionalFeature ui:parent, ui:idleInterface ;\n    \
+                 lv2:extensionData {} .\n",
+                ui_uri, self.ui_class, ui_binary, extension_data
+            );
+        }
+
+        fs::write(self.dir.join("manifest.ttl"), ttl)
+    }
+
+    /// Copies `binary` into the bundle and returns its file name
+    fn copy_binary(&self, binary: &Path) -> io::Result<String> {
+        let name = binary.file_name().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "binary has no file name")
+        })?;
+        fs::copy(binary, self.dir.join(name))?;
+        Ok(name.to_string_lossy().into_owned())
+    }
+}
+
+/// Runs a plugin with its UI under jalv
+pub struct Jalv {
+    bundle_dir: PathBuf,
+    plugin_uri: String,
+    program: String,
+    run_time: Duration,
+    args: Vec<String>,
+}
+
+impl Jalv {
+    pub fn new(bundle_dir: &Path, plugin_uri: &str) -> Self {
+        Self {
+            bundle_dir: bundle_dir.to_path_buf(),
+            plugin_uri: plugin_uri.to_string(),
+            program: String::from("jalv.gtk3"),
+            run_time: Duration::from_secs(2),
+            args: Vec::new(),
+        }
+    }
+
+    /// The jalv executable, `jalv.gtk3` by default
+    pub fn program(mut self, program: &str) -> Self {
+        self.program = program.to_string();
+        self
+    }
+
+    /// How long jalv runs before it is terminated, 2 seconds by default
+    pub fn run_time(mut self, run_time: Duration) -> Self {
+        self.run_time = run_time;
+        self
+    }
+
+    /// Passes an additional command line argument to jalv
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Runs jalv and returns the callbacks the UI received
+    ///
+    /// jalv is terminated by SIGTERM after the run time, so that it
+    /// closes the UI as on a regular shutdown.
+    pub fn run(&self) -> Result<CallTrace, JalvError> {
+        let trace_path = std::env::temp_dir().join(format!(
+            "lv2-ui-call-trace-{}-{:?}",
+            std::process::id(),
+            Instant::now()
+        ));
+        let _ = fs::remove_file(&trace_path);
+
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .arg(&self.plugin_uri)
+            .env("LV2_PATH", &self.bundle_dir)
+            .env(CALL_TRACE_ENV, &trace_path)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(JalvError::Spawn)?;
+
+        let started = Instant::now();
+        while started.elapsed() < self.run_time {
+            if let Some(status) = child.try_wait()? {
+                return Err(JalvError::Exited(status));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        Command::new("kill")
+            .arg("-TERM")
+            .arg(child.id().to_string())
+            .status()?;
+        let terminated = Instant::now();
+        while child.try_wait()?.is_none() {
+            if terminated.elapsed() > Duration::from_secs(5) {
+                ui_log!(Warning, "jalv did not terminate, killing it");
+                child.kill()?;
+                child.wait()?;
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        let trace = CallTrace::read(&trace_path)?;
+        let _ = fs::remove_file(&trace_path);
+        Ok(trace)
+    }
+}
@@ -0,0 +1,113 @@
+//! Non-standard extensions of the KXStudio ecosystem
+//!
+//! Hosts like Carla support extensions which never made it into LV2
+//! itself. Only available with the `kxstudio` feature.
+//!
+//! * `LegacyExternalUIHost` is the host feature of the external-ui
+//!   extension under its original URI, which older hosts still pass
+//!   instead of `ExternalUIHost`. Request both as `Option` in the
+//!   `InitFeatures` and use the one present, see `external_host()`.
+//! * `ProgramsHost` and `PluginUI::select_program()` implement the
+//!   programs extension, with which host and UI tell each other about
+//!   the selected program of the plugin. Set `PluginUI::PROGRAMS` to
+//!   true to provide the UI interface.
+
+use std::ffi::c_void;
+use std::ops::Deref;
+
+use lv2_core::feature::*;
+use urid::*;
+
+use crate::external_ui::ExternalUIHost;
+
+/// The external-ui widget class under its original URI, for the `rdf:type` of the UI
+pub const LEGACY_EXTERNAL_UI_WIDGET_URI: &[u8] = b"http://lv2plug.in/ns/extensions/ui#external\0";
+
+/// URI of the UI interface of the programs extension
+pub const PROGRAMS_UI_INTERFACE_URI: &[u8] =
+    b"http://kxstudio.sf.net/ns/lv2ext/programs#UIInterface\0";
+
+/// The external-ui host feature under its original URI
+///
+/// Dereferences to `ExternalUIHost`, the interface is the same.
+pub struct LegacyExternalUIHost<'a>(ExternalUIHost<'a>);
+
+unsafe impl<'a> UriBound for LegacyExternalUIHost<'a> {
+    const URI: &'static [u8] = b"http://lv2plug.in/ns/extensions/ui#external\0";
+}
+
+unsafe impl<'a> Feature for LegacyExternalUIHost<'a> {
+    unsafe fn from_feature_ptr(feature: *const c_void, class: ThreadingClass) -> Option<Self> {
+        ExternalUIHost::from_feature_ptr(feature, class).map(Self)
+    }
+}
+
+impl<'a> Deref for LegacyExternalUIHost<'a> {
+    type Target = ExternalUIHost<'a>;
+
+    fn deref(&self) -> &ExternalUIHost<'a> {
+        &self.0
+    }
+}
+
+impl<'a> LegacyExternalUIHost<'a> {
+    pub fn into_inner(self) -> ExternalUIHost<'a> {
+        self.0
+    }
+}
+
+/// The external-ui host feature the host has passed, under either URI
+pub fn external_host<'a>(
+    current: Option<ExternalUIHost<'a>>,
+    legacy: Option<LegacyExternalUIHost<'a>>,
+) -> Option<ExternalUIHost<'a>> {
+    current.or_else(|| legacy.map(LegacyExternalUIHost::into_inner))
+}
+
+#[repr(C)]
+struct ProgramsHostRaw {
+    handle: *mut c_void,
+    program_changed: Option<unsafe extern "C" fn(handle: *mut c_void, index: i32)>,
+}
+
+/// The UI interface of the programs extension, not to be used manually
+#[repr(C)]
+pub struct ProgramsUIInterface {
+    pub select_program:
+        Option<unsafe extern "C" fn(handle: lv2_sys::LV2UI_Handle, bank: u32, program: u32)>,
+}
+
+/// The host feature of the programs extension
+pub struct ProgramsHost<'a> {
+    internal: &'a ProgramsHostRaw,
+}
+
+unsafe impl<'a> UriBound for ProgramsHost<'a> {
+    const URI: &'static [u8] = b"http://kxstudio.sf.net/ns/lv2ext/programs#Host\0";
+}
+
+unsafe impl<'a> Feature for ProgramsHost<'a> {
+    unsafe fn from_feature_ptr(feature: *const c_void, _class: ThreadingClass) -> Option<Self> {
+        (feature as *const ProgramsHostRaw)
+            .as_ref()
+            .map(|internal| Self { internal })
+    }
+}
+
+impl<'a> ProgramsHost<'a> {
+    /// Tells the host that the program at `index` has changed, e.g. its name
+    pub fn program_changed(&self, index: u32) {
+        self.notify(index as i32);
+    }
+
+    /// Tells the host that the list of programs has changed
+    pub fn programs_changed(&self) {
+        self.notify(-1);
+    }
+
+    fn notify(&self, index: i32) {
+        if let Some(program_changed) = self.internal.program_changed {
+            unsafe { program_changed(self.internal.handle, index) }
+        }
+    }
+}
@@ -1,16 +1,68 @@
 extern crate lv2_atom;
 extern crate lv2_core;
-extern crate lv2_sys;
+#[doc(hidden)]
+pub extern crate lv2_sys as sys;
 extern crate urid;
 
+#[cfg(feature = "fft")]
+pub mod analysis;
+pub mod animation;
+pub mod backend;
+pub mod cache;
+pub mod capabilities;
+pub mod close_request;
+pub mod description;
+#[cfg(feature = "dev-runner")]
+pub mod dev_runner;
+pub mod diagnostics;
+pub mod external_ui;
+#[cfg(feature = "generic-ui")]
+pub mod generic_ui;
+pub mod instrument;
+#[cfg(feature = "jalv")]
+pub mod jalv;
+#[cfg(feature = "kxstudio")]
+pub mod kxstudio;
+pub mod logging;
+pub mod mapping;
+pub mod memory;
+pub mod meter;
+pub mod metrics;
+pub mod midi;
+pub mod midi_learn;
+pub mod notification;
+pub mod object;
+pub mod options;
+#[cfg(feature = "osc")]
+pub mod osc;
+pub mod patch;
+pub mod path_param;
 pub mod plugin_ui;
 pub mod port;
+pub mod port_collection;
+pub mod port_map;
+pub mod request_value;
+pub mod rules;
+pub mod scope;
+pub mod shared;
 mod space;
+pub mod stepping;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod touch;
+pub mod transport;
+#[cfg(feature = "ttl")]
+pub mod ttl;
+pub mod ui_class;
+pub mod units;
 pub mod uris;
+pub mod worker;
 
 pub mod prelude {
     use crate::*;
+    pub use external_ui::ExternalUIHost;
     pub use plugin_ui::*;
     pub use port::*;
+    pub use urid::uri;
     pub use uris::*;
 }
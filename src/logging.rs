@@ -0,0 +1,215 @@
+//! Where the messages of the framework go
+//!
+//! Warnings like a port event for an unknown port or a failed port
+//! write are passed to a `UiLogSink`. Each UI instance has its own
+//! sink. By default it is the host's `log:log` feature if the host
+//! provides its `printf` together with `urid:map`, otherwise stderr.
+//!
+//! The sink is changed by `set_sink()` from within any callback of
+//! the UI, usually in `PluginUI::new()`. It then applies to the
+//! instance being called:
+//!
+//! ```ignore
+//! fn new(...) -> Result<Self, PluginUIError> {
+//!     logging::set_sink(|level: LogLevel, message: &str| {
+//!         my_log_window.push(level, message.to_owned())
+//!     });
+//!     ...
+//! }
+//! ```
+//!
+//! With the `log` feature `LogCrateSink` passes the messages to the
+//! `log` crate. Messages outside the callbacks of a UI instance, e.g.
+//! from threads of the UI, go to stderr.
+
+use lv2_sys as sys;
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::rc::Rc;
+
+/// The severity of a message, like the `log:` entry classes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    Error,
+    Warning,
+    Note,
+    Trace,
+}
+
+/// A destination of log messages
+pub trait UiLogSink {
+    fn log(&self, level: LogLevel, message: &str);
+}
+
+impl<F: Fn(LogLevel, &str)> UiLogSink for F {
+    fn log(&self, level: LogLevel, message: &str) {
+        self(level, message)
+    }
+}
+
+/// Writes the messages to stderr
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StderrSink;
+
+impl UiLogSink for StderrSink {
+    fn log(&self, _level: LogLevel, message: &str) {
+        eprintln!("{}", message);
+    }
+}
+
+/// Passes the messages to the host's `log:log` feature
+pub struct HostLogSink {
+    log: sys::LV2_Log_Log,
+    // The URIDs of `log:Error`, `log:Warning`, `log:Note` and `log:Trace`
+    entry_types: [u32; 4],
+}
+
+impl HostLogSink {
+    /// Returns None if the features lack `log:log` or `urid:map`
+    ///
+    /// A `va_list` cannot be made in stable Rust, so `vprintf` is of no
+    /// use and a host without `printf` gets no messages.
+    pub(crate) fn from_features(features: *const *const sys::LV2_Feature) -> Option<Self> {
+        let log = crate::plugin_ui::retrieve_feature(features, sys::LV2_LOG__log)
+            as *const sys::LV2_Log_Log;
+        let map = crate::plugin_ui::retrieve_feature(features, sys::LV2_URID__map)
+            as *const sys::LV2_URID_Map;
+        let (log, map) = unsafe { (*log.as_ref()?, map.as_ref()?) };
+        let map_function = map.map?;
+        log.printf?;
+        // Zero means the URI could not be mapped
+        let map_uri =
+            |uri: &[u8]| match unsafe { map_function(map.handle, uri.as_ptr() as *const c_char) } {
+                0 => None,
+                urid => Some(urid),
+            };
+        Some(Self {
+            log,
+            entry_types: [
+                map_uri(sys::LV2_LOG__Error)?,
+                map_uri(sys::LV2_LOG__Warning)?,
+                map_uri(sys::LV2_LOG__Note)?,
+                map_uri(sys::LV2_LOG__Trace)?,
+            ],
+        })
+    }
+}
+
+impl UiLogSink for HostLogSink {
+    fn log(&self, level: LogLevel, message: &str) {
+        // The message is passed as format string, which needs no
+        // variadic arguments
+        let format = format!("{}\n", message.replace('%', "%%").replace('\0', ""));
+        let format = CString::new(format).unwrap_or_default();
+        let entry_type = self.entry_types[level as usize];
+        if let Some(printf) = self.log.printf {
+            unsafe { printf(self.log.handle, entry_type, format.as_ptr()) };
+        }
+    }
+}
+
+/// Passes the messages to the `log` crate, with the target `lv2_ui`
+#[cfg(feature = "log")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogCrateSink;
+
+#[cfg(feature = "log")]
+impl UiLogSink for LogCrateSink {
+    fn log(&self, level: LogLevel, message: &str) {
+        let level = match level {
+            LogLevel::Error => log::Level::Error,
+            LogLevel::Warning => log::Level::Warn,
+            LogLevel::Note => log::Level::Info,
+            LogLevel::Trace => log::Level::Trace,
+        };
+        log::log!(target: "lv2_ui", level, "{}", message);
+    }
+}
+
+/// The sink of a UI instance, replaced by `set_sink()`
+pub(crate) type SinkSlot = Rc<RefCell<Rc<dyn UiLogSink>>>;
+
+thread_local! {
+    static CURRENT: RefCell<Option<SinkSlot>> = const { RefCell::new(None) };
+    // Replaces stderr for the instances created by the mock host
+    #[cfg(feature = "testing")]
+    static FALLBACK: RefCell<Option<Rc<dyn UiLogSink>>> = const { RefCell::new(None) };
+}
+
+/// The sink a new UI instance starts with
+pub(crate) fn default_sink(features: *const *const sys::LV2_Feature) -> SinkSlot {
+    let sink: Rc<dyn UiLogSink> = match HostLogSink::from_features(features) {
+        Some(sink) => Rc::new(sink),
+        None => fallback_sink(),
+    };
+    Rc::new(RefCell::new(sink))
+}
+
+#[cfg(not(feature = "testing"))]
+fn fallback_sink() -> Rc<dyn UiLogSink> {
+    Rc::new(StderrSink)
+}
+
+#[cfg(feature = "testing")]
+fn fallback_sink() -> Rc<dyn UiLogSink> {
+    FALLBACK
+        .with(|fallback| fallback.borrow().clone())
+        .unwrap_or_else(|| Rc::new(StderrSink))
+}
+
+/// Runs `f` with `sink` instead of stderr for hosts without `log:log`
+#[cfg(feature = "testing")]
+pub(crate) fn with_fallback_sink<R>(sink: Rc<dyn UiLogSink>, f: impl FnOnce() -> R) -> R {
+    let previous = FALLBACK.with(|fallback| fallback.replace(Some(sink)));
+    let r = f();
+    FALLBACK.with(|fallback| *fallback.borrow_mut() = previous);
+    r
+}
+
+/// Runs `f` with `slot` as the sink of the current instance
+pub(crate) fn with_sink<R>(slot: &SinkSlot, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<SinkSlot>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(CURRENT.with(|current| current.replace(Some(slot.clone()))));
+    f()
+}
+
+/// Sets the sink of the UI instance whose callback is running
+///
+/// Returns false if called outside the callbacks of a UI instance.
+pub fn set_sink(sink: impl UiLogSink + 'static) -> bool {
+    CURRENT.with(|current| match current.borrow().as_ref() {
+        Some(slot) => {
+            *slot.borrow_mut() = Rc::new(sink);
+            true
+        }
+        None => false,
+    })
+}
+
+/// Passes `message` to the sink of the current UI instance
+pub fn log(level: LogLevel, message: &str) {
+    let sink = CURRENT.with(|current| current.borrow().as_ref().map(|slot| slot.borrow().clone()));
+    match sink {
+        Some(sink) => sink.log(level, message),
+        None => StderrSink.log(level, message),
+    }
+}
+
+/// Formats a message and passes it to `log()`
+macro_rules! ui_log {
+    ($level:ident, $($arg:tt)*) => {
+        $crate::logging::log($crate::logging::LogLevel::$level, &format!($($arg)*))
+    };
+}
+
+pub(crate) use ui_log;
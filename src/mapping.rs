@@ -0,0 +1,273 @@
+//! Mappings between knob positions and port values
+//!
+//! Widgets like knobs and faders work with a position in 0.0..=1.0,
+//! while the port has its own range and often a non-linear feel,
+//! e.g. a frequency knob is logarithmic and a gain fader in dB gets
+//! finer towards 0 dB. The mappings here convert between both, so
+//! that UIs built with different toolkits behave the same:
+//!
+//! ```ignore
+//! let map = DbMap::new(-90.0, 6.0);
+//! port.set_value(map.denormalize(knob_position));
+//! write_handle.write_port(&port);
+//! ```
+//!
+//! All mappings clamp the position to 0.0..=1.0 and the value to
+//! the range.
+
+/// Converts between a position in 0.0..=1.0 and a port value
+pub trait ValueMap {
+    /// Maps `value` to a position in 0.0..=1.0
+    fn normalize(&self, value: f32) -> f32;
+
+    /// Maps a position in 0.0..=1.0 to a value, the inverse of `normalize()`
+    fn denormalize(&self, normalized: f32) -> f32;
+}
+
+/// Linear mapping from `min` to `max`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinMap {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl LinMap {
+    pub fn new(min: f32, max: f32) -> Self {
+        Self { min, max }
+    }
+}
+
+impl ValueMap for LinMap {
+    fn normalize(&self, value: f32) -> f32 {
+        if self.max <= self.min {
+            return 0.0;
+        }
+        ((value - self.min) / (self.max - self.min)).clamp(0.0, 1.0)
+    }
+
+    fn denormalize(&self, normalized: f32) -> f32 {
+        self.min + (self.max - self.min) * normalized.clamp(0.0, 1.0)
+    }
+}
+
+/// Logarithmic mapping for positive ranges, e.g. frequencies
+///
+/// Every octave gets the same share of the knob. Falls back to a
+/// linear mapping if the range is not positive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LogMap {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl LogMap {
+    pub fn new(min: f32, max: f32) -> Self {
+        Self { min, max }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.min > 0.0 && self.max > self.min
+    }
+}
+
+impl ValueMap for LogMap {
+    fn normalize(&self, value: f32) -> f32 {
+        if !self.is_valid() {
+            return LinMap::new(self.min, self.max).normalize(value);
+        }
+        let value = value.clamp(self.min, self.max);
+        ((value / self.min).ln() / (self.max / self.min).ln()).clamp(0.0, 1.0)
+    }
+
+    fn denormalize(&self, normalized: f32) -> f32 {
+        if !self.is_valid() {
+            return LinMap::new(self.min, self.max).denormalize(normalized);
+        }
+        let value = self.min * (self.max / self.min).powf(normalized.clamp(0.0, 1.0));
+        value.clamp(self.min, self.max)
+    }
+}
+
+/// Power curve mapping, `value = min + (max - min) * position^exponent`
+///
+/// Exponents above 1 give finer control at the lower end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowMap {
+    pub min: f32,
+    pub max: f32,
+    pub exponent: f32,
+}
+
+impl PowMap {
+    pub fn new(min: f32, max: f32, exponent: f32) -> Self {
+        Self { min, max, exponent }
+    }
+}
+
+impl ValueMap for PowMap {
+    fn normalize(&self, value: f32) -> f32 {
+        let linear = LinMap::new(self.min, self.max).normalize(value);
+        if self.exponent <= 0.0 {
+            return linear;
+        }
+        linear.powf(1.0 / self.exponent)
+    }
+
+    fn denormalize(&self, normalized: f32) -> f32 {
+        let normalized = normalized.clamp(0.0, 1.0);
+        let curved = if self.exponent > 0.0 {
+            normalized.powf(self.exponent)
+        } else {
+            normalized
+        };
+        LinMap::new(self.min, self.max).denormalize(curved)
+    }
+}
+
+/// Mapping for gains in dB, like the faders of a mixing desk
+///
+/// The position follows the amplitude with a fourth root curve, so
+/// the upper part of the fader covers the range around 0 dB and
+/// `min_db` is at the bottom.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DbMap {
+    pub min_db: f32,
+    pub max_db: f32,
+}
+
+const DB_MAP_EXPONENT: f32 = 4.0;
+
+impl DbMap {
+    pub fn new(min_db: f32, max_db: f32) -> Self {
+        Self { min_db, max_db }
+    }
+
+    // The curved amplitude relative to `max_db`, 1.0 at `max_db`
+    fn curve(&self, db: f32) -> f32 {
+        db_to_amplitude(db - self.max_db).powf(1.0 / DB_MAP_EXPONENT)
+    }
+}
+
+impl ValueMap for DbMap {
+    fn normalize(&self, value: f32) -> f32 {
+        if self.max_db <= self.min_db {
+            return 0.0;
+        }
+        let bottom = self.curve(self.min_db);
+        let db = value.clamp(self.min_db, self.max_db);
+        ((self.curve(db) - bottom) / (1.0 - bottom)).clamp(0.0, 1.0)
+    }
+
+    fn denormalize(&self, normalized: f32) -> f32 {
+        if self.max_db <= self.min_db {
+            return self.min_db;
+        }
+        let bottom = self.curve(self.min_db);
+        let curve = bottom + (1.0 - bottom) * normalized.clamp(0.0, 1.0);
+        let db = self.max_db + amplitude_to_db(curve.powf(DB_MAP_EXPONENT));
+        db.clamp(self.min_db, self.max_db)
+    }
+}
+
+/// Converts a gain in dB to an amplitude factor
+pub fn db_to_amplitude(db: f32) -> f32 {
+    10.0_f32.powf(db / 20.0)
+}
+
+/// Converts an amplitude factor to a gain in dB, -inf for 0.0
+pub fn amplitude_to_db(amplitude: f32) -> f32 {
+    20.0 * amplitude.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32, tolerance: f32) {
+        assert!((a - b).abs() <= tolerance, "{} != {}", a, b);
+    }
+
+    fn positions() -> impl Iterator<Item = f32> {
+        (0..=100).map(|i| i as f32 / 100.0)
+    }
+
+    // Position -> value -> position, and the range ends
+    fn assert_round_trip(map: &impl ValueMap, min: f32, max: f32) {
+        for position in positions() {
+            let value = map.denormalize(position);
+            assert!(min <= value && value <= max, "{} out of range", value);
+            assert_close(map.normalize(value), position, 1e-4);
+        }
+        assert_eq!(map.normalize(min), 0.0);
+        assert_close(map.normalize(max), 1.0, 1e-6);
+        assert_eq!(map.normalize(min - 1.0), 0.0);
+        assert_close(map.normalize(max + 1.0), 1.0, 1e-6);
+        assert_eq!(map.denormalize(-1.0), map.denormalize(0.0));
+        assert_eq!(map.denormalize(2.0), map.denormalize(1.0));
+    }
+
+    #[test]
+    fn lin_map_round_trips() {
+        let map = LinMap::new(-12.0, 36.0);
+        assert_round_trip(&map, -12.0, 36.0);
+        assert_eq!(map.denormalize(0.25), 0.0);
+    }
+
+    #[test]
+    fn log_map_round_trips() {
+        let map = LogMap::new(20.0, 20000.0);
+        assert_round_trip(&map, 20.0, 20000.0);
+        // Every decade gets a third of the knob
+        assert_close(map.denormalize(1.0 / 3.0), 200.0, 0.01);
+        assert_close(map.denormalize(2.0 / 3.0), 2000.0, 0.1);
+    }
+
+    #[test]
+    fn pow_map_round_trips() {
+        let map = PowMap::new(0.0, 10.0, 2.0);
+        assert_round_trip(&map, 0.0, 10.0);
+        assert_close(map.denormalize(0.5), 2.5, 1e-6);
+    }
+
+    #[test]
+    fn db_map_round_trips() {
+        let map = DbMap::new(-90.0, 6.0);
+        for position in positions() {
+            let db = map.denormalize(position);
+            assert!((-90.0..=6.0).contains(&db), "{} out of range", db);
+            assert_close(map.normalize(db), position, 1e-4);
+        }
+        assert_eq!(map.denormalize(0.0), -90.0);
+        assert_close(map.denormalize(1.0), 6.0, 1e-4);
+        assert_eq!(map.normalize(-120.0), 0.0);
+        assert_eq!(map.normalize(12.0), 1.0);
+        // The upper part of the fader is around 0 dB
+        assert!(map.normalize(0.0) > 0.75);
+    }
+
+    #[test]
+    fn degenerate_ranges_fall_back() {
+        let lin = LinMap::new(2.0, 2.0);
+        assert_eq!(lin.normalize(2.0), 0.0);
+        assert_eq!(lin.denormalize(0.7), 2.0);
+
+        // Not positive, mapped linearly
+        for map in [LogMap::new(0.0, 100.0), LogMap::new(-50.0, 50.0)].iter() {
+            let linear = LinMap::new(map.min, map.max);
+            for position in positions() {
+                assert_eq!(map.denormalize(position), linear.denormalize(position));
+                let value = linear.denormalize(position);
+                assert_eq!(map.normalize(value), linear.normalize(value));
+            }
+        }
+        assert_eq!(LogMap::new(10.0, 10.0).normalize(10.0), 0.0);
+
+        let pow = PowMap::new(0.0, 10.0, 0.0);
+        assert_round_trip(&pow, 0.0, 10.0);
+        assert_eq!(pow.denormalize(0.5), 5.0);
+
+        let db = DbMap::new(0.0, -6.0);
+        assert_eq!(db.normalize(-3.0), 0.0);
+        assert_eq!(db.denormalize(0.5), 0.0);
+    }
+}
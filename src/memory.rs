@@ -0,0 +1,250 @@
+//! Memory budget for queues, histories and caches
+//!
+//! A UI staying open for a whole session keeps receiving atoms,
+//! recording entries and decoding resources. Each of them is bounded
+//! by a count, but large atoms or images can still add up. A
+//! `MemoryBudget` caps the bytes they hold together. The budget of
+//! the instance is set by `PluginUI::MEMORY_BUDGET` and handed out
+//! by `PluginUIInfo::memory_budget()`:
+//!
+//! ```ignore
+//! const MEMORY_BUDGET: Option<usize> = Some(16 << 20);
+//!
+//! fn new(plugin_ui_info: &PluginUIInfo, ...) -> Result<Self, PluginUIError> {
+//!     let budget = plugin_ui_info.memory_budget();
+//!     let waveform = UIAtomPort::new(urid, 4).with_memory_budget(&budget);
+//!     let images = ResourceCache::new(plugin_ui_info.bundle_path(), 64)
+//!         .with_memory_budget(&budget, |image: &Image| image.data.len());
+//!     ...
+//! }
+//! ```
+//!
+//! Each participant evicts its own data to stay within the budget:
+//! atom ports according to their `OverflowPolicy`, resource caches
+//! the least recently used resource and entry histories the oldest
+//! entry of the port with the most entries. Which part can use how
+//! much is configured by limits per `MemoryCategory`.
+//!
+//! The budget is not thread safe, it is meant for the UI thread.
+//! `MemoryBudget::usage()` gives the accounting, also as part of
+//! `Diagnostics`.
+
+use std::cell::Cell;
+use std::rc::Rc;
+
+/// What the memory of a budget is used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    /// Atoms queued by `UIAtomPort`s
+    AtomQueues,
+    /// Recorded entries like `EntryHistory`
+    History,
+    /// Resources kept by `ResourceCache`s
+    Caches,
+}
+
+impl MemoryCategory {
+    pub const ALL: [MemoryCategory; 3] = [
+        MemoryCategory::AtomQueues,
+        MemoryCategory::History,
+        MemoryCategory::Caches,
+    ];
+
+    fn slot(self) -> usize {
+        self as usize
+    }
+}
+
+#[derive(Default)]
+struct BudgetState {
+    limit: Option<usize>,
+    category_limits: [Option<usize>; 3],
+    used: [Cell<usize>; 3],
+    evicted: [Cell<usize>; 3],
+}
+
+/// A cap on the bytes held by the participants, shared by cloning
+#[derive(Clone)]
+pub struct MemoryBudget {
+    state: Rc<BudgetState>,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+impl MemoryBudget {
+    /// A budget of `limit` bytes in total
+    pub fn new(limit: usize) -> Self {
+        Self {
+            state: Rc::new(BudgetState {
+                limit: Some(limit),
+                ..BudgetState::default()
+            }),
+        }
+    }
+
+    /// A budget only accounting the memory, never evicting
+    pub fn unlimited() -> Self {
+        Self {
+            state: Rc::new(BudgetState::default()),
+        }
+    }
+
+    /// Caps the bytes used for `category` within the total limit
+    ///
+    /// Only to be called before the budget is handed out.
+    pub fn with_category_limit(mut self, category: MemoryCategory, limit: usize) -> Self {
+        match Rc::get_mut(&mut self.state) {
+            Some(state) => state.category_limits[category.slot()] = Some(limit),
+            None => panic!("category limit set on a budget already handed out"),
+        }
+        self
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.state.limit
+    }
+
+    pub fn category_limit(&self, category: MemoryCategory) -> Option<usize> {
+        self.state.category_limits[category.slot()]
+    }
+
+    /// The bytes used by all participants
+    pub fn used(&self) -> usize {
+        self.state.used.iter().map(Cell::get).sum()
+    }
+
+    pub fn used_by(&self, category: MemoryCategory) -> usize {
+        self.state.used[category.slot()].get()
+    }
+
+    /// The number of items evicted to stay within the budget
+    pub fn evicted_by(&self, category: MemoryCategory) -> usize {
+        self.state.evicted[category.slot()].get()
+    }
+
+    /// Creates an account for a participant of `category`
+    pub fn account(&self, category: MemoryCategory) -> MemoryAccount {
+        MemoryAccount {
+            budget: self.clone(),
+            category,
+            bytes: 0,
+        }
+    }
+
+    /// The accounting of all categories
+    pub fn usage(&self) -> MemoryUsage {
+        MemoryUsage {
+            limit: self.limit(),
+            categories: MemoryCategory::ALL
+                .iter()
+                .map(|&category| CategoryUsage {
+                    category,
+                    limit: self.category_limit(category),
+                    used: self.used_by(category),
+                    evicted: self.evicted_by(category),
+                })
+                .collect(),
+        }
+    }
+
+    fn fits(&self, category: MemoryCategory, additional: usize) -> bool {
+        let within = |limit: Option<usize>, used: usize| {
+            limit.is_none_or(|limit| used.saturating_add(additional) <= limit)
+        };
+        within(self.limit(), self.used())
+            && within(self.category_limit(category), self.used_by(category))
+    }
+}
+
+/// The memory held by one participant of a `MemoryBudget`
+///
+/// The participant reports the bytes it holds by `set()` after
+/// every change. They are released when the account is dropped.
+pub struct MemoryAccount {
+    budget: MemoryBudget,
+    category: MemoryCategory,
+    bytes: usize,
+}
+
+impl MemoryAccount {
+    /// Reports that the participant now holds `bytes`
+    pub fn set(&mut self, bytes: usize) {
+        let used = &self.budget.state.used[self.category.slot()];
+        used.set(used.get() - self.bytes + bytes);
+        self.bytes = bytes;
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// True if `additional` bytes more fit into the budget and the category limit
+    pub fn fits(&self, additional: usize) -> bool {
+        self.budget.fits(self.category, additional)
+    }
+
+    /// True if the budget or the category limit is exceeded
+    pub fn is_exceeded(&self) -> bool {
+        !self.fits(0)
+    }
+
+    /// Counts an item evicted to stay within the budget
+    pub fn record_eviction(&self) {
+        let evicted = &self.budget.state.evicted[self.category.slot()];
+        evicted.set(evicted.get() + 1);
+    }
+
+    pub fn budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+}
+
+impl Clone for MemoryAccount {
+    /// A new account holding the same bytes, as the clone of the participant does
+    fn clone(&self) -> Self {
+        let mut account = self.budget.account(self.category);
+        account.set(self.bytes);
+        account
+    }
+}
+
+impl Drop for MemoryAccount {
+    fn drop(&mut self) {
+        self.set(0);
+    }
+}
+
+impl std::fmt::Debug for MemoryAccount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryAccount")
+            .field("category", &self.category)
+            .field("bytes", &self.bytes)
+            .finish()
+    }
+}
+
+/// The memory used for a category at the time of `MemoryBudget::usage()`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CategoryUsage {
+    pub category: MemoryCategory,
+    pub limit: Option<usize>,
+    pub used: usize,
+    pub evicted: usize,
+}
+
+/// The accounting of a `MemoryBudget`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemoryUsage {
+    pub limit: Option<usize>,
+    pub categories: Vec<CategoryUsage>,
+}
+
+impl MemoryUsage {
+    pub fn used(&self) -> usize {
+        self.categories.iter().map(|category| category.used).sum()
+    }
+}
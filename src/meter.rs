@@ -0,0 +1,181 @@
+//! Peak meters fed by the `ui:peakProtocol`
+//!
+//! Instead of every value of an output port, hosts can deliver the
+//! peak of a port per period using the `ui:peakProtocol`, which is
+//! way cheaper for level meters. `PeakMeter` receives those peaks.
+//!
+//! Peaks need to be subscribed by the `ui:portSubscribe` feature.
+//! Meters are only worth the host's effort while they are visible,
+//! so `MeterSubscription` subscribes the meter ports when the meter
+//! view is shown and unsubscribes them when it is hidden:
+//!
+//! ```ignore
+//! fn show(&mut self) {
+//!     self.meter_subscription.set_visible(true, self.port_subscribe.as_ref());
+//! }
+//!
+//! fn hide(&mut self) {
+//!     self.meter_subscription.set_visible(false, self.port_subscribe.as_ref());
+//! }
+//! ```
+
+use lv2_sys as sys;
+
+use std::time::Instant;
+
+use urid::*;
+
+use crate::logging::ui_log;
+use crate::notification::PortSubscribe;
+use crate::uris::PeakProtocol;
+
+/// A UI port receiving the peaks of an output port
+///
+/// The port collection maps the port index to it by
+/// `UIPortsTrait::map_peak_port()`.
+pub struct PeakMeter {
+    peak: f32,
+    period_start: u32,
+    period_size: u32,
+    changed: bool,
+    received: Option<Instant>,
+    urid: URID<PeakProtocol>,
+    index: u32,
+}
+
+impl PeakMeter {
+    pub fn new(urid: URID<PeakProtocol>, index: u32) -> Self {
+        Self {
+            peak: 0.0,
+            period_start: 0,
+            period_size: 0,
+            changed: false,
+            received: None,
+            urid,
+            index,
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub(crate) fn shift_index(&mut self, offset: u32) {
+        self.index += offset;
+    }
+
+    /// The peak of the last period received
+    pub fn peak(&self) -> f32 {
+        self.peak
+    }
+
+    /// Returns the peak if a new one has been received, otherwise None.
+    pub fn changed_peak(&mut self) -> Option<f32> {
+        match self.changed {
+            false => None,
+            true => {
+                self.changed = false;
+                Some(self.peak)
+            }
+        }
+    }
+
+    /// The start of the last period received in audio frames
+    pub fn period_start(&self) -> u32 {
+        self.period_start
+    }
+
+    /// The size of the last period received in audio frames
+    pub fn period_size(&self) -> u32 {
+        self.period_size
+    }
+
+    /// The time the last peak has been received from the host
+    pub fn received(&self) -> Option<Instant> {
+        self.received
+    }
+
+    pub(crate) fn urid(&self) -> u32 {
+        self.urid.get()
+    }
+
+    pub(crate) fn put_peak_data(&mut self, data: &sys::LV2UI_Peak_Data, received: Instant) {
+        self.peak = data.peak;
+        self.period_start = data.period_start;
+        self.period_size = data.period_size;
+        self.changed = true;
+        self.received = Some(received);
+    }
+}
+
+/// Subscribes the peaks of meter ports while the meters are visible
+#[derive(Clone, Debug)]
+pub struct MeterSubscription {
+    ports: Vec<u32>,
+    protocol: URID<PeakProtocol>,
+    subscribed: bool,
+}
+
+impl MeterSubscription {
+    /// A subscription of the peaks of the output ports `ports`
+    ///
+    /// Nothing is subscribed until `set_visible(true, …)` is called.
+    pub fn new(ports: impl IntoIterator<Item = u32>, protocol: URID<PeakProtocol>) -> Self {
+        Self {
+            ports: ports.into_iter().collect(),
+            protocol,
+            subscribed: false,
+        }
+    }
+
+    /// A subscription of the ports of `meters`
+    pub fn for_meters<'a>(
+        meters: impl IntoIterator<Item = &'a PeakMeter>,
+        protocol: URID<PeakProtocol>,
+    ) -> Self {
+        Self::new(meters.into_iter().map(PeakMeter::index), protocol)
+    }
+
+    pub fn ports(&self) -> &[u32] {
+        &self.ports
+    }
+
+    pub fn is_subscribed(&self) -> bool {
+        self.subscribed
+    }
+
+    /// Tells the subscription whether the meters are visible
+    ///
+    /// Subscribes all ports when the meters become visible and
+    /// unsubscribes them when they get hidden. Nothing is sent to the
+    /// host if the visibility did not change. Without the
+    /// `ui:portSubscribe` feature only the visibility is tracked.
+    pub fn set_visible(&mut self, visible: bool, port_subscribe: Option<&PortSubscribe>) {
+        if visible == self.subscribed {
+            return;
+        }
+        self.subscribed = visible;
+        let port_subscribe = match port_subscribe {
+            Some(port_subscribe) => port_subscribe,
+            None => return,
+        };
+        for port_index in self.ports.iter() {
+            let done = match visible {
+                true => port_subscribe.subscribe(*port_index, self.protocol.get()),
+                false => port_subscribe.unsubscribe(*port_index, self.protocol.get()),
+            };
+            if !done {
+                ui_log!(
+                    Warning,
+                    "failed to change peak subscription of port {}",
+                    port_index
+                );
+            }
+        }
+    }
+
+    /// Unsubscribes all ports, to be called in `PluginUI::cleanup()`
+    pub fn unsubscribe(&mut self, port_subscribe: Option<&PortSubscribe>) {
+        self.set_visible(false, port_subscribe);
+    }
+}
@@ -0,0 +1,190 @@
+//! Metrics of the port traffic between UI and plugin
+//!
+//! Chatty UIs cost the host time in every port event and every write.
+//! A UI writing a port back whenever it receives it, e.g. a knob
+//! echoing the value the plugin reported, even makes a loop with the
+//! plugin. A `PortMetrics` installed on the write handle is told
+//! about every event from the host, every write to the host and the
+//! atoms dropped by full queues:
+//!
+//! ```ignore
+//! // in new()
+//! let traffic = Rc::new(RefCell::new(PortTraffic::new()));
+//! write_handle.set_metrics(traffic.clone());
+//!
+//! // in idle(), e.g. for a debug overlay
+//! for port in traffic.borrow().feedback_suspects(20.0) {
+//!     ui_log!(Warning, "port {} is written as often as received", port);
+//! }
+//! ```
+//!
+//! `PortTraffic` counts per port and measures the rates over the last
+//! second. UIs with their own profiling implement `PortMetrics`
+//! themselves. The metrics are called from the UI thread, calls made
+//! while the metrics are borrowed are left out.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Receives the port traffic, see `PluginPortWriteHandle::set_metrics()`
+pub trait PortMetrics {
+    /// The host delivered an event of `size` bytes, `format` 0 being a control value
+    fn event_received(&mut self, _port_index: u32, _format: u32, _size: u32) {}
+
+    /// A write has been passed to the host, whose write function took `duration`
+    fn written(&mut self, _port_index: u32, _protocol: u32, _size: usize, _duration: Duration) {}
+
+    /// `count` atoms of the port have been dropped by its overflow policy or memory budget
+    fn dropped(&mut self, _port_index: u32, _count: usize) {}
+}
+
+/// The traffic of one port counted by `PortTraffic`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PortTrafficStats {
+    /// The events received from the host
+    pub events: u64,
+    /// The bytes of the events received
+    pub event_bytes: u64,
+    /// The writes passed to the host
+    pub writes: u64,
+    /// The bytes of the writes
+    pub write_bytes: u64,
+    /// The time spent in the host's write function
+    pub write_time: Duration,
+    /// The atoms dropped
+    pub dropped: u64,
+}
+
+/// Counts the traffic per port and its rates
+#[derive(Clone, Debug)]
+pub struct PortTraffic {
+    ports: BTreeMap<u32, PortTrafficStats>,
+    window: Duration,
+    recent_events: VecDeque<(Instant, u32)>,
+    recent_writes: VecDeque<(Instant, u32)>,
+}
+
+impl Default for PortTraffic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PortTraffic {
+    /// Measures the rates over one second
+    pub fn new() -> Self {
+        Self::with_window(Duration::from_secs(1))
+    }
+
+    /// Measures the rates over `window`
+    pub fn with_window(window: Duration) -> Self {
+        Self {
+            ports: BTreeMap::new(),
+            window,
+            recent_events: VecDeque::new(),
+            recent_writes: VecDeque::new(),
+        }
+    }
+
+    /// The counts of the port, None if it had no traffic
+    pub fn port(&self, port_index: u32) -> Option<&PortTrafficStats> {
+        self.ports.get(&port_index)
+    }
+
+    /// The counts of all ports with traffic, by ascending index
+    pub fn ports(&self) -> impl Iterator<Item = (u32, &PortTrafficStats)> + '_ {
+        self.ports.iter().map(|(index, stats)| (*index, stats))
+    }
+
+    /// The counts of all ports added up
+    pub fn total(&self) -> PortTrafficStats {
+        self.ports
+            .values()
+            .fold(PortTrafficStats::default(), |total, stats| {
+                PortTrafficStats {
+                    events: total.events + stats.events,
+                    event_bytes: total.event_bytes + stats.event_bytes,
+                    writes: total.writes + stats.writes,
+                    write_bytes: total.write_bytes + stats.write_bytes,
+                    write_time: total.write_time + stats.write_time,
+                    dropped: total.dropped + stats.dropped,
+                }
+            })
+    }
+
+    /// The events per second of the port, or of all ports for None
+    pub fn events_per_second(&self, port_index: Option<u32>) -> f64 {
+        self.rate(&self.recent_events, port_index)
+    }
+
+    /// The writes per second to the port, or to all ports for None
+    pub fn writes_per_second(&self, port_index: Option<u32>) -> f64 {
+        self.rate(&self.recent_writes, port_index)
+    }
+
+    /// The ports receiving and written at least `min_rate` times per second
+    ///
+    /// A UI writing what it receives, and a plugin reporting what is
+    /// written, keep each other busy. The rates of such ports are
+    /// high in both directions.
+    pub fn feedback_suspects(&self, min_rate: f64) -> Vec<u32> {
+        self.ports
+            .keys()
+            .copied()
+            .filter(|port| {
+                self.events_per_second(Some(*port)) >= min_rate
+                    && self.writes_per_second(Some(*port)) >= min_rate
+            })
+            .collect()
+    }
+
+    /// Forgets all counts
+    pub fn reset(&mut self) {
+        self.ports.clear();
+        self.recent_events.clear();
+        self.recent_writes.clear();
+    }
+
+    fn rate(&self, recent: &VecDeque<(Instant, u32)>, port_index: Option<u32>) -> f64 {
+        let now = Instant::now();
+        let count = recent
+            .iter()
+            .filter(|(time, port)| {
+                now.duration_since(*time) < self.window && port_index.is_none_or(|p| p == *port)
+            })
+            .count();
+        count as f64 / self.window.as_secs_f64()
+    }
+
+    fn push_recent(recent: &mut VecDeque<(Instant, u32)>, window: Duration, port_index: u32) {
+        let now = Instant::now();
+        while recent
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time) >= window)
+        {
+            recent.pop_front();
+        }
+        recent.push_back((now, port_index));
+    }
+}
+
+impl PortMetrics for PortTraffic {
+    fn event_received(&mut self, port_index: u32, _format: u32, size: u32) {
+        let stats = self.ports.entry(port_index).or_default();
+        stats.events += 1;
+        stats.event_bytes += u64::from(size);
+        Self::push_recent(&mut self.recent_events, self.window, port_index);
+    }
+
+    fn written(&mut self, port_index: u32, _protocol: u32, size: usize, duration: Duration) {
+        let stats = self.ports.entry(port_index).or_default();
+        stats.writes += 1;
+        stats.write_bytes += size as u64;
+        stats.write_time += duration;
+        Self::push_recent(&mut self.recent_writes, self.window, port_index);
+    }
+
+    fn dropped(&mut self, port_index: u32, count: usize) {
+        self.ports.entry(port_index).or_default().dropped += count as u64;
+    }
+}
@@ -0,0 +1,124 @@
+//! Sending MIDI messages to the plugin
+//!
+//! On-screen keyboards and controller widgets send MIDI to the plugin
+//! through an atom port with `atom:eventTransfer`. The UI writes a
+//! single `midi:MidiEvent` atom, which the host puts into the event
+//! sequence of the port for the next run of the plugin:
+//!
+//! ```ignore
+//! fn on_key_pressed(&mut self, note: u8) {
+//!     self.ports.midi_in.write_midi_note_on(0, note, 100, self.urids.midi_event, &self.write_handle);
+//! }
+//! ```
+//!
+//! Channels are 0 to 15, data bytes 0 to 127. Out of range values
+//! are masked rather than rejected, so the plugin always receives a
+//! well-formed message.
+
+use lv2_atom as atom;
+
+use atom::prelude::*;
+use urid::*;
+
+use crate::logging::ui_log;
+use crate::plugin_ui::PluginPortWriteHandle;
+use crate::port::UIAtomPort;
+use crate::uris::MidiEvent;
+
+const NOTE_OFF: u8 = 0x80;
+const NOTE_ON: u8 = 0x90;
+const CONTROL_CHANGE: u8 = 0xb0;
+const PITCH_BEND: u8 = 0xe0;
+
+fn status(kind: u8, channel: u8) -> u8 {
+    kind | (channel & 0x0f)
+}
+
+impl UIAtomPort {
+    /// Writes the raw MIDI message `message` and sends it to the plugin
+    ///
+    /// Returns true if the message has been written.
+    pub fn write_midi(
+        &mut self,
+        message: &[u8],
+        midi_event: URID<MidiEvent>,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        if self.put_midi(message, midi_event).is_none() {
+            ui_log!(Error, "failed to write MIDI message");
+            return false;
+        }
+        write_handle.write_port(self);
+        true
+    }
+
+    pub fn write_midi_note_on(
+        &mut self,
+        channel: u8,
+        note: u8,
+        velocity: u8,
+        midi_event: URID<MidiEvent>,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        let message = [status(NOTE_ON, channel), note & 0x7f, velocity & 0x7f];
+        self.write_midi(&message, midi_event, write_handle)
+    }
+
+    pub fn write_midi_note_off(
+        &mut self,
+        channel: u8,
+        note: u8,
+        velocity: u8,
+        midi_event: URID<MidiEvent>,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        let message = [status(NOTE_OFF, channel), note & 0x7f, velocity & 0x7f];
+        self.write_midi(&message, midi_event, write_handle)
+    }
+
+    /// Sends a control change of `controller` to `value`
+    pub fn write_cc(
+        &mut self,
+        channel: u8,
+        controller: u8,
+        value: u8,
+        midi_event: URID<MidiEvent>,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        let message = [
+            status(CONTROL_CHANGE, channel),
+            controller & 0x7f,
+            value & 0x7f,
+        ];
+        self.write_midi(&message, midi_event, write_handle)
+    }
+
+    /// Sends a pitch bend of `bend`, from -8192 to 8191 with 0 meaning no bend
+    ///
+    /// Values out of range are clamped.
+    pub fn write_pitch_bend(
+        &mut self,
+        channel: u8,
+        bend: i16,
+        midi_event: URID<MidiEvent>,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        let value = (bend as i32 + 8192).clamp(0, 16383) as u16;
+        let message = [
+            status(PITCH_BEND, channel),
+            (value & 0x7f) as u8,
+            (value >> 7) as u8,
+        ];
+        self.write_midi(&message, midi_event, write_handle)
+    }
+
+    fn put_midi(&mut self, message: &[u8], midi_event: URID<MidiEvent>) -> Option<()> {
+        let space = self.clear_write_space().writer();
+        let mut header = [0u8; 8];
+        header[..4].copy_from_slice(&(message.len() as u32).to_ne_bytes());
+        header[4..].copy_from_slice(&midi_event.get().to_ne_bytes());
+        space.write_raw(&header, true)?;
+        space.write_raw(message, false)?;
+        Some(())
+    }
+}
@@ -0,0 +1,282 @@
+//! MIDI learn for control ports
+//!
+//! `MidiLearn` binds MIDI control changes to control ports. The user
+//! clicks a "learn" button of a widget, which calls `learn()`, and
+//! moves a controller. The next control change received by
+//! `handle_midi()` then is bound to the port. Subsequent control
+//! changes of the controller set the port value and are sent to the
+//! plugin.
+//!
+//! Bindings only live in the UI and are lost when it is closed,
+//! unless state sync is enabled. Then the bindings are sent to the
+//! plugin as a `patch:Set` of the parameter `uris::MidiBindings`.
+//! The plugin is supposed to keep it as part of its state and to
+//! send it back in a `patch:Set` when the state is restored or on a
+//! `patch:Get`. So the bindings are stored inside the host project.
+//!
+//! The parameter value is an `atom:Tuple` containing five atoms per
+//! binding: `atom:Int`s for channel, controller and port index and
+//! `atom:Float`s for the minimum and maximum of the port's range.
+
+use lv2_atom as atom;
+
+use atom::prelude::*;
+use urid::*;
+
+use crate::logging::ui_log;
+use crate::plugin_ui::PluginPortWriteHandle;
+use crate::port::*;
+use crate::uris::*;
+
+/// The URIDs needed to sync MIDI bindings to the plugin state
+#[derive(URIDCollection)]
+pub struct MidiLearnURIDs {
+    pub atom: AtomURIDCollection,
+    pub patch_set: URID<PatchSet>,
+    pub patch_property: URID<PatchProperty>,
+    pub patch_value: URID<PatchValue>,
+    pub midi_bindings: URID<MidiBindings>,
+}
+
+/// A control change bound to a control port
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MidiBinding {
+    /// The MIDI channel, 0 to 15
+    pub channel: u8,
+    /// The controller number, 0 to 127
+    pub controller: u8,
+    pub port_index: u32,
+    /// The port value for controller value 0
+    pub min: f32,
+    /// The port value for controller value 127
+    pub max: f32,
+}
+
+impl MidiBinding {
+    /// The port value for the controller value `value`
+    pub fn port_value(&self, value: u8) -> f32 {
+        self.min + (self.max - self.min) * f32::from(value.min(127)) / 127.0
+    }
+}
+
+/// Keeps the MIDI bindings of a plugin UI
+#[derive(Clone, Debug, Default)]
+pub struct MidiLearn {
+    bindings: Vec<MidiBinding>,
+    learning: Option<(u32, f32, f32)>,
+    state_sync: bool,
+    changed: bool,
+}
+
+impl MidiLearn {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enables sending the bindings to the plugin state
+    pub fn with_state_sync(mut self) -> Self {
+        self.state_sync = true;
+        self
+    }
+
+    pub fn set_state_sync(&mut self, state_sync: bool) {
+        self.state_sync = state_sync;
+    }
+
+    pub fn state_sync(&self) -> bool {
+        self.state_sync
+    }
+
+    /// Binds the next control change received to the port `port_index`
+    ///
+    /// `min` and `max` are the range of the port.
+    pub fn learn(&mut self, port_index: u32, min: f32, max: f32) {
+        self.learning = Some((port_index, min, max));
+    }
+
+    pub fn cancel_learn(&mut self) {
+        self.learning = None;
+    }
+
+    /// The index of the port waiting for a control change to be bound to
+    pub fn learning(&self) -> Option<u32> {
+        self.learning.map(|(port_index, _, _)| port_index)
+    }
+
+    /// Adds a binding, replacing the binding of the same controller
+    pub fn bind(&mut self, binding: MidiBinding) {
+        self.bindings
+            .retain(|b| (b.channel, b.controller) != (binding.channel, binding.controller));
+        self.bindings.push(binding);
+        self.changed = true;
+    }
+
+    /// Removes all bindings of the port `port_index`
+    pub fn unbind_port(&mut self, port_index: u32) {
+        let count = self.bindings.len();
+        self.bindings.retain(|b| b.port_index != port_index);
+        self.changed |= self.bindings.len() != count;
+    }
+
+    pub fn clear(&mut self) {
+        self.changed |= !self.bindings.is_empty();
+        self.bindings.clear();
+    }
+
+    pub fn bindings(&self) -> &[MidiBinding] {
+        &self.bindings
+    }
+
+    /// The binding of the port `port_index`, if there is one
+    pub fn binding_of_port(&self, port_index: u32) -> Option<&MidiBinding> {
+        self.bindings.iter().find(|b| b.port_index == port_index)
+    }
+
+    /// Processes a MIDI message
+    ///
+    /// Control changes of bound controllers set the value of the
+    /// port, which is then written to the plugin. Returns the index
+    /// of the port that has been set or learned.
+    pub fn handle_midi(
+        &mut self,
+        message: &[u8],
+        ports: &mut impl UIPortsTrait,
+        write_handle: &PluginPortWriteHandle,
+    ) -> Option<u32> {
+        let (status, controller, value) = match message {
+            [status, controller, value, ..] if status & 0xf0 == 0xb0 => {
+                (*status, *controller, *value)
+            }
+            _ => return None,
+        };
+        let channel = status & 0x0f;
+
+        if let Some((port_index, min, max)) = self.learning.take() {
+            self.bind(MidiBinding {
+                channel,
+                controller,
+                port_index,
+                min,
+                max,
+            });
+        }
+
+        let binding = *self
+            .bindings
+            .iter()
+            .find(|b| b.channel == channel && b.controller == controller)?;
+        let port = ports.map_control_port(binding.port_index)?;
+        port.set_value(binding.port_value(value));
+        write_handle.write_port(port);
+        Some(binding.port_index)
+    }
+
+    /// Sends the bindings to the plugin if they have changed and state sync is enabled
+    ///
+    /// Returns true if a message has been written.
+    pub fn sync_to_plugin(
+        &mut self,
+        port: &mut UIAtomPort,
+        urids: &MidiLearnURIDs,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        if !self.state_sync || !self.changed {
+            return false;
+        }
+        if self.write_patch_set(port, urids).is_none() {
+            ui_log!(Error, "failed to write MIDI bindings");
+            return false;
+        }
+        write_handle.write_port(port);
+        self.changed = false;
+        true
+    }
+
+    /// Restores the bindings from a `patch:Set` message sent by the plugin
+    ///
+    /// Returns true if the message was a `patch:Set` of the MIDI
+    /// bindings. Note that the message is consumed from the port, so
+    /// if the port also carries other messages, better read the
+    /// message yourself and pass the value to `restore()`.
+    pub fn read_patch_set(&mut self, port: &mut UIAtomPort, urids: &MidiLearnURIDs) -> bool {
+        let (header, reader) = match port.read_next(urids.atom.object, ()) {
+            Some(object) => object,
+            None => return false,
+        };
+        if header.otype != urids.patch_set {
+            return false;
+        }
+
+        let mut property = None;
+        let mut value = None;
+        for (property_header, atom) in reader {
+            if property_header.key == urids.patch_property {
+                property = atom.read(urids.atom.urid, ());
+            } else if property_header.key == urids.patch_value {
+                value = Some(atom);
+            }
+        }
+        match (property, value) {
+            (Some(property), Some(value)) if property == urids.midi_bindings => {
+                self.restore(value, urids)
+            }
+            _ => false,
+        }
+    }
+
+    /// Replaces the bindings by the ones stored in the parameter value `value`
+    ///
+    /// Returns false if `value` is not a valid bindings tuple.
+    pub fn restore(&mut self, value: UnidentifiedAtom, urids: &MidiLearnURIDs) -> bool {
+        let mut items = match value.read(urids.atom.tuple, ()) {
+            Some(items) => items,
+            None => return false,
+        };
+
+        let mut bindings = Vec::new();
+        while let Some(channel) = items.next() {
+            let binding = (|| {
+                Some(MidiBinding {
+                    channel: channel.read(urids.atom.int, ())? as u8,
+                    controller: items.next()?.read(urids.atom.int, ())? as u8,
+                    port_index: items.next()?.read(urids.atom.int, ())? as u32,
+                    min: items.next()?.read(urids.atom.float, ())?,
+                    max: items.next()?.read(urids.atom.float, ())?,
+                })
+            })();
+            match binding {
+                Some(binding) => bindings.push(binding),
+                None => return false,
+            }
+        }
+
+        self.bindings = bindings;
+        self.changed = false;
+        true
+    }
+
+    fn write_patch_set(&self, port: &mut UIAtomPort, urids: &MidiLearnURIDs) -> Option<()> {
+        let mut object = port.init(
+            urids.atom.object,
+            ObjectHeader {
+                id: None,
+                otype: urids.patch_set.into_general(),
+            },
+        )?;
+        object.init(
+            urids.patch_property,
+            None,
+            urids.atom.urid,
+            urids.midi_bindings.into_general(),
+        )?;
+        let mut tuple = object.init(urids.patch_value, None, urids.atom.tuple, ())?;
+        for binding in self.bindings.iter() {
+            tuple.init(urids.atom.int, i32::from(binding.channel))?;
+            tuple.init(urids.atom.int, i32::from(binding.controller))?;
+            tuple.init(urids.atom.int, binding.port_index as i32)?;
+            tuple.init(urids.atom.float, binding.min)?;
+            tuple.init(urids.atom.float, binding.max)?;
+        }
+        Some(())
+    }
+}
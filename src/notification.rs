@@ -0,0 +1,150 @@
+//! Receiving plugin output in hosts without port notifications
+//!
+//! Hosts are supposed to send the output ports listed as
+//! `ui:portNotification` in the UI's TTL to the UI. Some hosts don't
+//! honor that and never deliver atom output to the UI. Two fallbacks
+//! are provided:
+//!
+//! * `PortSubscribe` is the `ui:portSubscribe` host feature to
+//!   explicitly ask the host to deliver a port. Request it as
+//!   `Option<PortSubscribe<'static>>` in the `InitFeatures` and
+//!   subscribe the ports in `PluginUI::new()`.
+//! * `StatePoller` periodically writes a `patch:Get` to the plugin's
+//!   control port, asking it to send its state, as long as nothing
+//!   has been received. This requires the plugin to answer
+//!   `patch:Get` and the host to deliver at least some output.
+
+use lv2_sys as sys;
+
+use std::ffi::c_void;
+use std::time::{Duration, Instant};
+
+use lv2_atom::prelude::*;
+use lv2_core::feature::*;
+use urid::*;
+
+use crate::logging::ui_log;
+use crate::plugin_ui::PluginPortWriteHandle;
+use crate::port::*;
+use crate::uris::*;
+
+/// The `ui:portSubscribe` host feature
+pub struct PortSubscribe<'a> {
+    internal: &'a sys::LV2UI_Port_Subscribe,
+}
+
+unsafe impl<'a> UriBound for PortSubscribe<'a> {
+    const URI: &'static [u8] = sys::LV2_UI__portSubscribe;
+}
+
+unsafe impl<'a> Feature for PortSubscribe<'a> {
+    unsafe fn from_feature_ptr(feature: *const c_void, _class: ThreadingClass) -> Option<Self> {
+        (feature as *const sys::LV2UI_Port_Subscribe)
+            .as_ref()
+            .map(|internal| Self { internal })
+    }
+}
+
+impl<'a> PortSubscribe<'a> {
+    /// Asks the host to deliver the port `port_index` using `protocol`
+    ///
+    /// `protocol` is 0 for control ports or the URID of the port
+    /// protocol, e.g. `atom:eventTransfer`. Returns true on success.
+    pub fn subscribe(&self, port_index: u32, protocol: u32) -> bool {
+        match self.internal.subscribe {
+            Some(subscribe) => unsafe {
+                subscribe(self.internal.handle, port_index, protocol, std::ptr::null()) == 0
+            },
+            None => false,
+        }
+    }
+
+    /// Tells the host that the port `port_index` is no longer needed
+    pub fn unsubscribe(&self, port_index: u32, protocol: u32) -> bool {
+        match self.internal.unsubscribe {
+            Some(unsubscribe) => unsafe {
+                unsubscribe(self.internal.handle, port_index, protocol, std::ptr::null()) == 0
+            },
+            None => false,
+        }
+    }
+
+    /// Subscribes the atom port `port`
+    pub fn subscribe_atom_port(&self, port: &UIAtomPort) -> bool {
+        self.subscribe(port.index(), port.protocol())
+    }
+}
+
+/// The URIDs needed to write `patch:Get` messages
+#[derive(URIDCollection)]
+pub struct PatchGetURIDs {
+    pub atom: AtomURIDCollection,
+    pub patch_get: URID<PatchGet>,
+}
+
+/// Writes a `patch:Get` whenever nothing has been received for a while
+#[derive(Clone, Debug)]
+pub struct StatePoller {
+    interval: Duration,
+    last_request: Option<Instant>,
+}
+
+impl StatePoller {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_request: None,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// To be called in `idle()` with the atom port the plugin answers on
+    ///
+    /// Writes a `patch:Get` to `port` if neither a request has been
+    /// written nor an atom has been received within the interval.
+    /// Returns true if a request has been written.
+    pub fn poll(
+        &mut self,
+        port: &mut UIAtomPort,
+        urids: &PatchGetURIDs,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        let now = Instant::now();
+        let latest = match (self.last_request, port.received()) {
+            (Some(request), Some(received)) => Some(request.max(received)),
+            (request, received) => request.or(received),
+        };
+        match latest {
+            Some(latest) if now.duration_since(latest) < self.interval => false,
+            _ => self.request(port, urids, write_handle),
+        }
+    }
+
+    /// Writes a `patch:Get` right away
+    pub fn request(
+        &mut self,
+        port: &mut UIAtomPort,
+        urids: &PatchGetURIDs,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        let written = port
+            .init(
+                urids.atom.object,
+                ObjectHeader {
+                    id: None,
+                    otype: urids.patch_get.into_general(),
+                },
+            )
+            .is_some();
+        if !written {
+            ui_log!(Error, "failed to write patch:Get");
+            return false;
+        }
+        write_handle.write_port(port);
+        self.last_request = Some(Instant::now());
+        true
+    }
+}
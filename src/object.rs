@@ -0,0 +1,276 @@
+//! Reading and writing whole atom objects as structs
+//!
+//! Notifications from the plugin are often `atom:Object`s with a
+//! handful of properties. Instead of decoding them property by
+//! property, a struct implementing `FromAtomObject` is read by
+//! `UIAtomPort::read_object()` and one implementing `ToAtomObject` is
+//! sent by `UIAtomPort::send_object()`.
+//!
+//! With the `derive` feature both are derived, mapping every field
+//! to the property URID of the same name in a `URIDCollection`:
+//!
+//! ```ignore
+//! #[derive(URIDCollection)]
+//! struct URIDs {
+//!     atom: AtomURIDCollection,
+//!     notification: URID<Notification>,
+//!     gain: URID<Gain>,
+//!     level: URID<Level>,
+//!     name: URID<Name>,
+//! }
+//!
+//! #[derive(FromAtomObject, ToAtomObject)]
+//! #[atom_object(urids = "URIDs", otype = "notification")]
+//! struct Notification {
+//!     gain: f32,
+//!     #[atom_object(property = "level")]
+//!     peak_level: f64,
+//!     name: Option<std::string::String>,
+//! }
+//!
+//! if let Some(notification) = self.ports.notify.read_object::<Notification>(&self.urids) {
+//!     self.gain_knob.set(notification.gain);
+//! }
+//! ```
+//!
+//! The fields need to implement `patch::ParamValue`. `Option` fields
+//! may be missing in the object, all others are required. The
+//! collection needs an `atom` field holding the `AtomURIDCollection`.
+//!
+//! For sending an object without a struct for it,
+//! `UIAtomPort::write_object()` builds it property by property, see
+//! `ObjectBuilder`.
+
+use lv2_atom as atom;
+
+use atom::object::{ObjectHeader, ObjectReader, ObjectWriter};
+use atom::prelude::*;
+use urid::*;
+
+use crate::logging::ui_log;
+use crate::patch::ParamValue;
+use crate::plugin_ui::PluginPortWriteHandle;
+use crate::port::UIAtomPort;
+
+#[cfg(feature = "derive")]
+pub use lv2_ui_derive::{FromAtomObject, ToAtomObject};
+
+/// A type read from an `atom:Object`
+pub trait FromAtomObject: Sized {
+    /// The URIDs of the object type and the properties
+    type URIDs;
+
+    fn atom_urids(urids: &Self::URIDs) -> &AtomURIDCollection;
+
+    /// Reads the object, None if it is of an other type or lacks a property
+    fn from_object(header: ObjectHeader, reader: ObjectReader, urids: &Self::URIDs)
+        -> Option<Self>;
+}
+
+/// A type written as `atom:Object`
+pub trait ToAtomObject {
+    /// The URIDs of the object type and the properties
+    type URIDs;
+
+    fn atom_urids(urids: &Self::URIDs) -> &AtomURIDCollection;
+
+    /// The type of the object
+    fn otype(urids: &Self::URIDs) -> URID;
+
+    /// Writes all properties to `object`
+    fn write_properties(&self, object: &mut ObjectWriter, urids: &Self::URIDs) -> Option<()>;
+}
+
+impl UIAtomPort {
+    /// Reads the most recent atom as `T`
+    ///
+    /// Older queued atoms are discarded, see `read()`. Returns None
+    /// if the atom is no object of the type of `T`.
+    pub fn read_object<T: FromAtomObject>(&mut self, urids: &T::URIDs) -> Option<T> {
+        let (header, reader) = self.read(T::atom_urids(urids).object, ())?;
+        T::from_object(header, reader, urids)
+    }
+
+    /// Writes `value` as object and sends it to the plugin
+    ///
+    /// Returns true if the object has been written.
+    pub fn send_object<T: ToAtomObject>(
+        &mut self,
+        value: &T,
+        urids: &T::URIDs,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        if self.put_object(value, urids).is_none() {
+            ui_log!(Error, "failed to write object");
+            return false;
+        }
+        write_handle.write_port(self);
+        true
+    }
+
+    fn put_object<T: ToAtomObject>(&mut self, value: &T, urids: &T::URIDs) -> Option<()> {
+        let mut object = self.init(
+            T::atom_urids(urids).object,
+            ObjectHeader {
+                id: None,
+                otype: T::otype(urids),
+            },
+        )?;
+        value.write_properties(&mut object, urids)
+    }
+
+    /// Starts writing an object of the type `otype` to send to the plugin
+    ///
+    /// See `ObjectBuilder`.
+    pub fn write_object<'p, O: ?Sized>(
+        &'p mut self,
+        otype: URID<O>,
+        urids: &'p AtomURIDCollection,
+    ) -> ObjectBuilder<'p> {
+        ObjectBuilder {
+            port: self,
+            urids,
+            otype: otype.into_general(),
+            id: None,
+            properties: Vec::new(),
+        }
+    }
+}
+
+type PropertyWrite<'p> = Box<dyn FnOnce(PropertyWriter<'_, '_, '_>) -> Option<()> + 'p>;
+
+/// Builds an object and sends it to the plugin in one go
+///
+/// Hides initializing the object on the port and writing the port
+/// for the common case of sending one object:
+///
+/// ```ignore
+/// self.ports
+///     .control
+///     .write_object(self.urids.note, &self.urids.atom)
+///     .property(self.urids.pitch, |w| w.int(60))
+///     .property(self.urids.label, |w| w.string("C4"))
+///     .send(&self.write_handle);
+/// ```
+///
+/// The properties are written when `send()` is called, in the order
+/// they have been added.
+pub struct ObjectBuilder<'p> {
+    port: &'p mut UIAtomPort,
+    urids: &'p AtomURIDCollection,
+    otype: URID,
+    id: Option<URID>,
+    properties: Vec<(URID, PropertyWrite<'p>)>,
+}
+
+impl<'p> ObjectBuilder<'p> {
+    /// Sets the id of the object, which is none by default
+    pub fn id<I: ?Sized>(mut self, id: URID<I>) -> Self {
+        self.id = Some(id.into_general());
+        self
+    }
+
+    /// Adds the property `key`, its value written by `write`
+    pub fn property<K: ?Sized>(
+        mut self,
+        key: URID<K>,
+        write: impl FnOnce(PropertyWriter<'_, '_, '_>) -> Option<()> + 'p,
+    ) -> Self {
+        self.properties.push((key.into_general(), Box::new(write)));
+        self
+    }
+
+    /// Writes the object and sends it to the plugin
+    ///
+    /// Returns true if the object has been written.
+    pub fn send(self, write_handle: &PluginPortWriteHandle) -> bool {
+        let ObjectBuilder {
+            port,
+            urids,
+            otype,
+            id,
+            properties,
+        } = self;
+        if Self::write(port, urids, otype, id, properties).is_none() {
+            ui_log!(Error, "failed to write object");
+            return false;
+        }
+        write_handle.write_port(port);
+        true
+    }
+
+    fn write(
+        port: &mut UIAtomPort,
+        urids: &AtomURIDCollection,
+        otype: URID,
+        id: Option<URID>,
+        properties: Vec<(URID, PropertyWrite<'p>)>,
+    ) -> Option<()> {
+        let mut object = port.init(urids.object, ObjectHeader { id, otype })?;
+        for (key, write) in properties {
+            write(PropertyWriter {
+                object: &mut object,
+                key,
+                urids,
+            })?;
+        }
+        Some(())
+    }
+}
+
+/// Writes the value of a property, see `ObjectBuilder::property()`
+pub struct PropertyWriter<'w, 'a, 'b> {
+    object: &'w mut ObjectWriter<'a, 'b>,
+    key: URID,
+    urids: &'w AtomURIDCollection,
+}
+
+impl<'w, 'a, 'b> PropertyWriter<'w, 'a, 'b> {
+    /// Writes any value a patch parameter can have
+    pub fn value<T: ParamValue>(self, value: &T) -> Option<()> {
+        value.write_property(self.object, self.key, self.urids)
+    }
+
+    pub fn float(self, value: f32) -> Option<()> {
+        self.value(&value)
+    }
+
+    pub fn double(self, value: f64) -> Option<()> {
+        self.value(&value)
+    }
+
+    pub fn int(self, value: i32) -> Option<()> {
+        self.value(&value)
+    }
+
+    pub fn long(self, value: i64) -> Option<()> {
+        self.value(&value)
+    }
+
+    pub fn bool(self, value: bool) -> Option<()> {
+        self.value(&value)
+    }
+
+    pub fn urid<U: ?Sized>(self, value: URID<U>) -> Option<()> {
+        self.value(&value.into_general())
+    }
+
+    pub fn string(self, value: &str) -> Option<()> {
+        self.object
+            .init(self.key, None, self.urids.string, ())?
+            .append(value)?;
+        Some(())
+    }
+
+    /// The object and the key, to write other atom types
+    pub fn into_parts(self) -> (&'w mut ObjectWriter<'a, 'b>, URID, &'w AtomURIDCollection) {
+        (self.object, self.key, self.urids)
+    }
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use lv2_atom::object::{ObjectHeader, ObjectReader, ObjectWriter};
+    pub use lv2_atom::AtomURIDCollection;
+    pub use urid::URID;
+}
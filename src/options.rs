@@ -0,0 +1,116 @@
+//! Host options like the scale factor and the update rate
+//!
+//! Hosts pass options by the `opts:options` feature on
+//! instantiation. The ones the crate knows are read into
+//! `HostOptions`, available by `PluginUIInfo::host_options()`:
+//!
+//! ```ignore
+//! fn new(plugin_ui_info: &PluginUIInfo, ...) -> Result<Self, PluginUIError> {
+//!     let scale = plugin_ui_info.host_options().scale_factor().unwrap_or(1.0);
+//!     ...
+//! }
+//! ```
+//!
+//! The scale factor changes when the window is moved to a monitor
+//! of another resolution. With `PluginUI::OPTIONS_INTERFACE` the
+//! host can tell the UI by `opts:interface`, then
+//! `PluginUI::scale_factor_changed()` is called. Backends detecting
+//! the change themselves, see `X11Window::take_scale_change()`, are
+//! forwarded there by the UI.
+
+use lv2_sys as sys;
+
+use crate::plugin_ui::map_raw;
+
+/// The options of the host known to the crate
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HostOptions {
+    scale_factor: Option<f32>,
+    update_rate: Option<f32>,
+}
+
+/// What `HostOptions::apply()` changed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct OptionChanges {
+    pub scale_factor: Option<f32>,
+    pub update_rate: Option<f32>,
+}
+
+impl HostOptions {
+    /// The factor to scale the UI by for HiDPI displays, `ui:scaleFactor`
+    pub fn scale_factor(&self) -> Option<f32> {
+        self.scale_factor
+    }
+
+    /// The rate the host wants the UI to update at in Hz, `ui:updateRate`
+    pub fn update_rate(&self) -> Option<f32> {
+        self.update_rate
+    }
+
+    /// Reads the options of the `opts:options` feature
+    pub(crate) unsafe fn from_features(
+        features: *const *const sys::LV2_Feature,
+        urids: Option<&OptionURIDs>,
+    ) -> Self {
+        let mut options = Self::default();
+        let list = crate::plugin_ui::retrieve_feature(features, sys::LV2_OPTIONS__options);
+        if let Some(urids) = urids {
+            options.apply(list as *const sys::LV2_Options_Option, urids);
+        }
+        options
+    }
+
+    /// Takes the values of the options list `options`, terminated by a zero key
+    pub(crate) unsafe fn apply(
+        &mut self,
+        mut options: *const sys::LV2_Options_Option,
+        urids: &OptionURIDs,
+    ) -> OptionChanges {
+        let mut changes = OptionChanges::default();
+        while let Some(option) = options.as_ref() {
+            if option.key == 0 {
+                break;
+            }
+            let float = (option.type_ == urids.float
+                && option.size as usize >= std::mem::size_of::<f32>()
+                && !option.value.is_null())
+            .then(|| *(option.value as *const f32));
+            let (current, change) = match option.key {
+                key if key == urids.scale_factor => {
+                    (&mut self.scale_factor, &mut changes.scale_factor)
+                }
+                key if key == urids.update_rate => {
+                    (&mut self.update_rate, &mut changes.update_rate)
+                }
+                _ => {
+                    options = options.add(1);
+                    continue;
+                }
+            };
+            if float.is_some() && *current != float {
+                *current = float;
+                *change = float;
+            }
+            options = options.add(1);
+        }
+        changes
+    }
+}
+
+/// The URIDs of the options, mapped before the features are retrieved
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct OptionURIDs {
+    float: u32,
+    scale_factor: u32,
+    update_rate: u32,
+}
+
+impl OptionURIDs {
+    pub(crate) fn map(features: *const *const sys::LV2_Feature) -> Option<Self> {
+        Some(Self {
+            float: map_raw(features, sys::LV2_ATOM__Float)?,
+            scale_factor: map_raw(features, sys::LV2_UI__scaleFactor)?,
+            update_rate: map_raw(features, sys::LV2_UI__updateRate)?,
+        })
+    }
+}
@@ -0,0 +1,419 @@
+//! Bridge between OSC control surfaces and the plugin UI
+//!
+//! The `OscBridge` listens on a UDP socket in a background thread,
+//! maps the addresses of incoming OSC messages to control ports and
+//! writes their values to the plugin through a `PortWriteSender`.
+//! The writes reach the host after the next `idle()`, where `poll()`
+//! also updates the UI's control ports to the received values. Port
+//! changes can be mirrored back to the control surface with `send()`.
+//!
+//! Only the numeric OSC types `f`, `i` and `d` are understood.
+//! Bundles are unpacked, their time tags are ignored.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::logging::ui_log;
+use crate::plugin_ui::PluginPortWriteHandle;
+use crate::port::*;
+
+const MAX_PACKET_SIZE: usize = 1536;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type Addresses = Arc<Mutex<HashMap<String, u32>>>;
+
+/// Listens for OSC messages and translates them into port writes
+pub struct OscBridge {
+    socket: UdpSocket,
+    target: Option<SocketAddr>,
+    addresses: Addresses,
+    receiver: mpsc::Receiver<(u32, f32)>,
+    running: Arc<AtomicBool>,
+    listener: Option<thread::JoinHandle<()>>,
+}
+
+impl OscBridge {
+    /// Binds the bridge to a local UDP address and starts the listener thread
+    ///
+    /// The listener writes to the plugin through a sender of `write_handle`.
+    pub fn bind<A: ToSocketAddrs>(
+        address: A,
+        write_handle: &PluginPortWriteHandle,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let listener_socket = socket.try_clone()?;
+
+        let addresses = Addresses::default();
+        let listener_addresses = addresses.clone();
+        let sender = write_handle.sender();
+        let (updates, receiver) = mpsc::channel();
+        let running = Arc::new(AtomicBool::new(true));
+        let listener_running = running.clone();
+
+        let listener = thread::spawn(move || {
+            let mut buffer = [0u8; MAX_PACKET_SIZE];
+            let mut messages = Vec::new();
+            loop {
+                let received = listener_socket.recv(&mut buffer);
+                if !listener_running.load(Ordering::Relaxed) {
+                    return;
+                }
+                let size = match received {
+                    Ok(size) => size,
+                    Err(_) => continue,
+                };
+                messages.clear();
+                if parse_packet(&buffer[..size], &mut messages).is_none() {
+                    ui_log!(Warning, "received malformed OSC packet");
+                }
+                let addresses = match listener_addresses.lock() {
+                    Ok(addresses) => addresses,
+                    Err(_) => return,
+                };
+                for (address, value) in messages.drain(..) {
+                    let port_index = match addresses.get(&address) {
+                        Some(index) => *index,
+                        None => continue,
+                    };
+                    sender.write_control(port_index, value);
+                    // The UI is gone if the receiver is
+                    let _ = updates.send((port_index, value));
+                }
+            }
+        });
+
+        Ok(Self {
+            socket,
+            target: None,
+            addresses,
+            receiver,
+            running,
+            listener: Some(listener),
+        })
+    }
+
+    /// The local address the bridge listens on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Sets the address port changes are mirrored to
+    pub fn set_target<A: ToSocketAddrs>(&mut self, address: A) -> io::Result<()> {
+        self.target = address.to_socket_addrs()?.next();
+        Ok(())
+    }
+
+    /// Maps an OSC address like `/amp/gain` to a control port
+    pub fn map_address(&mut self, address: &str, port_index: u32) {
+        if let Ok(mut addresses) = self.addresses.lock() {
+            addresses.insert(address.to_owned(), port_index);
+        }
+    }
+
+    /// Updates the control ports to the values received since the last call
+    ///
+    /// Supposed to be called from `idle()`. The values have already
+    /// been written to the plugin by the listener thread, this keeps
+    /// the UI in sync. Returns the number of ports that have been
+    /// updated.
+    pub fn poll(&mut self, ports: &mut impl UIPortsTrait) -> usize {
+        let mut updated = 0;
+        while let Ok((port_index, value)) = self.receiver.try_recv() {
+            match ports.map_control_port(port_index) {
+                Some(port) => {
+                    port.set_value(value);
+                    updated += 1;
+                }
+                None => ui_log!(Warning, "OSC address maps to unknown port {}", port_index),
+            }
+        }
+        updated
+    }
+
+    /// Mirrors a port value back to the control surface
+    ///
+    /// The value is sent to all OSC addresses that are mapped to the
+    /// port. Does nothing if no target is set.
+    pub fn send(&self, port_index: u32, value: f32) -> io::Result<()> {
+        let target = match self.target {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+        let addresses = match self.addresses.lock() {
+            Ok(addresses) => addresses,
+            Err(_) => return Ok(()),
+        };
+        for (address, _) in addresses.iter().filter(|(_, i)| **i == port_index) {
+            self.socket
+                .send_to(&encode_message(address, value), target)?;
+        }
+        Ok(())
+    }
+
+    /// Wakes the listener thread up by an empty datagram to the socket
+    fn wake_listener(&self) -> io::Result<()> {
+        let mut address = self.socket.local_addr()?;
+        if address.ip().is_unspecified() {
+            match address {
+                SocketAddr::V4(_) => address.set_ip(Ipv4Addr::LOCALHOST.into()),
+                SocketAddr::V6(_) => address.set_ip(Ipv6Addr::LOCALHOST.into()),
+            }
+        }
+        self.socket.send_to(&[], address).map(|_| ())
+    }
+}
+
+impl Drop for OscBridge {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        // Without the wake up, joining waits for the read timeout
+        let _ = self.wake_listener();
+        if let Some(listener) = self.listener.take() {
+            let _ = listener.join();
+        }
+    }
+}
+
+fn read_string(data: &[u8]) -> Option<(&str, &[u8])> {
+    let end = data.iter().position(|b| *b == 0)?;
+    let padded = (end + 4) & !3;
+    if padded > data.len() {
+        return None;
+    }
+    let string = std::str::from_utf8(&data[..end]).ok()?;
+    Some((string, &data[padded..]))
+}
+
+fn read_u32(data: &[u8]) -> Option<(u32, &[u8])> {
+    if data.len() < 4 {
+        return None;
+    }
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&data[..4]);
+    Some((u32::from_be_bytes(bytes), &data[4..]))
+}
+
+fn read_u64(data: &[u8]) -> Option<(u64, &[u8])> {
+    if data.len() < 8 {
+        return None;
+    }
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[..8]);
+    Some((u64::from_be_bytes(bytes), &data[8..]))
+}
+
+fn parse_packet(data: &[u8], messages: &mut Vec<(String, f32)>) -> Option<()> {
+    if data.starts_with(b"#bundle\0") {
+        let (_timetag, mut rest) = read_u64(&data[8..])?;
+        while !rest.is_empty() {
+            let (size, elements) = read_u32(rest)?;
+            let size = size as usize;
+            if size > elements.len() {
+                return None;
+            }
+            parse_packet(&elements[..size], messages)?;
+            rest = &elements[size..];
+        }
+        return Some(());
+    }
+
+    let (address, rest) = read_string(data)?;
+    let (type_tags, arguments) = read_string(rest)?;
+    let mut type_tags = type_tags.chars();
+    if type_tags.next() != Some(',') {
+        return None;
+    }
+
+    // Only the first argument carries the port value
+    let value = match type_tags.next() {
+        Some('f') => f32::from_bits(read_u32(arguments)?.0),
+        Some('i') => read_u32(arguments)?.0 as i32 as f32,
+        Some('d') => f64::from_bits(read_u64(arguments)?.0) as f32,
+        _ => return Some(()),
+    };
+
+    messages.push((address.to_owned(), value));
+    Some(())
+}
+
+fn write_string(buffer: &mut Vec<u8>, string: &str) {
+    buffer.extend_from_slice(string.as_bytes());
+    let padded = (string.len() + 4) & !3;
+    buffer.resize(buffer.len() + padded - string.len(), 0);
+}
+
+fn encode_message(address: &str, value: f32) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(address.len() + 12);
+    write_string(&mut buffer, address);
+    write_string(&mut buffer, ",f");
+    buffer.extend_from_slice(&value.to_bits().to_be_bytes());
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lv2_sys as sys;
+    use std::time::Instant;
+
+    unsafe extern "C" fn record_write(
+        controller: sys::LV2UI_Controller,
+        port_index: u32,
+        _buffer_size: u32,
+        _port_protocol: u32,
+        buffer: *const std::ffi::c_void,
+    ) {
+        let writes = &mut *(controller as *mut Vec<(u32, f32)>);
+        writes.push((port_index, *(buffer as *const f32)));
+    }
+
+    fn parse(data: &[u8]) -> Option<Vec<(String, f32)>> {
+        let mut messages = Vec::new();
+        parse_packet(data, &mut messages).map(|_| messages)
+    }
+
+    fn message(address: &str, type_tags: &str, arguments: &[u8]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        write_string(&mut buffer, address);
+        write_string(&mut buffer, type_tags);
+        buffer.extend_from_slice(arguments);
+        buffer
+    }
+
+    fn bundle(elements: &[Vec<u8>]) -> Vec<u8> {
+        let mut buffer = b"#bundle\0".to_vec();
+        buffer.extend_from_slice(&1u64.to_be_bytes());
+        for element in elements {
+            buffer.extend_from_slice(&(element.len() as u32).to_be_bytes());
+            buffer.extend_from_slice(element);
+        }
+        buffer
+    }
+
+    #[test]
+    fn parses_numeric_arguments() {
+        let float = encode_message("/amp/gain", 0.5);
+        assert_eq!(parse(&float), Some(vec![("/amp/gain".to_owned(), 0.5)]));
+
+        let int = message("/amp/mode", ",i", &(-3i32).to_be_bytes());
+        assert_eq!(parse(&int), Some(vec![("/amp/mode".to_owned(), -3.0)]));
+
+        let double = message("/amp/gain", ",d", &0.25f64.to_bits().to_be_bytes());
+        assert_eq!(parse(&double), Some(vec![("/amp/gain".to_owned(), 0.25)]));
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let packet = encode_message("/amp/gain", 0.5);
+        for len in 0..packet.len() {
+            assert_eq!(parse(&packet[..len]), None, "truncated to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn rejects_unpadded_strings() {
+        assert_eq!(parse(b"/amp\0\0\0\0,f\0"), None);
+        assert_eq!(parse(b"/a\0"), None);
+        assert_eq!(parse(b"/amp"), None);
+    }
+
+    #[test]
+    fn rejects_type_tags_without_comma() {
+        assert_eq!(
+            parse(&message("/amp/gain", "f", &0.5f32.to_bits().to_be_bytes())),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_messages_without_numeric_argument() {
+        assert_eq!(parse(&message("/amp/reset", ",", &[])), Some(vec![]));
+        assert_eq!(parse(&message("/amp/name", ",s", b"amp\0")), Some(vec![]));
+    }
+
+    #[test]
+    fn rejects_invalid_utf8_addresses() {
+        assert_eq!(parse(b"/\xff\0\0,f\0\0\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn parses_bundles() {
+        let packet = bundle(&[
+            encode_message("/amp/gain", 0.5),
+            bundle(&[encode_message("/amp/mix", 1.0)]),
+        ]);
+        assert_eq!(
+            parse(&packet),
+            Some(vec![
+                ("/amp/gain".to_owned(), 0.5),
+                ("/amp/mix".to_owned(), 1.0)
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_bundle_elements_exceeding_the_packet() {
+        let mut packet = bundle(&[encode_message("/amp/gain", 0.5)]);
+        packet.truncate(packet.len() - 4);
+        assert_eq!(parse(&packet), None);
+
+        assert_eq!(parse(&b"#bundle\0\0\0\0\0"[..]), None);
+    }
+
+    #[test]
+    fn listener_writes_to_the_plugin() {
+        let mut writes: Vec<(u32, f32)> = Vec::new();
+        let write_handle = PluginPortWriteHandle::new(
+            Some(record_write),
+            &mut writes as *mut Vec<(u32, f32)> as sys::LV2UI_Controller,
+        );
+        let mut bridge = OscBridge::bind("127.0.0.1:0", &write_handle).unwrap();
+        bridge.map_address("/amp/gain", 3);
+        let mut ports = DynUIPorts::new();
+        ports.add_control(3);
+
+        let surface = UdpSocket::bind("127.0.0.1:0").unwrap();
+        surface
+            .send_to(
+                &encode_message("/amp/unmapped", 1.0),
+                bridge.local_addr().unwrap(),
+            )
+            .unwrap();
+        surface
+            .send_to(
+                &encode_message("/amp/gain", 0.5),
+                bridge.local_addr().unwrap(),
+            )
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while bridge.poll(&mut ports) == 0 {
+            assert!(Instant::now() < deadline, "no OSC message received");
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(ports.map_control_port(3).unwrap().value(), 0.5);
+
+        write_handle.flush();
+        assert_eq!(writes, vec![(3, 0.5)]);
+    }
+
+    #[test]
+    fn drop_does_not_wait_for_the_read_timeout() {
+        let write_handle = PluginPortWriteHandle::new(None, std::ptr::null_mut());
+        for address in ["127.0.0.1:0", "0.0.0.0:0"].iter() {
+            let bridge = OscBridge::bind(address, &write_handle).unwrap();
+            // Let the listener block in `recv()`
+            thread::sleep(Duration::from_millis(10));
+            let start = Instant::now();
+            drop(bridge);
+            assert!(start.elapsed() < POLL_INTERVAL / 2, "{}", address);
+        }
+    }
+}
@@ -0,0 +1,428 @@
+//! Plugin parameters exchanged as patch messages
+//!
+//! Modern plugins expose parameters as properties set by `patch:Set`
+//! messages on an atom port rather than as control ports. A
+//! `PatchParam` is such a parameter on the UI side. It writes
+//! `patch:Set` and `patch:Get` messages and takes the values of the
+//! `patch:Set` and `patch:Put` messages the plugin sends:
+//!
+//! ```ignore
+//! fn update(&mut self, _reason: UpdateReason) {
+//!     let mut params: [&mut dyn PatchParamTrait; 2] = [&mut self.cutoff, &mut self.sample];
+//!     receive_patches(self.ports.control_out(), &self.urids, &mut params);
+//!     if let Some(cutoff) = self.cutoff.changed_value() {
+//!         self.cutoff_knob.set(cutoff);
+//!     }
+//! }
+//!
+//! fn on_cutoff_knob(&mut self, cutoff: f32) {
+//!     self.cutoff.set(cutoff, self.ports.control_in(), &self.urids, &self.write_handle);
+//! }
+//! ```
+//!
+//! Right after opening, the UI does not know the values of the
+//! parameters. `StateRequest` asks the plugin for all of them by an
+//! empty `patch:Get`, usually answered by a `patch:Put`:
+//!
+//! ```ignore
+//! // in new()
+//! let mut state_request = StateRequest::new();
+//! state_request.send(ports.control_in(), &urids, &write_handle);
+//!
+//! // in update()
+//! let mut params: [&mut dyn PatchParamTrait; 2] = [&mut self.cutoff, &mut self.sample];
+//! self.state_request.receive(self.ports.control_out(), &self.urids, &mut params);
+//! ```
+
+use lv2_atom as atom;
+
+use atom::object::ObjectWriter;
+use atom::prelude::*;
+use urid::*;
+
+use crate::logging::ui_log;
+use crate::plugin_ui::PluginPortWriteHandle;
+use crate::port::*;
+use crate::uris::*;
+
+/// The URIDs needed to exchange patch messages
+#[derive(URIDCollection)]
+pub struct PatchURIDs {
+    pub atom: AtomURIDCollection,
+    pub patch_get: URID<PatchGet>,
+    pub patch_set: URID<PatchSet>,
+    pub patch_put: URID<PatchPut>,
+    pub patch_property: URID<PatchProperty>,
+    pub patch_value: URID<PatchValue>,
+    pub patch_body: URID<PatchBody>,
+}
+
+/// A type a patch parameter can have
+pub trait ParamValue: Sized + Clone {
+    /// Reads the value from an atom, None if the atom has another type
+    fn read_atom(atom: UnidentifiedAtom, urids: &AtomURIDCollection) -> Option<Self>;
+
+    /// Writes the value as property `key` of `object`
+    fn write_property(
+        &self,
+        object: &mut ObjectWriter,
+        key: URID,
+        urids: &AtomURIDCollection,
+    ) -> Option<()>;
+}
+
+macro_rules! scalar_param_value {
+    ($type:ty, $field:ident) => {
+        impl ParamValue for $type {
+            fn read_atom(atom: UnidentifiedAtom, urids: &AtomURIDCollection) -> Option<Self> {
+                atom.read(urids.$field, ())
+            }
+
+            fn write_property(
+                &self,
+                object: &mut ObjectWriter,
+                key: URID,
+                urids: &AtomURIDCollection,
+            ) -> Option<()> {
+                object.init(key, None, urids.$field, *self).map(|_| ())
+            }
+        }
+    };
+}
+
+scalar_param_value!(f32, float);
+scalar_param_value!(f64, double);
+scalar_param_value!(i32, int);
+scalar_param_value!(i64, long);
+
+// `atom:Bool` is transmitted as integer
+impl ParamValue for bool {
+    fn read_atom(atom: UnidentifiedAtom, urids: &AtomURIDCollection) -> Option<Self> {
+        atom.read(urids.bool, ()).map(|b| b != 0)
+    }
+
+    fn write_property(
+        &self,
+        object: &mut ObjectWriter,
+        key: URID,
+        urids: &AtomURIDCollection,
+    ) -> Option<()> {
+        object.init(key, None, urids.bool, *self as i32).map(|_| ())
+    }
+}
+
+impl ParamValue for URID {
+    fn read_atom(atom: UnidentifiedAtom, urids: &AtomURIDCollection) -> Option<Self> {
+        atom.read(urids.urid, ())
+    }
+
+    fn write_property(
+        &self,
+        object: &mut ObjectWriter,
+        key: URID,
+        urids: &AtomURIDCollection,
+    ) -> Option<()> {
+        object.init(key, None, urids.urid, *self).map(|_| ())
+    }
+}
+
+impl ParamValue for std::string::String {
+    fn read_atom(atom: UnidentifiedAtom, urids: &AtomURIDCollection) -> Option<Self> {
+        atom.read(urids.string, ()).map(|s| s.to_string())
+    }
+
+    fn write_property(
+        &self,
+        object: &mut ObjectWriter,
+        key: URID,
+        urids: &AtomURIDCollection,
+    ) -> Option<()> {
+        object.init(key, None, urids.string, ())?.append(self)?;
+        Some(())
+    }
+}
+
+/// The object-safe part of `PatchParam`, to handle parameters of different types together
+pub trait PatchParamTrait {
+    /// The URID of the property
+    fn property(&self) -> URID;
+
+    /// Takes the value from `atom`, returns false if it has the wrong type
+    fn receive_value(&mut self, atom: UnidentifiedAtom, urids: &AtomURIDCollection) -> bool;
+}
+
+/// A parameter of the plugin, identified by its property URID
+#[derive(Clone, Debug)]
+pub struct PatchParam<T: ParamValue> {
+    property: URID,
+    value: Option<T>,
+    changed: bool,
+}
+
+impl<T: ParamValue> PatchParam<T> {
+    /// A parameter with no value, until the plugin sends one or `set()` is called
+    pub fn new(property: URID) -> Self {
+        Self {
+            property,
+            value: None,
+            changed: false,
+        }
+    }
+
+    /// A parameter with the value the plugin is expected to start with
+    pub fn with_default(mut self, value: T) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    pub fn value(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    /// Returns the value if the plugin has sent a new one, otherwise None.
+    pub fn changed_value(&mut self) -> Option<T> {
+        match self.changed {
+            false => None,
+            true => {
+                self.changed = false;
+                self.value.clone()
+            }
+        }
+    }
+
+    /// Sets the value and sends it to the plugin as `patch:Set`
+    ///
+    /// Returns true if the message has been written.
+    pub fn set(
+        &mut self,
+        value: T,
+        port: &mut UIAtomPort,
+        urids: &PatchURIDs,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        if self.write_set(&value, port, urids).is_none() {
+            ui_log!(Error, "failed to write patch:Set");
+            return false;
+        }
+        write_handle.write_port(port);
+        self.value = Some(value);
+        true
+    }
+
+    /// Asks the plugin to send the value by a `patch:Get`
+    ///
+    /// The answer arrives as `patch:Set`, see `receive_patches()`.
+    pub fn request(
+        &self,
+        port: &mut UIAtomPort,
+        urids: &PatchURIDs,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        if self.write_get(port, urids).is_none() {
+            ui_log!(Error, "failed to write patch:Get");
+            return false;
+        }
+        write_handle.write_port(port);
+        true
+    }
+
+    fn write_set(&self, value: &T, port: &mut UIAtomPort, urids: &PatchURIDs) -> Option<()> {
+        let mut object = port.init(
+            urids.atom.object,
+            ObjectHeader {
+                id: None,
+                otype: urids.patch_set.into_general(),
+            },
+        )?;
+        object.init(urids.patch_property, None, urids.atom.urid, self.property)?;
+        value.write_property(&mut object, urids.patch_value.into_general(), &urids.atom)
+    }
+
+    fn write_get(&self, port: &mut UIAtomPort, urids: &PatchURIDs) -> Option<()> {
+        let mut object = port.init(
+            urids.atom.object,
+            ObjectHeader {
+                id: None,
+                otype: urids.patch_get.into_general(),
+            },
+        )?;
+        object.init(urids.patch_property, None, urids.atom.urid, self.property)?;
+        Some(())
+    }
+}
+
+impl<T: ParamValue> PatchParamTrait for PatchParam<T> {
+    fn property(&self) -> URID {
+        self.property
+    }
+
+    fn receive_value(&mut self, atom: UnidentifiedAtom, urids: &AtomURIDCollection) -> bool {
+        match T::read_atom(atom, urids) {
+            Some(value) => {
+                self.value = Some(value);
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Routes the `patch:Set` and `patch:Put` messages received on `port` to `params`
+///
+/// All queued atoms are consumed. Other messages are dropped, so if
+/// the port also carries other messages, read the objects yourself
+/// and pass them to `receive_patch()`. Returns the number of values
+/// taken.
+pub fn receive_patches(
+    port: &mut UIAtomPort,
+    urids: &PatchURIDs,
+    params: &mut [&mut dyn PatchParamTrait],
+) -> usize {
+    port.drain()
+        .filter_map(|atom| atom.read(urids.atom.object, ()))
+        .map(|(header, reader)| receive_patch(header, reader, urids, params))
+        .sum()
+}
+
+/// Routes a `patch:Set` or `patch:Put` object to `params`
+///
+/// Returns the number of values taken, 0 if the object is no such
+/// message.
+pub fn receive_patch(
+    header: ObjectHeader,
+    reader: atom::object::ObjectReader,
+    urids: &PatchURIDs,
+    params: &mut [&mut dyn PatchParamTrait],
+) -> usize {
+    if header.otype == urids.patch_set {
+        let mut property = None;
+        let mut value = None;
+        for (property_header, atom) in reader {
+            if property_header.key == urids.patch_property {
+                property = atom.read(urids.atom.urid, ());
+            } else if property_header.key == urids.patch_value {
+                value = Some(atom);
+            }
+        }
+        match (property, value) {
+            (Some(property), Some(value)) => receive_property(property, value, urids, params),
+            _ => 0,
+        }
+    } else if header.otype == urids.patch_put {
+        let body = reader
+            .filter(|(property_header, _)| property_header.key == urids.patch_body)
+            .find_map(|(_, atom)| atom.read(urids.atom.object, ()));
+        match body {
+            Some((_, body)) => body
+                .map(|(property_header, value)| {
+                    receive_property(property_header.key, value, urids, params)
+                })
+                .sum(),
+            None => 0,
+        }
+    } else {
+        0
+    }
+}
+
+fn receive_property(
+    property: URID,
+    value: UnidentifiedAtom,
+    urids: &PatchURIDs,
+    params: &mut [&mut dyn PatchParamTrait],
+) -> usize {
+    let param = match params.iter_mut().find(|param| param.property() == property) {
+        Some(param) => param,
+        None => return 0,
+    };
+    if param.receive_value(value, &urids.atom) {
+        1
+    } else {
+        ui_log!(
+            Warning,
+            "patch value of wrong type for property {}",
+            property.get()
+        );
+        0
+    }
+}
+
+/// Requests the values of all parameters from the plugin
+///
+/// Writes a `patch:Get` without property, which plugins answer with
+/// a `patch:Put` of all their parameters or a `patch:Set` for each
+/// of them.
+pub fn request_all(
+    port: &mut UIAtomPort,
+    urids: &PatchURIDs,
+    write_handle: &PluginPortWriteHandle,
+) -> bool {
+    let written = port
+        .init(
+            urids.atom.object,
+            ObjectHeader {
+                id: None,
+                otype: urids.patch_get.into_general(),
+            },
+        )
+        .is_some();
+    if !written {
+        ui_log!(Error, "failed to write patch:Get");
+        return false;
+    }
+    write_handle.write_port(port);
+    true
+}
+
+/// Tracks the request of the plugin state when the UI opens
+#[derive(Clone, Debug, Default)]
+pub struct StateRequest {
+    sent: bool,
+    answered: bool,
+}
+
+impl StateRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends the request, see `request_all()`
+    ///
+    /// Can be called in `PluginUI::new()`, as the write handle is
+    /// usable from then on.
+    pub fn send(
+        &mut self,
+        port: &mut UIAtomPort,
+        urids: &PatchURIDs,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        self.sent = request_all(port, urids, write_handle);
+        self.answered = false;
+        self.sent
+    }
+
+    /// Routes the patch messages received on `port` to `params`
+    ///
+    /// Same as `receive_patches()`, but also marks the request
+    /// answered once a value has been taken.
+    pub fn receive(
+        &mut self,
+        port: &mut UIAtomPort,
+        urids: &PatchURIDs,
+        params: &mut [&mut dyn PatchParamTrait],
+    ) -> usize {
+        let count = receive_patches(port, urids, params);
+        self.answered |= self.sent && count > 0;
+        count
+    }
+
+    pub fn is_sent(&self) -> bool {
+        self.sent
+    }
+
+    /// True if the plugin has sent parameter values since the request
+    pub fn is_answered(&self) -> bool {
+        self.answered
+    }
+}
@@ -0,0 +1,190 @@
+//! File parameters of the plugin
+//!
+//! Plugins loading files, e.g. samples or impulse responses, expose
+//! the file as a patch parameter of the type `atom:Path`. A
+//! `PathParam` is such a parameter on the UI side. With the
+//! `ui:requestValue` feature, `browse()` asks the host to show its
+//! file dialog. The host sends the file the user selects to the
+//! plugin as `patch:Set`, so the UI does not need a dialog of its
+//! own:
+//!
+//! ```ignore
+//! // in new()
+//! let sample = PathParam::new(urids.sample.into_general(), urids.path);
+//!
+//! fn on_load_button(&mut self) {
+//!     if let Some(request_value) = &self.request_value {
+//!         if let Err(e) = self.sample.browse(request_value) {
+//!             eprintln!("can't browse for a sample: {:?}", e);
+//!         }
+//!     }
+//! }
+//!
+//! fn update(&mut self, _reason: UpdateReason) {
+//!     let mut params: [&mut dyn PatchParamTrait; 1] = [&mut self.sample];
+//!     receive_patches(self.ports.control_out(), &self.urids.patch, &mut params);
+//!     if let Some(path) = self.sample.changed_path() {
+//!         self.sample_label.set_text(&path.to_string_lossy());
+//!     }
+//! }
+//! ```
+//!
+//! UIs with a dialog of their own send the selected file by `set()`.
+
+use lv2_atom as atom;
+
+use std::path::{Path, PathBuf};
+
+use atom::object::ObjectHeader;
+use atom::prelude::*;
+use atom::space::{FramedMutSpace, Space};
+use urid::*;
+
+use crate::logging::ui_log;
+use crate::patch::{PatchParamTrait, PatchURIDs};
+use crate::plugin_ui::PluginPortWriteHandle;
+use crate::port::UIAtomPort;
+use crate::request_value::{RequestValue, RequestValueError};
+
+/// The `atom:Path` type, a null terminated path string
+///
+/// Reading returns the path without the terminator. Writing returns
+/// the frame, the path bytes and the terminator are to be written
+/// with `write_raw()`.
+pub struct AtomPath;
+
+unsafe impl UriBound for AtomPath {
+    const URI: &'static [u8] = lv2_sys::LV2_ATOM__Path;
+}
+
+impl<'a, 'b> Atom<'a, 'b> for AtomPath
+where
+    'a: 'b,
+{
+    type ReadParameter = ();
+    type ReadHandle = &'a str;
+    type WriteParameter = ();
+    type WriteHandle = FramedMutSpace<'a, 'b>;
+
+    fn read(body: Space<'a>, _: ()) -> Option<&'a str> {
+        let data = body.data()?;
+        let data = match data.iter().position(|&byte| byte == 0) {
+            Some(end) => &data[..end],
+            None => data,
+        };
+        std::str::from_utf8(data).ok()
+    }
+
+    fn init(frame: FramedMutSpace<'a, 'b>, _: ()) -> Option<FramedMutSpace<'a, 'b>> {
+        Some(frame)
+    }
+}
+
+/// The URIDs needed by `PathParam` besides the `PatchURIDs`
+#[derive(URIDCollection)]
+pub struct PathURIDs {
+    pub patch: PatchURIDs,
+    pub path: URID<AtomPath>,
+}
+
+/// A file parameter of the plugin, identified by its property URID
+#[derive(Clone, Debug)]
+pub struct PathParam {
+    property: URID,
+    path_type: URID<AtomPath>,
+    path: Option<PathBuf>,
+    changed: bool,
+}
+
+impl PathParam {
+    /// A parameter with no path, until the plugin sends one or `set()` is called
+    pub fn new(property: URID, path_type: URID<AtomPath>) -> Self {
+        Self {
+            property,
+            path_type,
+            path: None,
+            changed: false,
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Returns the path if the plugin has sent a new one, otherwise None.
+    pub fn changed_path(&mut self) -> Option<&Path> {
+        match self.changed {
+            false => None,
+            true => {
+                self.changed = false;
+                self.path.as_deref()
+            }
+        }
+    }
+
+    /// Asks the host to let the user select a file
+    ///
+    /// The host sends the selected file to the plugin, which reports
+    /// it back like any other change of the parameter.
+    pub fn browse(&self, request_value: &RequestValue) -> Result<(), RequestValueError> {
+        request_value.request(self.property, self.path_type)
+    }
+
+    /// Sets the path and sends it to the plugin as `patch:Set`
+    ///
+    /// Returns true if the message has been written. Paths which are
+    /// not valid UTF-8 can't be sent.
+    pub fn set(
+        &mut self,
+        path: &Path,
+        port: &mut UIAtomPort,
+        urids: &PatchURIDs,
+        write_handle: &PluginPortWriteHandle,
+    ) -> bool {
+        if self.write_set(path, port, urids).is_none() {
+            ui_log!(Error, "failed to write patch:Set of {}", path.display());
+            return false;
+        }
+        write_handle.write_port(port);
+        self.path = Some(path.to_path_buf());
+        true
+    }
+
+    fn write_set(&self, path: &Path, port: &mut UIAtomPort, urids: &PatchURIDs) -> Option<()> {
+        let path = path.to_str()?;
+        let mut object = port.init(
+            urids.atom.object,
+            ObjectHeader {
+                id: None,
+                otype: urids.patch_set.into_general(),
+            },
+        )?;
+        object.init(urids.patch_property, None, urids.atom.urid, self.property)?;
+        let mut frame = object.init(urids.patch_value, None, self.path_type, ())?;
+        frame.write_raw(path.as_bytes(), false)?;
+        frame.write_raw(&[0], false)?;
+        Some(())
+    }
+}
+
+impl PatchParamTrait for PathParam {
+    fn property(&self) -> URID {
+        self.property
+    }
+
+    /// Takes `atom:Path` values, and `atom:String` ones from plugins
+    /// not distinguishing them
+    fn receive_value(&mut self, atom: UnidentifiedAtom, urids: &AtomURIDCollection) -> bool {
+        let path = atom
+            .read(self.path_type, ())
+            .or_else(|| atom.read(urids.string, ()));
+        match path {
+            Some(path) => {
+                self.path = Some(PathBuf::from(path));
+                self.changed = true;
+                true
+            }
+            None => false,
+        }
+    }
+}
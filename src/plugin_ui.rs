@@ -1,41 +1,676 @@
 use lv2_sys as sys;
 
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::ffi::CStr;
 use std::os::raw::c_char;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 use std::str::Utf8Error;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use lv2_core::prelude::*;
 use std::fmt::Debug;
 use urid::*;
 
+use crate::capabilities::HostCapabilities;
+use crate::close_request::CloseRequest;
+use crate::description::PortExpectation;
+use crate::diagnostics::*;
+use crate::external_ui::*;
+use crate::instrument::{self, Timer};
+use crate::logging::{self, ui_log, SinkSlot};
+use crate::memory::MemoryBudget;
+use crate::metrics::PortMetrics;
+use crate::options::*;
 use crate::port::*;
+use crate::worker::CancellationToken;
 
 #[derive(Debug)]
 pub enum PluginUIInfoError {
+    /// The bundle path is no UTF-8, on platforms where paths have to be
     InvalidBundlePathUtf8(Utf8Error),
+    /// The bundle path is neither UTF-8 nor in the code page of the
+    /// system, the raw bytes are attached
+    UndecodableBundlePath(Vec<u8>),
+}
+
+/// Why `PluginUI::new()` failed, logged through the host's log feature
+#[derive(Debug)]
+pub enum PluginUIError {
+    /// A host feature the UI needs is missing, by its URI
+    MissingFeature(String),
+    /// A port the UI needs is missing, e.g. by its symbol
+    MissingPort(String),
+    /// The window could not be opened, the error of the backend
+    Window(Box<dyn Debug>),
+    /// A resource of the bundle could not be loaded
+    Resource(PathBuf, std::io::Error),
+    /// Any other reason
+    Other(String),
+}
+
+impl PluginUIError {
+    /// A missing host feature by its null terminated URI, e.g. `lv2_sys::LV2_URID__map`
+    pub fn missing_feature(uri: &[u8]) -> Self {
+        let uri = uri.strip_suffix(&[0]).unwrap_or(uri);
+        PluginUIError::MissingFeature(std::string::String::from_utf8_lossy(uri).into_owned())
+    }
+}
+
+#[cfg(feature = "x11")]
+impl From<crate::backend::x11::X11WindowError> for PluginUIError {
+    fn from(e: crate::backend::x11::X11WindowError) -> Self {
+        PluginUIError::Window(Box::new(e))
+    }
+}
+
+#[cfg(feature = "x11")]
+impl From<crate::backend::x11_software::X11SoftwareError> for PluginUIError {
+    fn from(e: crate::backend::x11_software::X11SoftwareError) -> Self {
+        PluginUIError::Window(Box::new(e))
+    }
+}
+
+#[cfg(feature = "x11-calloop")]
+impl From<crate::backend::x11_calloop::X11LoopError> for PluginUIError {
+    fn from(e: crate::backend::x11_calloop::X11LoopError) -> Self {
+        PluginUIError::Window(Box::new(e))
+    }
+}
+
+#[cfg(feature = "winit")]
+impl From<crate::backend::winit::WinitWindowError> for PluginUIError {
+    fn from(e: crate::backend::winit::WinitWindowError) -> Self {
+        PluginUIError::Window(Box::new(e))
+    }
+}
+
+#[cfg(feature = "gl")]
+impl From<crate::backend::gl::GlError> for PluginUIError {
+    fn from(e: crate::backend::gl::GlError) -> Self {
+        PluginUIError::Window(Box::new(e))
+    }
+}
+
+#[cfg(feature = "canvas")]
+impl From<crate::backend::canvas::CanvasError> for PluginUIError {
+    fn from(e: crate::backend::canvas::CanvasError) -> Self {
+        PluginUIError::Window(Box::new(e))
+    }
+}
+
+/// Reads the bundle path in the encoding of the platform
+///
+/// On Unix any bytes make a path. Windows hosts usually pass UTF-8,
+/// but some pass the path in the ANSI code page of the system, which
+/// breaks e.g. for user names with non-ASCII characters.
+unsafe fn bundle_path_from_raw<'a>(
+    bundle_path: *const c_char,
+) -> Result<Cow<'a, Path>, PluginUIInfoError> {
+    let bytes = CStr::from_ptr(bundle_path).to_bytes();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(Cow::Borrowed(Path::new(std::ffi::OsStr::from_bytes(bytes))))
+    }
+    #[cfg(windows)]
+    {
+        match std::str::from_utf8(bytes) {
+            Ok(path) => Ok(Cow::Borrowed(Path::new(path))),
+            Err(_) => {
+                let path = windows_ansi::decode(bytes)
+                    .ok_or_else(|| PluginUIInfoError::UndecodableBundlePath(bytes.to_vec()))?;
+                ui_log!(
+                    Note,
+                    "bundle path is not UTF-8, decoded from the ANSI code page: {:?}",
+                    path
+                );
+                Ok(Cow::Owned(path))
+            }
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        std::str::from_utf8(bytes)
+            .map(|path| Cow::Borrowed(Path::new(path)))
+            .map_err(PluginUIInfoError::InvalidBundlePathUtf8)
+    }
+}
+
+#[cfg(windows)]
+mod windows_ansi {
+    use std::convert::TryFrom;
+    use std::os::raw::c_char;
+    use std::os::windows::ffi::OsStringExt;
+    use std::path::PathBuf;
+
+    const CP_ACP: u32 = 0;
+    const MB_ERR_INVALID_CHARS: u32 = 0x8;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn MultiByteToWideChar(
+            code_page: u32,
+            flags: u32,
+            multi_byte: *const c_char,
+            multi_byte_len: i32,
+            wide: *mut u16,
+            wide_len: i32,
+        ) -> i32;
+    }
+
+    /// Decodes `bytes` from the ANSI code page, None if they are invalid in it
+    pub(super) fn decode(bytes: &[u8]) -> Option<PathBuf> {
+        let len = i32::try_from(bytes.len()).ok()?;
+        let source = bytes.as_ptr() as *const c_char;
+        let flags = MB_ERR_INVALID_CHARS;
+        let wide_len =
+            unsafe { MultiByteToWideChar(CP_ACP, flags, source, len, std::ptr::null_mut(), 0) };
+        if wide_len <= 0 {
+            return None;
+        }
+        let mut wide = vec![0u16; wide_len as usize];
+        let written =
+            unsafe { MultiByteToWideChar(CP_ACP, flags, source, len, wide.as_mut_ptr(), wide_len) };
+        if written != wide_len {
+            return None;
+        }
+        Some(PathBuf::from(std::ffi::OsString::from_wide(&wide)))
+    }
 }
 
 /// Wrapper for the LV2UI_Write_Function
+///
+/// Measures how long the host takes for the writes and can defer
+/// them to the end of `idle()`, see `diagnostics`. Clones share the
+/// write mode, the deferred writes and the measurements.
+///
+/// The host's write function may only be called from the UI thread,
+/// so the handle is not `Send`. Threads of the UI write through a
+/// `PortWriteSender` instead.
 pub struct PluginPortWriteHandle {
     write_function: sys::LV2UI_Write_Function,
     controller: sys::LV2UI_Controller,
+    state: Rc<WriteState>,
 }
 
 impl PluginPortWriteHandle {
+    pub(crate) fn new(
+        write_function: sys::LV2UI_Write_Function,
+        controller: sys::LV2UI_Controller,
+    ) -> Self {
+        Self {
+            write_function,
+            controller,
+            state: Rc::new(WriteState::default()),
+        }
+    }
+
     pub fn write_port(&self, port: &impl UIPort) {
+        let data = match port.size() {
+            0 => &[][..],
+            size => unsafe { std::slice::from_raw_parts(port.data() as *const u8, size) },
+        };
+        self.write_raw(port.index(), port.protocol(), data);
+        port.mark_written();
+    }
+
+    /// Writes the current value of a control port to the plugin
+    pub fn write_control(&self, port: &UIControlPort) {
+        self.write_port(port);
+    }
+
+    /// Sets the value of a control port and writes it to the plugin
+    ///
+    /// The value is constrained like by `UIControlPort::set_value()`.
+    pub fn set_control(&self, port: &mut UIControlPort, value: f32) {
+        port.set_value(value);
+        self.write_port(port);
+    }
+
+    /// Initializes an atom on `port`, lets `fill` write its content and sends it to the plugin
+    ///
+    /// Replaces calling `UIAtomPort::init()` and `write_port()`
+    /// separately:
+    ///
+    /// ```ignore
+    /// write_handle.write_atom(&mut ports.notify, urids.atom.string, (), |writer| {
+    ///     writer.append("hello")?;
+    ///     Some(())
+    /// });
+    /// ```
+    ///
+    /// Returns true if the atom has been written and sent.
+    pub fn write_atom<A, P, F>(
+        &self,
+        port: &mut UIAtomPort,
+        urid: URID<A>,
+        parameter: P,
+        fill: F,
+    ) -> bool
+    where
+        A: for<'a> lv2_atom::Atom<'a, 'a, WriteParameter = P>,
+        F: for<'a> FnOnce(&mut <A as lv2_atom::Atom<'a, 'a>>::WriteHandle) -> Option<()>,
+    {
+        let written = port
+            .init(urid, parameter)
+            .and_then(|mut handle| fill(&mut handle));
+        if written.is_none() {
+            ui_log!(Error, "failed to write atom to port {}", port.index());
+            return false;
+        }
+        self.write_port(port);
+        true
+    }
+
+    /// Writes the control ports of `ports` whose values the UI has set since they were last written
+    ///
+    /// Saves enumerating the changed ports in `idle()`. Values the
+    /// host has delivered are not written back. Atom ports are not
+    /// included, their messages are written by `write_port()`.
+    /// Returns the number of ports written.
+    pub fn write_batch(&self, ports: &mut impl UIPortsTrait) -> usize {
+        let mut written = 0;
+        ports.visit_dirty_ports(|port| {
+            if let PortRef::Control(port) = port {
+                self.write_port(port);
+                written += 1;
+            }
+        });
+        written
+    }
+
+    pub(crate) fn write_raw(&self, port_index: u32, protocol: u32, data: &[u8]) {
+        if self.state.is_deferring() {
+            self.state.defer(port_index, protocol, data);
+        } else {
+            self.call_write_function(port_index, protocol, data);
+        }
+    }
+
+    fn call_write_function(&self, port_index: u32, protocol: u32, data: &[u8]) {
         if let Some(write_function) = self.write_function {
+            let start = Instant::now();
             unsafe {
                 write_function(
                     self.controller,
-                    port.index(),
-                    port.size() as u32,
-                    port.protocol(),
-                    port.data(),
+                    port_index,
+                    data.len() as u32,
+                    protocol,
+                    data.as_ptr() as *const std::ffi::c_void,
                 );
             }
+            let elapsed = start.elapsed();
+            self.state.record(elapsed);
+            instrument::write(port_index, protocol, data.len(), elapsed);
+            self.state
+                .with_metrics(|metrics| metrics.written(port_index, protocol, data.len(), elapsed));
+        }
+    }
+
+    /// Passes the deferred writes and the ones of the `PortWriteSender`s to the host
+    ///
+    /// Called after every `idle()`, UIs not relying on the idle
+    /// interface call it themselves. Flushing explicitly ignores the
+    /// interval of `WriteMode::Throttled`.
+    pub fn flush(&self) {
+        self.take_remote_writes();
+        let pending = self.state.take_pending();
+        if pending.is_empty() {
+            return;
+        }
+        for write in pending.iter() {
+            self.call_write_function(write.port_index, write.protocol, &write.data);
+        }
+        self.state.record_batch();
+    }
+
+    /// Reports the port traffic of the instance to `metrics`, see the `metrics` module
+    ///
+    /// Replaces the metrics set before, clones of the handle share them.
+    pub fn set_metrics(&self, metrics: Rc<RefCell<dyn PortMetrics>>) {
+        self.state.set_metrics(Some(metrics));
+    }
+
+    /// Stops reporting to the metrics set by `set_metrics()`
+    pub fn remove_metrics(&self) {
+        self.state.set_metrics(None);
+    }
+
+    pub fn write_mode(&self) -> WriteMode {
+        self.state.mode()
+    }
+
+    /// Sets when the writes are passed to the host, `WriteMode::Immediate` by default
+    ///
+    /// Writes deferred so far are passed when switching to
+    /// `WriteMode::Immediate`.
+    pub fn set_write_mode(&self, mode: WriteMode) {
+        self.state.set_mode(mode);
+        if !self.state.is_deferring() {
+            self.flush();
+        }
+    }
+
+    /// The measurements of the host's write function so far
+    pub fn diagnostics(&self) -> WriteDiagnostics {
+        self.state.diagnostics()
+    }
+
+    /// Starts the measurements over, keeping the write mode
+    pub fn reset_diagnostics(&self) {
+        self.state.reset_diagnostics();
+    }
+
+    /// The last port events the host has delivered to the UI, oldest first
+    pub fn recent_port_events(&self) -> Vec<PortEventRecord> {
+        self.state.recent_events()
+    }
+
+    pub(crate) fn controller(&self) -> sys::LV2UI_Controller {
+        self.controller
+    }
+
+    /// Flushes unless `WriteMode::Throttled` holds the writes back
+    pub(crate) fn flush_due(&self) {
+        self.take_remote_writes();
+        if self.state.is_batch_due() {
+            self.flush();
+        }
+    }
+
+    /// A sender to write to the plugin from other threads
+    ///
+    /// The writes are queued and passed on after the next `idle()`,
+    /// see `PortWriteSender`.
+    pub fn sender(&self) -> PortWriteSender {
+        PortWriteSender {
+            queue: self.state.remote(),
+        }
+    }
+
+    /// Passes the writes queued by `PortWriteSender`s on like writes of the UI thread
+    fn take_remote_writes(&self) {
+        for write in self.state.take_remote() {
+            self.write_raw(write.port_index, write.protocol, &write.data);
+        }
+    }
+}
+
+impl Clone for PluginPortWriteHandle {
+    fn clone(&self) -> Self {
+        Self {
+            write_function: self.write_function,
+            controller: self.controller,
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// Writes to the plugin from threads other than the UI thread
+///
+/// UIs decoding files or analyzing audio on worker threads send the
+/// results to the plugin through a sender. The writes are queued and
+/// passed to the host after the next `idle()` of the UI, as the
+/// host's write function is not thread-safe. UIs not relying on the
+/// idle interface pass them by `PluginPortWriteHandle::flush()`.
+///
+/// ```ignore
+/// let sender = write_handle.sender();
+/// worker.spawn(move |_token| {
+///     let gain = analyze_loudness(&file);
+///     sender.write_control(GAIN_PORT, gain);
+/// });
+/// ```
+#[derive(Clone)]
+pub struct PortWriteSender {
+    queue: Arc<Mutex<Vec<PendingWrite>>>,
+}
+
+impl PortWriteSender {
+    /// Queues a value for the control port `port_index`
+    pub fn write_control(&self, port_index: u32, value: f32) {
+        self.write_raw(port_index, 0, value.to_ne_bytes().to_vec());
+    }
+
+    /// Queues the content of `port`, e.g. an atom built on the thread
+    pub fn write_port(&self, port: &impl UIPort) {
+        let data = match port.size() {
+            0 => Vec::new(),
+            size => unsafe { std::slice::from_raw_parts(port.data() as *const u8, size) }.to_vec(),
+        };
+        self.write_raw(port.index(), port.protocol(), data);
+    }
+
+    /// Queues a write with the protocol `protocol`, 0 for control ports
+    pub fn write_raw(&self, port_index: u32, protocol: u32, data: Vec<u8>) {
+        match self.queue.lock() {
+            Ok(mut queue) => queue.push(PendingWrite {
+                port_index,
+                protocol,
+                data,
+            }),
+            Err(_) => ui_log!(Error, "port write queue poisoned, dropping write"),
+        }
+    }
+}
+
+/// Where the window of the plugin UI is supposed to go
+///
+/// If the host provides the `ui:parent` feature, the UI is embedded
+/// into the host's window. Otherwise the UI needs to open a
+/// standalone top-level window. See `backend` for windowing backends
+/// handling both cases.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowMode {
+    /// The UI window is to be a child of the given parent window
+    Embedded(*mut std::ffi::c_void),
+    /// The UI needs to open a top-level window on its own
+    TopLevel,
+}
+
+impl WindowMode {
+    /// Determines the window mode from the `parent_window` passed to `PluginUI::new()`
+    pub fn from_parent(parent_window: *mut std::ffi::c_void) -> Self {
+        if parent_window.is_null() {
+            WindowMode::TopLevel
+        } else {
+            WindowMode::Embedded(parent_window)
+        }
+    }
+}
+
+/// Why `PluginUI::update()` is called
+///
+/// The framework calls `update()` for port events. The other reasons
+/// come from the UI's own event handling and the helpers of this
+/// crate, so a UI can route all its updates through `update()` and do
+/// only the work needed, e.g. only recompute the layout on resize.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateReason {
+    /// The host delivered an event for the port with the index
+    HostPortEvent(u32),
+    /// The host delivered the number of port events since the last
+    /// `idle()`, see `PortEventBatching::UntilIdle`
+    HostPortEvents(u32),
+    /// A timer of the UI expired, e.g. for animations
+    Timer,
+    /// The UI's window has been resized
+    Resize,
+    /// The UI's window needs to be redrawn, e.g. after being uncovered
+    Expose,
+    /// A host option like the scale factor or the update rate changed
+    OptionChange,
+    /// The plugin state has been restored, e.g. a preset was loaded
+    StateRestore,
+}
+
+/// The timing of an idle call, passed to `PluginUI::idle_with()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdleContext {
+    since_last_idle: Option<Duration>,
+    update_rate: Option<f32>,
+}
+
+impl IdleContext {
+    pub fn new(since_last_idle: Option<Duration>, update_rate: Option<f32>) -> Self {
+        Self {
+            since_last_idle,
+            update_rate,
         }
     }
+
+    /// The time since the previous idle call, None for the first one
+    pub fn since_last_idle(&self) -> Option<Duration> {
+        self.since_last_idle
+    }
+
+    /// The time since the previous idle call, zero for the first one
+    pub fn delta(&self) -> Duration {
+        self.since_last_idle.unwrap_or_default()
+    }
+
+    /// The rate in Hz the host declared by `ui:updateRate`, see `options`
+    pub fn update_rate(&self) -> Option<f32> {
+        self.update_rate
+    }
+}
+
+/// An interface returned by `PluginUI::extension_data()`
+///
+/// The interface lives as long as the binary, e.g. a `static` of
+/// the C struct the extension defines:
+///
+/// ```ignore
+/// static INSPECT: InspectInterface = InspectInterface {
+///     inspect: Some(inspect),
+/// };
+///
+/// fn extension_data(uri: &Uri) -> Option<ExtensionInterface> {
+///     match uri.to_bytes_with_nul() {
+///         INSPECT_URI => Some(ExtensionInterface::new(&INSPECT)),
+///         _ => None,
+///     }
+/// }
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ExtensionInterface(*const std::ffi::c_void);
+
+impl ExtensionInterface {
+    pub fn new<I: Sync + 'static>(interface: &'static I) -> Self {
+        Self(interface as *const I as *const std::ffi::c_void)
+    }
+
+    pub fn as_ptr(self) -> *const std::ffi::c_void {
+        self.0
+    }
+}
+
+/// A port event delivered by the host, see `PluginUI::port_changed()`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortEvent {
+    /// The new value of a control port, as delivered by the host
+    Control { index: u32, value: f32 },
+    /// An atom for an atom port, already passed to the port
+    Atom { index: u32 },
+}
+
+/// When `PluginUI::update()` is called for port events
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortEventBatching {
+    /// After every port event with `UpdateReason::HostPortEvent`
+    PerEvent,
+    /// Once for all port events since the last `idle()` or `run()`,
+    /// right before it, by `PluginUI::update_batch()` with the ports
+    /// changed. Its default calls `update()` with
+    /// `UpdateReason::HostPortEvents`.
+    ///
+    /// Saves the work of many updates for hosts flushing all ports at
+    /// once, but needs the host to call the idle interface or the
+    /// UI to be an external UI.
+    UntilIdle,
+}
+
+/// The ports the host delivered events for since the last `idle()`
+///
+/// Passed to `PluginUI::update_batch()` with `PortEventBatching::UntilIdle`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChangedPorts {
+    events: u32,
+    ports: Vec<u32>,
+}
+
+impl ChangedPorts {
+    /// The number of port events, counting repeated events for a port
+    pub fn events(&self) -> u32 {
+        self.events
+    }
+
+    /// The indices of the ports, ascending and each once
+    pub fn ports(&self) -> &[u32] {
+        &self.ports
+    }
+
+    pub fn contains(&self, port_index: u32) -> bool {
+        self.ports.binary_search(&port_index).is_ok()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events == 0
+    }
+
+    fn push(&mut self, port_index: u32) {
+        self.events += 1;
+        if let Err(position) = self.ports.binary_search(&port_index) {
+            self.ports.insert(position, port_index);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.events = 0;
+        self.ports.clear();
+    }
+}
+
+/// What to do if the UI is instantiated for another plugin than `PluginUI::PLUGIN_URI`
+///
+/// This happens when the host is misconfigured, e.g. the TTL of the
+/// UI is attached to the wrong plugin.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PluginUriMismatch {
+    /// The instantiation fails
+    Fail,
+    /// A warning is printed and the UI is instantiated as usual
+    Warn,
+    /// A warning is printed and the UI is instantiated in degraded
+    /// mode, see `PluginUIInfo::is_degraded()`
+    Degraded,
+}
+
+/// What to do with port events the ports of the UI don't fit, see `PortMismatch`
+///
+/// This happens when the TTL and the port collection disagree, e.g.
+/// a port has been added to the plugin but not to the UI, or the
+/// protocol of an atom port is not the one the host sends. Buffers
+/// not holding what their format says, e.g. a control value of
+/// another size than an `f32`, are rejected as well. The data is
+/// dropped in any case.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DispatchPolicy {
+    /// Nothing happens
+    Ignore,
+    /// A warning is printed for every event
+    Log,
+    /// A warning is printed for the first event of each kind and port
+    LogOnce,
+    /// `PluginUI::port_mismatch()` is called for every event
+    Callback,
+    /// The UI panics in debug builds, which poisons the instance, and
+    /// behaves like `LogOnce` in release builds
+    DebugPanic,
 }
 
 /// Information about the Plugin UI
@@ -44,7 +679,14 @@ impl PluginPortWriteHandle {
 pub struct PluginUIInfo<'a> {
     plugin_uri: &'a Uri,
     ui_uri: &'a Uri,
-    bundle_path: &'a Path,
+    bundle_path: Cow<'a, Path>,
+    degraded: bool,
+    cancellation: CancellationToken,
+    close_request: CloseRequest,
+    memory_budget: MemoryBudget,
+    host_capabilities: HostCapabilities,
+    host_options: HostOptions,
+    remaining_features: Option<RefCell<FeatureCache<'static>>>,
 }
 
 impl<'a> PluginUIInfo<'a> {
@@ -53,23 +695,28 @@ impl<'a> PluginUIInfo<'a> {
         plugin_uri: *const c_char,
         bundle_path: *const c_char,
     ) -> Result<Self, PluginUIInfoError> {
-        let bundle_path = Path::new(
-            Uri::from_ptr(bundle_path)
-                .to_str()
-                .map_err(PluginUIInfoError::InvalidBundlePathUtf8)?,
-        );
-        Ok(Self::new(
+        let bundle_path = bundle_path_from_raw(bundle_path)?;
+        let mut info = Self::new(
             Uri::from_ptr(plugin_uri),
             Uri::from_ptr((*descriptor).URI),
-            bundle_path,
-        ))
+            Path::new(""),
+        );
+        info.bundle_path = bundle_path;
+        Ok(info)
     }
 
     pub fn new(plugin_uri: &'a Uri, ui_uri: &'a Uri, bundle_path: &'a Path) -> Self {
         Self {
             plugin_uri,
             ui_uri,
-            bundle_path,
+            bundle_path: Cow::Borrowed(bundle_path),
+            degraded: false,
+            cancellation: CancellationToken::new(),
+            close_request: CloseRequest::new(),
+            memory_budget: MemoryBudget::unlimited(),
+            host_capabilities: HostCapabilities::default(),
+            host_options: HostOptions::default(),
+            remaining_features: None,
         }
     }
 
@@ -88,7 +735,158 @@ impl<'a> PluginUIInfo<'a> {
     /// This is useful to get if the plugin needs to store extra resources in its bundle directory,
     /// such as presets, or any other kind of data.
     pub fn bundle_path(&self) -> &Path {
-        self.bundle_path
+        &self.bundle_path
+    }
+
+    /// The bundle path for display, characters not valid in UTF-8 replaced
+    pub fn bundle_path_lossy(&self) -> Cow<'_, str> {
+        self.bundle_path.to_string_lossy()
+    }
+
+    /// True if the UI is instantiated for an unexpected plugin
+    ///
+    /// Then the UI should not rely on the plugin's ports and
+    /// messages, e.g. only show a generic control panel. See
+    /// `PluginUriMismatch::Degraded`.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    /// The token of the instance, cancelled right before `PluginUI::cleanup()`
+    ///
+    /// Hand it, or a child of it, to background work started by the
+    /// UI, see `worker`.
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    /// The handle to ask the host to close the UI, see `close_request`
+    pub fn close_request(&self) -> CloseRequest {
+        self.close_request.clone()
+    }
+
+    /// The memory budget of the instance, see `PluginUI::MEMORY_BUDGET` and `memory`
+    pub fn memory_budget(&self) -> MemoryBudget {
+        self.memory_budget.clone()
+    }
+
+    /// The features the host supplied, see `capabilities`
+    pub fn host_capabilities(&self) -> &HostCapabilities {
+        &self.host_capabilities
+    }
+
+    /// The options the host passed on instantiation, see `options`
+    pub fn host_options(&self) -> &HostOptions {
+        &self.host_options
+    }
+
+    /// Takes a host feature not retrieved by `PluginUI::InitFeatures`
+    ///
+    /// Gives access to host extensions the crate does not model,
+    /// wrapped by a type implementing `Feature`. The features are
+    /// only available during `PluginUI::new()` and each is taken
+    /// once, None is returned if the host did not supply it.
+    pub fn take_feature<F: Feature>(&self) -> Option<F> {
+        self.remaining_features
+            .as_ref()?
+            .borrow_mut()
+            .retrieve_feature::<F, Option<F>>(ThreadingClass::Instantiation)
+            .ok()
+            .flatten()
+    }
+
+    /// Copies the URIs and the bundle path out of the host's memory
+    ///
+    /// The info itself borrows from the host, so it can only be kept
+    /// by the UI in its owned variant.
+    pub fn to_owned(&self) -> OwnedPluginUIInfo {
+        OwnedPluginUIInfo {
+            plugin_uri: self.plugin_uri.to_owned(),
+            ui_uri: self.ui_uri.to_owned(),
+            bundle_path: self.bundle_path.to_path_buf(),
+            degraded: self.degraded,
+            cancellation: self.cancellation.clone(),
+            close_request: self.close_request.clone(),
+            memory_budget: self.memory_budget.clone(),
+            host_capabilities: self.host_capabilities.clone(),
+            host_options: self.host_options,
+        }
+    }
+}
+
+/// A `PluginUIInfo` owning its data, see `PluginUIInfo::to_owned()`
+///
+/// The features not retrieved by `PluginUI::InitFeatures` are left
+/// behind, see `PluginUIInfo::take_feature()`.
+#[derive(Clone)]
+pub struct OwnedPluginUIInfo {
+    plugin_uri: UriBuf,
+    ui_uri: UriBuf,
+    bundle_path: PathBuf,
+    degraded: bool,
+    cancellation: CancellationToken,
+    close_request: CloseRequest,
+    memory_budget: MemoryBudget,
+    host_capabilities: HostCapabilities,
+    host_options: HostOptions,
+}
+
+impl OwnedPluginUIInfo {
+    pub fn plugin_uri(&self) -> &Uri {
+        &self.plugin_uri
+    }
+
+    pub fn ui_uri(&self) -> &Uri {
+        &self.ui_uri
+    }
+
+    pub fn bundle_path(&self) -> &Path {
+        &self.bundle_path
+    }
+
+    /// The bundle path for display, characters not valid in UTF-8 replaced
+    pub fn bundle_path_lossy(&self) -> Cow<'_, str> {
+        self.bundle_path.to_string_lossy()
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.degraded
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    pub fn close_request(&self) -> CloseRequest {
+        self.close_request.clone()
+    }
+
+    pub fn memory_budget(&self) -> MemoryBudget {
+        self.memory_budget.clone()
+    }
+
+    pub fn host_capabilities(&self) -> &HostCapabilities {
+        &self.host_capabilities
+    }
+
+    pub fn host_options(&self) -> &HostOptions {
+        &self.host_options
+    }
+
+    /// Borrows it as `PluginUIInfo` again, e.g. to create a sub-UI
+    pub fn as_info(&self) -> PluginUIInfo<'_> {
+        PluginUIInfo {
+            plugin_uri: &self.plugin_uri,
+            ui_uri: &self.ui_uri,
+            bundle_path: Cow::Borrowed(&self.bundle_path),
+            degraded: self.degraded,
+            cancellation: self.cancellation.clone(),
+            close_request: self.close_request.clone(),
+            memory_budget: self.memory_budget.clone(),
+            host_capabilities: self.host_capabilities.clone(),
+            host_options: self.host_options,
+            remaining_features: None,
+        }
     }
 }
 
@@ -103,78 +901,370 @@ impl<'a> PluginUIInfo<'a> {
 /// which dereferences raw pointers, does safety checks and then calls
 /// the corresponding methods in PluginUI.
 ///
-pub trait PluginUI: Sized + 'static {
+/// The trait does not require `'static`, so a `PluginUI` can have
+/// lifetime parameters and borrow data, e.g. from statics or when it
+/// is a part of another UI.
+pub trait PluginUI: Sized {
     /// The type of the port collection
     type UIPorts: UIPortsTrait;
 
-    /// The host features used by this plugin UI
+    /// The host features used by this plugin UI
+    ///
+    /// This collection will be created by the framework when the
+    /// plugin UI is initialized.
+    ///
+    /// If a host feature is missing, the plugin UI creation simply
+    /// fails and your plugin host will tell you so.
+    type InitFeatures: FeatureCollection<'static>;
+
+    /// The URI of the plugin the UI is made for, including the terminating null byte
+    ///
+    /// If set, the plugin URI passed by the host is checked on
+    /// instantiation and `PLUGIN_URI_MISMATCH` decides what happens
+    /// if it does not match. By default no check is done.
+    const PLUGIN_URI: Option<&'static [u8]> = None;
+
+    /// What to do if the plugin URI passed by the host does not match `PLUGIN_URI`
+    const PLUGIN_URI_MISMATCH: PluginUriMismatch = PluginUriMismatch::Warn;
+
+    /// The URIs of the host features the UI needs, null terminated, see `description`
+    const REQUIRED_FEATURES: &'static [&'static [u8]] = &[];
+
+    /// The URIs of the host features the UI uses if present, null terminated
+    const OPTIONAL_FEATURES: &'static [&'static [u8]] = &[];
+
+    /// The ports the UI expects the plugin to have, see `description`
+    const PORTS: &'static [PortExpectation] = &[];
+
+    /// What to do with port events the ports don't fit
+    const DISPATCH_POLICY: DispatchPolicy = DispatchPolicy::Log;
+
+    /// When `update()` is called for port events
+    const PORT_EVENT_BATCHING: PortEventBatching = PortEventBatching::PerEvent;
+
+    /// The bytes the queues, histories and caches of the UI may hold together, see `memory`
+    ///
+    /// By default the memory is only accounted.
+    const MEMORY_BUDGET: Option<usize> = None;
+
+    /// True if the UI provides `ui:idleInterface`, so the host calls `idle()`
+    ///
+    /// UIs driven by their own event loop may leave it out. The
+    /// interfaces exported need to match the `lv2:extensionData` of
+    /// the TTL, see `description`.
+    const IDLE_INTERFACE: bool = true;
+
+    /// True if the UI provides `ui:showInterface`, so the host calls `show()` and `hide()`
+    const SHOW_INTERFACE: bool = false;
+
+    /// True if the UI provides `ui:resize`, so the host calls `resized()`
+    const RESIZE_INTERFACE: bool = false;
+
+    /// True if the UI provides `opts:interface`, so the host can change options, see `options`
+    const OPTIONS_INTERFACE: bool = false;
+
+    /// True if the UI implements the KXStudio programs extension, see `kxstudio`
+    #[cfg(feature = "kxstudio")]
+    const PROGRAMS: bool = false;
+
+    /// Create a plugin UI instance
+    ///
+    /// `parent_window` is null if the host did not provide the
+    /// `ui:parent` feature. Use `WindowMode::from_parent()` to tell
+    /// the cases apart, or `backend::session::Session::window_mode()`
+    /// to also fall back to a top-level window where embedding is not
+    /// possible.
+    ///
+    /// On failure the error is logged and the instantiation fails.
+    /// The errors of the windowing backends convert into
+    /// `PluginUIError::Window`, so `?` passes them on.
+    fn new(
+        plugin_ui_info: &PluginUIInfo,
+        features: &mut Self::InitFeatures,
+        parent_window: *mut std::ffi::c_void,
+        write_handle: PluginPortWriteHandle,
+    ) -> Result<Self, PluginUIError>;
+
+    /// Cleanup the PluguinUI
+    fn cleanup(&mut self);
+
+    /// Supposed to return a mutable reference to the UI's port collection
+    fn ports(&mut self) -> &mut Self::UIPorts;
+
+    /// Called when some port has been updated.
+    ///
+    /// The plugin UI then should check all its ports what has changed
+    /// and trigger repaint (exposure) events to update the UI
+    /// accordingly. `reason` tells why, see `UpdateReason`.
+    fn update(&mut self, reason: UpdateReason);
+
+    /// Called once per `idle()` for the port events batched by `PortEventBatching::UntilIdle`
+    ///
+    /// `changed` tells which ports have received events, so the UI
+    /// only needs to look at those. By default `update()` is called
+    /// with `UpdateReason::HostPortEvents`.
+    fn update_batch(&mut self, changed: &ChangedPorts) {
+        self.update(UpdateReason::HostPortEvents(changed.events()))
+    }
+
+    /// Called periodically from the hosts. The UI then can process UI
+    /// events and communicate events back to the plugin by updating
+    /// its ports.
+    ///
+    /// Returns non-zero if the UI has been closed. UIs needing the
+    /// time between the calls implement `idle_with()` instead.
+    fn idle(&mut self) -> i32 {
+        0
+    }
+
+    /// Called for the idle interface, with the timing of the call
+    ///
+    /// By default this is forwarded to `idle()`.
+    fn idle_with(&mut self, _context: &IdleContext) -> i32 {
+        self.idle()
+    }
+
+    /// Supposed to return the LV2UI_Widget pointer
+    fn widget(&self) -> sys::LV2UI_Widget;
+
+    /// Supposed to return true if the UI is a KXStudio external UI
+    ///
+    /// Then the UI opens its own window rather than being embedded
+    /// into the host's window and `widget()` is not used. The host
+    /// calls `run()`, `show()` and `hide()` instead. See
+    /// `external_ui` for details.
+    fn is_external(&self) -> bool {
+        false
+    }
+
+    /// Called periodically by the host if the UI is an external UI.
+    ///
+    /// By default this is forwarded to `idle()`.
+    fn run(&mut self) {
+        self.idle();
+    }
+
+    /// Called when the host wants an external UI to show its window
+    ///
+    /// Also called through the show interface, see `SHOW_INTERFACE`.
+    fn show(&mut self) {}
+
+    /// Called when the host wants an external UI to hide its window
+    fn hide(&mut self) {}
+
+    /// Called when the UI has become visible
+    ///
+    /// Lets the UI start work only needed while it is seen, e.g.
+    /// meters, animations or port subscriptions. Called after
+    /// `show()` if the UI was hidden, and by the UI forwarding the
+    /// map events of its backend, see `X11Window::take_visibility_change()`.
+    /// Both may report the same change, so starting the work needs
+    /// to be idempotent. By default nothing happens.
+    fn on_show(&mut self) {}
+
+    /// Called when the UI has been hidden, to stop what `on_show()` started
+    ///
+    /// Called after `hide()` if the UI was shown, and by the UI
+    /// forwarding the unmap events of its backend. By default
+    /// nothing happens.
+    fn on_hide(&mut self) {}
+
+    /// Called when the host closes the UI, right before `cleanup()`
+    ///
+    /// A shown UI gets `on_hide()` before. By default nothing happens.
+    fn on_close(&mut self) {}
+
+    /// The size the UI would like to have initially
+    ///
+    /// Passed to the host's `ui:resize` feature right after `new()`,
+    /// if the host provides it. `(0, 0)`, the default, leaves the
+    /// size to the host.
+    fn size_hint(&self) -> (u32, u32) {
+        (0, 0)
+    }
+
+    /// Called when the UI's window has been resized
     ///
-    /// This collection will be created by the framework when the
-    /// plugin UI is initialized.
+    /// Called by the host through `RESIZE_INTERFACE` or by the UI
+    /// forwarding the resize events of its backend, see
+    /// `X11Window::take_resize()`. By default this is forwarded to
+    /// `update()`.
+    fn resized(&mut self, _width: u32, _height: u32) {
+        self.update(UpdateReason::Resize);
+    }
+
+    /// Called when the scale factor for HiDPI displays has changed
     ///
-    /// If a host feature is missing, the plugin UI creation simply
-    /// fails and your plugin host will tell you so.
-    type InitFeatures: FeatureCollection<'static>;
+    /// Called when the host sets `ui:scaleFactor` through
+    /// `OPTIONS_INTERFACE` or by the UI forwarding the changes its
+    /// backend detects, see `options`. By default this is forwarded
+    /// to `update()`.
+    fn scale_factor_changed(&mut self, _scale_factor: f32) {
+        self.update(UpdateReason::OptionChange);
+    }
 
-    /// Create a plugin UI instance
-    fn new(
-        plugin_ui_info: &PluginUIInfo,
-        features: &mut Self::InitFeatures,
-        parent_window: *mut std::ffi::c_void,
-        write_handle: PluginPortWriteHandle,
-    ) -> Option<Self>;
+    /// Called when the host selects a program of the plugin, see `kxstudio`
+    #[cfg(feature = "kxstudio")]
+    fn select_program(&mut self, _bank: u32, _program: u32) {}
 
-    /// Cleanup the PluguinUI
-    fn cleanup(&mut self);
+    /// Called when the UI's window has been moved into another parent window
+    ///
+    /// Some hosts, e.g. tabbed ones, reparent the window after
+    /// instantiation. LV2 has no notification for that, so the
+    /// windowing backends detect it and the UI forwards it here, see
+    /// `X11Window::take_parent_change()`. By default nothing happens.
+    fn parent_changed(&mut self, _new_parent: *mut std::ffi::c_void) {}
 
-    /// Supposed to return a mutable reference to the UI's port collection
-    fn ports(&mut self) -> &mut Self::UIPorts;
+    /// Called when a file has been dropped onto the UI's window
+    ///
+    /// `x` and `y` are the position of the drop in pixels relative
+    /// to the window. Hosts don't take part in drag and drop, so the
+    /// windowing backends receive the drops and the UI forwards them
+    /// here, see `backend::dnd`. By default nothing happens.
+    fn file_dropped(&mut self, _path: &Path, _x: f64, _y: f64) {}
 
-    /// Called when some port has been updated.
+    /// Called when the plugin reports that its state has changed
     ///
-    /// The plugin UI then should check all its ports what has changed
-    /// and trigger repaint (exposure) events to update the UI
-    /// accordingly.
-    fn update(&mut self);
+    /// Plugins send a `state:StateChanged` object on an atom port
+    /// when their state differs from the saved one, e.g. to update a
+    /// "modified" indicator. The object is recognized if the host
+    /// provides `urid:map`. It is also passed to the port as usual,
+    /// before this is called. By default nothing happens.
+    fn state_changed(&mut self) {}
 
-    /// Called periodically from the hosts. The UI then can process UI
-    /// events and communicate events back to the plugin by updating
-    /// its ports.
-    fn idle(&mut self) -> i32;
+    /// Called for every port event, right after it has been passed to the port
+    ///
+    /// Lets a UI react to the one port that changed instead of
+    /// checking all of them. Events the ports don't fit and atoms
+    /// handled by `visit_atom()` are left out. `update()` is still called afterwards
+    /// according to `PORT_EVENT_BATCHING`, so UIs only implementing
+    /// this can leave `update()` empty. By default nothing happens.
+    fn port_changed(&mut self, _event: PortEvent) {}
 
-    /// Supposed to return the LV2UI_Widget pointer
-    fn widget(&self) -> sys::LV2UI_Widget;
+    /// Called for port events the ports don't fit, with `DispatchPolicy::Callback`
+    ///
+    /// The data of the event has been dropped. By default nothing
+    /// happens.
+    fn port_mismatch(&mut self, _mismatch: PortMismatch) {}
+
+    /// Called with an atom for an atom port while it is still in the host's buffer
+    ///
+    /// Return true if the atom has been handled. Then it is not
+    /// copied into the `UIAtomPort`. By default all atoms are
+    /// copied. Handling large atoms like waveforms or sample dumps
+    /// here saves the copy.
+    fn visit_atom(&mut self, _atom: BorrowedAtom) -> bool {
+        false
+    }
+
+    /// Returns the UI's own interface for the extension `uri`
+    ///
+    /// Lets a UI provide interfaces of host integrations the crate
+    /// does not model. Called by the host without an instance, for
+    /// URIs other than the interfaces the crate provides. By default
+    /// the UI provides none.
+    fn extension_data(_uri: &Uri) -> Option<ExtensionInterface> {
+        None
+    }
 
     /// Updates a specific ports, when the host wants to message.
     /// Neither to be called manually nor to be reimplemented
+    ///
+    /// Returns the event passed to the port, None if `visit_atom()`
+    /// handled the atom. `update()` is called afterwards according to
+    /// `PORT_EVENT_BATCHING`.
     fn port_event(
         &mut self,
         port_index: u32,
         buffer_size: u32,
         format: u32,
         buffer: *const std::ffi::c_void,
-    ) {
+    ) -> Result<Option<PortEvent>, PortMismatch> {
+        let port_urid = self.ports().map_atom_port(port_index).map(|p| p.urid());
+        let visited = format != 0
+            && port_urid == Some(format)
+            && check_atom(port_index, buffer_size, format, buffer).is_ok()
+            && self.visit_atom(BorrowedAtom::new(
+                unsafe { std::slice::from_raw_parts(buffer as *const u8, buffer_size as usize) },
+                port_index,
+            ));
+        if visited {
+            return Ok(None);
+        }
         self.ports()
-            .port_event(port_index, buffer_size, format, buffer);
-        self.update();
+            .dispatch_port_event(port_index, buffer_size, format, buffer)
+            .map(Some)
     }
 }
 
+/// The handle the host gets for an instantiated `PluginUI`
+///
+/// The handle owns the UI. It is allocated on instantiation and freed
+/// by the host calling `cleanup()`.
 #[repr(C)]
 pub struct PluginUIInstance<T: PluginUI> {
+    // Needs to be the first field, as the host passes a pointer to it
+    // to the callbacks of the external-ui extension.
+    external_widget: ExternalUIWidget,
     instance: T,
     widget: sys::LV2UI_Widget,
     features: *const *const sys::LV2_Feature,
+    poisoned: bool,
+    write_handle: PluginPortWriteHandle,
+    state_changed_urids: Option<(u32, u32)>,
+    pending_port_events: ChangedPorts,
+    log_sink: SinkSlot,
+    cancellation: CancellationToken,
+    options: HostOptions,
+    option_urids: Option<OptionURIDs>,
+    last_idle: Option<Instant>,
+    close_request: CloseRequest,
+    // Set for external UIs, called once the UI asked to close
+    ui_closed: Option<UIClosedCallback>,
+    // Shown by the host through `show()`
+    shown: bool,
+    // The mismatches already logged by `DispatchPolicy::LogOnce`
+    reported_mismatches: HashSet<PortMismatch>,
 }
 
-fn retrieve_parent_window(features: *const *const sys::LV2_Feature) -> *mut std::ffi::c_void {
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Runs `f` catching a panic, so that it does not unwind into the host
+fn catch_panic<R>(callback: &str, f: impl FnOnce() -> R) -> Option<R> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(r) => Some(r),
+        Err(payload) => {
+            ui_log!(
+                Error,
+                "plugin UI panicked in {}: {}",
+                callback,
+                panic_message(payload.as_ref())
+            );
+            None
+        }
+    }
+}
+
+pub(crate) fn retrieve_feature(
+    features: *const *const sys::LV2_Feature,
+    uri: &[u8],
+) -> *mut std::ffi::c_void {
     let mut fptr = features;
 
     while !fptr.is_null() {
         unsafe {
-            if CStr::from_ptr((**fptr).URI)
-                == CStr::from_bytes_with_nul_unchecked(sys::LV2_UI__parent)
-            {
+            if (*fptr).is_null() {
+                break;
+            }
+            if CStr::from_ptr((**fptr).URI) == CStr::from_bytes_with_nul_unchecked(uri) {
                 return (**fptr).data;
             }
             fptr = fptr.add(1);
@@ -183,6 +1273,61 @@ fn retrieve_parent_window(features: *const *const sys::LV2_Feature) -> *mut std:
     std::ptr::null_mut()
 }
 
+fn retrieve_parent_window(features: *const *const sys::LV2_Feature) -> *mut std::ffi::c_void {
+    retrieve_feature(features, sys::LV2_UI__parent)
+}
+
+/// Asks the host to resize the UI's window if it provides `ui:resize`
+fn request_size(features: *const *const sys::LV2_Feature, width: u32, height: u32) {
+    let resize = retrieve_feature(features, sys::LV2_UI__resize) as *const sys::LV2UI_Resize;
+    let resize = match unsafe { resize.as_ref() } {
+        Some(resize) => resize,
+        None => return,
+    };
+    let length = |length: u32| length.min(i32::MAX as u32) as i32;
+    if let Some(ui_resize) = resize.ui_resize {
+        if unsafe { ui_resize(resize.handle, length(width), length(height)) } != 0 {
+            ui_log!(Note, "host refused the size {}x{}", width, height);
+        }
+    }
+}
+
+/// Maps the null terminated `uri` if the host provides `urid:map`
+///
+/// For URIDs the framework needs before `InitFeatures` are retrieved.
+pub(crate) fn map_raw(features: *const *const sys::LV2_Feature, uri: &[u8]) -> Option<u32> {
+    let map = retrieve_feature(features, sys::LV2_URID__map) as *const sys::LV2_URID_Map;
+    let map = unsafe { map.as_ref()? };
+    let map_function = map.map?;
+    // Zero means the URI could not be mapped
+    match unsafe { map_function(map.handle, uri.as_ptr() as *const c_char) } {
+        0 => None,
+        urid => Some(urid),
+    }
+}
+
+/// Maps the URIDs of `atom:Object` and `state:StateChanged` if the host provides `urid:map`
+fn map_state_changed(features: *const *const sys::LV2_Feature) -> Option<(u32, u32)> {
+    Some((
+        map_raw(features, sys::LV2_ATOM__Object)?,
+        map_raw(features, sys::LV2_STATE__StateChanged)?,
+    ))
+}
+
+/// True if the atom in `buffer` is a `state:StateChanged` object
+unsafe fn is_state_changed(
+    buffer_size: u32,
+    buffer: *const std::ffi::c_void,
+    (object, state_changed): (u32, u32),
+) -> bool {
+    // `LV2_Atom` header followed by the id and type of the object
+    if buffer.is_null() || buffer_size < 16 {
+        return false;
+    }
+    let words = buffer as *const u32;
+    words.add(1).read_unaligned() == object && words.add(3).read_unaligned() == state_changed
+}
+
 impl<T: PluginUI> PluginUIInstance<T> {
     pub unsafe extern "C" fn instantiate(
         descriptor: *const sys::LV2UI_Descriptor,
@@ -192,11 +1337,47 @@ impl<T: PluginUI> PluginUIInstance<T> {
         controller: sys::LV2UI_Controller,
         widget: *mut sys::LV2UI_Widget,
         features: *const *const sys::LV2_Feature,
+    ) -> sys::LV2UI_Handle {
+        let timer = Timer::start();
+        let log_sink = logging::default_sink(features);
+        let handle = logging::with_sink(&log_sink.clone(), || {
+            Self::instantiate_logged(
+                descriptor,
+                plugin_uri,
+                bundle_path,
+                write_function,
+                controller,
+                widget,
+                features,
+                log_sink,
+            )
+        });
+        let ui_uri = descriptor
+            .as_ref()
+            .filter(|descriptor| !descriptor.URI.is_null())
+            .map(|descriptor| CStr::from_ptr(descriptor.URI));
+        instrument::instantiated(ui_uri, !handle.is_null(), timer);
+        handle
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn instantiate_logged(
+        descriptor: *const sys::LV2UI_Descriptor,
+        plugin_uri: *const c_char,
+        bundle_path: *const c_char,
+        write_function: sys::LV2UI_Write_Function,
+        controller: sys::LV2UI_Controller,
+        widget: *mut sys::LV2UI_Widget,
+        features: *const *const sys::LV2_Feature,
+        log_sink: SinkSlot,
     ) -> sys::LV2UI_Handle {
         let descriptor = match descriptor.as_ref() {
             Some(descriptor) => descriptor,
             None => {
-                eprintln!("Failed to initialize plugin UI: Descriptor points to null");
+                ui_log!(
+                    Error,
+                    "Failed to initialize plugin UI: Descriptor points to null"
+                );
                 return std::ptr::null_mut();
             }
         };
@@ -204,7 +1385,8 @@ impl<T: PluginUI> PluginUIInstance<T> {
         let plugin_ui_info = match PluginUIInfo::from_raw(descriptor, plugin_uri, bundle_path) {
             Ok(info) => info,
             Err(e) => {
-                eprintln!(
+                ui_log!(
+                    Error,
                     "Failed to initialize plugin: Illegal info from host: {:?}",
                     e
                 );
@@ -212,6 +1394,40 @@ impl<T: PluginUI> PluginUIInstance<T> {
             }
         };
 
+        let mut plugin_ui_info = plugin_ui_info;
+        if let Some(expected) = T::PLUGIN_URI {
+            let expected = match Uri::from_bytes_with_nul(expected) {
+                Ok(expected) => expected,
+                Err(e) => {
+                    ui_log!(
+                        Error,
+                        "Failed to initialize plugin UI: Invalid PLUGIN_URI: {}",
+                        e
+                    );
+                    return std::ptr::null_mut();
+                }
+            };
+            if plugin_ui_info.plugin_uri() != expected {
+                ui_log!(
+                    Warning,
+                    "Plugin UI for {:?} instantiated for plugin {:?}",
+                    expected,
+                    plugin_ui_info.plugin_uri()
+                );
+                match T::PLUGIN_URI_MISMATCH {
+                    PluginUriMismatch::Fail => return std::ptr::null_mut(),
+                    PluginUriMismatch::Warn => {}
+                    PluginUriMismatch::Degraded => plugin_ui_info.degraded = true,
+                }
+            }
+        }
+        if let Some(limit) = T::MEMORY_BUDGET {
+            plugin_ui_info.memory_budget = MemoryBudget::new(limit);
+        }
+        plugin_ui_info.host_capabilities = HostCapabilities::from_raw(features);
+        let option_urids = OptionURIDs::map(features);
+        plugin_ui_info.host_options = HostOptions::from_features(features, option_urids.as_ref());
+
         let mut feature_cache = FeatureCache::from_raw(features);
 
         let parent_widget = retrieve_parent_window(features);
@@ -220,38 +1436,163 @@ impl<T: PluginUI> PluginUIInstance<T> {
             match T::InitFeatures::from_cache(&mut feature_cache, ThreadingClass::Instantiation) {
                 Ok(f) => f,
                 Err(e) => {
-                    eprintln!("extension data {}", e);
+                    ui_log!(Error, "extension data {}", e);
                     return std::ptr::null_mut();
                 }
             };
 
-        let write_handle = PluginPortWriteHandle {
-            write_function,
-            controller,
-        };
+        plugin_ui_info.remaining_features = Some(RefCell::new(feature_cache));
+
+        let write_handle = PluginPortWriteHandle::new(write_function, controller);
+        let instance_write_handle = write_handle.clone();
+
+        let instance = catch_panic("new()", || {
+            T::new(
+                &plugin_ui_info,
+                &mut init_features,
+                parent_widget,
+                write_handle,
+            )
+        })
+        .and_then(|result| {
+            result
+                .map_err(|e| ui_log!(Error, "Failed to initialize plugin UI: {:?}", e))
+                .ok()
+        });
 
-        match T::new(
-            &plugin_ui_info,
-            &mut init_features,
-            parent_widget,
-            write_handle,
-        ) {
-            Some(instance) => {
-                *widget = instance.widget();
-                let handle = Box::new(Self {
+        match instance {
+            Some(mut instance) => {
+                let ui_widget = catch_panic("widget()", || match instance.is_external() {
+                    true => None,
+                    false => Some(instance.widget()),
+                });
+                // Without a widget there is nothing the host could embed
+                let ui_widget = match ui_widget {
+                    Some(ui_widget) => ui_widget,
+                    None => {
+                        catch_panic("cleanup()", || instance.cleanup());
+                        catch_panic("drop()", move || drop(instance));
+                        return std::ptr::null_mut();
+                    }
+                };
+                let size_hint = catch_panic("size_hint()", || instance.size_hint());
+                let handle = Box::leak(Box::new(Self {
+                    external_widget: ExternalUIWidget {
+                        run: Some(Self::external_run),
+                        show: Some(Self::external_show),
+                        hide: Some(Self::external_hide),
+                    },
                     instance,
-                    widget: *widget,
+                    widget: std::ptr::null_mut(),
                     features,
-                });
-                Box::leak(handle) as *mut Self as sys::LV2UI_Handle
+                    poisoned: false,
+                    write_handle: instance_write_handle,
+                    state_changed_urids: map_state_changed(features),
+                    pending_port_events: ChangedPorts::default(),
+                    log_sink,
+                    cancellation: plugin_ui_info.cancellation_token(),
+                    options: plugin_ui_info.host_options,
+                    option_urids,
+                    last_idle: None,
+                    close_request: plugin_ui_info.close_request(),
+                    ui_closed: None,
+                    shown: false,
+                    reported_mismatches: HashSet::new(),
+                }));
+                match ui_widget {
+                    Some(ui_widget) => handle.widget = ui_widget,
+                    None => {
+                        handle.widget = &mut handle.external_widget as *mut ExternalUIWidget
+                            as sys::LV2UI_Widget;
+                        handle.ui_closed = ui_closed_callback(features);
+                    }
+                }
+                *widget = handle.widget;
+                if let Some((width, height)) = size_hint.filter(|&(w, h)| w > 0 && h > 0) {
+                    request_size(features, width, height);
+                }
+                #[cfg(feature = "jalv")]
+                crate::jalv::trace("instantiate");
+                handle as *mut Self as sys::LV2UI_Handle
             }
             None => std::ptr::null_mut(),
         }
     }
 
+    #[cfg(feature = "testing")]
+    pub(crate) fn ui_mut(&mut self) -> &mut T {
+        &mut self.instance
+    }
+
+    /// Runs `f` on the UI unless the UI has panicked before
+    ///
+    /// A panic is caught and marks the UI as poisoned. After that
+    /// the UI is not called anymore, except for `cleanup()`.
+    unsafe fn guarded<R>(
+        handle: sys::LV2UI_Handle,
+        callback: &str,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> Option<R> {
+        let handle = (handle as *mut Self).as_mut()?;
+        #[cfg(feature = "jalv")]
+        crate::jalv::trace(callback.trim_end_matches("()"));
+        if handle.poisoned {
+            return None;
+        }
+        let instance = &mut handle.instance;
+        let r = logging::with_sink(&handle.log_sink, || catch_panic(callback, || f(instance)));
+        handle.poisoned = r.is_none();
+        r
+    }
+
+    /// Cleans up the UI and frees the handle
     pub unsafe extern "C" fn cleanup(handle: sys::LV2UI_Handle) {
-        let handle = handle as *mut Self;
-        (*handle).instance.cleanup();
+        if handle.is_null() {
+            return;
+        }
+        #[cfg(feature = "jalv")]
+        crate::jalv::trace("cleanup");
+        let mut handle = Box::from_raw(handle as *mut Self);
+        let log_sink = handle.log_sink.clone();
+        handle.cancellation.cancel();
+        logging::with_sink(&log_sink, || {
+            // The UI gets the chance to close its window even if poisoned
+            let instance = &mut handle.instance;
+            if !handle.poisoned {
+                if handle.shown {
+                    catch_panic("on_hide()", || instance.on_hide());
+                }
+                catch_panic("on_close()", || instance.on_close());
+            }
+            catch_panic("cleanup()", || instance.cleanup());
+            catch_panic("drop()", move || drop(handle));
+        });
+    }
+
+    unsafe fn mismatched(handle: sys::LV2UI_Handle, mismatch: PortMismatch) {
+        let instance = match (handle as *mut Self).as_mut() {
+            Some(instance) => instance,
+            None => return,
+        };
+        let log_once = |instance: &mut Self| {
+            if instance.reported_mismatches.insert(mismatch) {
+                logging::with_sink(&instance.log_sink, || mismatch.log());
+            }
+        };
+        match T::DISPATCH_POLICY {
+            DispatchPolicy::Ignore => {}
+            DispatchPolicy::Log => logging::with_sink(&instance.log_sink, || mismatch.log()),
+            DispatchPolicy::LogOnce => log_once(instance),
+            DispatchPolicy::Callback => {
+                Self::guarded(handle, "port_mismatch()", |ui| ui.port_mismatch(mismatch));
+            }
+            DispatchPolicy::DebugPanic if cfg!(debug_assertions) => {
+                Self::guarded::<()>(handle, "port_event()", |_| {
+                    panic!("port event not dispatched: {:?}", mismatch)
+                });
+            }
+            DispatchPolicy::DebugPanic => log_once(instance),
+        }
     }
 
     pub unsafe extern "C" fn port_event(
@@ -261,30 +1602,524 @@ impl<T: PluginUI> PluginUIInstance<T> {
         format: u32,
         buffer: *const std::ffi::c_void,
     ) {
-        let handle = handle as *mut Self;
-        (*handle)
-            .instance
-            .port_event(port_index, buffer_size, format, buffer);
+        let timer = Timer::start();
+        // Nothing of a malformed event is read, not even for the diagnostics
+        if let Err(malformed) = check_buffer(port_index, buffer_size, format, buffer) {
+            Self::mismatched(handle, malformed);
+            instrument::port_event(port_index, format, buffer_size, timer);
+            return;
+        }
+        let mut metered = false;
+        if let Some(handle) = (handle as *const Self).as_ref() {
+            handle
+                .write_handle
+                .state
+                .record_event(port_index, format, buffer_size);
+            metered = handle.write_handle.state.has_metrics();
+        }
+        // The atoms dropped are only counted for the metrics
+        let dropped_before = if format != 0 && metered {
+            Self::guarded(handle, "ports()", |ui| {
+                ui.ports()
+                    .map_atom_port(port_index)
+                    .map(|port| port.dropped())
+            })
+            .flatten()
+        } else {
+            None
+        };
+        let dispatched = Self::guarded(handle, "port_event()", |ui| {
+            ui.port_event(port_index, buffer_size, format, buffer)
+        });
+        // The data of a mismatch has been dropped, so nothing changed
+        let event = match dispatched {
+            Some(Ok(event)) => event,
+            Some(Err(mismatch)) => {
+                Self::mismatched(handle, mismatch);
+                instrument::port_event(port_index, format, buffer_size, timer);
+                return;
+            }
+            None => None,
+        };
+        if let Some(before) = dropped_before {
+            let after = Self::guarded(handle, "ports()", |ui| {
+                ui.ports()
+                    .map_atom_port(port_index)
+                    .map(|port| port.dropped())
+            });
+            if let (Some(after), Some(handle)) = (after.flatten(), (handle as *const Self).as_ref())
+            {
+                if after > before {
+                    handle
+                        .write_handle
+                        .state
+                        .with_metrics(|metrics| metrics.dropped(port_index, after - before));
+                }
+            }
+        }
+        if let Some(event) = event {
+            Self::guarded(handle, "port_changed()", |ui| ui.port_changed(event));
+        }
+        match T::PORT_EVENT_BATCHING {
+            PortEventBatching::PerEvent => {
+                Self::guarded(handle, "update()", |ui| {
+                    ui.update(UpdateReason::HostPortEvent(port_index))
+                });
+            }
+            PortEventBatching::UntilIdle => {
+                if let Some(handle) = (handle as *mut Self).as_mut() {
+                    handle.pending_port_events.push(port_index);
+                }
+            }
+        }
+        let state_changed = (handle as *const Self)
+            .as_ref()
+            .and_then(|handle| handle.state_changed_urids)
+            .is_some_and(|urids| format != 0 && is_state_changed(buffer_size, buffer, urids));
+        if state_changed {
+            Self::guarded(handle, "state_changed()", |ui| ui.state_changed());
+        }
+        instrument::port_event(port_index, format, buffer_size, timer);
     }
 
+    // The interfaces returned by `extension_data()`, as constants they
+    // are promoted to statics
+    const IDLE_INTERFACE: sys::LV2UI_Idle_Interface = sys::LV2UI_Idle_Interface {
+        idle: Some(Self::idle),
+    };
+
+    const SHOW_INTERFACE: sys::LV2UI_Show_Interface = sys::LV2UI_Show_Interface {
+        show: Some(Self::show),
+        hide: Some(Self::hide),
+    };
+
+    // The host passes the UI handle, not this one
+    const RESIZE_INTERFACE: sys::LV2UI_Resize = sys::LV2UI_Resize {
+        handle: std::ptr::null_mut(),
+        ui_resize: Some(Self::ui_resize),
+    };
+
+    const OPTIONS_INTERFACE: sys::LV2_Options_Interface = sys::LV2_Options_Interface {
+        get: Some(Self::get_options),
+        set: Some(Self::set_options),
+    };
+
+    #[cfg(feature = "kxstudio")]
+    const PROGRAMS_INTERFACE: crate::kxstudio::ProgramsUIInterface =
+        crate::kxstudio::ProgramsUIInterface {
+            select_program: Some(Self::select_program),
+        };
+
+    /// Returns the interfaces enabled by `PluginUI::IDLE_INTERFACE` and the like
+    ///
+    /// Other URIs are passed to `PluginUI::extension_data()`.
     pub unsafe extern "C" fn extension_data(uri: *const c_char) -> *const std::ffi::c_void {
-        if CStr::from_ptr(uri) == CStr::from_bytes_with_nul_unchecked(sys::LV2_UI__idleInterface) {
-            let interface = Box::new(sys::LV2UI_Idle_Interface {
-                idle: Some(Self::idle),
-            });
-            Box::leak(interface) as *mut sys::LV2UI_Idle_Interface as *const std::ffi::c_void
+        let uri_is = |interface: &[u8]| {
+            CStr::from_ptr(uri) == CStr::from_bytes_with_nul_unchecked(interface)
+        };
+        if T::IDLE_INTERFACE && uri_is(sys::LV2_UI__idleInterface) {
+            &Self::IDLE_INTERFACE as *const sys::LV2UI_Idle_Interface as *const std::ffi::c_void
+        } else if T::SHOW_INTERFACE && uri_is(sys::LV2_UI__showInterface) {
+            &Self::SHOW_INTERFACE as *const sys::LV2UI_Show_Interface as *const std::ffi::c_void
+        } else if T::RESIZE_INTERFACE && uri_is(sys::LV2_UI__resize) {
+            &Self::RESIZE_INTERFACE as *const sys::LV2UI_Resize as *const std::ffi::c_void
+        } else if T::OPTIONS_INTERFACE && uri_is(sys::LV2_OPTIONS__interface) {
+            &Self::OPTIONS_INTERFACE as *const sys::LV2_Options_Interface as *const std::ffi::c_void
         } else {
-            std::ptr::null()
+            #[cfg(feature = "kxstudio")]
+            if T::PROGRAMS
+                && CStr::from_ptr(uri)
+                    == CStr::from_bytes_with_nul_unchecked(
+                        crate::kxstudio::PROGRAMS_UI_INTERFACE_URI,
+                    )
+            {
+                return &Self::PROGRAMS_INTERFACE as *const crate::kxstudio::ProgramsUIInterface
+                    as *const std::ffi::c_void;
+            }
+            T::extension_data(Uri::from_ptr(uri))
+                .map_or(std::ptr::null(), ExtensionInterface::as_ptr)
+        }
+    }
+
+    // The UI has no options to be queried
+    unsafe extern "C" fn get_options(
+        _handle: sys::LV2_Handle,
+        _options: *mut sys::LV2_Options_Option,
+    ) -> u32 {
+        sys::LV2_Options_Status_LV2_OPTIONS_ERR_UNKNOWN
+    }
+
+    /// Takes the known options and tells the UI about the changes
+    unsafe extern "C" fn set_options(
+        handle: sys::LV2_Handle,
+        options: *const sys::LV2_Options_Option,
+    ) -> u32 {
+        let changes = match (handle as *mut Self).as_mut() {
+            Some(handle) => match handle.option_urids.as_ref() {
+                Some(urids) => handle.options.apply(options, urids),
+                None => return sys::LV2_Options_Status_LV2_OPTIONS_ERR_UNKNOWN,
+            },
+            None => return sys::LV2_Options_Status_LV2_OPTIONS_ERR_UNKNOWN,
+        };
+        if let Some(scale_factor) = changes.scale_factor {
+            Self::guarded(handle, "scale_factor_changed()", |ui| {
+                ui.scale_factor_changed(scale_factor)
+            });
+        }
+        if changes.update_rate.is_some() {
+            Self::guarded(handle, "update()", |ui| {
+                ui.update(UpdateReason::OptionChange)
+            });
+        }
+        sys::LV2_Options_Status_LV2_OPTIONS_SUCCESS
+    }
+
+    /// Returns non-zero if the UI has panicked
+    unsafe extern "C" fn show(handle: sys::LV2UI_Handle) -> i32 {
+        Self::set_shown(handle, true).map_or(1, |_| 0)
+    }
+
+    unsafe extern "C" fn hide(handle: sys::LV2UI_Handle) -> i32 {
+        Self::set_shown(handle, false).map_or(1, |_| 0)
+    }
+
+    /// Calls `show()` or `hide()`, followed by `on_show()` or `on_hide()` on a change
+    unsafe fn set_shown(handle: sys::LV2UI_Handle, shown: bool) -> Option<()> {
+        match shown {
+            true => Self::guarded(handle, "show()", |ui| ui.show())?,
+            false => Self::guarded(handle, "hide()", |ui| ui.hide())?,
         }
+        let was_shown = std::mem::replace(&mut (handle as *mut Self).as_mut()?.shown, shown);
+        match (was_shown, shown) {
+            (false, true) => Self::guarded(handle, "on_show()", |ui| ui.on_show()),
+            (true, false) => Self::guarded(handle, "on_hide()", |ui| ui.on_hide()),
+            _ => Some(()),
+        }
+    }
+
+    unsafe extern "C" fn ui_resize(
+        handle: sys::LV2UI_Feature_Handle,
+        width: i32,
+        height: i32,
+    ) -> i32 {
+        let size = |length: i32| length.max(0) as u32;
+        Self::guarded(handle, "resized()", |ui| {
+            ui.resized(size(width), size(height))
+        })
+        .map_or(1, |_| 0)
+    }
+
+    #[cfg(feature = "kxstudio")]
+    unsafe extern "C" fn select_program(handle: sys::LV2UI_Handle, bank: u32, program: u32) {
+        Self::guarded(handle, "select_program()", |ui| {
+            ui.select_program(bank, program)
+        });
     }
 
+    /// Returns non-zero if the UI has been closed, which is also the case when it panicked
+    ///
+    /// The UI is closed if it returns non-zero or has asked by its
+    /// `CloseRequest`. Passes the deferred and the queued writes to
+    /// the host afterwards.
     pub unsafe extern "C" fn idle(handle: sys::LV2UI_Handle) -> i32 {
-        let handle = handle as *mut Self;
-        let r = (*handle).instance.idle();
-        r
+        let timer = Timer::start();
+        Self::update_pending(handle);
+        let context = match (handle as *mut Self).as_mut() {
+            Some(handle) => {
+                let now = Instant::now();
+                let last_idle = handle.last_idle.replace(now);
+                IdleContext::new(
+                    last_idle.map(|last| now.duration_since(last)),
+                    handle.options.update_rate(),
+                )
+            }
+            None => return 1,
+        };
+        let r = Self::guarded(handle, "idle()", |ui| ui.idle_with(&context)).unwrap_or(1);
+        instrument::idle(r, timer);
+        match (handle as *mut Self).as_ref() {
+            Some(handle) => {
+                logging::with_sink(&handle.log_sink, || handle.write_handle.flush_due());
+                match handle.close_request.is_requested() {
+                    true => 1,
+                    false => r,
+                }
+            }
+            None => r,
+        }
+    }
+
+    /// Calls `update()` for the port events batched since the last idle
+    unsafe fn update_pending(handle: sys::LV2UI_Handle) {
+        // Taken out while the UI runs, put back to reuse its allocation
+        let mut pending = match (handle as *mut Self).as_mut() {
+            Some(handle) => std::mem::take(&mut handle.pending_port_events),
+            None => return,
+        };
+        if !pending.is_empty() {
+            Self::guarded(handle, "update_batch()", |ui| ui.update_batch(&pending));
+        }
+        pending.clear();
+        if let Some(handle) = (handle as *mut Self).as_mut() {
+            handle.pending_port_events = pending;
+        }
+    }
+
+    unsafe extern "C" fn external_run(widget: *mut ExternalUIWidget) {
+        Self::update_pending(widget as sys::LV2UI_Handle);
+        Self::guarded(widget as sys::LV2UI_Handle, "run()", |ui| ui.run());
+        if let Some(handle) = (widget as *mut Self).as_mut() {
+            logging::with_sink(&handle.log_sink, || handle.write_handle.flush_due());
+            // Taken, so the host is told only once
+            if handle.close_request.is_requested() {
+                if let Some(ui_closed) = handle.ui_closed.take() {
+                    ui_closed(handle.write_handle.controller());
+                }
+            }
+        }
+    }
+
+    unsafe extern "C" fn external_show(widget: *mut ExternalUIWidget) {
+        Self::set_shown(widget as sys::LV2UI_Handle, true);
+    }
+
+    unsafe extern "C" fn external_hide(widget: *mut ExternalUIWidget) {
+        Self::set_shown(widget as sys::LV2UI_Handle, false);
     }
 }
 
+/// Trait for the C descriptor of a plugin UI
+///
+/// Usually you don't need to implement this by hand. Every
+/// `PluginUI` that is also `UriBound`, e.g. by annotating it with
+/// the `#[uri("…")]` attribute, gets a descriptor wiring up the
+/// callbacks of `PluginUIInstance`.
+///
+/// ```ignore
+/// #[uri("https://example.org/amp#ui")]
+/// struct AmpUI { ... }
+///
+/// impl PluginUI for AmpUI { ... }
+///
+/// lv2_ui_descriptors!(AmpUI);
+/// ```
 pub unsafe trait PluginUIInstanceDescriptor {
     const DESCRIPTOR: sys::LV2UI_Descriptor;
 }
+
+unsafe impl<T: PluginUI + UriBound> PluginUIInstanceDescriptor for T {
+    const DESCRIPTOR: sys::LV2UI_Descriptor = sys::LV2UI_Descriptor {
+        URI: T::URI.as_ptr() as *const c_char,
+        instantiate: Some(PluginUIInstance::<T>::instantiate),
+        cleanup: Some(PluginUIInstance::<T>::cleanup),
+        port_event: Some(PluginUIInstance::<T>::port_event),
+        extension_data: Some(PluginUIInstance::<T>::extension_data),
+    };
+}
+
+/// Export the plugin UI descriptors of the binary
+///
+/// Generates the `lv2ui_descriptor` function, which is the entry
+/// point the host uses to discover the UIs of a binary. One binary
+/// can contain several UIs, for example an X11 UI and a UI only
+/// implementing the show interface, or UIs for different plugins.
+/// The host selects them by index, which is the position of the type
+/// in the list passed to the macro.
+///
+/// Every type passed needs to implement `PluginUIInstanceDescriptor`.
+///
+/// ```ignore
+/// lv2_ui_descriptors!(AmpX11UIDescriptor, AmpShowUIDescriptor);
+/// ```
+///
+/// Prefixing the types by their `ui_class::UIClass` exports only the
+/// UIs valid for the compile target, the indices counting only those:
+///
+/// ```ignore
+/// lv2_ui_descriptors!(X11UI => AmpX11UI, CocoaUI => AmpCocoaUI, UI => AmpShowUI);
+/// ```
+#[macro_export]
+macro_rules! lv2_ui_descriptors {
+    ($($class:ident => $descriptor:ty),* $(,)?) => {
+        #[no_mangle]
+        #[allow(unused_mut, unused_variables)]
+        pub unsafe extern "C" fn lv2ui_descriptor(index: u32) -> *const $crate::sys::LV2UI_Descriptor {
+            use $crate::plugin_ui::PluginUIInstanceDescriptor;
+            let mut remaining = index;
+            $(
+                $crate::__lv2_ui_class_cfg!($class, {
+                    if remaining == 0 {
+                        return &<$descriptor as PluginUIInstanceDescriptor>::DESCRIPTOR;
+                    }
+                    remaining -= 1;
+                });
+            )*
+            std::ptr::null()
+        }
+    };
+    ($($descriptor:ty),*) => {
+        #[no_mangle]
+        pub unsafe extern "C" fn lv2ui_descriptor(index: u32) -> *const $crate::sys::LV2UI_Descriptor {
+            use $crate::plugin_ui::PluginUIInstanceDescriptor;
+            let descriptors: &'static [&'static $crate::sys::LV2UI_Descriptor] =
+                &[$(&<$descriptor as PluginUIInstanceDescriptor>::DESCRIPTOR),*];
+            match descriptors.get(index as usize) {
+                Some(descriptor) => *descriptor,
+                None => std::ptr::null(),
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct GainPorts {
+        gain: UIControlPort,
+    }
+
+    impl UIPortsTrait for GainPorts {
+        fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+            match port_index {
+                0 => Some(&mut self.gain),
+                _ => None,
+            }
+        }
+
+        fn map_atom_port(&mut self, _port_index: u32) -> Option<&mut UIAtomPort> {
+            None
+        }
+
+        fn visit_ports(&mut self, mut f: impl FnMut(PortRef)) {
+            f(PortRef::Control(&mut self.gain));
+        }
+    }
+
+    // Borrows its label, so it is not `'static` for every `'a`
+    struct LabelUI<'a> {
+        ports: GainPorts,
+        #[cfg_attr(not(feature = "testing"), allow(dead_code))]
+        label: &'a str,
+    }
+
+    impl<'a> PluginUI for LabelUI<'a> {
+        type InitFeatures = ();
+        type UIPorts = GainPorts;
+
+        const OPTIONS_INTERFACE: bool = true;
+
+        #[cfg(feature = "kxstudio")]
+        const PROGRAMS: bool = true;
+
+        fn new(
+            _plugin_ui_info: &PluginUIInfo,
+            _features: &mut (),
+            _parent_window: *mut std::ffi::c_void,
+            _write_handle: PluginPortWriteHandle,
+        ) -> Result<Self, PluginUIError> {
+            Ok(Self {
+                ports: GainPorts {
+                    gain: UIControlPort::new(0),
+                },
+                label: "gain",
+            })
+        }
+
+        fn cleanup(&mut self) {}
+
+        fn ports(&mut self) -> &mut GainPorts {
+            &mut self.ports
+        }
+
+        fn update(&mut self, _reason: UpdateReason) {}
+
+        fn idle(&mut self) -> i32 {
+            0
+        }
+
+        fn widget(&self) -> sys::LV2UI_Widget {
+            std::ptr::null_mut()
+        }
+    }
+
+    #[test]
+    fn extension_data_returns_the_same_interfaces() {
+        let extension_data = |uri: &[u8]| unsafe {
+            PluginUIInstance::<LabelUI>::extension_data(uri.as_ptr() as *const c_char)
+        };
+        let idle = extension_data(sys::LV2_UI__idleInterface);
+        assert!(!idle.is_null());
+        assert_eq!(idle, extension_data(sys::LV2_UI__idleInterface));
+        assert!(extension_data(sys::LV2_UI__showInterface).is_null());
+        assert!(extension_data(sys::LV2_UI__resize).is_null());
+        let options = extension_data(sys::LV2_OPTIONS__interface);
+        assert!(!options.is_null());
+        assert_eq!(options, extension_data(sys::LV2_OPTIONS__interface));
+        #[cfg(feature = "kxstudio")]
+        {
+            let programs = extension_data(crate::kxstudio::PROGRAMS_UI_INTERFACE_URI);
+            assert!(!programs.is_null());
+            assert_eq!(
+                programs,
+                extension_data(crate::kxstudio::PROGRAMS_UI_INTERFACE_URI)
+            );
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn ui_with_lifetime_is_instantiated() {
+        fn instantiate<'a>(host: &mut crate::testing::MockUIHost, label: &'a str) {
+            let mut ui = host.instantiate::<LabelUI<'a>>().unwrap();
+            ui.control_event(0, 0.5);
+            assert_eq!(ui.ui().label, label);
+            assert_eq!(ui.ui().ports.gain.value(), 0.5);
+        }
+        let label = String::from("gain");
+        instantiate(&mut crate::testing::MockUIHost::new(), &label);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bundle_path_keeps_invalid_utf8_bytes() {
+        use std::os::unix::ffi::OsStrExt;
+        let raw = b"/usr/lib/lv2/b\xe4ss.lv2/\0";
+        let path = unsafe { bundle_path_from_raw(raw.as_ptr() as *const c_char) }.unwrap();
+        assert!(matches!(path, Cow::Borrowed(_)));
+        assert_eq!(path.as_os_str().as_bytes(), &raw[..raw.len() - 1]);
+        assert!(path.to_str().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn bundle_path_from_utf8() {
+        let raw = "/usr/lib/lv2/bäss.lv2/\0";
+        let path = unsafe { bundle_path_from_raw(raw.as_ptr() as *const c_char) }.unwrap();
+        assert_eq!(path, Path::new("/usr/lib/lv2/bäss.lv2/"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn bundle_path_from_utf8_is_borrowed() {
+        let raw = "C:\\Program Files\\LV2\\bäss.lv2\\\0";
+        let path = unsafe { bundle_path_from_raw(raw.as_ptr() as *const c_char) }.unwrap();
+        assert!(matches!(path, Cow::Borrowed(_)));
+        assert_eq!(path, Path::new("C:\\Program Files\\LV2\\bäss.lv2\\"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_ansi_decodes_ascii() {
+        assert_eq!(
+            windows_ansi::decode(b"C:\\LV2\\amp.lv2\\"),
+            Some(std::path::PathBuf::from("C:\\LV2\\amp.lv2\\"))
+        );
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn windows_ansi_rejects_empty_input() {
+        assert_eq!(windows_ansi::decode(b""), None);
+    }
+}
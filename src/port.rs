@@ -1,9 +1,24 @@
 use lv2_atom as atom;
+use lv2_sys as sys;
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+use std::time::Instant;
 
 use atom::prelude::*;
 use urid::*;
 
+use crate::logging::ui_log;
+use crate::mapping::*;
+use crate::memory::*;
+use crate::meter::PeakMeter;
+use crate::plugin_ui::{PluginPortWriteHandle, PortEvent};
 use crate::space::*;
+use crate::stepping::*;
+use crate::touch::{GestureGuard, Touch};
+use crate::units::*;
+use crate::uris::PeakProtocol;
 
 /// Trait for an UIPort
 ///
@@ -24,12 +39,131 @@ pub trait UIPort {
 
     /// The pointer to the data transmitted
     fn data(&self) -> *const std::ffi::c_void;
+
+    /// Called by `PluginPortWriteHandle::write_port()` after the port has been written
+    fn mark_written(&self) {}
+}
+
+/// Range and properties of a control port as declared in the plugin's TTL
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ControlMetadata {
+    pub min: f32,
+    pub max: f32,
+    pub default: f32,
+    /// The port is an on/off switch, `lv2:toggled`
+    pub toggled: bool,
+    /// The port only takes integer values, `lv2:integer`
+    pub integer: bool,
+    /// The port is best shown on a logarithmic scale, `pprops:logarithmic`
+    pub logarithmic: bool,
+    /// The unit of the value, `units:unit`
+    pub unit: Option<Unit>,
+}
+
+impl ControlMetadata {
+    pub fn new(min: f32, max: f32, default: f32) -> Self {
+        Self {
+            min,
+            max,
+            default,
+            toggled: false,
+            integer: false,
+            logarithmic: false,
+            unit: None,
+        }
+    }
+
+    pub fn toggled(mut self) -> Self {
+        self.toggled = true;
+        self
+    }
+
+    pub fn integer(mut self) -> Self {
+        self.integer = true;
+        self
+    }
+
+    pub fn logarithmic(mut self) -> Self {
+        self.logarithmic = true;
+        self
+    }
+
+    pub fn unit(mut self, unit: Unit) -> Self {
+        self.unit = Some(unit);
+        self
+    }
+
+    /// Limits `value` to the range and snaps it to integers or on/off
+    pub fn constrain(&self, value: f32) -> f32 {
+        if self.toggled {
+            return if value > 0.0 { self.max } else { self.min };
+        }
+        let value = if self.integer { value.round() } else { value };
+        value.max(self.min).min(self.max)
+    }
+
+    /// Maps `value` to 0.0..=1.0, e.g. for a knob position
+    ///
+    /// Logarithmic mapping is only used if the range is positive.
+    pub fn to_normalized(&self, value: f32) -> f32 {
+        if self.logarithmic {
+            LogMap::new(self.min, self.max).normalize(value)
+        } else {
+            LinMap::new(self.min, self.max).normalize(value)
+        }
+    }
+
+    /// Maps 0.0..=1.0 to a constrained port value, the inverse of `to_normalized()`
+    pub fn from_normalized(&self, normalized: f32) -> f32 {
+        let value = if self.logarithmic {
+            LogMap::new(self.min, self.max).denormalize(normalized)
+        } else {
+            LinMap::new(self.min, self.max).denormalize(normalized)
+        };
+        self.constrain(value)
+    }
+
+    /// Formats `value` for display, using the unit if there is one
+    pub fn display_value(&self, value: f32) -> std::string::String {
+        match (self.toggled, self.integer, self.unit) {
+            (true, _, _) => std::string::String::from(if value > self.min { "on" } else { "off" }),
+            (false, true, Some(unit)) => format!("{} {}", format_number(value, 0), unit.symbol()),
+            (false, true, None) => format_number(value, 0),
+            (false, false, Some(unit)) => unit.format(value),
+            (false, false, None) => format_number(value, 2),
+        }
+    }
+
+    /// Parses a value typed in by the user and constrains it
+    ///
+    /// See `Unit::parse()` for the accepted formats. Toggled ports
+    /// also accept "on" and "off".
+    pub fn parse(&self, text: &str) -> Option<f32> {
+        let value = match (self.toggled, self.unit) {
+            (true, _) if text.trim().eq_ignore_ascii_case("on") => self.max,
+            (true, _) if text.trim().eq_ignore_ascii_case("off") => self.min,
+            (_, Some(unit)) => unit.parse(text)?,
+            (_, None) => text.trim().replace('\u{2212}', "-").parse().ok()?,
+        };
+        Some(self.constrain(value))
+    }
 }
 
 /// A UI port for a Control Port
 pub struct UIControlPort {
     value: f32,
     changed: bool,
+    metadata: Option<ControlMetadata>,
+    step_rule: StepRule,
+    modulated_value: Option<f32>,
+    modulation_changed: bool,
+    received: Option<Instant>,
+    gesture: Rc<Cell<bool>>,
+    in_gesture: bool,
+    dirty: Cell<bool>,
+    enabled: bool,
+    visible: bool,
+    observers: Vec<Box<dyn FnMut(f32)>>,
     index: u32,
 }
 
@@ -41,18 +175,216 @@ impl UIControlPort {
         UIControlPort {
             value: 0.0,
             changed: false,
+            metadata: None,
+            step_rule: StepRule::default(),
+            modulated_value: None,
+            modulation_changed: false,
+            received: None,
+            gesture: Rc::new(Cell::new(false)),
+            in_gesture: false,
+            dirty: Cell::new(false),
+            enabled: true,
+            visible: true,
+            observers: Vec::new(),
             index,
         }
     }
 
+    /// Attaches the port's metadata, setting the value to its default
+    pub fn with_metadata(mut self, metadata: ControlMetadata) -> Self {
+        self.value = metadata.default;
+        self.metadata = Some(metadata);
+        self
+    }
+
+    pub fn metadata(&self) -> Option<&ControlMetadata> {
+        self.metadata.as_ref()
+    }
+
+    /// Sets how far `step()` moves the value
+    pub fn with_step_rule(mut self, step_rule: StepRule) -> Self {
+        self.step_rule = step_rule;
+        self
+    }
+
+    pub fn step_rule(&self) -> StepRule {
+        self.step_rule
+    }
+
+    /// Moves the value by `steps` steps of `size`, negative steps move down
+    ///
+    /// Integer ports move by at least one per step, toggled ports are
+    /// switched on by upward and off by downward steps. See
+    /// `stepping` for details.
+    pub fn step(&mut self, steps: i32, size: StepSize) {
+        if steps == 0 {
+            return;
+        }
+        if let Some(metadata) = &self.metadata {
+            if metadata.toggled {
+                let value = if steps > 0 {
+                    metadata.max
+                } else {
+                    metadata.min
+                };
+                self.set_value(value);
+                return;
+            }
+        }
+        let delta = self.step_rule.step(size) * steps as f32;
+        let value = match self.step_rule {
+            StepRule::Normalized { .. } => match &self.metadata {
+                Some(metadata) => metadata.from_normalized(self.normalized() + delta),
+                None => (self.value + delta).clamp(0.0, 1.0),
+            },
+            StepRule::Absolute { .. } => self.value + delta,
+        };
+        let value = match &self.metadata {
+            Some(metadata) if metadata.integer && value.round() == self.value.round() => {
+                self.value.round() + steps.signum() as f32
+            }
+            _ => value,
+        };
+        self.set_value(value);
+    }
+
     /// Sets the value of the port.
     ///
-    /// Can be used to communicate a change of the value to the Plugin
+    /// Can be used to communicate a change of the value to the
+    /// Plugin. If the port has metadata, the value is constrained
+    /// to it.
     pub fn set_value(&mut self, v: f32) {
-        self.value = v;
+        self.store_value(v);
+        self.dirty.set(true);
+        self.in_gesture = self.is_grabbed();
+    }
+
+    /// Takes a value the host has delivered, which does not need to be written back
+    pub(crate) fn receive_value(&mut self, v: f32) {
+        self.store_value(v);
+        let value = self.value;
+        for observer in self.observers.iter_mut() {
+            observer(value);
+        }
+    }
+
+    /// Registers `f` to be called with the new value whenever the host delivers one
+    ///
+    /// The observers are called in the order they have been registered,
+    /// right in `port_event()`, before `update()`. Values set by the UI
+    /// itself are not reported. As the observers can't borrow the UI,
+    /// they usually share state with it, e.g. by an `Rc<Cell<_>>`:
+    ///
+    /// ```ignore
+    /// let gain = Rc::new(Cell::new(0.0));
+    /// let label_gain = gain.clone();
+    /// ports.gain.on_change(move |value| label_gain.set(value));
+    /// ```
+    pub fn on_change(&mut self, f: impl FnMut(f32) + 'static) {
+        self.observers.push(Box::new(f));
+    }
+
+    fn store_value(&mut self, v: f32) {
+        self.value = match &self.metadata {
+            Some(metadata) => metadata.constrain(v),
+            None => v,
+        };
         self.changed = true;
     }
 
+    /// Returns false if the port is irrelevant in the current state, see `rules`
+    ///
+    /// Widgets of disabled ports are supposed to be greyed out.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns false if the port is to be hidden in the current state, see `rules`
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Sets the flags computed by `PortRules`, returns true if they changed
+    pub(crate) fn set_availability(&mut self, enabled: bool, visible: bool) -> bool {
+        let changed = (self.enabled, self.visible) != (enabled, visible);
+        self.enabled = enabled;
+        self.visible = visible;
+        changed
+    }
+
+    /// Returns true if the UI has set the value and it has not been written to the plugin
+    ///
+    /// See `PluginPortWriteHandle::write_batch()`.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.get()
+    }
+
+    /// Tells the host that the user starts changing the value
+    ///
+    /// The port is grabbed until the returned guard is dropped, so
+    /// the host records the values written meanwhile as one touch
+    /// automation pass. See `touch`.
+    pub fn begin_gesture(&self, touch: &Touch) -> GestureGuard {
+        if self.is_grabbed() {
+            ui_log!(Warning, "port {} is already in a gesture", self.index);
+        }
+        GestureGuard::new(touch, self.index, self.gesture.clone())
+    }
+
+    /// Returns true while a gesture on the port is going on
+    pub fn is_grabbed(&self) -> bool {
+        self.gesture.get()
+    }
+
+    /// Returns true if the last value was set during a gesture
+    ///
+    /// Such values are part of the touch automation the host records.
+    /// Values set outside a gesture, e.g. by stepping with the
+    /// keyboard, are single changes.
+    pub fn is_gesture_value(&self) -> bool {
+        self.in_gesture
+    }
+
+    /// Sets the value from a position in 0.0..=1.0, e.g. of a knob
+    ///
+    /// Without metadata the position is taken as value.
+    pub fn set_normalized(&mut self, normalized: f32) {
+        let value = match &self.metadata {
+            Some(metadata) => metadata.from_normalized(normalized),
+            None => normalized.clamp(0.0, 1.0),
+        };
+        self.set_value(value);
+    }
+
+    /// The value formatted for display, e.g. "−6.0 dB"
+    ///
+    /// Uses the unit of the metadata, see `units`.
+    pub fn display_value(&self) -> std::string::String {
+        match &self.metadata {
+            Some(metadata) => metadata.display_value(self.value),
+            None => format_number(self.value, 2),
+        }
+    }
+
+    /// Parses a value typed in by the user, e.g. "440 Hz"
+    ///
+    /// The value is not set, pass it to `set_value()`. Returns None
+    /// if the text cannot be parsed.
+    pub fn parse(&self, text: &str) -> Option<f32> {
+        match &self.metadata {
+            Some(metadata) => metadata.parse(text),
+            None => text.trim().replace('\u{2212}', "-").parse().ok(),
+        }
+    }
+
+    /// The value mapped to 0.0..=1.0, see `set_normalized()`
+    pub fn normalized(&self) -> f32 {
+        match &self.metadata {
+            Some(metadata) => metadata.to_normalized(self.value),
+            None => self.value.clamp(0.0, 1.0),
+        }
+    }
+
     /// Returns the changed value if it has been changed, otherwise None.
     ///
     pub fn changed_value(&mut self) -> Option<f32> {
@@ -64,12 +396,78 @@ impl UIControlPort {
             }
         }
     }
+
+    /// Returns the current value of the port without touching the changed flag
+    pub fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Returns true if the value has been changed and not yet acknowledged
+    ///
+    /// Unlike `changed_value()` this does not clear the changed flag,
+    /// so several widgets observing the port can all check it. Call
+    /// `clear_changed()` once all of them are done.
+    pub fn is_changed(&self) -> bool {
+        self.changed
+    }
+
+    /// Acknowledges the change of the value
+    pub fn clear_changed(&mut self) {
+        self.changed = false;
+    }
+
+    /// Returns the modulated value of the port, if the plugin has sent one.
+    ///
+    /// Plugins can report the value a parameter currently has after
+    /// modulation on a separate output port. The port collection
+    /// maps that port to this one using `map_modulation_port()`.
+    /// The base value remains the one set by `set_value()`, so
+    /// widgets can render both, e.g. as a knob with a modulation
+    /// ring.
+    pub fn modulated_value(&self) -> Option<f32> {
+        self.modulated_value
+    }
+
+    /// Returns the modulated value if it has been changed, otherwise None.
+    ///
+    pub fn changed_modulated_value(&mut self) -> Option<f32> {
+        match self.modulation_changed {
+            false => None,
+            true => {
+                self.modulation_changed = false;
+                self.modulated_value
+            }
+        }
+    }
+
+    pub(crate) fn set_modulated_value(&mut self, v: f32) {
+        self.modulated_value = Some(v);
+        self.modulation_changed = true;
+    }
+
+    /// The time the last value or modulated value has been received from the host
+    ///
+    /// See `transport::TransportClock` to relate it to transport frames.
+    pub fn received(&self) -> Option<Instant> {
+        self.received
+    }
+
+    pub(crate) fn stamp(&mut self, time: Instant) {
+        self.received = Some(time);
+    }
+
+    pub(crate) fn shift_index(&mut self, offset: u32) {
+        self.index += offset;
+    }
 }
 
 impl UIPort for UIControlPort {
     fn index(&self) -> u32 {
         self.index
     }
+    fn mark_written(&self) {
+        self.dirty.set(false);
+    }
     fn protocol(&self) -> u32 {
         0
     }
@@ -81,28 +479,238 @@ impl UIPort for UIControlPort {
     }
 }
 
+/// A labelled value of a control port, `lv2:scalePoint`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScalePoint {
+    pub label: std::string::String,
+    pub value: f32,
+}
+
+impl ScalePoint {
+    pub fn new(label: impl Into<std::string::String>, value: f32) -> Self {
+        Self {
+            label: label.into(),
+            value,
+        }
+    }
+}
+
+/// A UI port for a control port selecting one of its scale points
+///
+/// Used for enumeration ports like filter types or mode selectors.
+/// The port collection maps the port index to the underlying
+/// `UIControlPort`:
+///
+/// ```ignore
+/// fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+///     match port_index {
+///         2 => Some(self.filter_type.port_mut()),
+///         _ => None,
+///     }
+/// }
+/// ```
+pub struct UIEnumPort {
+    port: UIControlPort,
+    scale_points: Vec<ScalePoint>,
+}
+
+impl UIEnumPort {
+    /// Instantiates an UIEnumPort, selecting the first scale point
+    pub fn new(index: u32, scale_points: Vec<ScalePoint>) -> Self {
+        let mut port = UIControlPort::new(index);
+        if let Some(first) = scale_points.first() {
+            port.value = first.value;
+        }
+        Self { port, scale_points }
+    }
+
+    pub fn scale_points(&self) -> &[ScalePoint] {
+        &self.scale_points
+    }
+
+    /// The index of the scale point selected by the port value
+    ///
+    /// If the plugin sent a value between scale points, the nearest
+    /// one is taken. None if there are no scale points.
+    pub fn selected_index(&self) -> Option<usize> {
+        let value = self.port.value();
+        self.scale_points
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| (a.value - value).abs().total_cmp(&(b.value - value).abs()))
+            .map(|(index, _)| index)
+    }
+
+    /// The scale point selected by the port value, see `selected_index()`
+    pub fn selected(&self) -> Option<&ScalePoint> {
+        self.selected_index().map(|index| &self.scale_points[index])
+    }
+
+    /// Selects the scale point labelled `label`
+    ///
+    /// Returns false if there is no such scale point.
+    pub fn select(&mut self, label: &str) -> bool {
+        match self.scale_points.iter().position(|p| p.label == label) {
+            Some(index) => self.select_index(index),
+            None => false,
+        }
+    }
+
+    /// Selects the scale point at `index`
+    ///
+    /// Returns false if `index` is out of range.
+    pub fn select_index(&mut self, index: usize) -> bool {
+        match self.scale_points.get(index) {
+            Some(point) => {
+                self.port.set_value(point.value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the selection by `steps` scale points
+    ///
+    /// With `wrap` the selection cycles from the last scale point to
+    /// the first and vice versa, otherwise it stops at the ends.
+    pub fn step(&mut self, steps: i32, wrap: bool) {
+        let count = self.scale_points.len() as i64;
+        let current = match self.selected_index() {
+            Some(index) => index as i64,
+            None => return,
+        };
+        let index = match wrap {
+            true => (current + i64::from(steps)).rem_euclid(count),
+            false => (current + i64::from(steps)).clamp(0, count - 1),
+        };
+        self.select_index(index as usize);
+    }
+
+    /// The underlying control port
+    pub fn port(&self) -> &UIControlPort {
+        &self.port
+    }
+
+    pub fn port_mut(&mut self) -> &mut UIControlPort {
+        &mut self.port
+    }
+}
+
+impl UIPort for UIEnumPort {
+    fn index(&self) -> u32 {
+        self.port.index()
+    }
+    fn protocol(&self) -> u32 {
+        self.port.protocol()
+    }
+    fn size(&self) -> usize {
+        self.port.size()
+    }
+    fn data(&self) -> *const std::ffi::c_void {
+        self.port.data()
+    }
+    fn mark_written(&self) {
+        self.port.mark_written()
+    }
+}
+
+/// What happens when an atom arrives at an `UIAtomPort` with a full queue
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// The oldest queued atom is dropped to make room
+    DropOldest,
+    /// The arriving atom is dropped
+    DropNewest,
+}
+
 /// UI Port for a LV2 Atom port
+///
+/// The host may deliver several atoms between two `idle()` calls.
+/// They are queued up to a capacity of `DEFAULT_QUEUE_CAPACITY`,
+/// which can be changed by `with_queue()`.
 pub struct UIAtomPort {
     space_to_plugin: SelfAllocatingSpace,
     space_to_ui: SelfAllocatingSpace,
+    queue: VecDeque<SelfAllocatingSpace>,
+    drained: Vec<SelfAllocatingSpace>,
+    // Spaces read already, reused by `put_buffer()`
+    free: Vec<SelfAllocatingSpace>,
+    queue_capacity: usize,
+    overflow_policy: OverflowPolicy,
+    dropped: usize,
     urid: URID<atom::uris::EventTransfer>,
+    received: Option<Instant>,
+    observers: Vec<Box<dyn FnMut()>>,
+    memory: Option<MemoryAccount>,
     index: u32,
 }
 
 impl UIAtomPort {
     /// Instantiates an UIAtomPort.
     ///
-    /// Not to be called manually
+    /// Usually created by `from_map()` or a `UIPortFactory`.
     pub fn new(urid: URID<atom::uris::EventTransfer>, index: u32) -> UIAtomPort {
         UIAtomPort {
-            space_to_plugin: SelfAllocatingSpace::new(),
+            space_to_plugin: SelfAllocatingSpace::with_capacity(Self::DEFAULT_WRITE_CAPACITY),
             space_to_ui: SelfAllocatingSpace::new(),
+            queue: VecDeque::new(),
+            drained: Vec::new(),
+            free: Vec::new(),
+            queue_capacity: Self::DEFAULT_QUEUE_CAPACITY,
+            overflow_policy: OverflowPolicy::DropOldest,
+            dropped: 0,
             urid,
+            received: None,
+            observers: Vec::new(),
+            memory: None,
             index,
         }
     }
 
-    /// Reads an atom from an UI Atom port
+    /// Instantiates an UIAtomPort, mapping `atom:eventTransfer` by `map`
+    ///
+    /// Returns None if the URI cannot be mapped.
+    pub fn from_map(map: &impl Map, index: u32) -> Option<UIAtomPort> {
+        Some(Self::new(map.map_type()?, index))
+    }
+
+    pub const DEFAULT_QUEUE_CAPACITY: usize = 16;
+
+    pub const DEFAULT_WRITE_CAPACITY: usize = 8192;
+
+    /// Sets the maximum size in bytes of an atom written to the plugin
+    ///
+    /// Writing an atom exceeding it fails.
+    pub fn with_write_capacity(mut self, capacity: usize) -> Self {
+        self.space_to_plugin = SelfAllocatingSpace::with_capacity(capacity);
+        self
+    }
+
+    /// Sets the number of atoms queued and what happens when the queue is full
+    pub fn with_queue(mut self, capacity: usize, overflow_policy: OverflowPolicy) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self.overflow_policy = overflow_policy;
+        self
+    }
+
+    /// Accounts the queued atoms to `budget`, see `memory`
+    ///
+    /// If an arriving atom does not fit into the budget, queued atoms
+    /// are dropped according to the overflow policy, like for a full
+    /// queue.
+    pub fn with_memory_budget(mut self, budget: &MemoryBudget) -> Self {
+        self.memory = Some(budget.account(MemoryCategory::AtomQueues));
+        self.account_queue();
+        self
+    }
+
+    fn account_queue(&mut self) {
+        if let Some(memory) = &mut self.memory {
+            memory.set(self.queue.iter().map(SelfAllocatingSpace::len).sum());
+        }
+    }
+
+    /// Reads the most recent atom, discarding older queued ones
     ///
     /// See `lv2_atom` for details
     pub fn read<'a, A: atom::Atom<'a, 'a>>(
@@ -110,9 +718,99 @@ impl UIAtomPort {
         urid: URID<A>,
         parameter: A::ReadParameter,
     ) -> Option<A::ReadHandle> {
+        if let Some(latest) = self.queue.pop_back() {
+            let previous = std::mem::replace(&mut self.space_to_ui, latest);
+            self.release(previous);
+            while let Some(space) = self.queue.pop_front() {
+                self.release(space);
+            }
+            self.account_queue();
+        }
         A::read(self.space_to_ui.take()?.split_atom_body(urid)?.0, parameter)
     }
 
+    /// Reads the oldest queued atom
+    ///
+    /// The atom is removed from the queue, even if it is not of the
+    /// type `urid`. See `lv2_atom` for details
+    pub fn read_next<'a, A: atom::Atom<'a, 'a>>(
+        &'a mut self,
+        urid: URID<A>,
+        parameter: A::ReadParameter,
+    ) -> Option<A::ReadHandle> {
+        A::read(self.pop_next()?.split_atom_body(urid)?.0, parameter)
+    }
+
+    fn pop_next(&mut self) -> Option<atom::space::Space<'_>> {
+        let next = self.queue.pop_front()?;
+        let previous = std::mem::replace(&mut self.space_to_ui, next);
+        self.release(previous);
+        self.account_queue();
+        self.space_to_ui.take()
+    }
+
+    /// Empties the queue, iterating over the atoms from oldest to newest
+    pub fn drain(&mut self) -> impl Iterator<Item = UnidentifiedAtom<'_>> + '_ {
+        // The newest atom is kept for `last()`
+        let latest = self.queue.pop_back();
+        while let Some(space) = self.drained.pop() {
+            self.release(space);
+        }
+        self.drained.extend(self.queue.drain(..));
+        self.account_queue();
+        let has_latest = latest.is_some();
+        if let Some(latest) = latest {
+            let previous = std::mem::replace(&mut self.space_to_ui, latest);
+            self.release(previous);
+            self.space_to_ui.mark_read();
+        }
+        self.drained
+            .iter()
+            .chain(Some(&self.space_to_ui).filter(|_| has_latest))
+            .map(|space| unsafe { UnidentifiedAtom::new(space.as_space()) })
+    }
+
+    /// Reads the oldest queued atom without removing it from the queue
+    ///
+    /// See `lv2_atom` for details
+    pub fn peek<'a, A: atom::Atom<'a, 'a>>(
+        &'a self,
+        urid: URID<A>,
+        parameter: A::ReadParameter,
+    ) -> Option<A::ReadHandle> {
+        A::read(
+            self.queue.front()?.as_space().split_atom_body(urid)?.0,
+            parameter,
+        )
+    }
+
+    /// Reads the most recently received atom, whether it has been read or not
+    ///
+    /// Unlike the other read methods this can be called any number
+    /// of times, e.g. to redraw a widget from the last value. See
+    /// `lv2_atom` for details
+    pub fn last<'a, A: atom::Atom<'a, 'a>>(
+        &'a self,
+        urid: URID<A>,
+        parameter: A::ReadParameter,
+    ) -> Option<A::ReadHandle> {
+        let space = self.queue.back().unwrap_or(&self.space_to_ui);
+        if space.len() == 0 {
+            return None;
+        }
+        A::read(space.as_space().split_atom_body(urid)?.0, parameter)
+    }
+
+    /// The number of queued atoms
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The number of atoms dropped because the queue was full
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
     /// Initiates atom writing to an UI Atom port
     ///
     /// See `lv2_atom` for details
@@ -121,8 +819,14 @@ impl UIAtomPort {
         urid: URID<A>,
         parameter: A::WriteParameter,
     ) -> Option<A::WriteHandle> {
-        self.space_to_plugin = SelfAllocatingSpace::new();
-        (&mut self.space_to_plugin as &mut dyn MutSpace).init(urid, parameter)
+        self.space_to_plugin.clear();
+        (self.space_to_plugin.writer() as &mut dyn MutSpace).init(urid, parameter)
+    }
+
+    /// The emptied space for writing raw data to the plugin
+    pub(crate) fn clear_write_space(&mut self) -> &mut SelfAllocatingSpace {
+        self.space_to_plugin.clear();
+        &mut self.space_to_plugin
     }
 
     pub(crate) unsafe fn put_buffer(
@@ -130,12 +834,83 @@ impl UIAtomPort {
         buffer: std::ptr::NonNull<std::ffi::c_void>,
         size: usize,
     ) {
-        self.space_to_ui.put_buffer(buffer, size);
+        if self.queue.len() >= self.queue_capacity {
+            self.dropped += 1;
+            match self.overflow_policy {
+                OverflowPolicy::DropOldest => {
+                    if let Some(oldest) = self.queue.pop_front() {
+                        self.release(oldest);
+                    }
+                }
+                OverflowPolicy::DropNewest => return,
+            }
+        }
+        if !self.make_room(size) {
+            return;
+        }
+        let mut space = self.free.pop().unwrap_or_else(SelfAllocatingSpace::new);
+        space.put_buffer(buffer, size);
+        self.queue.push_back(space);
+        self.account_queue();
+        for observer in self.observers.iter_mut() {
+            observer();
+        }
+    }
+
+    // Drops queued atoms until `size` more bytes fit into the memory
+    // budget, returns false if the arriving atom is to be dropped
+    fn make_room(&mut self, size: usize) -> bool {
+        self.account_queue();
+        while let Some(memory) = self.memory.as_ref().filter(|memory| !memory.fits(size)) {
+            memory.record_eviction();
+            self.dropped += 1;
+            match self.overflow_policy {
+                OverflowPolicy::DropOldest if !self.queue.is_empty() => {
+                    if let Some(oldest) = self.queue.pop_front() {
+                        self.release(oldest);
+                    }
+                    self.account_queue();
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    // Keeps `space` for reuse, as many as can be queued
+    fn release(&mut self, mut space: SelfAllocatingSpace) {
+        if self.free.len() < self.queue_capacity {
+            space.clear();
+            self.free.push(space);
+        }
+    }
+
+    /// Registers `f` to be called whenever an atom from the host has been queued
+    ///
+    /// Like `UIControlPort::on_change()`, called in `port_event()`
+    /// before `update()`. The atom is still to be read from the queue.
+    pub fn on_change(&mut self, f: impl FnMut() + 'static) {
+        self.observers.push(Box::new(f));
     }
 
     pub(crate) fn urid(&mut self) -> u32 {
         self.urid.get()
     }
+
+    /// The time the last atom has been received from the host
+    ///
+    /// See `transport::TransportClock` to relate it to transport frames.
+    pub fn received(&self) -> Option<Instant> {
+        self.received
+    }
+
+    pub(crate) fn stamp(&mut self, time: Instant) {
+        self.received = Some(time);
+    }
+
+    pub(crate) fn shift_index(&mut self, offset: u32) {
+        self.index += offset;
+    }
 }
 
 impl UIPort for UIAtomPort {
@@ -153,43 +928,1012 @@ impl UIPort for UIAtomPort {
     }
 }
 
-/// Trait for a UIPort collection
-pub trait UIPortsTrait: Sized {
-    fn port_event(
-        &mut self,
-        port_index: u32,
-        buffer_size: u32,
-        format: u32,
-        buffer: *const std::ffi::c_void,
-    ) {
-        match format {
-            0 => {
-                let value: f32 = unsafe { *(buffer as *const f32) };
-                match self.map_control_port(port_index) {
-                    Some(ref mut port) => port.set_value(value),
-                    None => eprintln!("unknown control port: {}", port_index),
-                }
+/// An atom read directly from the host's buffer
+///
+/// Passed to `PluginUI::visit_atom()`, which is called before the
+/// atom would be copied into its `UIAtomPort`. The atom is only
+/// valid during that call.
+pub struct BorrowedAtom<'a> {
+    data: &'a [u8],
+    port_index: u32,
+    received: Instant,
+}
+
+impl<'a> BorrowedAtom<'a> {
+    pub(crate) fn new(data: &'a [u8], port_index: u32) -> Self {
+        Self {
+            data,
+            port_index,
+            received: Instant::now(),
+        }
+    }
+
+    /// The index of the port the atom has been sent to
+    pub fn port_index(&self) -> u32 {
+        self.port_index
+    }
+
+    /// The time the atom has been received from the host
+    pub fn received(&self) -> Instant {
+        self.received
+    }
+
+    /// The URID of the atom's type, or 0 if the buffer is too short
+    pub fn type_urid(&self) -> u32 {
+        match self.data.get(4..8) {
+            Some(bytes) => {
+                let mut type_urid = [0u8; 4];
+                type_urid.copy_from_slice(bytes);
+                u32::from_ne_bytes(type_urid)
+            }
+            None => 0,
+        }
+    }
+
+    /// The size of the atom including its header
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Reads the atom
+    ///
+    /// See `lv2_atom` for details
+    pub fn read<A: atom::Atom<'a, 'a>>(
+        &self,
+        urid: URID<A>,
+        parameter: A::ReadParameter,
+    ) -> Option<A::ReadHandle> {
+        A::read(
+            atom::space::Space::from_slice(self.data)
+                .split_atom_body(urid)?
+                .0,
+            parameter,
+        )
+    }
+}
+
+struct PendingWrite {
+    port_index: u32,
+    protocol: u32,
+    data: Vec<u8>,
+}
+
+/// Collects port writes to send them to the plugin back-to-back
+///
+/// See `UIPortsTrait::transaction()`.
+pub struct PortTransaction<'a, P: UIPortsTrait> {
+    ports: &'a mut P,
+    writes: Vec<PendingWrite>,
+}
+
+impl<'a, P: UIPortsTrait> PortTransaction<'a, P> {
+    /// The port collection, e.g. to read values
+    pub fn ports(&mut self) -> &mut P {
+        self.ports
+    }
+
+    /// Sets the value of a control port, clamped and stepped like `UIControlPort::set_value()`
+    ///
+    /// If the port is set several times within the transaction,
+    /// only the last value is sent, at the position of the first
+    /// write. Returns false if there is no such control port.
+    pub fn set_control(&mut self, port_index: u32, value: f32) -> bool {
+        let port = match self.ports.map_control_port(port_index) {
+            Some(port) => port,
+            None => return false,
+        };
+        port.set_value(value);
+        port.mark_written();
+        let data = port.value().to_ne_bytes().to_vec();
+        match self
+            .writes
+            .iter_mut()
+            .find(|w| w.port_index == port_index && w.protocol == 0)
+        {
+            Some(write) => write.data = data,
+            None => self.writes.push(PendingWrite {
+                port_index,
+                protocol: 0,
+                data,
+            }),
+        }
+        true
+    }
+
+    /// Writes an atom to an atom port
+    ///
+    /// `write` is supposed to write the atom using `UIAtomPort::init()`
+    /// and to return true on success. Every atom written is sent.
+    pub fn write_atom<F>(&mut self, port_index: u32, write: F) -> bool
+    where
+        F: FnOnce(&mut UIAtomPort) -> bool,
+    {
+        let port = match self.ports.map_atom_port(port_index) {
+            Some(port) => port,
+            None => return false,
+        };
+        if !write(port) {
+            return false;
+        }
+        let data = unsafe { std::slice::from_raw_parts(port.data() as *const u8, port.size()) };
+        self.writes.push(PendingWrite {
+            port_index,
+            protocol: port.protocol(),
+            data: data.to_vec(),
+        });
+        true
+    }
+}
+
+/// Creates the ports of a port collection
+///
+/// Maps the URIDs the ports need once, so the ports can be created
+/// in `PluginUI::new()` without passing the map around:
+///
+/// ```ignore
+/// let factory = UIPortFactory::new(&features.map)?;
+/// let ports = AmpPorts {
+///     gain: factory.control(0),
+///     notify: factory.atom(1),
+///     level: factory.peak_meter(2),
+/// };
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct UIPortFactory {
+    event_transfer: URID<atom::uris::EventTransfer>,
+    peak_protocol: URID<PeakProtocol>,
+}
+
+impl UIPortFactory {
+    /// Returns None if one of the URIs cannot be mapped
+    pub fn new(map: &impl Map) -> Option<Self> {
+        Some(Self {
+            event_transfer: map.map_type()?,
+            peak_protocol: map.map_type()?,
+        })
+    }
+
+    pub fn control(&self, index: u32) -> UIControlPort {
+        UIControlPort::new(index)
+    }
+
+    pub fn atom(&self, index: u32) -> UIAtomPort {
+        UIAtomPort::new(self.event_transfer, index)
+    }
+
+    pub fn peak_meter(&self, index: u32) -> PeakMeter {
+        PeakMeter::new(self.peak_protocol, index)
+    }
+}
+
+/// A port of a collection, see `UIPortsTrait::visit_ports()`
+pub enum PortRef<'a> {
+    Control(&'a mut UIControlPort),
+    Atom(&'a mut UIAtomPort),
+    Peak(&'a mut PeakMeter),
+}
+
+impl<'a> PortRef<'a> {
+    pub fn index(&self) -> u32 {
+        match self {
+            PortRef::Control(port) => port.index(),
+            PortRef::Atom(port) => port.index(),
+            PortRef::Peak(meter) => meter.index(),
+        }
+    }
+
+    /// True if the UI has set the value of a control port and not written it yet
+    ///
+    /// Atom ports are never dirty, their messages are written when
+    /// they are put together. Peak meters are never written.
+    pub fn is_dirty(&self) -> bool {
+        match self {
+            PortRef::Control(port) => port.is_dirty(),
+            PortRef::Atom(_) | PortRef::Peak(_) => false,
+        }
+    }
+}
+
+/// A port event the port collection could not dispatch, see `DispatchPolicy`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PortMismatch {
+    /// A control value for a port that is no control port of the collection
+    UnknownControlPort(u32),
+    /// An atom for a port that is no atom port of the collection
+    UnknownAtomPort(u32),
+    /// An atom for a port expecting another protocol
+    UridMismatch {
+        port_index: u32,
+        expected: u32,
+        received: u32,
+    },
+    /// A buffer that is null or does not hold what the format says
+    Malformed {
+        port_index: u32,
+        format: u32,
+        buffer_size: u32,
+    },
+}
+
+impl PortMismatch {
+    pub fn port_index(&self) -> u32 {
+        match *self {
+            PortMismatch::UnknownControlPort(port_index)
+            | PortMismatch::UnknownAtomPort(port_index)
+            | PortMismatch::UridMismatch { port_index, .. }
+            | PortMismatch::Malformed { port_index, .. } => port_index,
+        }
+    }
+
+    pub(crate) fn log(&self) {
+        match self {
+            PortMismatch::UnknownControlPort(port_index) => {
+                ui_log!(Warning, "unknown control port: {}", port_index)
             }
-            urid => match self.map_atom_port(port_index) {
-                Some(ref mut port) => {
+            PortMismatch::UnknownAtomPort(port_index) => {
+                ui_log!(Warning, "unknown atom port: {}", port_index)
+            }
+            PortMismatch::UridMismatch { port_index, .. } => {
+                ui_log!(Warning, "urids of port {} don't match", port_index)
+            }
+            PortMismatch::Malformed {
+                port_index,
+                format,
+                buffer_size,
+            } => ui_log!(
+                Warning,
+                "malformed event for port {}: format {}, {} bytes",
+                port_index,
+                format,
+                buffer_size
+            ),
+        }
+    }
+}
+
+/// Checks that the buffer of a port event is there and a control value is one `f32`
+pub(crate) fn check_buffer(
+    port_index: u32,
+    buffer_size: u32,
+    format: u32,
+    buffer: *const std::ffi::c_void,
+) -> Result<(), PortMismatch> {
+    let control_size = std::mem::size_of::<f32>() as u32;
+    if buffer.is_null() || (format == 0 && buffer_size != control_size) {
+        return Err(PortMismatch::Malformed {
+            port_index,
+            format,
+            buffer_size,
+        });
+    }
+    Ok(())
+}
+
+/// Checks that the buffer of a port event holds a complete atom
+pub(crate) fn check_atom(
+    port_index: u32,
+    buffer_size: u32,
+    format: u32,
+    buffer: *const std::ffi::c_void,
+) -> Result<(), PortMismatch> {
+    check_buffer(port_index, buffer_size, format, buffer)?;
+    let header = std::mem::size_of::<sys::LV2_Atom>();
+    let complete = buffer_size as usize >= header && {
+        let atom = unsafe { (buffer as *const sys::LV2_Atom).read_unaligned() };
+        header + atom.size as usize <= buffer_size as usize
+    };
+    if !complete {
+        return Err(PortMismatch::Malformed {
+            port_index,
+            format,
+            buffer_size,
+        });
+    }
+    Ok(())
+}
+
+/// Trait for a UIPort collection
+pub trait UIPortsTrait: Sized {
+    /// Runs `f` collecting its port writes and sends them to the plugin afterwards
+    ///
+    /// The writes are sent back-to-back in the order they have been
+    /// done, so that multi-port updates like loading a scene arrive
+    /// at the plugin coherently. If `f` panics nothing is sent.
+    fn transaction<R, F>(&mut self, write_handle: &PluginPortWriteHandle, f: F) -> R
+    where
+        F: FnOnce(&mut PortTransaction<Self>) -> R,
+    {
+        let mut transaction = PortTransaction {
+            ports: self,
+            writes: Vec::new(),
+        };
+        let r = f(&mut transaction);
+        for write in transaction.writes {
+            write_handle.write_raw(write.port_index, write.protocol, &write.data);
+        }
+        r
+    }
+
+    /// Passes a port event to the port, logging the events it does not fit
+    fn port_event(
+        &mut self,
+        port_index: u32,
+        buffer_size: u32,
+        format: u32,
+        buffer: *const std::ffi::c_void,
+    ) {
+        if let Err(mismatch) = self.dispatch_port_event(port_index, buffer_size, format, buffer) {
+            mismatch.log();
+        }
+    }
+
+    /// Passes a port event to the port, the data is dropped if it does not fit
+    ///
+    /// Returns the event the port received, for `PluginUI::port_changed()`.
+    fn dispatch_port_event(
+        &mut self,
+        port_index: u32,
+        buffer_size: u32,
+        format: u32,
+        buffer: *const std::ffi::c_void,
+    ) -> Result<PortEvent, PortMismatch> {
+        check_buffer(port_index, buffer_size, format, buffer)?;
+        let now = Instant::now();
+        match format {
+            0 => {
+                let value = unsafe { (buffer as *const f32).read_unaligned() };
+                if let Some(port) = self.map_control_port(port_index) {
+                    port.receive_value(value);
+                    port.stamp(now);
+                } else if let Some(port) = self.map_modulation_port(port_index) {
+                    port.set_modulated_value(value);
+                    port.stamp(now);
+                } else {
+                    return Err(PortMismatch::UnknownControlPort(port_index));
+                }
+                Ok(PortEvent::Control {
+                    index: port_index,
+                    value,
+                })
+            }
+            urid => {
+                if let Some(port) = self.map_atom_port(port_index) {
                     if port.urid() == urid {
+                        check_atom(port_index, buffer_size, format, buffer)?;
                         if let Some(pointer) =
                             std::ptr::NonNull::new(buffer as *mut std::ffi::c_void)
                         {
                             unsafe {
                                 port.put_buffer(pointer, buffer_size as usize);
                             }
+                            port.stamp(now);
                         }
                     } else {
-                        eprintln!("urids of port {} don't match", port_index);
+                        return Err(PortMismatch::UridMismatch {
+                            port_index,
+                            expected: port.urid(),
+                            received: urid,
+                        });
+                    }
+                } else if let Some(meter) = self.map_peak_port(port_index) {
+                    let size = std::mem::size_of::<sys::LV2UI_Peak_Data>();
+                    if meter.urid() != urid {
+                        return Err(PortMismatch::UridMismatch {
+                            port_index,
+                            expected: meter.urid(),
+                            received: urid,
+                        });
+                    } else if (buffer_size as usize) < size {
+                        return Err(PortMismatch::Malformed {
+                            port_index,
+                            format,
+                            buffer_size,
+                        });
                     }
+                    let data = unsafe { (buffer as *const sys::LV2UI_Peak_Data).read_unaligned() };
+                    meter.put_peak_data(&data, now);
+                } else {
+                    return Err(PortMismatch::UnknownAtomPort(port_index));
                 }
-                None => eprintln!("unknown atom port: {}", port_index),
-            },
+                Ok(PortEvent::Atom { index: port_index })
+            }
         }
     }
 
     fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort>;
 
     fn map_atom_port(&mut self, port_index: u32) -> Option<&mut UIAtomPort>;
+
+    /// Maps the index of a port carrying modulated values to its base port
+    ///
+    /// Values arriving on the port `port_index` are stored as the
+    /// modulated value of the returned control port. By default no
+    /// port carries modulated values.
+    fn map_modulation_port(&mut self, _port_index: u32) -> Option<&mut UIControlPort> {
+        None
+    }
+
+    /// Maps the index of a port delivered by the `ui:peakProtocol`, see `meter`
+    ///
+    /// By default no port is metered.
+    fn map_peak_port(&mut self, _port_index: u32) -> Option<&mut PeakMeter> {
+        None
+    }
+
+    /// Calls `f` for every port of the collection, by ascending index
+    ///
+    /// Lets generic code like write batching or debug overlays
+    /// enumerate the ports without knowing the collection. Control
+    /// ports, atom ports and peak meters are visited. `ui_ports!` and
+    /// `#[derive(UIPorts)]` generate it from their fields.
+    ///
+    /// By default no port is visited, so hand-written collections
+    /// need to implement it for `visit_dirty_ports()`, `NestedPorts`,
+    /// the diagnostics and the session recording to see their ports.
+    fn visit_ports(&mut self, _f: impl FnMut(PortRef)) {}
+
+    /// Calls `f` for every port the UI has changed and not written yet
+    ///
+    /// See `PortRef::is_dirty()`.
+    fn visit_dirty_ports(&mut self, mut f: impl FnMut(PortRef)) {
+        self.visit_ports(|port| {
+            if port.is_dirty() {
+                f(port)
+            }
+        });
+    }
+}
+
+/// A port of a `DynUIPorts` collection
+pub enum DynPort {
+    Control(UIControlPort),
+    Atom(UIAtomPort),
+}
+
+/// A port collection defined at runtime
+///
+/// For UIs whose port layout is not known at compile time, like
+/// generic UIs or bridges, the ports are registered by their index
+/// after reading the layout, e.g. from the plugin's TTL:
+///
+/// ```ignore
+/// let mut ports = DynUIPorts::new();
+/// for (index, symbol) in controls {
+///     ports.add_control(index);
+/// }
+/// ports.add_atom(notify_index, event_transfer);
+/// ```
+///
+/// Registering a port at an index already taken replaces that port.
+#[derive(Default)]
+pub struct DynUIPorts {
+    ports: BTreeMap<u32, DynPort>,
+}
+
+impl DynUIPorts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a control port at `index` and returns it
+    pub fn add_control(&mut self, index: u32) -> &mut UIControlPort {
+        self.insert_control(UIControlPort::new(index))
+    }
+
+    /// Registers an atom port at `index` and returns it
+    pub fn add_atom(
+        &mut self,
+        index: u32,
+        urid: URID<atom::uris::EventTransfer>,
+    ) -> &mut UIAtomPort {
+        self.insert_atom(UIAtomPort::new(urid, index))
+    }
+
+    /// Registers a control port already set up, e.g. with metadata
+    pub fn insert_control(&mut self, port: UIControlPort) -> &mut UIControlPort {
+        let index = port.index();
+        self.ports.insert(index, DynPort::Control(port));
+        self.map_control_port(index).unwrap()
+    }
+
+    /// Registers an atom port already set up, e.g. with a queue size
+    pub fn insert_atom(&mut self, port: UIAtomPort) -> &mut UIAtomPort {
+        let index = port.index();
+        self.ports.insert(index, DynPort::Atom(port));
+        self.map_atom_port(index).unwrap()
+    }
+
+    /// Unregisters the port at `index` and returns it
+    pub fn remove(&mut self, index: u32) -> Option<DynPort> {
+        self.ports.remove(&index)
+    }
+
+    pub fn get(&self, index: u32) -> Option<&DynPort> {
+        self.ports.get(&index)
+    }
+
+    /// The indices of the registered ports, ascending
+    pub fn indices(&self) -> impl Iterator<Item = u32> + '_ {
+        self.ports.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ports.is_empty()
+    }
+}
+
+impl UIPortsTrait for DynUIPorts {
+    fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+        match self.ports.get_mut(&port_index)? {
+            DynPort::Control(port) => Some(port),
+            DynPort::Atom(_) => None,
+        }
+    }
+
+    fn map_atom_port(&mut self, port_index: u32) -> Option<&mut UIAtomPort> {
+        match self.ports.get_mut(&port_index)? {
+            DynPort::Atom(port) => Some(port),
+            DynPort::Control(_) => None,
+        }
+    }
+
+    fn visit_ports(&mut self, mut f: impl FnMut(PortRef)) {
+        for port in self.ports.values_mut() {
+            match port {
+                DynPort::Control(port) => f(PortRef::Control(port)),
+                DynPort::Atom(port) => f(PortRef::Atom(port)),
+            }
+        }
+    }
+}
+
+/// A sub-collection of ports starting at an index offset
+///
+/// Large plugins repeat a group of ports per voice or band. The
+/// group is written once as a collection with indices starting at
+/// 0 and nested into the collection of the UI at the index of its
+/// first port:
+///
+/// ```ignore
+/// struct EqPorts {
+///     gain: UIControlPort,
+///     low: NestedPorts<BandPorts>,
+///     high: NestedPorts<BandPorts>,
+/// }
+///
+/// impl UIPortsTrait for EqPorts {
+///     fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+///         if port_index == 0 {
+///             return Some(&mut self.gain);
+///         }
+///         if let Some(port) = self.low.map_control_port(port_index) {
+///             return Some(port);
+///         }
+///         self.high.map_control_port(port_index)
+///     }
+///     ...
+/// }
+///
+/// let ports = EqPorts {
+///     gain: UIControlPort::new(0),
+///     low: NestedPorts::new(BandPorts::new(), 16),
+///     high: NestedPorts::new(BandPorts::new(), 32),
+/// };
+/// ```
+///
+/// The ports of the sub-collection are moved to their indices in
+/// the plugin, so they are written there. Port events are passed on
+/// with the offset subtracted. The nested collection dereferences to
+/// the sub-collection, so its ports are still reached by
+/// `self.ports.low.frequency`. `ui_ports!` nests collections by its
+/// `nested` section, `#[derive(UIPorts)]` by `#[nested(offset = N)]`
+/// fields.
+pub struct NestedPorts<P: UIPortsTrait> {
+    ports: P,
+    offset: u32,
+}
+
+impl<P: UIPortsTrait> NestedPorts<P> {
+    /// Nests `ports`, their indices starting at 0, at the index `offset`
+    pub fn new(mut ports: P, offset: u32) -> Self {
+        ports.visit_ports(|port| match port {
+            PortRef::Control(port) => port.shift_index(offset),
+            PortRef::Atom(port) => port.shift_index(offset),
+            PortRef::Peak(meter) => meter.shift_index(offset),
+        });
+        Self { ports, offset }
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn into_inner(self) -> P {
+        self.ports
+    }
+}
+
+impl<P: UIPortsTrait> std::ops::Deref for NestedPorts<P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        &self.ports
+    }
+}
+
+impl<P: UIPortsTrait> std::ops::DerefMut for NestedPorts<P> {
+    fn deref_mut(&mut self) -> &mut P {
+        &mut self.ports
+    }
+}
+
+impl<P: UIPortsTrait> UIPortsTrait for NestedPorts<P> {
+    fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+        self.ports
+            .map_control_port(port_index.checked_sub(self.offset)?)
+    }
+
+    fn map_atom_port(&mut self, port_index: u32) -> Option<&mut UIAtomPort> {
+        self.ports
+            .map_atom_port(port_index.checked_sub(self.offset)?)
+    }
+
+    fn map_modulation_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+        self.ports
+            .map_modulation_port(port_index.checked_sub(self.offset)?)
+    }
+
+    fn map_peak_port(&mut self, port_index: u32) -> Option<&mut PeakMeter> {
+        self.ports
+            .map_peak_port(port_index.checked_sub(self.offset)?)
+    }
+
+    // The ports already carry their indices in the plugin
+    fn visit_ports(&mut self, f: impl FnMut(PortRef)) {
+        self.ports.visit_ports(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom_port() -> UIAtomPort {
+        UIAtomPort::new(unsafe { URID::new_unchecked(1) }, 0)
+    }
+
+    // An `atom:Int` with the type URID 2, as the host sends it
+    fn int_atom(value: i32) -> [u8; 12] {
+        let mut atom = [0u8; 12];
+        atom[..4].copy_from_slice(&4u32.to_ne_bytes());
+        atom[4..8].copy_from_slice(&2u32.to_ne_bytes());
+        atom[8..].copy_from_slice(&value.to_ne_bytes());
+        atom
+    }
+
+    unsafe fn put(port: &mut UIAtomPort, atom: &[u8]) {
+        port.put_buffer(std::ptr::NonNull::from(&atom[0]).cast(), atom.len());
+    }
+
+    // The value of an atom made by `int_atom()`
+    fn int_value(space: atom::space::Space) -> i32 {
+        let mut value = [0u8; 4];
+        value.copy_from_slice(&space.data().unwrap()[8..12]);
+        i32::from_ne_bytes(value)
+    }
+
+    fn queued_values(port: &UIAtomPort) -> Vec<i32> {
+        port.queue
+            .iter()
+            .map(|space| int_value(space.as_space()))
+            .collect()
+    }
+
+    fn put_values(port: &mut UIAtomPort, values: std::ops::Range<i32>) {
+        for value in values {
+            unsafe { put(port, &int_atom(value)) };
+        }
+    }
+
+    // Written before `visit_ports()` existed
+    struct GainPorts {
+        gain: UIControlPort,
+    }
+
+    impl UIPortsTrait for GainPorts {
+        fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+            match port_index {
+                0 => Some(&mut self.gain),
+                _ => None,
+            }
+        }
+
+        fn map_atom_port(&mut self, _port_index: u32) -> Option<&mut UIAtomPort> {
+            None
+        }
+    }
+
+    #[test]
+    fn visit_ports_visits_nothing_by_default() {
+        let mut ports = GainPorts {
+            gain: UIControlPort::new(0),
+        };
+        ports.gain.set_value(1.0);
+        let mut visited = 0;
+        ports.visit_ports(|_| visited += 1);
+        ports.visit_dirty_ports(|_| visited += 1);
+        assert_eq!(visited, 0);
+    }
+
+    #[test]
+    fn queued_atoms_are_aligned() {
+        let mut port = atom_port();
+        // The host's buffer is not necessarily aligned
+        let mut buffer = [0u8; 13];
+        for value in 0..3 {
+            buffer[1..].copy_from_slice(&int_atom(value));
+            unsafe { put(&mut port, &buffer[1..]) };
+        }
+        for space in port.queue.iter() {
+            assert_eq!(space.as_ptr() as usize % 8, 0);
+        }
+        assert_eq!(port.drain().count(), 3);
+        for space in port.drained.iter().chain(Some(&port.space_to_ui)) {
+            assert_eq!(space.as_ptr() as usize % 8, 0);
+        }
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_newest_atoms() {
+        let mut port = atom_port().with_queue(2, OverflowPolicy::DropOldest);
+        put_values(&mut port, 0..4);
+        assert_eq!(queued_values(&port), vec![2, 3]);
+        assert_eq!(port.dropped(), 2);
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_oldest_atoms() {
+        let mut port = atom_port().with_queue(2, OverflowPolicy::DropNewest);
+        put_values(&mut port, 0..4);
+        assert_eq!(queued_values(&port), vec![0, 1]);
+        assert_eq!(port.dropped(), 2);
+    }
+
+    #[test]
+    fn atoms_are_read_next_from_oldest_to_newest() {
+        let mut port = atom_port();
+        put_values(&mut port, 0..3);
+        let mut values = Vec::new();
+        while let Some(space) = port.pop_next() {
+            values.push(int_value(space));
+        }
+        assert_eq!(values, vec![0, 1, 2]);
+        assert_eq!(port.queued(), 0);
+    }
+
+    #[test]
+    fn atoms_are_drained_from_oldest_to_newest() {
+        let mut port = atom_port();
+        put_values(&mut port, 0..3);
+        assert_eq!(port.drain().count(), 3);
+        let values: Vec<i32> = port
+            .drained
+            .iter()
+            .chain(Some(&port.space_to_ui))
+            .map(|space| int_value(space.as_space()))
+            .collect();
+        assert_eq!(values, vec![0, 1, 2]);
+        assert_eq!(port.queued(), 0);
+
+        put_values(&mut port, 3..4);
+        assert_eq!(port.drain().count(), 1);
+        assert_eq!(int_value(port.space_to_ui.as_space()), 3);
+    }
+
+    #[test]
+    fn read_spaces_are_reused() {
+        let mut port = atom_port();
+        put_values(&mut port, 0..1);
+        let first = port.queue[0].as_ptr();
+        port.pop_next();
+        put_values(&mut port, 1..2);
+        // Releases the space of the first atom
+        port.pop_next();
+        put_values(&mut port, 2..3);
+        assert_eq!(port.queue[0].as_ptr(), first);
+        assert_eq!(queued_values(&port), vec![2]);
+    }
+
+    #[test]
+    fn free_spaces_are_bounded_by_the_queue_capacity() {
+        let mut port = atom_port().with_queue(2, OverflowPolicy::DropOldest);
+        for _ in 0..4 {
+            put_values(&mut port, 0..6);
+            port.drain().count();
+            assert!(port.free.len() <= 2);
+        }
+        put_values(&mut port, 0..6);
+        while port.pop_next().is_some() {}
+        assert!(port.free.len() <= 2);
+    }
+
+    // A band of an equalizer, nested per band
+    struct BandPorts {
+        frequency: UIControlPort,
+        notify: UIAtomPort,
+        level: PeakMeter,
+    }
+
+    impl BandPorts {
+        fn new() -> Self {
+            Self {
+                frequency: UIControlPort::new(0),
+                notify: UIAtomPort::new(unsafe { URID::new_unchecked(1) }, 1),
+                level: PeakMeter::new(unsafe { URID::new_unchecked(5) }, 2),
+            }
+        }
+    }
+
+    impl UIPortsTrait for BandPorts {
+        fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+            match port_index {
+                0 => Some(&mut self.frequency),
+                _ => None,
+            }
+        }
+
+        fn map_atom_port(&mut self, port_index: u32) -> Option<&mut UIAtomPort> {
+            match port_index {
+                1 => Some(&mut self.notify),
+                _ => None,
+            }
+        }
+
+        fn map_peak_port(&mut self, port_index: u32) -> Option<&mut PeakMeter> {
+            match port_index {
+                2 => Some(&mut self.level),
+                _ => None,
+            }
+        }
+
+        fn map_modulation_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+            match port_index {
+                3 => Some(&mut self.frequency),
+                _ => None,
+            }
+        }
+
+        fn visit_ports(&mut self, mut f: impl FnMut(PortRef)) {
+            f(PortRef::Control(&mut self.frequency));
+            f(PortRef::Atom(&mut self.notify));
+            f(PortRef::Peak(&mut self.level));
+        }
+    }
+
+    fn visited_indices(ports: &mut impl UIPortsTrait) -> Vec<u32> {
+        let mut indices = Vec::new();
+        ports.visit_ports(|port| {
+            indices.push(match port {
+                PortRef::Control(port) => port.index(),
+                PortRef::Atom(port) => port.index(),
+                PortRef::Peak(meter) => meter.index(),
+            })
+        });
+        indices
+    }
+
+    fn control_event(ports: &mut impl UIPortsTrait, port_index: u32, value: f32) -> PortEvent {
+        let buffer = value.to_ne_bytes();
+        ports
+            .dispatch_port_event(port_index, 4, 0, buffer.as_ptr() as *const std::ffi::c_void)
+            .unwrap()
+    }
+
+    #[test]
+    fn nested_ports_are_moved_by_the_offset() {
+        let mut nested = NestedPorts::new(BandPorts::new(), 16);
+        assert_eq!(nested.offset(), 16);
+        assert_eq!(nested.frequency.index(), 16);
+        assert_eq!(visited_indices(&mut nested), vec![16, 17, 18]);
+
+        assert_eq!(nested.map_control_port(16).unwrap().index(), 16);
+        assert_eq!(nested.map_atom_port(17).unwrap().index(), 17);
+        assert_eq!(nested.map_peak_port(18).unwrap().index(), 18);
+        assert_eq!(nested.map_modulation_port(19).unwrap().index(), 16);
+        // The indices of the sub-collection itself are not mapped
+        assert!(nested.map_control_port(0).is_none());
+        assert!(nested.map_atom_port(1).is_none());
+        assert!(nested.map_control_port(17).is_none());
+
+        let mut band = nested.into_inner();
+        assert_eq!(visited_indices(&mut band), vec![16, 17, 18]);
+    }
+
+    #[test]
+    fn port_events_are_dispatched_to_nested_ports() {
+        let mut nested = NestedPorts::new(BandPorts::new(), 16);
+        assert_eq!(
+            control_event(&mut nested, 16, 440.0),
+            PortEvent::Control {
+                index: 16,
+                value: 440.0
+            }
+        );
+        assert_eq!(nested.frequency.value(), 440.0);
+        control_event(&mut nested, 19, 220.0);
+        assert_eq!(nested.frequency.modulated_value(), Some(220.0));
+
+        let atom = int_atom(7);
+        let event = nested.dispatch_port_event(
+            17,
+            atom.len() as u32,
+            1,
+            atom.as_ptr() as *const std::ffi::c_void,
+        );
+        assert_eq!(event.unwrap(), PortEvent::Atom { index: 17 });
+        assert_eq!(queued_values(&nested.notify), vec![7]);
+
+        let peak = sys::LV2UI_Peak_Data {
+            period_start: 0,
+            period_size: 256,
+            peak: 0.5,
+        };
+        let event = nested.dispatch_port_event(
+            18,
+            std::mem::size_of::<sys::LV2UI_Peak_Data>() as u32,
+            5,
+            &peak as *const sys::LV2UI_Peak_Data as *const std::ffi::c_void,
+        );
+        assert_eq!(event.unwrap(), PortEvent::Atom { index: 18 });
+        assert_eq!(nested.level.peak(), 0.5);
+
+        let buffer = 1.0f32.to_ne_bytes();
+        let event = nested.dispatch_port_event(0, 4, 0, buffer.as_ptr() as *const std::ffi::c_void);
+        assert_eq!(event, Err(PortMismatch::UnknownControlPort(0)));
+    }
+
+    #[test]
+    fn dyn_ports_are_mapped_by_index() {
+        let mut ports = DynUIPorts::new();
+        assert!(ports.is_empty());
+        ports.add_control(4);
+        ports.insert_atom(atom_port().with_queue(2, OverflowPolicy::DropNewest));
+        ports.add_control(2).set_value(0.5);
+
+        assert_eq!(ports.len(), 3);
+        assert_eq!(ports.indices().collect::<Vec<_>>(), vec![0, 2, 4]);
+        assert_eq!(visited_indices(&mut ports), vec![0, 2, 4]);
+        assert_eq!(ports.map_control_port(2).unwrap().value(), 0.5);
+        assert!(ports.map_control_port(0).is_none());
+        assert!(ports.map_atom_port(2).is_none());
+        assert!(ports.map_atom_port(0).is_some());
+
+        assert_eq!(
+            control_event(&mut ports, 4, 0.25),
+            PortEvent::Control {
+                index: 4,
+                value: 0.25
+            }
+        );
+        assert_eq!(ports.map_control_port(4).unwrap().value(), 0.25);
+    }
+
+    #[test]
+    fn dyn_ports_are_replaced_and_removed() {
+        let mut ports = DynUIPorts::new();
+        ports.add_control(0).set_value(0.5);
+        ports.add_control(0);
+        assert_eq!(ports.len(), 1);
+        assert_eq!(ports.map_control_port(0).unwrap().value(), 0.0);
+
+        ports.insert_atom(atom_port());
+        assert!(matches!(ports.get(0), Some(DynPort::Atom(_))));
+        assert!(matches!(ports.remove(0), Some(DynPort::Atom(_))));
+        assert!(ports.remove(0).is_none());
+        assert!(ports.is_empty());
+    }
 }
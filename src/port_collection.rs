@@ -0,0 +1,284 @@
+//! UI ports generated from the plugin's port collection
+//!
+//! A plugin written with `lv2_core` describes its ports by a
+//! `PortCollection`, a struct whose fields are the ports in the
+//! order of their indices. With the `derive` feature, `UIPorts`
+//! derives the matching UI port collection from the same struct, so
+//! the indices cannot drift apart:
+//!
+//! ```ignore
+//! // in a crate shared by plugin and UI
+//! #[derive(PortCollection, UIPorts)]
+//! #[ui_ports(name = "AmpUIPorts")]
+//! pub struct AmpPorts {
+//!     gain: InputPort<Control>,
+//!     input: InputPort<Audio>,
+//!     output: OutputPort<Audio>,
+//!     notify: OutputPort<AtomPort>,
+//! }
+//!
+//! // in the UI
+//! let mut ports = AmpUIPorts::new(features.map.map_type()?);
+//! ports.gain.set_value(0.5);
+//! ```
+//!
+//! The derived struct has a `UIControlPort` for every control port
+//! and a `UIAtomPort` for every atom port, named like the fields.
+//! Audio and CV ports are left out but keep their indices. It
+//! implements `UIPortsTrait` and its `new()` takes the URID of
+//! `atom:eventTransfer` if there are atom ports. `AmpUIPorts::PORTS`
+//! lists the ports for `PluginUI::PORTS`, taking the field names as
+//! symbols.
+//!
+//! Port types are recognized by their names. Fields of other types
+//! are declared by `#[ui_ports(kind = "control")]`, `"atom"` or
+//! `"skip"`.
+//!
+//! Port collections repeated per voice or band are nested by
+//! `#[nested(offset = N)]`, giving the index of their first port.
+//! The nested collection derives `UIPorts` itself, its field in the
+//! UI collection is a `NestedPorts` of its UI collection:
+//!
+//! ```ignore
+//! #[derive(PortCollection, UIPorts)]
+//! #[ui_ports(name = "EqUIPorts")]
+//! pub struct EqPorts {
+//!     gain: InputPort<Control>,
+//!     #[nested(offset = 16)]
+//!     low: BandPorts,
+//!     #[nested(offset = 32)]
+//!     high: BandPorts,
+//! }
+//! ```
+//!
+//! Nested collections come after the ports and are not part of
+//! `PORTS`. The `new()` of a collection nesting others always takes
+//! the URID of `atom:eventTransfer`.
+//!
+//! UIs of plugins not written with `lv2_core` declare their ports by
+//! `ui_ports!`, which also defines constants for the indices.
+
+#[cfg(feature = "derive")]
+pub use lv2_ui_derive::UIPorts;
+
+use lv2_atom::uris::EventTransfer;
+use urid::URID;
+
+use crate::port::UIPortsTrait;
+
+/// The UI port collection derived from a port collection by `#[derive(UIPorts)]`
+///
+/// Lets a derived collection nest the one of another port collection.
+pub trait DerivedUIPorts {
+    type UIPorts: UIPortsTrait;
+
+    /// Creates the UI ports, `event_transfer` is ignored if there are no atom ports
+    fn ui_ports(event_transfer: URID<EventTransfer>) -> Self::UIPorts;
+}
+
+#[doc(hidden)]
+pub mod __private {
+    pub use lv2_atom::uris::EventTransfer;
+    pub use urid::{Map, URID};
+}
+
+/// Declares a port collection and constants for its indices
+///
+/// Every line declares a port by `CONSTANT: symbol => index: kind`,
+/// `kind` being `control`, `atom` or `peak`. The struct gets a field
+/// named like the symbol for every port and the module a constant
+/// for every index, so the UI code refers to `ports::GAIN` instead
+/// of a number:
+///
+/// ```ignore
+/// ui_ports! {
+///     pub struct AmpPorts in ports {
+///         GAIN: gain => 0: control,
+///         NOTIFY: notify => 3: atom,
+///         LEVEL: level => 4: peak,
+///     }
+/// }
+///
+/// let mut ports = AmpPorts::new(&features.map)?;
+/// write_handle.write_raw(ports::GAIN, 0, &0.5f32.to_ne_bytes());
+/// ```
+///
+/// The struct implements `UIPortsTrait` and `AmpPorts::PORTS`
+/// lists the ports for `PluginUI::PORTS`. `new()` maps the URIDs
+/// the atom and peak ports need and returns None if that fails. The
+/// ports are expected in ascending order of their indices.
+///
+/// Collections repeated per voice or band are nested by a `nested`
+/// section after the ports, giving the index of their first port:
+///
+/// ```ignore
+/// ui_ports! {
+///     pub struct EqPorts in ports {
+///         GAIN: gain => 0: control,
+///     }
+///     nested {
+///         low: BandPorts => 16,
+///         high: BandPorts => 32,
+///     }
+/// }
+/// ```
+///
+/// The fields are `NestedPorts` of the collections, created by their
+/// `new(map)` as the ones of `ui_ports!` are. The nested ports are
+/// not part of `PORTS` and come after the others.
+#[macro_export]
+macro_rules! ui_ports {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident in $module:ident {
+            $($constant:ident: $symbol:ident => $index:literal: $kind:ident),* $(,)?
+        }
+        $(nested {
+            $($nested:ident: $nested_ty:ty => $offset:literal),* $(,)?
+        })?
+    ) => {
+        #[doc = concat!("The port indices of `", stringify!($name), "`")]
+        $vis mod $module {
+            $(pub const $constant: u32 = $index;)*
+        }
+
+        $(#[$meta])*
+        $vis struct $name {
+            $(pub $symbol: $crate::__ui_ports_kind!($kind, type),)*
+            $($(pub $nested: $crate::port::NestedPorts<$nested_ty>,)*)?
+        }
+
+        impl $name {
+            pub const PORTS: &'static [$crate::description::PortExpectation] = &[
+                $($crate::description::PortExpectation::$kind(
+                    $module::$constant,
+                    stringify!($symbol),
+                ),)*
+            ];
+
+            pub fn new(map: &impl $crate::port_collection::__private::Map) -> Option<Self> {
+                Some(Self {
+                    $($symbol: $crate::__ui_ports_kind!($kind, new, map, $module::$constant),)*
+                    $($($nested: $crate::port::NestedPorts::new(<$nested_ty>::new(map)?, $offset),)*)?
+                })
+            }
+        }
+
+        impl $crate::port::UIPortsTrait for $name {
+            fn map_control_port(
+                &mut self,
+                port_index: u32,
+            ) -> Option<&mut $crate::port::UIControlPort> {
+                $($crate::__ui_ports_kind!($kind, map control, self.$symbol, port_index, $module::$constant);)*
+                $($(if let Some(port) = $crate::port::UIPortsTrait::map_control_port(
+                    &mut self.$nested,
+                    port_index,
+                ) {
+                    return Some(port);
+                })*)?
+                None
+            }
+
+            fn map_atom_port(&mut self, port_index: u32) -> Option<&mut $crate::port::UIAtomPort> {
+                $($crate::__ui_ports_kind!($kind, map atom, self.$symbol, port_index, $module::$constant);)*
+                $($(if let Some(port) = $crate::port::UIPortsTrait::map_atom_port(
+                    &mut self.$nested,
+                    port_index,
+                ) {
+                    return Some(port);
+                })*)?
+                None
+            }
+
+            #[allow(unused_variables)]
+            fn map_modulation_port(
+                &mut self,
+                port_index: u32,
+            ) -> Option<&mut $crate::port::UIControlPort> {
+                $($(if let Some(port) = $crate::port::UIPortsTrait::map_modulation_port(
+                    &mut self.$nested,
+                    port_index,
+                ) {
+                    return Some(port);
+                })*)?
+                None
+            }
+
+            fn map_peak_port(&mut self, port_index: u32) -> Option<&mut $crate::meter::PeakMeter> {
+                $($crate::__ui_ports_kind!($kind, map peak, self.$symbol, port_index, $module::$constant);)*
+                $($(if let Some(port) = $crate::port::UIPortsTrait::map_peak_port(
+                    &mut self.$nested,
+                    port_index,
+                ) {
+                    return Some(port);
+                })*)?
+                None
+            }
+
+            #[allow(unused_mut, unused_variables)]
+            fn visit_ports(&mut self, mut f: impl FnMut($crate::port::PortRef)) {
+                $($crate::__ui_ports_kind!($kind, visit, self.$symbol, f);)*
+                $($($crate::port::UIPortsTrait::visit_ports(&mut self.$nested, &mut f);)*)?
+            }
+        }
+    };
+}
+
+/// Expands the parts of `ui_ports!` depending on the kind of a port
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __ui_ports_kind {
+    (control, type) => {
+        $crate::port::UIControlPort
+    };
+    (atom, type) => {
+        $crate::port::UIAtomPort
+    };
+    (peak, type) => {
+        $crate::meter::PeakMeter
+    };
+    (control, new, $map:expr, $index:expr) => {
+        $crate::port::UIControlPort::new($index)
+    };
+    (atom, new, $map:expr, $index:expr) => {
+        $crate::port::UIAtomPort::from_map($map, $index)?
+    };
+    (peak, new, $map:expr, $index:expr) => {
+        $crate::meter::PeakMeter::new(
+            $crate::port_collection::__private::Map::map_type($map)?,
+            $index,
+        )
+    };
+    (control, map control, $port:expr, $port_index:expr, $index:expr) => {
+        if $port_index == $index {
+            return Some(&mut $port);
+        }
+    };
+    (atom, map atom, $port:expr, $port_index:expr, $index:expr) => {
+        if $port_index == $index {
+            return Some(&mut $port);
+        }
+    };
+    (peak, map peak, $port:expr, $port_index:expr, $index:expr) => {
+        if $port_index == $index {
+            return Some(&mut $port);
+        }
+    };
+    ($kind:ident, map $other:ident, $($rest:tt)*) => {};
+    (control, visit, $port:expr, $f:expr) => {
+        $f($crate::port::PortRef::Control(&mut $port))
+    };
+    (atom, visit, $port:expr, $f:expr) => {
+        $f($crate::port::PortRef::Atom(&mut $port))
+    };
+    (peak, visit, $port:expr, $f:expr) => {
+        $f($crate::port::PortRef::Peak(&mut $port))
+    };
+    ($kind:ident, $($rest:tt)*) => {
+        compile_error!(concat!(
+            "unknown port kind `",
+            stringify!($kind),
+            "`, expected control, atom or peak"
+        ))
+    };
+}
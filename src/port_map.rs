@@ -0,0 +1,105 @@
+//! Looking up ports by their symbol
+//!
+//! Port indices are only stable as long as the plugin keeps its port
+//! order. The symbols of the ports are meant to stay, so hosts
+//! providing the `ui:portMap` feature let the UI resolve the indices
+//! on instantiation:
+//!
+//! ```ignore
+//! fn new(_: &PluginUIInfo, features: &mut Features<'static>, ...) -> Result<Self, PluginUIError> {
+//!     let port_map = &features.port_map;
+//!     let missing = |symbol: &str| PluginUIError::MissingPort(symbol.to_owned());
+//!     let event_transfer = features.map.map_type().ok_or_else(|| missing("notify"))?;
+//!     let ports = AmpPorts {
+//!         gain: port_map.control_port("gain").ok_or_else(|| missing("gain"))?,
+//!         notify: port_map
+//!             .atom_port("notify", event_transfer)
+//!             .ok_or_else(|| missing("notify"))?,
+//!     };
+//!     ...
+//! }
+//! ```
+//!
+//! Hosts without the feature require the indices known at compile
+//! time, so request it as `Option<PortMap<'static>>` and fall back to
+//! them with `PortMapFallback::index_or()`. `PortMap::check()` compares the
+//! `PluginUI::PORTS` declared with the indices of the host.
+
+use lv2_sys as sys;
+
+use std::ffi::{c_void, CString};
+
+use lv2_atom as atom;
+use lv2_core::feature::*;
+use urid::*;
+
+use crate::description::{PortExpectation, PortMismatch};
+use crate::port::{UIAtomPort, UIControlPort};
+
+/// The index `ui:portMap` returns for unknown symbols, `LV2UI_INVALID_PORT_INDEX`
+const INVALID_PORT_INDEX: u32 = u32::MAX;
+
+/// The `ui:portMap` host feature
+pub struct PortMap<'a> {
+    internal: &'a sys::LV2UI_Port_Map,
+}
+
+unsafe impl<'a> UriBound for PortMap<'a> {
+    const URI: &'static [u8] = sys::LV2_UI__portMap;
+}
+
+unsafe impl<'a> Feature for PortMap<'a> {
+    unsafe fn from_feature_ptr(feature: *const c_void, _class: ThreadingClass) -> Option<Self> {
+        (feature as *const sys::LV2UI_Port_Map)
+            .as_ref()
+            .map(|internal| Self { internal })
+    }
+}
+
+impl<'a> PortMap<'a> {
+    /// The index of the port with the symbol `symbol`, None if there is none
+    pub fn index(&self, symbol: &str) -> Option<u32> {
+        let port_index = self.internal.port_index?;
+        let symbol = CString::new(symbol).ok()?;
+        match unsafe { port_index(self.internal.handle, symbol.as_ptr()) } {
+            INVALID_PORT_INDEX => None,
+            index => Some(index),
+        }
+    }
+
+    /// A control port for the symbol `symbol`
+    pub fn control_port(&self, symbol: &str) -> Option<UIControlPort> {
+        self.index(symbol).map(UIControlPort::new)
+    }
+
+    /// An atom port for the symbol `symbol`
+    pub fn atom_port(
+        &self,
+        symbol: &str,
+        urid: URID<atom::uris::EventTransfer>,
+    ) -> Option<UIAtomPort> {
+        self.index(symbol).map(|index| UIAtomPort::new(urid, index))
+    }
+
+    /// Compares the expected ports with the ones of the plugin, see `UIDescription::check_ports()`
+    pub fn check(&self, expectations: &[PortExpectation]) -> Vec<PortMismatch> {
+        expectations
+            .iter()
+            .filter_map(|port| port.mismatch(self.index(port.symbol)))
+            .collect()
+    }
+}
+
+/// Extension for `Option<PortMap>`, falling back to indices known at compile time
+pub trait PortMapFallback {
+    /// The index of the port `symbol` if the host maps it, `index` otherwise
+    fn index_or(&self, symbol: &str, index: u32) -> u32;
+}
+
+impl<'a> PortMapFallback for Option<PortMap<'a>> {
+    fn index_or(&self, symbol: &str, index: u32) -> u32 {
+        self.as_ref()
+            .and_then(|port_map| port_map.index(symbol))
+            .unwrap_or(index)
+    }
+}
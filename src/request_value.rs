@@ -0,0 +1,79 @@
+//! Asking the host for a parameter value
+//!
+//! With the `ui:requestValue` feature the UI asks the host to let the
+//! user pick a value for a parameter, e.g. by a file dialog for an
+//! `atom:Path`. The host then sets the parameter by sending a
+//! `patch:Set` to the plugin, which the UI receives like any other
+//! change. Request it as `Option<RequestValue<'static>>` in the
+//! `InitFeatures`, not all hosts provide it.
+
+use lv2_sys as sys;
+
+use std::ffi::c_void;
+
+use lv2_core::feature::*;
+use urid::*;
+
+/// The reasons the host can refuse to request a value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestValueError {
+    /// A request is already in progress
+    Busy,
+    /// The parameter is not known to the host
+    Unknown,
+    /// The host can't request values of this type
+    Unsupported,
+}
+
+/// The `ui:requestValue` host feature
+pub struct RequestValue<'a> {
+    internal: &'a sys::LV2UI_Request_Value,
+}
+
+unsafe impl<'a> UriBound for RequestValue<'a> {
+    const URI: &'static [u8] = sys::LV2_UI__requestValue;
+}
+
+unsafe impl<'a> Feature for RequestValue<'a> {
+    unsafe fn from_feature_ptr(feature: *const c_void, _class: ThreadingClass) -> Option<Self> {
+        (feature as *const sys::LV2UI_Request_Value)
+            .as_ref()
+            .map(|internal| Self { internal })
+    }
+}
+
+impl<'a> RequestValue<'a> {
+    /// Asks the host for a value of the parameter `key`
+    ///
+    /// `value_type` is the type of the value, e.g. `atom:Path`. The
+    /// request returns right away, the value arrives later as
+    /// `patch:Set` from the plugin, if the user picks one.
+    pub fn request<K: ?Sized, T: ?Sized>(
+        &self,
+        key: URID<K>,
+        value_type: URID<T>,
+    ) -> Result<(), RequestValueError> {
+        let request = match self.internal.request {
+            Some(request) => request,
+            None => return Err(RequestValueError::Unsupported),
+        };
+        let status = unsafe {
+            request(
+                self.internal.handle,
+                key.get(),
+                value_type.get(),
+                std::ptr::null(),
+            )
+        };
+        match status {
+            sys::LV2UI_Request_Value_Status_LV2UI_REQUEST_VALUE_SUCCESS => Ok(()),
+            sys::LV2UI_Request_Value_Status_LV2UI_REQUEST_VALUE_BUSY => {
+                Err(RequestValueError::Busy)
+            }
+            sys::LV2UI_Request_Value_Status_LV2UI_REQUEST_VALUE_ERR_UNKNOWN => {
+                Err(RequestValueError::Unknown)
+            }
+            _ => Err(RequestValueError::Unsupported),
+        }
+    }
+}
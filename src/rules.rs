@@ -0,0 +1,163 @@
+//! Ports depending on the values of other ports
+//!
+//! Often a parameter is only relevant in some modes of the plugin,
+//! e.g. the attack time is meaningless while the compressor is
+//! bypassed. `PortRules` declares such dependencies. Applied to the
+//! port collection, it sets the enabled and visible flags of the
+//! control ports, so all widgets grey out or hide consistently:
+//!
+//! ```ignore
+//! // in new()
+//! let rules = PortRules::new()
+//!     .enabled_when(ATTACK, Condition::not_equal(MODE, BYPASS))
+//!     .visible_when(SIDECHAIN_GAIN, Condition::above(SIDECHAIN, 0.5));
+//!
+//! fn update(&mut self, _reason: UpdateReason) {
+//!     if self.rules.apply(&mut self.ports) {
+//!         self.redraw_all();
+//!     }
+//!     let attack_enabled = self.ports.attack.is_enabled();
+//! }
+//! ```
+//!
+//! Ports without a rule are always enabled and visible.
+
+use crate::port::UIPortsTrait;
+
+/// A condition on the values of control ports
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    /// The value of the port rounds to `value`, for integer or enumeration ports
+    Equal {
+        port: u32,
+        value: f32,
+    },
+    NotEqual {
+        port: u32,
+        value: f32,
+    },
+    Above {
+        port: u32,
+        value: f32,
+    },
+    Below {
+        port: u32,
+        value: f32,
+    },
+    Not(Box<Condition>),
+    All(Vec<Condition>),
+    Any(Vec<Condition>),
+}
+
+impl Condition {
+    pub fn equal(port: u32, value: f32) -> Self {
+        Condition::Equal { port, value }
+    }
+
+    pub fn not_equal(port: u32, value: f32) -> Self {
+        Condition::NotEqual { port, value }
+    }
+
+    pub fn above(port: u32, value: f32) -> Self {
+        Condition::Above { port, value }
+    }
+
+    pub fn below(port: u32, value: f32) -> Self {
+        Condition::Below { port, value }
+    }
+
+    /// True if the condition holds for the port values given by `value_of`
+    ///
+    /// Conditions on unknown ports do not hold.
+    pub fn holds(&self, value_of: &mut impl FnMut(u32) -> Option<f32>) -> bool {
+        match self {
+            Condition::Equal { port, value } => {
+                value_of(*port).is_some_and(|v| v.round() == value.round())
+            }
+            Condition::NotEqual { port, value } => {
+                value_of(*port).is_some_and(|v| v.round() != value.round())
+            }
+            Condition::Above { port, value } => value_of(*port).is_some_and(|v| v > *value),
+            Condition::Below { port, value } => value_of(*port).is_some_and(|v| v < *value),
+            Condition::Not(condition) => !condition.holds(value_of),
+            Condition::All(conditions) => conditions.iter().all(|c| c.holds(value_of)),
+            Condition::Any(conditions) => conditions.iter().any(|c| c.holds(value_of)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Effect {
+    Enabled,
+    Visible,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    port: u32,
+    effect: Effect,
+    condition: Condition,
+}
+
+/// The dependencies between the ports of a UI
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PortRules {
+    rules: Vec<Rule>,
+}
+
+impl PortRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The port `port` is enabled only while `condition` holds
+    pub fn enabled_when(mut self, port: u32, condition: Condition) -> Self {
+        self.rules.push(Rule {
+            port,
+            effect: Effect::Enabled,
+            condition,
+        });
+        self
+    }
+
+    /// The port `port` is visible only while `condition` holds
+    pub fn visible_when(mut self, port: u32, condition: Condition) -> Self {
+        self.rules.push(Rule {
+            port,
+            effect: Effect::Visible,
+            condition,
+        });
+        self
+    }
+
+    /// Recomputes the enabled and visible flags of the control ports
+    ///
+    /// Several rules for the same port and effect all need to hold.
+    /// Returns true if a flag has changed.
+    pub fn apply(&self, ports: &mut impl UIPortsTrait) -> bool {
+        let mut flags: Vec<(u32, bool, bool)> = Vec::new();
+        for rule in self.rules.iter() {
+            let holds = rule
+                .condition
+                .holds(&mut |index| ports.map_control_port(index).map(|port| port.value()));
+            let position = match flags.iter().position(|(port, ..)| *port == rule.port) {
+                Some(position) => position,
+                None => {
+                    flags.push((rule.port, true, true));
+                    flags.len() - 1
+                }
+            };
+            match rule.effect {
+                Effect::Enabled => flags[position].1 &= holds,
+                Effect::Visible => flags[position].2 &= holds,
+            }
+        }
+        let mut changed = false;
+        for (index, enabled, visible) in flags {
+            if let Some(port) = ports.map_control_port(index) {
+                changed |= port.set_availability(enabled, visible);
+            }
+        }
+        changed
+    }
+}
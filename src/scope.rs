@@ -0,0 +1,326 @@
+//! Triggering for oscilloscope displays
+//!
+//! Scope UIs usually receive raw waveform data in vectors of
+//! arbitrary length, e.g. as `atom:Vector`s of floats. To get a
+//! stable display, the frames shown need to start at a trigger
+//! point. `Trigger` detects level crossings across vector
+//! boundaries, `TriggeredCapture` assembles complete display frames
+//! starting at trigger points.
+
+/// The direction of the level crossing to trigger on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Slope {
+    Rising,
+    Falling,
+}
+
+/// Level and slope trigger with hold-off
+///
+/// The trigger keeps the last sample it has seen, so a crossing
+/// between the end of one vector and the start of the next is
+/// detected as well.
+#[derive(Clone, Debug)]
+pub struct Trigger {
+    level: f32,
+    slope: Slope,
+    hold_off: usize,
+    hold_off_remaining: usize,
+    previous: Option<f32>,
+}
+
+impl Trigger {
+    /// Creates a trigger firing when the signal crosses `level` in direction `slope`
+    pub fn new(level: f32, slope: Slope) -> Self {
+        Self {
+            level,
+            slope,
+            hold_off: 0,
+            hold_off_remaining: 0,
+            previous: None,
+        }
+    }
+
+    /// Sets the number of samples after a trigger during which no new trigger is accepted
+    pub fn with_hold_off(mut self, samples: usize) -> Self {
+        self.hold_off = samples;
+        self
+    }
+
+    pub fn set_level(&mut self, level: f32) {
+        self.level = level;
+    }
+
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    pub fn set_slope(&mut self, slope: Slope) {
+        self.slope = slope;
+    }
+
+    pub fn slope(&self) -> Slope {
+        self.slope
+    }
+
+    /// Forgets the signal history and a running hold-off
+    pub fn reset(&mut self) {
+        self.previous = None;
+        self.hold_off_remaining = 0;
+    }
+
+    /// Searches the next trigger point in `samples`
+    ///
+    /// Returns the index of the first sample after the crossing.
+    /// Only the samples up to and including that index are
+    /// consumed. The remaining samples should be passed to
+    /// `advance()` or to the next call of `find()`.
+    pub fn find(&mut self, samples: &[f32]) -> Option<usize> {
+        for (i, &sample) in samples.iter().enumerate() {
+            let previous = self.previous.replace(sample);
+            if self.hold_off_remaining > 0 {
+                self.hold_off_remaining -= 1;
+                continue;
+            }
+            let previous = match previous {
+                Some(previous) => previous,
+                None => continue,
+            };
+            let crossed = match self.slope {
+                Slope::Rising => previous < self.level && sample >= self.level,
+                Slope::Falling => previous > self.level && sample <= self.level,
+            };
+            if crossed {
+                self.hold_off_remaining = self.hold_off;
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    /// Consumes `samples` without looking for trigger points
+    ///
+    /// Keeps track of the signal history and the hold-off.
+    pub fn advance(&mut self, samples: &[f32]) {
+        if let Some(last) = samples.last() {
+            self.previous = Some(*last);
+        }
+        self.hold_off_remaining = self.hold_off_remaining.saturating_sub(samples.len());
+    }
+}
+
+/// Assembles display frames of a fixed length starting at trigger points
+///
+/// In auto mode a frame is captured even without a trigger if there
+/// was none for the given number of samples, so the display does
+/// not freeze for signals never crossing the trigger level.
+#[derive(Clone, Debug)]
+pub struct TriggeredCapture {
+    trigger: Trigger,
+    frame_length: usize,
+    auto_after: Option<usize>,
+    untriggered: usize,
+    capturing: bool,
+    current: Vec<f32>,
+    frame: Vec<f32>,
+    frame_ready: bool,
+}
+
+impl TriggeredCapture {
+    pub fn new(trigger: Trigger, frame_length: usize) -> Self {
+        Self {
+            trigger,
+            frame_length,
+            auto_after: None,
+            untriggered: 0,
+            capturing: false,
+            current: Vec::with_capacity(frame_length),
+            frame: Vec::with_capacity(frame_length),
+            frame_ready: false,
+        }
+    }
+
+    /// Enables auto mode, capturing after `samples` samples without a trigger
+    pub fn with_auto_mode(mut self, samples: usize) -> Self {
+        self.auto_after = Some(samples);
+        self
+    }
+
+    pub fn trigger(&mut self) -> &mut Trigger {
+        &mut self.trigger
+    }
+
+    /// Feeds received samples into the capture
+    ///
+    /// Returns true if a new frame has been completed.
+    pub fn push(&mut self, mut samples: &[f32]) -> bool {
+        let mut completed = false;
+        if self.frame_length == 0 {
+            return completed;
+        }
+        // Samples at the start of `samples` the trigger has seen already
+        let mut consumed = 0;
+        while !samples.is_empty() {
+            if !self.capturing {
+                // In auto mode only up to where the capture starts anyway
+                let searched = match self.auto_after {
+                    Some(auto_after) => auto_after
+                        .saturating_sub(self.untriggered)
+                        .min(samples.len()),
+                    None => samples.len(),
+                };
+                match self.trigger.find(&samples[..searched]) {
+                    Some(position) => {
+                        samples = &samples[position..];
+                        consumed = 1;
+                        self.start_capture();
+                    }
+                    None => {
+                        self.untriggered += searched;
+                        samples = &samples[searched..];
+                        match self.auto_after {
+                            Some(auto_after) if self.untriggered >= auto_after => {
+                                self.start_capture()
+                            }
+                            _ => return completed,
+                        }
+                    }
+                }
+            }
+
+            let needed = self.frame_length - self.current.len();
+            let (captured, rest) = samples.split_at(needed.min(samples.len()));
+            self.current.extend_from_slice(captured);
+            self.trigger
+                .advance(&captured[consumed.min(captured.len())..]);
+            consumed = 0;
+            samples = rest;
+
+            if self.current.len() == self.frame_length {
+                std::mem::swap(&mut self.current, &mut self.frame);
+                self.current.clear();
+                self.capturing = false;
+                self.frame_ready = true;
+                completed = true;
+            }
+        }
+        completed
+    }
+
+    /// The last complete frame, if there is one
+    pub fn frame(&self) -> Option<&[f32]> {
+        match self.frame_ready {
+            true => Some(&self.frame),
+            false => None,
+        }
+    }
+
+    /// Discards the frame in progress and the last complete frame
+    pub fn reset(&mut self) {
+        self.trigger.reset();
+        self.untriggered = 0;
+        self.capturing = false;
+        self.current.clear();
+        self.frame.clear();
+        self.frame_ready = false;
+    }
+
+    fn start_capture(&mut self) {
+        self.capturing = true;
+        self.untriggered = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rises from -3.5 to 3.5 and falls back to -3.5 every 8 samples
+    fn sawtooth(start: usize, len: usize) -> Vec<f32> {
+        (start..start + len).map(|i| (i % 8) as f32 - 3.5).collect()
+    }
+
+    #[test]
+    fn triggers_on_the_rising_slope() {
+        let mut trigger = Trigger::new(0.0, Slope::Rising);
+        assert_eq!(trigger.find(&[1.0, -1.0, -0.5, 0.5, 1.0, -1.0]), Some(3));
+        assert_eq!(trigger.find(&[1.0, -1.0]), None);
+    }
+
+    #[test]
+    fn triggers_on_the_falling_slope() {
+        let mut trigger = Trigger::new(0.0, Slope::Falling);
+        assert_eq!(trigger.find(&[-1.0, 1.0, 0.5, -0.5, -1.0, 1.0]), Some(3));
+        assert_eq!(trigger.find(&[1.0, -1.0]), Some(1));
+    }
+
+    #[test]
+    fn triggers_across_vector_boundaries() {
+        let mut trigger = Trigger::new(0.0, Slope::Rising);
+        assert_eq!(trigger.find(&[-1.0]), None);
+        assert_eq!(trigger.find(&[1.0]), Some(0));
+
+        trigger.reset();
+        assert_eq!(trigger.find(&[1.0]), None);
+    }
+
+    #[test]
+    fn hold_off_suppresses_triggers() {
+        let mut trigger = Trigger::new(0.0, Slope::Rising).with_hold_off(3);
+        let samples = [-1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0];
+        assert_eq!(trigger.find(&samples), Some(1));
+        // The crossing at 3 is within the hold-off
+        assert_eq!(trigger.find(&samples[2..]), Some(3));
+
+        let mut trigger = Trigger::new(0.0, Slope::Rising).with_hold_off(3);
+        assert_eq!(trigger.find(&samples[..2]), Some(1));
+        trigger.advance(&samples[2..4]);
+        assert_eq!(trigger.find(&samples[4..]), Some(1));
+    }
+
+    #[test]
+    fn frames_start_at_trigger_points() {
+        let mut capture = TriggeredCapture::new(Trigger::new(0.0, Slope::Rising), 4);
+        let mut frames = 0;
+        for start in (0..80).step_by(5) {
+            if capture.push(&sawtooth(start, 5)) {
+                assert_eq!(capture.frame(), Some(&[0.5, 1.5, 2.5, 3.5][..]));
+                frames += 1;
+            }
+        }
+        assert_eq!(frames, 10);
+    }
+
+    #[test]
+    fn hold_off_runs_on_while_capturing() {
+        let trigger = Trigger::new(0.0, Slope::Rising).with_hold_off(2);
+        let mut capture = TriggeredCapture::new(trigger, 2);
+        let samples = [-1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0];
+        let frames = samples
+            .chunks(1)
+            .filter(|sample| capture.push(sample))
+            .count();
+        // The crossing at 3 is within the hold-off of the one at 1
+        assert_eq!(frames, 2);
+    }
+
+    #[test]
+    fn auto_mode_captures_after_the_timeout() {
+        let mut capture =
+            TriggeredCapture::new(Trigger::new(-10.0, Slope::Rising), 4).with_auto_mode(6);
+        let ramp: Vec<f32> = (0..10).map(|i| i as f32).collect();
+        assert!(capture.push(&ramp));
+        assert_eq!(capture.frame(), Some(&[6.0, 7.0, 8.0, 9.0][..]));
+
+        assert!(!capture.push(&ramp[..5]));
+        assert!(capture.push(&ramp[5..]));
+        assert_eq!(capture.frame(), Some(&[6.0, 7.0, 8.0, 9.0][..]));
+    }
+
+    #[test]
+    fn auto_mode_prefers_triggers() {
+        let mut capture =
+            TriggeredCapture::new(Trigger::new(0.0, Slope::Rising), 2).with_auto_mode(6);
+        assert!(capture.push(&[-1.0, -1.0, 1.0, 2.0]));
+        assert_eq!(capture.frame(), Some(&[1.0, 2.0][..]));
+    }
+}
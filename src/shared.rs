@@ -0,0 +1,281 @@
+//! Sharing visualization data with a plugin in the same crate
+//!
+//! If plugin and UI come in the same binary, the UI can access the
+//! plugin instance directly using the `instance-access` feature.
+//! This is the fastest way to get large visualization data like
+//! spectra or waveforms to the UI, but the plugin instance lives in
+//! the audio thread, so the data needs to be shared in a lock free
+//! way, which is easy to get wrong.
+//!
+//! `triple_buffer()` creates a vetted channel for that. The plugin
+//! keeps the `TripleWriter` and publishes a new value whenever it
+//! has one, without ever blocking. The UI gets the `TripleReader`
+//! and always reads the latest value published completely:
+//!
+//! ```ignore
+//! // in the plugin
+//! let (writer, reader) = triple_buffer([0.0f32; 512]);
+//! self.spectrum = writer;
+//! self.spectrum_reader = Mutex::new(Some(reader));
+//!
+//! // in the UI
+//! let plugin = unsafe { features.instance_access.instance::<AmpPlugin>() };
+//! let reader = plugin.spectrum_reader.lock().unwrap().take();
+//! ```
+//!
+//! What the instance handle points to depends on how the plugin
+//! exports its descriptor, so only the plugin crate knows how to
+//! find its data there.
+//!
+//! The values should be `Copy` types like fixed size arrays. The
+//! writer drops the values it overwrites, so values that allocate
+//! would free memory in the audio thread.
+//!
+//! When the tests are built with `--cfg loom`, the synchronization
+//! uses the `loom` crate, so the buffer is model checked:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" cargo test --lib shared
+//! ```
+
+use std::ffi::c_void;
+
+use lv2_core::feature::*;
+use urid::*;
+
+#[cfg(all(test, loom))]
+use loom::cell::UnsafeCell;
+#[cfg(all(test, loom))]
+use loom::sync::atomic::{AtomicU8, Ordering};
+#[cfg(all(test, loom))]
+use loom::sync::Arc;
+#[cfg(not(all(test, loom)))]
+use std::sync::atomic::{AtomicU8, Ordering};
+#[cfg(not(all(test, loom)))]
+use std::sync::Arc;
+
+// The same interface as `loom::cell::UnsafeCell`
+#[cfg(not(all(test, loom)))]
+struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+#[cfg(not(all(test, loom)))]
+impl<T> UnsafeCell<T> {
+    fn new(value: T) -> Self {
+        Self(std::cell::UnsafeCell::new(value))
+    }
+
+    fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+        f(self.0.get())
+    }
+
+    fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        f(self.0.get())
+    }
+}
+
+/// The `instance-access` feature, giving the UI the handle of the plugin instance
+pub struct InstanceAccess {
+    handle: *mut c_void,
+}
+
+unsafe impl UriBound for InstanceAccess {
+    const URI: &'static [u8] = lv2_sys::LV2_INSTANCE_ACCESS_URI;
+}
+
+unsafe impl Feature for InstanceAccess {
+    unsafe fn from_feature_ptr(feature: *const c_void, _class: ThreadingClass) -> Option<Self> {
+        if feature.is_null() {
+            None
+        } else {
+            Some(Self {
+                handle: feature as *mut c_void,
+            })
+        }
+    }
+}
+
+impl InstanceAccess {
+    /// The `LV2_Handle` of the plugin instance
+    pub fn handle(&self) -> *mut c_void {
+        self.handle
+    }
+
+    /// The plugin instance as `P`
+    ///
+    /// # Safety
+    ///
+    /// The handle needs to point to a `P`, which is only known by the
+    /// crate exporting the plugin, and the plugin must not be
+    /// accessed mutably by the UI thread.
+    pub unsafe fn instance<P>(&self) -> &P {
+        &*(self.handle as *const P)
+    }
+}
+
+// The index of the back buffer, with the flag that it holds a value
+// that has not been read yet
+const INDEX: u8 = 0b011;
+const FRESH: u8 = 0b100;
+
+struct TripleBufferShared<T> {
+    slots: [UnsafeCell<T>; 3],
+    back: AtomicU8,
+}
+
+// Writer and reader never access the same slot, the slot indices are
+// exchanged through `back`.
+unsafe impl<T: Send> Sync for TripleBufferShared<T> {}
+
+/// Creates a triple buffer, all slots holding `initial`
+pub fn triple_buffer<T: Clone + Send>(initial: T) -> (TripleWriter<T>, TripleReader<T>) {
+    let shared = Arc::new(TripleBufferShared {
+        slots: [
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial),
+        ],
+        back: AtomicU8::new(1),
+    });
+    (
+        TripleWriter {
+            shared: shared.clone(),
+            write: 0,
+        },
+        TripleReader { shared, read: 2 },
+    )
+}
+
+/// The writing end of a triple buffer, usually in the plugin
+///
+/// Writing never blocks and never allocates.
+pub struct TripleWriter<T> {
+    shared: Arc<TripleBufferShared<T>>,
+    write: u8,
+}
+
+impl<T> TripleWriter<T> {
+    /// Publishes `value`
+    pub fn write(&mut self, value: T) {
+        self.shared.slots[self.write as usize].with_mut(|slot| unsafe { *slot = value });
+        self.publish();
+    }
+
+    /// Modifies the slot being written in place and publishes it
+    ///
+    /// The slot holds an older value, not necessarily the last one
+    /// published, so `f` needs to overwrite all of it.
+    pub fn write_with(&mut self, f: impl FnOnce(&mut T)) {
+        self.shared.slots[self.write as usize].with_mut(|slot| f(unsafe { &mut *slot }));
+        self.publish();
+    }
+
+    fn publish(&mut self) {
+        // Release makes the slot's content visible to the reader
+        // acquiring it, Acquire makes sure the reader is done with
+        // the slot taken over.
+        let back = self.shared.back.swap(self.write | FRESH, Ordering::AcqRel);
+        self.write = back & INDEX;
+    }
+}
+
+/// The reading end of a triple buffer, usually in the UI
+pub struct TripleReader<T> {
+    shared: Arc<TripleBufferShared<T>>,
+    read: u8,
+}
+
+impl<T> TripleReader<T> {
+    /// True if a value has been published since the last `read()`
+    pub fn has_update(&self) -> bool {
+        self.shared.back.load(Ordering::Relaxed) & FRESH != 0
+    }
+
+    /// The latest value published
+    pub fn read(&mut self) -> &T {
+        if self.has_update() {
+            let back = self.shared.back.swap(self.read, Ordering::AcqRel);
+            self.read = back & INDEX;
+        }
+        self.shared.slots[self.read as usize].with(|slot| unsafe { &*slot })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(loom))]
+    #[test]
+    fn reader_starts_with_the_initial_value() {
+        let (_writer, mut reader) = triple_buffer(7u32);
+        assert!(!reader.has_update());
+        assert_eq!(*reader.read(), 7);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn read_takes_the_latest_value() {
+        let (mut writer, mut reader) = triple_buffer(0u32);
+        writer.write(1);
+        assert!(reader.has_update());
+        writer.write(2);
+        writer.write(3);
+        assert_eq!(*reader.read(), 3);
+        assert!(!reader.has_update());
+        assert_eq!(*reader.read(), 3);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn slots_are_not_shared_between_writer_and_reader() {
+        let (mut writer, mut reader) = triple_buffer([0u32; 2]);
+        writer.write([1, 1]);
+        assert_eq!(*reader.read(), [1, 1]);
+        // The slot handed to the writer is not the one being read
+        for i in 2..6 {
+            writer.write_with(|slot| *slot = [i, i]);
+            assert_eq!(*reader.read(), [i, i]);
+        }
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn reader_sees_published_values_in_order() {
+        loom::model(|| {
+            let (mut writer, mut reader) = triple_buffer(0u32);
+
+            let writing = loom::thread::spawn(move || {
+                writer.write(1);
+                writer.write(2);
+            });
+
+            let first = *reader.read();
+            let second = *reader.read();
+            assert!(first <= second);
+
+            writing.join().unwrap();
+            assert_eq!(*reader.read(), 2);
+            assert!(!reader.has_update());
+        });
+    }
+
+    #[cfg(loom)]
+    #[test]
+    fn values_written_in_place_are_published_completely() {
+        loom::model(|| {
+            let (mut writer, mut reader) = triple_buffer([0u32; 2]);
+
+            let writing = loom::thread::spawn(move || {
+                for i in 1..3 {
+                    writer.write_with(|slot| *slot = [i, i]);
+                }
+            });
+
+            let value = *reader.read();
+            assert_eq!(value[0], value[1]);
+
+            writing.join().unwrap();
+            assert_eq!(*reader.read(), [2, 2]);
+        });
+    }
+}
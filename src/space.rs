@@ -2,65 +2,244 @@ use lv2_atom as atom;
 
 use atom::prelude::*;
 
+use std::marker::PhantomData;
+use std::ptr::NonNull;
+
+use crate::logging::ui_log;
+
 /// Smart pointer in the style of lv2_atom::space to be used to
 /// communicate between Plugin <-> UI
 ///
+/// The buffer is allocated once and only accessed through a raw
+/// pointer afterwards. Writing never reallocates it, so the slices
+/// returned by `allocate()` remain valid while nested atoms are
+/// written. If the capacity is exhausted, `allocate()` fails.
+///
+/// The buffer is allocated as `u64`s, so the atoms in it are 64-bit
+/// aligned as the atom specification requires.
 pub struct SelfAllocatingSpace {
-    data: Vec<u8>,
+    buffer: NonNull<u8>,
+    capacity: usize,
+    len: usize,
     already_read: bool,
+    // Reported once per write exceeding the capacity
+    overflowed: bool,
 }
 
+// The space exclusively owns its buffer
+unsafe impl Send for SelfAllocatingSpace {}
+
 impl SelfAllocatingSpace {
+    /// Creates an empty space, to be filled by `put_buffer()`
     pub fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    /// Creates a space that can be written up to `capacity` bytes
+    ///
+    /// The capacity is rounded up to a multiple of 8.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let words = capacity.div_ceil(8);
+        let buffer = Box::into_raw(vec![0u64; words].into_boxed_slice());
         SelfAllocatingSpace {
-            data: Vec::new(),
+            buffer: unsafe { NonNull::new_unchecked(buffer as *mut u8) },
+            capacity: words * 8,
+            len: 0,
             already_read: false,
+            overflowed: false,
         }
     }
 
+    /// The space to write to, the slices it hands out borrow the space
+    pub fn writer<'a>(&'a mut self) -> &'a mut SpaceWriter<'a> {
+        // `SpaceWriter` is a transparent wrapper of the space
+        unsafe { &mut *(self as *mut Self as *mut SpaceWriter<'a>) }
+    }
+
     pub unsafe fn put_buffer(&mut self, buffer: std::ptr::NonNull<std::ffi::c_void>, size: usize) {
-        self.data.set_len(0);
-        self.data.reserve(size);
+        if size > self.capacity {
+            *self = Self::with_capacity(size);
+        }
         std::ptr::copy_nonoverlapping(
             buffer.cast().as_ptr() as *const u8,
-            self.data.as_mut_ptr(),
+            self.buffer.as_ptr(),
             size,
         );
-        self.data.set_len(size);
+        self.len = size;
         self.already_read = false;
+        self.overflowed = false;
     }
 
     pub fn take(&mut self) -> Option<atom::space::Space> {
-        if self.data.len() == 0 || self.already_read {
+        if self.len == 0 || self.already_read {
             return None;
         }
-        let space = atom::space::Space::from_slice(&self.data);
         self.already_read = true;
-        Some(space)
+        Some(self.as_space())
+    }
+
+    pub fn mark_read(&mut self) {
+        self.already_read = true;
+    }
+
+    /// The data as space without marking it read
+    pub fn as_space(&self) -> atom::space::Space<'_> {
+        debug_assert_eq!(self.buffer.as_ptr() as usize % 8, 0);
+        atom::space::Space::from_slice(self.as_slice())
+    }
+
+    /// Discards the data, keeping the buffer for the next write
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.already_read = false;
+        self.overflowed = false;
     }
 
     pub fn len(&self) -> usize {
-        self.data.len()
+        self.len
     }
 
     pub fn as_ptr(&self) -> *const std::ffi::c_void {
-        self.data.as_ptr() as *const std::ffi::c_void
+        self.buffer.as_ptr() as *const std::ffi::c_void
     }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.buffer.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for SelfAllocatingSpace {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+                self.buffer.as_ptr() as *mut u64,
+                self.capacity / 8,
+            )));
+        }
+    }
+}
+
+/// Writes into a `SelfAllocatingSpace`, see `SelfAllocatingSpace::writer()`
+///
+/// Only reachable as `&'a mut SpaceWriter<'a>` borrowing the space,
+/// so the slices handed out by `allocate()` cannot outlive it.
+#[repr(transparent)]
+pub struct SpaceWriter<'a> {
+    space: SelfAllocatingSpace,
+    borrow: PhantomData<&'a mut ()>,
 }
 
-impl<'a> MutSpace<'a> for SelfAllocatingSpace {
+impl<'a> MutSpace<'a> for SpaceWriter<'a> {
     fn allocate(&mut self, size: usize, apply_padding: bool) -> Option<(usize, &'a mut [u8])> {
+        let space = &mut self.space;
         let padding = if apply_padding {
-            (8 - self.data.len() % 8) % 8
+            (8 - space.len % 8) % 8
         } else {
             0
         };
-        self.data.resize(self.data.len() + padding, 0);
-        let start_point = self.data.len();
-        self.data.resize(start_point + size, 0);
-        let return_slice = &mut self.data[start_point..];
-        Some((0, unsafe {
-            std::slice::from_raw_parts_mut(return_slice.as_mut_ptr(), size)
-        }))
+        let start_point = space.len + padding;
+        if start_point + size > space.capacity {
+            if !space.overflowed {
+                space.overflowed = true;
+                ui_log!(
+                    Error,
+                    "atom exceeds the write capacity of {} bytes, see UIAtomPort::with_write_capacity()",
+                    space.capacity
+                );
+            }
+            return None;
+        }
+        space.len = start_point + size;
+        // Regions handed out never overlap and the buffer is never
+        // moved while the space is borrowed, so earlier slices stay
+        // valid.
+        let return_slice = unsafe {
+            let start = space.buffer.as_ptr().add(space.len - size - padding);
+            std::ptr::write_bytes(start, 0, padding + size);
+            std::slice::from_raw_parts_mut(start.add(padding), size)
+        };
+        Some((padding, return_slice))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_capacity_rounds_up_to_words() {
+        assert_eq!(SelfAllocatingSpace::with_capacity(0).capacity, 0);
+        assert_eq!(SelfAllocatingSpace::with_capacity(1).capacity, 8);
+        assert_eq!(SelfAllocatingSpace::with_capacity(8).capacity, 8);
+        assert_eq!(SelfAllocatingSpace::with_capacity(13).capacity, 16);
+    }
+
+    fn is_aligned(ptr: *const u8) -> bool {
+        ptr.align_offset(8) == 0
+    }
+
+    #[test]
+    fn new_space_is_aligned() {
+        assert!(is_aligned(SelfAllocatingSpace::new().as_ptr() as *const u8));
+        for capacity in [0, 1, 8, 13, 8192] {
+            let space = SelfAllocatingSpace::with_capacity(capacity);
+            assert!(
+                is_aligned(space.as_ptr() as *const u8),
+                "capacity {}",
+                capacity
+            );
+        }
+    }
+
+    #[test]
+    fn padded_allocations_are_aligned() {
+        let mut space = SelfAllocatingSpace::with_capacity(64);
+        let writer = space.writer();
+        for size in [3, 4, 9, 1] {
+            let (_, slice) = writer.allocate(size, true).unwrap();
+            assert!(is_aligned(slice.as_ptr()), "size {}", size);
+        }
+    }
+
+    #[test]
+    fn growing_put_buffer_keeps_alignment() {
+        // Starts at an odd address, so the source is not aligned
+        let source: Vec<u8> = (0..33).collect();
+        let mut space = SelfAllocatingSpace::with_capacity(8);
+        unsafe {
+            space.put_buffer(NonNull::from(&source[1]).cast(), 32);
+        }
+        assert!(is_aligned(space.as_ptr() as *const u8));
+        assert_eq!(space.as_slice(), &source[1..]);
+    }
+
+    #[test]
+    fn allocate_pads_to_eight_bytes() {
+        let mut space = SelfAllocatingSpace::with_capacity(32);
+        let writer = space.writer();
+
+        let (padding, slice) = writer.allocate(3, true).unwrap();
+        assert_eq!((padding, slice.len()), (0, 3));
+        let (padding, slice) = writer.allocate(4, true).unwrap();
+        assert_eq!((padding, slice.len()), (5, 4));
+        let (padding, _) = writer.allocate(2, false).unwrap();
+        assert_eq!(padding, 0);
+
+        assert_eq!(space.len(), 14);
+    }
+
+    #[test]
+    fn allocate_returns_none_on_overflow() {
+        let mut space = SelfAllocatingSpace::with_capacity(16);
+        let writer = space.writer();
+
+        assert!(writer.allocate(12, true).is_some());
+        assert!(writer.allocate(4, true).is_none());
+        assert!(writer.allocate(4, false).is_some());
+        assert!(writer.allocate(1, false).is_none());
+
+        assert_eq!(space.len(), 16);
+        space.clear();
+        assert!(space.writer().allocate(16, true).is_some());
     }
 }
@@ -0,0 +1,254 @@
+//! Stepping values by keyboard or scroll wheel and recent entries
+//!
+//! `UIControlPort::step()` moves the value of a port by a small or a
+//! large step according to the port's `StepRule`, taking its
+//! metadata into account: integer ports move by at least one,
+//! toggled ports switch on and off. `UIEnumPort::step()` cycles
+//! through the scale points. So arrow keys and scroll wheels behave
+//! the same in all UIs:
+//!
+//! ```ignore
+//! match key {
+//!     Key::Up => port.step(1, StepSize::Small),
+//!     Key::PageDown => port.step(-1, StepSize::Large),
+//!     _ => {}
+//! }
+//! write_handle.write_port(&port);
+//! ```
+//!
+//! `EntryHistory` remembers the values recently typed in for each
+//! port, e.g. to offer them in a drop down of the value entry.
+
+use std::collections::{HashMap, VecDeque};
+
+use crate::memory::*;
+use crate::port::*;
+
+/// The number of entries kept per port by default
+pub const DEFAULT_HISTORY_LENGTH: usize = 8;
+
+/// Which of the steps of a `StepRule` to take
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepSize {
+    /// Usually arrow keys and scroll wheel
+    Small,
+    /// Usually page keys and scroll wheel with modifier
+    Large,
+}
+
+/// How far a step moves the value of a port
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StepRule {
+    /// Steps are fractions of the knob range, see `UIControlPort::normalized()`
+    Normalized { small: f32, large: f32 },
+    /// Steps are in units of the port value, e.g. 0.5 dB
+    Absolute { small: f32, large: f32 },
+}
+
+impl StepRule {
+    /// The step of `size`
+    pub fn step(&self, size: StepSize) -> f32 {
+        match (self, size) {
+            (StepRule::Normalized { small, .. }, StepSize::Small)
+            | (StepRule::Absolute { small, .. }, StepSize::Small) => *small,
+            (StepRule::Normalized { large, .. }, StepSize::Large)
+            | (StepRule::Absolute { large, .. }, StepSize::Large) => *large,
+        }
+    }
+}
+
+impl Default for StepRule {
+    /// 100 small steps or 10 large steps over the range
+    fn default() -> Self {
+        StepRule::Normalized {
+            small: 0.01,
+            large: 0.1,
+        }
+    }
+}
+
+/// The values recently entered for each port, most recent first
+#[derive(Clone, Debug)]
+pub struct EntryHistory {
+    entries: HashMap<u32, VecDeque<f32>>,
+    length: usize,
+    memory: Option<MemoryAccount>,
+}
+
+impl Default for EntryHistory {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_LENGTH)
+    }
+}
+
+impl EntryHistory {
+    /// Keeps up to `length` entries per port
+    pub fn new(length: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            length,
+            memory: None,
+        }
+    }
+
+    /// Accounts the entries to `budget`, see `memory`
+    ///
+    /// While an entry does not fit, the oldest entry of the port with
+    /// the most entries is dropped.
+    pub fn with_memory_budget(mut self, budget: &MemoryBudget) -> Self {
+        self.memory = Some(budget.account(MemoryCategory::History));
+        self.account();
+        self
+    }
+
+    fn account(&mut self) {
+        let bytes =
+            self.entries.values().map(VecDeque::len).sum::<usize>() * std::mem::size_of::<f32>();
+        if let Some(memory) = &mut self.memory {
+            memory.set(bytes);
+        }
+    }
+
+    fn make_room(&mut self) {
+        self.account();
+        while let Some(memory) = self
+            .memory
+            .as_ref()
+            .filter(|memory| !memory.fits(std::mem::size_of::<f32>()))
+        {
+            let longest = self
+                .entries
+                .values_mut()
+                .filter(|entries| !entries.is_empty())
+                .max_by_key(|entries| entries.len());
+            match longest {
+                Some(entries) => {
+                    memory.record_eviction();
+                    entries.pop_back();
+                }
+                None => break,
+            }
+            self.account();
+        }
+    }
+
+    /// Records `value` as entered for the port `port_index`
+    ///
+    /// An equal entry is moved to the front rather than repeated.
+    pub fn push(&mut self, port_index: u32, value: f32) {
+        if let Some(entries) = self.entries.get_mut(&port_index) {
+            entries.retain(|v| *v != value);
+            // Dropped anyway, so other ports keep their entries
+            entries.truncate(self.length.saturating_sub(1));
+        }
+        self.make_room();
+        let entries = self.entries.entry(port_index).or_default();
+        entries.push_front(value);
+        entries.truncate(self.length);
+        self.account();
+    }
+
+    /// The values entered for the port `port_index`, most recent first
+    pub fn recent(&self, port_index: u32) -> impl Iterator<Item = f32> + '_ {
+        self.entries
+            .get(&port_index)
+            .into_iter()
+            .flat_map(|entries| entries.iter().copied())
+    }
+
+    /// Parses `text` typed in for `port`, sets the value and records it
+    ///
+    /// Returns the value set, None if `text` cannot be parsed. See
+    /// `UIControlPort::parse()`.
+    pub fn enter(&mut self, port: &mut UIControlPort, text: &str) -> Option<f32> {
+        let value = port.parse(text)?;
+        port.set_value(value);
+        let value = port.value();
+        self.push(port.index(), value);
+        Some(value)
+    }
+
+    /// Forgets the entries of the port `port_index`
+    pub fn clear_port(&mut self, port_index: u32) {
+        self.entries.remove(&port_index);
+        self.account();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.account();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recent(history: &EntryHistory, port_index: u32) -> Vec<f32> {
+        history.recent(port_index).collect()
+    }
+
+    #[test]
+    fn equal_entries_are_moved_to_the_front() {
+        let mut history = EntryHistory::default();
+        for value in [1.0, 2.0, 3.0, 1.0].iter() {
+            history.push(0, *value);
+        }
+        assert_eq!(recent(&history, 0), vec![1.0, 3.0, 2.0]);
+    }
+
+    #[test]
+    fn entries_are_truncated_per_port() {
+        let mut history = EntryHistory::new(3);
+        for value in 0..5 {
+            history.push(0, value as f32);
+        }
+        history.push(1, 10.0);
+        assert_eq!(recent(&history, 0), vec![4.0, 3.0, 2.0]);
+        assert_eq!(recent(&history, 1), vec![10.0]);
+        assert_eq!(recent(&history, 2), Vec::<f32>::new());
+
+        history.clear_port(0);
+        assert_eq!(recent(&history, 0), Vec::<f32>::new());
+        assert_eq!(recent(&history, 1), vec![10.0]);
+    }
+
+    #[test]
+    fn the_longest_port_is_evicted_from() {
+        // Room for four entries
+        let budget = MemoryBudget::new(4 * std::mem::size_of::<f32>());
+        let mut history = EntryHistory::default().with_memory_budget(&budget);
+        for value in [1.0, 2.0, 3.0].iter() {
+            history.push(0, *value);
+        }
+        history.push(1, 10.0);
+        assert_eq!(budget.evicted_by(MemoryCategory::History), 0);
+
+        history.push(1, 11.0);
+        assert_eq!(recent(&history, 0), vec![3.0, 2.0]);
+        assert_eq!(recent(&history, 1), vec![11.0, 10.0]);
+        assert_eq!(budget.evicted_by(MemoryCategory::History), 1);
+        assert_eq!(
+            budget.used_by(MemoryCategory::History),
+            budget.limit().unwrap()
+        );
+
+        history.clear();
+        assert_eq!(budget.used_by(MemoryCategory::History), 0);
+    }
+
+    #[test]
+    fn entries_dropped_by_truncation_are_not_evicted() {
+        let budget = MemoryBudget::new(4 * std::mem::size_of::<f32>());
+        let mut history = EntryHistory::new(2).with_memory_budget(&budget);
+        history.push(0, 1.0);
+        history.push(0, 2.0);
+        history.push(1, 10.0);
+        history.push(1, 11.0);
+
+        history.push(0, 3.0);
+        assert_eq!(recent(&history, 0), vec![3.0, 2.0]);
+        assert_eq!(recent(&history, 1), vec![11.0, 10.0]);
+        assert_eq!(budget.evicted_by(MemoryCategory::History), 0);
+    }
+}
@@ -0,0 +1,1038 @@
+//! Test harness to exercise plugin UIs without a real host
+//!
+//! `MockUIHost` provides the features a host usually passes to a UI
+//! (`urid:map`, `urid:unmap` and `ui:parent`) and captures everything
+//! the UI writes to the plugin and logs. A UI is
+//! instantiated through the same C callbacks a real host uses:
+//!
+//! ```ignore
+//! let mut host = MockUIHost::new();
+//! let mut ui = host.instantiate::<AmpUI>().unwrap();
+//! ui.control_event(0, 0.5);
+//! ui.idle();
+//! assert_eq!(ui.take_writes()[0].control_value(), Some(0.5));
+//! ```
+//!
+//! Code calling the C interface directly, like a custom descriptor
+//! or a feature wrapper, is tested with the parts of the mock host
+//! on their own: `FeatureArray` builds the null terminated feature
+//! list a host passes, `WriteRecorder` is a write function recording
+//! the writes and `descriptor()` fills an `LV2UI_Descriptor` with the
+//! callbacks of a UI type.
+//!
+//! A session with a UI can be kept as golden regression test. The
+//! `Recording` of a session, taken by `MockUIInstance::session()`,
+//! holds the events the UI received together with the writes it did
+//! and its final control values. Stored as text, `replay_test()`
+//! replays it on a fresh instance and asserts the UI still reacts
+//! the same way:
+//!
+//! ```ignore
+//! let recording = Recording::parse(include_str!("amp-session.txt")).unwrap();
+//! replay_test::<AmpUI>(&recording);
+//! ```
+//!
+//! Only available with the `testing` feature.
+
+use lv2_sys as sys;
+
+use std::cell::RefCell;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::rc::Rc;
+
+use lv2_atom as atom;
+use urid::*;
+
+use crate::logging::{self, LogLevel, UiLogSink};
+use crate::plugin_ui::*;
+use crate::port::{PortRef, UIPort, UIPortsTrait};
+
+/// A port write the UI has sent to the plugin
+#[derive(Clone, Debug, PartialEq)]
+pub struct PortWrite {
+    pub port_index: u32,
+    pub protocol: u32,
+    pub data: Vec<u8>,
+}
+
+impl PortWrite {
+    /// The written value, if this is a write to a control port
+    pub fn control_value(&self) -> Option<f32> {
+        if self.protocol != 0 || self.data.len() != std::mem::size_of::<f32>() {
+            return None;
+        }
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&self.data);
+        Some(f32::from_ne_bytes(bytes))
+    }
+}
+
+/// A message the UI has logged
+///
+/// `log_type` is the URID of `log:Error`, `log:Warning`, `log:Note`
+/// or `log:Trace` in the map of the mock host.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    pub log_type: u32,
+    pub message: String,
+}
+
+// Records the messages of the UIs instantiated by `MockUIHost`
+struct MockLogSink {
+    entries: Rc<RefCell<Vec<LogEntry>>>,
+    entry_types: [u32; 4],
+}
+
+impl UiLogSink for MockLogSink {
+    fn log(&self, level: LogLevel, message: &str) {
+        self.entries.borrow_mut().push(LogEntry {
+            log_type: self.entry_types[level as usize],
+            message: message.to_owned(),
+        });
+    }
+}
+
+unsafe extern "C" fn mock_map(handle: sys::LV2_URID_Map_Handle, uri: *const c_char) -> u32 {
+    let mapper = &*(handle as *const HashURIDMapper);
+    mapper
+        .map_uri(CStr::from_ptr(uri))
+        .map(URID::get)
+        .unwrap_or(0)
+}
+
+unsafe extern "C" fn mock_unmap(handle: sys::LV2_URID_Unmap_Handle, urid: u32) -> *const c_char {
+    let mapper = &*(handle as *const HashURIDMapper);
+    match URID::<()>::new(urid).and_then(|urid| mapper.unmap(urid)) {
+        Some(uri) => uri.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+unsafe extern "C" fn mock_write(
+    controller: sys::LV2UI_Controller,
+    port_index: u32,
+    buffer_size: u32,
+    protocol: u32,
+    buffer: *const c_void,
+) {
+    let log = &*(controller as *const RefCell<WriteLog>);
+    let data = if buffer.is_null() {
+        Vec::new()
+    } else {
+        std::slice::from_raw_parts(buffer as *const u8, buffer_size as usize).to_vec()
+    };
+    let write = PortWrite {
+        port_index,
+        protocol,
+        data,
+    };
+    let mut log = log.borrow_mut();
+    log.history.push(write.clone());
+    log.writes.push(write);
+}
+
+// The writes not taken yet and all writes ever done
+#[derive(Default)]
+struct WriteLog {
+    writes: Vec<PortWrite>,
+    history: Vec<PortWrite>,
+}
+
+/// A null terminated array of `LV2_Feature` pointers, as hosts pass them
+///
+/// The pointers stay valid while the array is alive, also when more
+/// features are pushed. The feature data is not owned.
+pub struct FeatureArray {
+    // Boxed, so the pointers survive the vector growing
+    #[allow(clippy::vec_box)]
+    features: Vec<Box<sys::LV2_Feature>>,
+    pointers: Vec<*const sys::LV2_Feature>,
+}
+
+impl Default for FeatureArray {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeatureArray {
+    pub fn new() -> Self {
+        Self {
+            features: Vec::new(),
+            pointers: vec![std::ptr::null()],
+        }
+    }
+
+    /// Adds the feature `uri`, a null terminated URI like the ones of `lv2_sys`
+    pub fn push(&mut self, uri: &'static [u8], data: *mut c_void) {
+        assert_eq!(uri.last(), Some(&0), "feature URI is not null terminated");
+        let feature = Box::new(sys::LV2_Feature {
+            URI: uri.as_ptr() as *const c_char,
+            data,
+        });
+        self.pointers.pop();
+        self.pointers.push(feature.as_ref());
+        self.pointers.push(std::ptr::null());
+        self.features.push(feature);
+    }
+
+    pub fn with(mut self, uri: &'static [u8], data: *mut c_void) -> Self {
+        self.push(uri, data);
+        self
+    }
+
+    /// The array to pass as `features` argument
+    pub fn as_ptr(&self) -> *const *const sys::LV2_Feature {
+        self.pointers.as_ptr()
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+}
+
+/// A write function recording the port writes
+///
+/// Pass `write_function()` and `controller()` where a host passes
+/// them, or use `write_handle()` to test code writing ports.
+pub struct WriteRecorder {
+    log: Box<RefCell<WriteLog>>,
+}
+
+impl Default for WriteRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WriteRecorder {
+    pub fn new() -> Self {
+        Self {
+            log: Box::new(RefCell::new(WriteLog::default())),
+        }
+    }
+
+    pub fn write_function(&self) -> sys::LV2UI_Write_Function {
+        Some(mock_write)
+    }
+
+    /// The controller to pass along with `write_function()`
+    ///
+    /// It is only valid while the recorder is alive.
+    pub fn controller(&self) -> sys::LV2UI_Controller {
+        self.log.as_ref() as *const RefCell<WriteLog> as sys::LV2UI_Controller
+    }
+
+    /// A write handle writing to the recorder
+    ///
+    /// The handle must not be used after the recorder is dropped.
+    pub fn write_handle(&self) -> PluginPortWriteHandle {
+        PluginPortWriteHandle::new(self.write_function(), self.controller())
+    }
+
+    /// Removes and returns all port writes recorded so far
+    pub fn take_writes(&self) -> Vec<PortWrite> {
+        std::mem::take(&mut self.log.borrow_mut().writes)
+    }
+
+    /// The number of port writes recorded ever, also the ones taken
+    pub fn write_count(&self) -> usize {
+        self.log.borrow().history.len()
+    }
+
+    /// All port writes recorded from the `start`th on, also the ones taken
+    pub fn writes_since(&self, start: usize) -> Vec<PortWrite> {
+        self.log
+            .borrow()
+            .history
+            .get(start..)
+            .map_or_else(Vec::new, <[PortWrite]>::to_vec)
+    }
+}
+
+/// A descriptor with the callbacks of the plugin UI `T`
+///
+/// `uri` needs to stay valid as long as the descriptor is used.
+pub fn descriptor<T: PluginUI>(uri: &CStr) -> sys::LV2UI_Descriptor {
+    sys::LV2UI_Descriptor {
+        URI: uri.as_ptr(),
+        instantiate: Some(PluginUIInstance::<T>::instantiate),
+        cleanup: Some(PluginUIInstance::<T>::cleanup),
+        port_event: Some(PluginUIInstance::<T>::port_event),
+        extension_data: Some(PluginUIInstance::<T>::extension_data),
+    }
+}
+
+/// A fake host to instantiate plugin UIs in tests
+pub struct MockUIHost {
+    mapper: Box<HashURIDMapper>,
+    map_interface: Box<sys::LV2_URID_Map>,
+    unmap_interface: Box<sys::LV2_URID_Unmap>,
+    log_entries: Rc<RefCell<Vec<LogEntry>>>,
+    writes: WriteRecorder,
+    parent: *mut c_void,
+    extra_features: Vec<(&'static [u8], *mut c_void)>,
+    plugin_uri: CString,
+    ui_uri: CString,
+    bundle_path: CString,
+}
+
+impl Default for MockUIHost {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockUIHost {
+    pub fn new() -> Self {
+        let mapper = Box::new(HashURIDMapper::new());
+
+        let map_interface = Box::new(sys::LV2_URID_Map {
+            handle: mapper.as_ref() as *const HashURIDMapper as *mut c_void,
+            map: Some(mock_map),
+        });
+        let unmap_interface = Box::new(sys::LV2_URID_Unmap {
+            handle: mapper.as_ref() as *const HashURIDMapper as *mut c_void,
+            unmap: Some(mock_unmap),
+        });
+
+        Self {
+            mapper,
+            map_interface,
+            unmap_interface,
+            log_entries: Rc::new(RefCell::new(Vec::new())),
+            writes: WriteRecorder::new(),
+            // Any non null value will do, the mock host has no window
+            parent: std::ptr::NonNull::dangling().as_ptr(),
+            extra_features: Vec::new(),
+            plugin_uri: CString::new("urn:lv2-ui:mock-plugin").unwrap(),
+            ui_uri: CString::new("urn:lv2-ui:mock-plugin#ui").unwrap(),
+            bundle_path: CString::new("/tmp/mock.lv2/").unwrap(),
+        }
+    }
+
+    /// Sets the parent window passed with `ui:parent`; null omits the feature
+    pub fn with_parent(mut self, parent: *mut c_void) -> Self {
+        self.parent = parent;
+        self
+    }
+
+    pub fn with_plugin_uri(mut self, uri: &str) -> Self {
+        self.plugin_uri = CString::new(uri).expect("URI contains a null byte");
+        self
+    }
+
+    pub fn with_ui_uri(mut self, uri: &str) -> Self {
+        self.ui_uri = CString::new(uri).expect("URI contains a null byte");
+        self
+    }
+
+    pub fn with_bundle_path(mut self, path: &str) -> Self {
+        self.bundle_path = CString::new(path).expect("path contains a null byte");
+        self
+    }
+
+    /// Passes an additional feature to the UI
+    ///
+    /// `data` has to stay valid as long as UIs instantiated by this
+    /// host are alive.
+    pub fn add_feature(&mut self, uri: &'static [u8], data: *mut c_void) {
+        self.extra_features.push((uri, data));
+    }
+
+    /// The URID map of the host, to map URIDs in tests
+    pub fn map(&self) -> &HashURIDMapper {
+        &self.mapper
+    }
+
+    /// Removes and returns all port writes the UIs have done so far
+    pub fn take_writes(&self) -> Vec<PortWrite> {
+        self.writes.take_writes()
+    }
+
+    /// Removes and returns all log messages of the UIs so far
+    pub fn take_log_entries(&self) -> Vec<LogEntry> {
+        self.log_entries.replace(Vec::new())
+    }
+
+    /// The features the host passes to UIs
+    ///
+    /// The array points into the host, so it must not be used after
+    /// the host is dropped.
+    pub fn features(&mut self) -> FeatureArray {
+        let mut features = FeatureArray::new()
+            .with(
+                sys::LV2_URID__map,
+                self.map_interface.as_mut() as *mut sys::LV2_URID_Map as *mut c_void,
+            )
+            .with(
+                sys::LV2_URID__unmap,
+                self.unmap_interface.as_mut() as *mut sys::LV2_URID_Unmap as *mut c_void,
+            );
+        if !self.parent.is_null() {
+            features.push(sys::LV2_UI__parent, self.parent);
+        }
+        for &(uri, data) in self.extra_features.iter() {
+            features.push(uri, data);
+        }
+        features
+    }
+
+    /// Instantiates the plugin UI `T`
+    ///
+    /// Returns None if the instantiation failed.
+    pub fn instantiate<T: PluginUI>(&mut self) -> Option<MockUIInstance<'_, T>> {
+        let features = self.features();
+        let descriptor = Box::new(descriptor::<T>(&self.ui_uri));
+
+        let log_sink = Rc::new(MockLogSink {
+            entries: self.log_entries.clone(),
+            entry_types: [
+                sys::LV2_LOG__Error,
+                sys::LV2_LOG__Warning,
+                sys::LV2_LOG__Note,
+                sys::LV2_LOG__Trace,
+            ]
+            .map(|uri| unsafe {
+                mock_map(self.map_interface.handle, uri.as_ptr() as *const c_char)
+            }),
+        });
+
+        let mut widget: sys::LV2UI_Widget = std::ptr::null_mut();
+        let handle = logging::with_fallback_sink(log_sink, || unsafe {
+            PluginUIInstance::<T>::instantiate(
+                descriptor.as_ref(),
+                self.plugin_uri.as_ptr(),
+                self.bundle_path.as_ptr(),
+                self.writes.write_function(),
+                self.writes.controller(),
+                &mut widget,
+                features.as_ptr(),
+            )
+        });
+        if handle.is_null() {
+            return None;
+        }
+
+        let event_transfer = self
+            .mapper
+            .map_type::<atom::uris::EventTransfer>()
+            .map(URID::get)
+            .unwrap_or(0);
+
+        Some(MockUIInstance {
+            first_write: self.writes.write_count(),
+            host: self,
+            handle: handle as *mut PluginUIInstance<T>,
+            descriptor,
+            widget,
+            event_transfer,
+            steps: Vec::new(),
+            _features: features,
+        })
+    }
+}
+
+/// A plugin UI instantiated by `MockUIHost`
+///
+/// The UI is cleaned up when the instance is dropped.
+pub struct MockUIInstance<'a, T: PluginUI> {
+    host: &'a MockUIHost,
+    handle: *mut PluginUIInstance<T>,
+    descriptor: Box<sys::LV2UI_Descriptor>,
+    widget: sys::LV2UI_Widget,
+    event_transfer: u32,
+    steps: Vec<SessionStep>,
+    first_write: usize,
+    _features: FeatureArray,
+}
+
+impl<'a, T: PluginUI> MockUIInstance<'a, T> {
+    /// The plugin UI object itself
+    pub fn ui(&mut self) -> &mut T {
+        unsafe { (*self.handle).ui_mut() }
+    }
+
+    /// The widget the UI returned on instantiation
+    pub fn widget(&self) -> sys::LV2UI_Widget {
+        self.widget
+    }
+
+    pub fn host(&self) -> &MockUIHost {
+        self.host
+    }
+
+    /// Removes and returns all port writes the UI has done so far
+    pub fn take_writes(&self) -> Vec<PortWrite> {
+        self.host.take_writes()
+    }
+
+    /// Delivers a port event like a host would
+    pub fn raw_port_event(&mut self, port_index: u32, format: u32, buffer: &[u8]) {
+        self.steps.push(match format {
+            0 if buffer.len() == std::mem::size_of::<f32>() => {
+                let mut bytes = [0u8; 4];
+                bytes.copy_from_slice(buffer);
+                SessionStep::Control {
+                    port_index,
+                    value: f32::from_ne_bytes(bytes),
+                }
+            }
+            format if format == self.event_transfer => SessionStep::Atom {
+                port_index,
+                atom: buffer.to_vec(),
+            },
+            format => SessionStep::Raw {
+                port_index,
+                format,
+                data: buffer.to_vec(),
+            },
+        });
+        if let Some(port_event) = self.descriptor.port_event {
+            unsafe {
+                port_event(
+                    self.handle as sys::LV2UI_Handle,
+                    port_index,
+                    buffer.len() as u32,
+                    format,
+                    buffer.as_ptr() as *const c_void,
+                )
+            }
+        }
+    }
+
+    /// Delivers a new value of a control port
+    pub fn control_event(&mut self, port_index: u32, value: f32) {
+        self.raw_port_event(port_index, 0, &value.to_ne_bytes());
+    }
+
+    /// Delivers an atom to an atom port using `atom:eventTransfer`
+    ///
+    /// `atom` needs to contain the complete atom including its header.
+    pub fn atom_event(&mut self, port_index: u32, atom: &[u8]) {
+        let format = self.event_transfer;
+        self.raw_port_event(port_index, format, atom);
+    }
+
+    /// Tells the UI its window has been moved into `new_parent`, like a backend would
+    pub fn reparent(&mut self, new_parent: *mut c_void) {
+        self.ui().parent_changed(new_parent);
+    }
+
+    /// Calls the idle interface of the UI, returns its result
+    ///
+    /// Returns None if the UI does not provide the idle interface.
+    pub fn idle(&mut self) -> Option<i32> {
+        self.steps.push(SessionStep::Idle);
+        let extension_data = self.descriptor.extension_data?;
+        let interface = unsafe {
+            extension_data(sys::LV2_UI__idleInterface.as_ptr() as *const c_char)
+                as *const sys::LV2UI_Idle_Interface
+        };
+        let idle = unsafe { interface.as_ref()?.idle? };
+        Some(unsafe { idle(self.handle as sys::LV2UI_Handle) })
+    }
+
+    /// Calls `show()` of the show interface of the UI, returns its result
+    ///
+    /// Returns None if the UI does not provide the show interface.
+    pub fn show(&mut self) -> Option<i32> {
+        let show = self.show_interface()?.show?;
+        Some(unsafe { show(self.handle as sys::LV2UI_Handle) })
+    }
+
+    /// Calls `hide()` of the show interface of the UI, returns its result
+    ///
+    /// Returns None if the UI does not provide the show interface.
+    pub fn hide(&mut self) -> Option<i32> {
+        let hide = self.show_interface()?.hide?;
+        Some(unsafe { hide(self.handle as sys::LV2UI_Handle) })
+    }
+
+    fn show_interface(&self) -> Option<&'static sys::LV2UI_Show_Interface> {
+        let extension_data = self.descriptor.extension_data?;
+        unsafe {
+            (extension_data(sys::LV2_UI__showInterface.as_ptr() as *const c_char)
+                as *const sys::LV2UI_Show_Interface)
+                .as_ref()
+        }
+    }
+
+    /// The session with the UI so far
+    ///
+    /// Holds the events delivered, all writes the UI has done, also
+    /// the ones taken already, and the current values of its control
+    /// ports.
+    pub fn session(&mut self) -> Recording {
+        let mut controls = Vec::new();
+        self.ui().ports().visit_ports(|port| {
+            if let PortRef::Control(port) = port {
+                controls.push((port.index(), port.value()));
+            }
+        });
+        Recording {
+            steps: self.steps.clone(),
+            writes: self.host.writes.writes_since(self.first_write),
+            controls,
+        }
+    }
+}
+
+impl<'a, T: PluginUI> Drop for MockUIInstance<'a, T> {
+    fn drop(&mut self) {
+        if let Some(cleanup) = self.descriptor.cleanup {
+            unsafe { cleanup(self.handle as sys::LV2UI_Handle) }
+        }
+    }
+}
+
+/// A host event of a recorded session
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionStep {
+    Control {
+        port_index: u32,
+        value: f32,
+    },
+    /// An atom delivered using `atom:eventTransfer`
+    Atom {
+        port_index: u32,
+        atom: Vec<u8>,
+    },
+    /// An event of an other format, the URID as mapped by the mock host
+    Raw {
+        port_index: u32,
+        format: u32,
+        data: Vec<u8>,
+    },
+    Idle,
+}
+
+/// A session with a UI: the events it received and how it reacted
+///
+/// The text form written by `to_text()` has one step or expectation
+/// per line, so golden sessions give readable diffs:
+///
+/// ```text
+/// control 0 0.5
+/// atom 3 0800000005000000…
+/// idle
+/// write 0 0 0000003f
+/// value 0 0.5
+/// ```
+///
+/// Data is hex encoded. URIDs are the ones of the mock host, which
+/// maps in a fixed order, so they are the same on every replay.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recording {
+    pub steps: Vec<SessionStep>,
+    /// The writes the UI is expected to do, in order
+    pub writes: Vec<PortWrite>,
+    /// The expected final values of control ports
+    pub controls: Vec<(u32, f32)>,
+}
+
+/// The error of parsing a recording, with the line it occurred in
+#[derive(Debug)]
+pub struct RecordingParseError {
+    pub line: usize,
+}
+
+impl Recording {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn control(mut self, port_index: u32, value: f32) -> Self {
+        self.steps.push(SessionStep::Control { port_index, value });
+        self
+    }
+
+    pub fn atom(mut self, port_index: u32, atom: &[u8]) -> Self {
+        self.steps.push(SessionStep::Atom {
+            port_index,
+            atom: atom.to_vec(),
+        });
+        self
+    }
+
+    pub fn idle(mut self) -> Self {
+        self.steps.push(SessionStep::Idle);
+        self
+    }
+
+    pub fn expect_write(mut self, write: PortWrite) -> Self {
+        self.writes.push(write);
+        self
+    }
+
+    /// Expects the control value `value` to be written to `port_index`
+    pub fn expect_control_write(self, port_index: u32, value: f32) -> Self {
+        self.expect_write(PortWrite {
+            port_index,
+            protocol: 0,
+            data: value.to_ne_bytes().to_vec(),
+        })
+    }
+
+    /// Expects the control port `port_index` to end up at `value`
+    pub fn expect_value(mut self, port_index: u32, value: f32) -> Self {
+        self.controls.push((port_index, value));
+        self
+    }
+
+    pub fn to_text(&self) -> std::string::String {
+        let mut text = std::string::String::new();
+        for step in self.steps.iter() {
+            let line = match step {
+                SessionStep::Control { port_index, value } => {
+                    format!("control {} {}", port_index, value)
+                }
+                SessionStep::Atom { port_index, atom } => {
+                    format!("atom {} {}", port_index, hex(atom))
+                }
+                SessionStep::Raw {
+                    port_index,
+                    format,
+                    data,
+                } => format!("raw {} {} {}", port_index, format, hex(data)),
+                SessionStep::Idle => "idle".to_string(),
+            };
+            text.push_str(&line);
+            text.push('\n');
+        }
+        for write in self.writes.iter() {
+            text.push_str(&format!(
+                "write {} {} {}\n",
+                write.port_index,
+                write.protocol,
+                hex(&write.data)
+            ));
+        }
+        for (port_index, value) in self.controls.iter() {
+            text.push_str(&format!("value {} {}\n", port_index, value));
+        }
+        text
+    }
+
+    /// Reads the text form written by `to_text()`
+    ///
+    /// Empty lines and lines starting with `#` are ignored.
+    pub fn parse(text: &str) -> Result<Self, RecordingParseError> {
+        let mut recording = Self::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            recording
+                .parse_line(line)
+                .ok_or(RecordingParseError { line: number + 1 })?;
+        }
+        Ok(recording)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Option<()> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["control", port_index, value] => self.steps.push(SessionStep::Control {
+                port_index: port_index.parse().ok()?,
+                value: value.parse().ok()?,
+            }),
+            ["atom", port_index, atom] => self.steps.push(SessionStep::Atom {
+                port_index: port_index.parse().ok()?,
+                atom: unhex(atom)?,
+            }),
+            ["raw", port_index, format, data] => self.steps.push(SessionStep::Raw {
+                port_index: port_index.parse().ok()?,
+                format: format.parse().ok()?,
+                data: unhex(data)?,
+            }),
+            ["idle"] => self.steps.push(SessionStep::Idle),
+            ["write", port_index, protocol, data] => self.writes.push(PortWrite {
+                port_index: port_index.parse().ok()?,
+                protocol: protocol.parse().ok()?,
+                data: unhex(data)?,
+            }),
+            // Writes without data have no third word
+            ["write", port_index, protocol] => self.writes.push(PortWrite {
+                port_index: port_index.parse().ok()?,
+                protocol: protocol.parse().ok()?,
+                data: Vec::new(),
+            }),
+            ["value", port_index, value] => self
+                .controls
+                .push((port_index.parse().ok()?, value.parse().ok()?)),
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+fn hex(data: &[u8]) -> std::string::String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match std::str::from_utf8(pair) {
+            Ok(pair) if pair.len() == 2 => u8::from_str_radix(pair, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Replays the steps of `recording` on a fresh instance of `T`
+///
+/// Returns the observed session, e.g. to write a new golden session.
+/// Panics if the UI can't be instantiated.
+pub fn replay<T: PluginUI>(recording: &Recording) -> Recording {
+    let mut host = MockUIHost::new();
+    let mut instance = host
+        .instantiate::<T>()
+        .expect("failed to instantiate the UI");
+    for step in recording.steps.iter() {
+        match step {
+            SessionStep::Control { port_index, value } => {
+                instance.control_event(*port_index, *value)
+            }
+            SessionStep::Atom { port_index, atom } => instance.atom_event(*port_index, atom),
+            SessionStep::Raw {
+                port_index,
+                format,
+                data,
+            } => instance.raw_port_event(*port_index, *format, data),
+            SessionStep::Idle => {
+                instance.idle();
+            }
+        }
+    }
+    instance.session()
+}
+
+/// Replays `recording` on `T` and asserts the UI reacts as recorded
+///
+/// The writes need to match exactly and in order. Only the control
+/// ports listed in the recording are checked.
+pub fn replay_test<T: PluginUI>(recording: &Recording) {
+    let observed = replay::<T>(recording);
+    assert_eq!(
+        observed.writes, recording.writes,
+        "the port writes differ from the recorded session"
+    );
+    for (port_index, value) in recording.controls.iter() {
+        let observed_value = observed
+            .controls
+            .iter()
+            .find(|(index, _)| index == port_index)
+            .map(|(_, value)| *value);
+        assert_eq!(
+            observed_value,
+            Some(*value),
+            "the final value of control port {} differs from the recorded session",
+            port_index
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::port::{UIAtomPort, UIControlPort};
+
+    struct DoublingPorts {
+        gain: UIControlPort,
+        doubled: UIControlPort,
+    }
+
+    impl UIPortsTrait for DoublingPorts {
+        fn map_control_port(&mut self, port_index: u32) -> Option<&mut UIControlPort> {
+            match port_index {
+                0 => Some(&mut self.gain),
+                1 => Some(&mut self.doubled),
+                _ => None,
+            }
+        }
+
+        fn map_atom_port(&mut self, _port_index: u32) -> Option<&mut UIAtomPort> {
+            None
+        }
+
+        fn visit_ports(&mut self, mut f: impl FnMut(PortRef)) {
+            f(PortRef::Control(&mut self.gain));
+            f(PortRef::Control(&mut self.doubled));
+        }
+    }
+
+    // Writes twice the gain it receives to port 1
+    struct DoublingUI {
+        ports: DoublingPorts,
+        write_handle: PluginPortWriteHandle,
+    }
+
+    impl PluginUI for DoublingUI {
+        type InitFeatures = ();
+        type UIPorts = DoublingPorts;
+
+        fn new(
+            _plugin_ui_info: &PluginUIInfo,
+            _features: &mut (),
+            _parent_window: *mut c_void,
+            write_handle: PluginPortWriteHandle,
+        ) -> Result<Self, PluginUIError> {
+            Ok(Self {
+                ports: DoublingPorts {
+                    gain: UIControlPort::new(0),
+                    doubled: UIControlPort::new(1),
+                },
+                write_handle,
+            })
+        }
+
+        fn cleanup(&mut self) {}
+
+        fn ports(&mut self) -> &mut DoublingPorts {
+            &mut self.ports
+        }
+
+        fn update(&mut self, reason: UpdateReason) {
+            if reason == UpdateReason::HostPortEvent(0) {
+                let doubled = self.ports.gain.value() * 2.0;
+                self.ports.doubled.set_value(doubled);
+                self.write_handle.write_port(&self.ports.doubled);
+            }
+        }
+
+        fn idle(&mut self) -> i32 {
+            0
+        }
+
+        fn widget(&self) -> sys::LV2UI_Widget {
+            std::ptr::null_mut()
+        }
+    }
+
+    #[test]
+    fn recording_text_round_trips() {
+        let mut recording = Recording::new()
+            .control(0, 0.25)
+            .atom(3, &[8, 0, 0, 0, 5, 0, 0, 0])
+            .idle()
+            .expect_control_write(1, 0.5)
+            .expect_write(PortWrite {
+                port_index: 2,
+                protocol: 7,
+                data: Vec::new(),
+            })
+            .expect_value(1, 0.5);
+        recording.steps.push(SessionStep::Raw {
+            port_index: 4,
+            format: 9,
+            data: vec![0xab, 0xcd],
+        });
+
+        let text = recording.to_text();
+        assert_eq!(
+            text,
+            format!(
+                "control 0 0.25\n\
+                 atom 3 0800000005000000\n\
+                 idle\n\
+                 raw 4 9 abcd\n\
+                 write 1 0 {}\n\
+                 write 2 7 \n\
+                 value 1 0.5\n",
+                hex(&0.5f32.to_ne_bytes())
+            )
+        );
+        assert_eq!(Recording::parse(&text).unwrap(), recording);
+    }
+
+    #[test]
+    fn parse_skips_comments_and_reports_the_line() {
+        let recording = Recording::parse("# golden session\n\n  idle  \n").unwrap();
+        assert_eq!(recording.steps, vec![SessionStep::Idle]);
+
+        for text in ["idle\n\ncontrol x 0.5", "idle\nidle\natom 0 abc", "a\n"].iter() {
+            let line = text.lines().count();
+            assert_eq!(Recording::parse(text).unwrap_err().line, line, "{}", text);
+        }
+    }
+
+    #[test]
+    fn unhex_decodes_pairs_only() {
+        assert_eq!(unhex("00ff7F"), Some(vec![0x00, 0xff, 0x7f]));
+        assert_eq!(unhex(""), Some(Vec::new()));
+        assert_eq!(unhex("0"), None);
+        assert_eq!(unhex("0g"), None);
+        assert_eq!(unhex("é0"), None);
+        assert_eq!(unhex(&hex(&[1, 2, 254])), Some(vec![1, 2, 254]));
+    }
+
+    #[test]
+    fn mock_host_records_the_session() {
+        let mut host = MockUIHost::new();
+        let mut instance = host.instantiate::<DoublingUI>().unwrap();
+        instance.control_event(0, 0.25);
+        assert_eq!(instance.idle(), Some(0));
+
+        let writes = instance.take_writes();
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].port_index, 1);
+        assert_eq!(writes[0].control_value(), Some(0.5));
+
+        let session = instance.session();
+        assert_eq!(
+            session.steps,
+            vec![
+                SessionStep::Control {
+                    port_index: 0,
+                    value: 0.25
+                },
+                SessionStep::Idle
+            ]
+        );
+        // Taken writes are still part of the session
+        assert_eq!(session.writes, writes);
+        assert_eq!(session.controls, vec![(0, 0.25), (1, 0.5)]);
+    }
+
+    #[test]
+    fn recorded_sessions_replay() {
+        let mut host = MockUIHost::new();
+        let mut instance = host.instantiate::<DoublingUI>().unwrap();
+        instance.control_event(0, 0.25);
+        instance.idle();
+        instance.control_event(0, 1.0);
+        let recording = instance.session();
+        drop(instance);
+
+        assert_eq!(replay::<DoublingUI>(&recording), recording);
+        replay_test::<DoublingUI>(&recording);
+        replay_test::<DoublingUI>(&Recording::parse(&recording.to_text()).unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "the port writes differ")]
+    fn replay_test_fails_on_other_writes() {
+        let recording = Recording::new()
+            .control(0, 0.25)
+            .expect_control_write(1, 1.0);
+        replay_test::<DoublingUI>(&recording);
+    }
+
+    #[test]
+    #[should_panic(expected = "the final value of control port 1 differs")]
+    fn replay_test_fails_on_other_values() {
+        let recording = Recording::new()
+            .control(0, 0.25)
+            .expect_control_write(1, 0.5)
+            .expect_value(1, 0.25);
+        replay_test::<DoublingUI>(&recording);
+    }
+}
@@ -0,0 +1,99 @@
+//! Telling the host about user interaction with control ports
+//!
+//! While the user drags a knob, the host should know the port is
+//! grabbed, so it records the values as touch automation instead of
+//! playing back existing automation. With the `ui:touch` feature
+//! `UIControlPort::begin_gesture()` returns a `GestureGuard`, which
+//! grabs the port and releases it again when dropped:
+//!
+//! ```ignore
+//! fn on_drag_start(&mut self) {
+//!     if let Some(touch) = &self.touch {
+//!         self.gesture = Some(self.ports.cutoff.begin_gesture(touch));
+//!     }
+//! }
+//!
+//! fn on_drag(&mut self, position: f32) {
+//!     self.ports.cutoff.set_normalized(position);
+//!     self.write_handle.write_port(&self.ports.cutoff);
+//! }
+//!
+//! fn on_drag_end(&mut self) {
+//!     self.gesture = None;
+//! }
+//! ```
+//!
+//! Request the feature as `Option<Touch<'static>>` in the
+//! `InitFeatures`, not all hosts provide it.
+
+use lv2_sys as sys;
+
+use std::cell::Cell;
+use std::ffi::c_void;
+use std::rc::Rc;
+
+use lv2_core::feature::*;
+use urid::*;
+
+/// The `ui:touch` host feature
+pub struct Touch<'a> {
+    internal: &'a sys::LV2UI_Touch,
+}
+
+unsafe impl<'a> UriBound for Touch<'a> {
+    const URI: &'static [u8] = sys::LV2_UI__touch;
+}
+
+unsafe impl<'a> Feature for Touch<'a> {
+    unsafe fn from_feature_ptr(feature: *const c_void, _class: ThreadingClass) -> Option<Self> {
+        (feature as *const sys::LV2UI_Touch)
+            .as_ref()
+            .map(|internal| Self { internal })
+    }
+}
+
+impl<'a> Touch<'a> {
+    /// Tells the host that the port `port_index` is grabbed or released
+    pub fn touch(&self, port_index: u32, grabbed: bool) {
+        if let Some(touch) = self.internal.touch {
+            unsafe { touch(self.internal.handle, port_index, grabbed) }
+        }
+    }
+}
+
+/// An ongoing gesture on a control port, see `UIControlPort::begin_gesture()`
+///
+/// The port is released when the guard is dropped. The guard does
+/// not borrow the `Touch`, so it can be kept in the UI next to it,
+/// but it must not outlive the UI.
+pub struct GestureGuard {
+    touch: sys::LV2UI_Touch,
+    port_index: u32,
+    active: Rc<Cell<bool>>,
+}
+
+impl GestureGuard {
+    pub(crate) fn new(touch: &Touch, port_index: u32, active: Rc<Cell<bool>>) -> Self {
+        touch.touch(port_index, true);
+        active.set(true);
+        Self {
+            touch: *touch.internal,
+            port_index,
+            active,
+        }
+    }
+
+    pub fn port_index(&self) -> u32 {
+        self.port_index
+    }
+}
+
+impl Drop for GestureGuard {
+    fn drop(&mut self) {
+        self.active.set(false);
+        Touch {
+            internal: &self.touch,
+        }
+        .touch(self.port_index, false);
+    }
+}
@@ -0,0 +1,288 @@
+//! Correlation of UI time with the host's transport
+//!
+//! Ports record the `Instant` every event has been received at, see
+//! `UIControlPort::received()` and `UIAtomPort::received()`. Plugins
+//! following the host's transport usually forward the
+//! `time:Position` objects they get to the UI. `TransportClock`
+//! takes those and extrapolates the transport frame and the musical
+//! position for any `Instant`, so displays can align received
+//! events with musical time, e.g. a step sequencer's play head.
+//!
+//! The correlation is as precise as the delivery of port events,
+//! which is usually in the range of the UI update rate.
+//!
+//! `Transport` takes the positions arriving on a `UIAtomPort` in
+//! `update()` and provides the position extrapolated to now in
+//! `idle()`, e.g. to animate a play head:
+//!
+//! ```ignore
+//! fn update(&mut self, _reason: UpdateReason) {
+//!     self.transport.receive(self.ports.notify(), &self.time_urids);
+//!     if let Some(position) = self.transport.changed_position() {
+//!         self.tempo_label.set(position.beats_per_minute);
+//!     }
+//! }
+//!
+//! fn idle(&mut self) -> i32 {
+//!     if let Some(beats) = self.transport.beats_now() {
+//!         self.play_head.move_to(beats);
+//!     }
+//!     0
+//! }
+//! ```
+
+use lv2_atom as atom;
+
+use std::time::{Duration, Instant};
+
+use atom::prelude::*;
+use urid::*;
+
+use crate::port::UIAtomPort;
+use crate::uris::*;
+
+/// The URIDs needed to read `time:Position` objects
+#[derive(URIDCollection)]
+pub struct TimeURIDs {
+    pub atom: AtomURIDCollection,
+    pub position: URID<TimePosition>,
+    pub frame: URID<TimeFrame>,
+    pub speed: URID<TimeSpeed>,
+    pub bar: URID<TimeBar>,
+    pub bar_beat: URID<TimeBarBeat>,
+    pub beats_per_bar: URID<TimeBeatsPerBar>,
+    pub beats_per_minute: URID<TimeBeatsPerMinute>,
+}
+
+/// The transport position as reported by the host
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TransportPosition {
+    pub frame: i64,
+    /// The transport speed, 0.0 when stopped and 1.0 when rolling
+    pub speed: f32,
+    pub bar: Option<i64>,
+    pub bar_beat: Option<f32>,
+    pub beats_per_bar: Option<f32>,
+    pub beats_per_minute: Option<f32>,
+}
+
+/// Extrapolates the transport position from the last `time:Position` received
+#[derive(Clone, Debug)]
+pub struct TransportClock {
+    sample_rate: f64,
+    anchor: Option<(Instant, TransportPosition)>,
+}
+
+impl TransportClock {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            sample_rate,
+            anchor: None,
+        }
+    }
+
+    /// Sets the transport position the host reported at `received`
+    pub fn update(&mut self, position: TransportPosition, received: Instant) {
+        self.anchor = Some((received, position));
+    }
+
+    /// Reads a `time:Position` object received at `received`
+    ///
+    /// Properties missing in the object are kept from the last
+    /// position, as hosts may only send the changed ones. Returns
+    /// false if the object is not a `time:Position` or lacks a
+    /// frame when there was no position before.
+    pub fn read_position(
+        &mut self,
+        header: ObjectHeader,
+        reader: atom::object::ObjectReader,
+        urids: &TimeURIDs,
+        received: Instant,
+    ) -> bool {
+        if header.otype != urids.position {
+            return false;
+        }
+        let previous = self.anchor.map(|(_, position)| position);
+        let mut frame = previous.map(|position| position.frame);
+        let mut position = previous.unwrap_or(TransportPosition {
+            frame: 0,
+            speed: 0.0,
+            bar: None,
+            bar_beat: None,
+            beats_per_bar: None,
+            beats_per_minute: None,
+        });
+
+        for (property, value) in reader {
+            let number = match read_number(value, urids) {
+                Some(number) => number,
+                None => continue,
+            };
+            if property.key == urids.frame {
+                frame = Some(number as i64);
+            } else if property.key == urids.speed {
+                position.speed = number as f32;
+            } else if property.key == urids.bar {
+                position.bar = Some(number as i64);
+            } else if property.key == urids.bar_beat {
+                position.bar_beat = Some(number as f32);
+            } else if property.key == urids.beats_per_bar {
+                position.beats_per_bar = Some(number as f32);
+            } else if property.key == urids.beats_per_minute {
+                position.beats_per_minute = Some(number as f32);
+            }
+        }
+
+        match frame {
+            Some(frame) => {
+                position.frame = frame;
+                self.update(position, received);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The last position received
+    pub fn position(&self) -> Option<&TransportPosition> {
+        self.anchor.as_ref().map(|(_, position)| position)
+    }
+
+    /// True if the transport was rolling at the last position received
+    pub fn is_rolling(&self) -> bool {
+        self.position()
+            .is_some_and(|position| position.speed != 0.0)
+    }
+
+    /// The transport frame at `instant`
+    pub fn frame_at(&self, instant: Instant) -> Option<f64> {
+        let (anchor, position) = self.anchor.as_ref()?;
+        Some(
+            position.frame as f64
+                + self.frames_per_second(position) * seconds_since(*anchor, instant),
+        )
+    }
+
+    /// The instant the transport is at `frame`
+    ///
+    /// None if the transport is not rolling.
+    pub fn instant_of_frame(&self, frame: f64) -> Option<Instant> {
+        let (anchor, position) = self.anchor.as_ref()?;
+        let frames_per_second = self.frames_per_second(position);
+        if frames_per_second == 0.0 {
+            return None;
+        }
+        let seconds = (frame - position.frame as f64) / frames_per_second;
+        if seconds >= 0.0 {
+            anchor.checked_add(Duration::from_secs_f64(seconds))
+        } else {
+            anchor.checked_sub(Duration::from_secs_f64(-seconds))
+        }
+    }
+
+    /// The musical position in beats since the start of bar 0 at `instant`
+    ///
+    /// None if the host did not report bar, beat and tempo.
+    pub fn beats_at(&self, instant: Instant) -> Option<f64> {
+        let (anchor, position) = self.anchor.as_ref()?;
+        let beats = position.bar? as f64 * f64::from(position.beats_per_bar?)
+            + f64::from(position.bar_beat?);
+        let beats_per_second =
+            f64::from(position.beats_per_minute?) / 60.0 * f64::from(position.speed);
+        Some(beats + beats_per_second * seconds_since(*anchor, instant))
+    }
+
+    fn frames_per_second(&self, position: &TransportPosition) -> f64 {
+        self.sample_rate * f64::from(position.speed)
+    }
+}
+
+/// Receives the transport position from the `time:Position` objects on an atom port
+#[derive(Clone, Debug)]
+pub struct Transport {
+    clock: TransportClock,
+    changed: bool,
+}
+
+impl Transport {
+    pub fn new(sample_rate: f64) -> Self {
+        Self {
+            clock: TransportClock::new(sample_rate),
+            changed: false,
+        }
+    }
+
+    /// Takes the `time:Position` objects queued on `port`, to be called in `update()`
+    ///
+    /// All queued atoms are consumed. Other atoms are dropped, so if
+    /// the port also carries other messages, read the objects
+    /// yourself and pass them to `clock_mut().read_position()`.
+    /// Returns true if a position has been received.
+    pub fn receive(&mut self, port: &mut UIAtomPort, urids: &TimeURIDs) -> bool {
+        let received = port.received().unwrap_or_else(Instant::now);
+        let clock = &mut self.clock;
+        let received = port
+            .drain()
+            .filter_map(|atom| atom.read(urids.atom.object, ()))
+            .map(|(header, reader)| clock.read_position(header, reader, urids, received))
+            .fold(false, |any, read| any | read);
+        self.changed |= received;
+        received
+    }
+
+    /// The last position received
+    pub fn position(&self) -> Option<&TransportPosition> {
+        self.clock.position()
+    }
+
+    /// Returns the position if a new one has been received, otherwise None.
+    pub fn changed_position(&mut self) -> Option<TransportPosition> {
+        match self.changed {
+            false => None,
+            true => {
+                self.changed = false;
+                self.clock.position().copied()
+            }
+        }
+    }
+
+    pub fn is_rolling(&self) -> bool {
+        self.clock.is_rolling()
+    }
+
+    /// The transport frame extrapolated to now
+    pub fn frame_now(&self) -> Option<f64> {
+        self.clock.frame_at(Instant::now())
+    }
+
+    /// The musical position in beats extrapolated to now, see `TransportClock::beats_at()`
+    pub fn beats_now(&self) -> Option<f64> {
+        self.clock.beats_at(Instant::now())
+    }
+
+    pub fn clock(&self) -> &TransportClock {
+        &self.clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut TransportClock {
+        &mut self.clock
+    }
+}
+
+fn seconds_since(anchor: Instant, instant: Instant) -> f64 {
+    if instant >= anchor {
+        (instant - anchor).as_secs_f64()
+    } else {
+        -(anchor - instant).as_secs_f64()
+    }
+}
+
+// Hosts differ in the numeric types of the position properties
+fn read_number(value: UnidentifiedAtom, urids: &TimeURIDs) -> Option<f64> {
+    value
+        .read(urids.atom.long, ())
+        .map(|v| v as f64)
+        .or_else(|| value.read(urids.atom.int, ()).map(f64::from))
+        .or_else(|| value.read(urids.atom.float, ()).map(f64::from))
+        .or_else(|| value.read(urids.atom.double, ()))
+}
@@ -0,0 +1,91 @@
+//! Generating the Turtle declaration of the UIs of a binary
+//!
+//! The `ui:ui`, `ui:binary`, `lv2:requiredFeature` and
+//! `ui:portNotification` statements need to match the Rust code.
+//! `UIManifest` writes them from the `description::UIDescription` of
+//! every UI, e.g. from an xtask or the build script of a crate
+//! depending on the UI crate:
+//!
+//! ```ignore
+//! UIManifest::new(UIClass::binary_name("amp_ui"))
+//!     .with_ui::<AmpX11UI>(UIClass::X11UI)
+//!     .with_ui::<AmpShowUI>(UIClass::UI)
+//!     .write("amp.lv2/amp_ui.ttl")?;
+//! ```
+//!
+//! As `lv2_ui_descriptors!` does, the manifest only lists the UIs
+//! whose class is valid for the compile target. Every UI with a
+//! `PluginUI::PLUGIN_URI` is also declared as `ui:ui` of its plugin.
+
+use std::io;
+use std::path::Path;
+
+use urid::UriBound;
+
+use crate::description::UIDescription;
+use crate::plugin_ui::PluginUI;
+use crate::ui_class::UIClass;
+
+/// The prefixes the statements are written with
+const PREFIXES: &str = "@prefix lv2: <http://lv2plug.in/ns/lv2core#> .\n\
+                        @prefix ui: <http://lv2plug.in/ns/extensions/ui#> .\n";
+
+/// The Turtle declaration of the UIs in one binary
+#[derive(Clone, Debug)]
+pub struct UIManifest {
+    binary: std::string::String,
+    uis: Vec<(UIClass, UIDescription)>,
+}
+
+impl UIManifest {
+    /// A manifest for the UIs in `binary`, relative to the bundle
+    pub fn new(binary: impl Into<std::string::String>) -> Self {
+        Self {
+            binary: binary.into(),
+            uis: Vec::new(),
+        }
+    }
+
+    /// Adds the UI type `T` of the class `class`
+    pub fn with_ui<T: PluginUI + UriBound>(self, class: UIClass) -> Self {
+        self.with_description(UIDescription::of::<T>(), class)
+    }
+
+    /// Adds a UI by its description, e.g. one not known as type
+    pub fn with_description(mut self, description: UIDescription, class: UIClass) -> Self {
+        self.uis.push((class, description));
+        self
+    }
+
+    /// The Turtle document, prefixes included
+    pub fn to_ttl(&self) -> std::string::String {
+        let mut ttl = std::string::String::from(PREFIXES);
+        for (class, description) in self.uis.iter() {
+            let entry = match description.ttl(*class, &self.binary) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            ttl.push('\n');
+            if let Some(plugin_uri) = description.plugin_uri {
+                ttl.push_str(&format!(
+                    "<{}>\n    ui:ui <{}> .\n\n",
+                    plugin_uri, description.uri
+                ));
+            }
+            ttl.push_str(&entry);
+        }
+        ttl
+    }
+
+    /// Writes the document to `path`
+    ///
+    /// An unchanged file is not touched, so build tools watching it
+    /// don't rebuild.
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let ttl = self.to_ttl();
+        if std::fs::read_to_string(path.as_ref()).is_ok_and(|written| written == ttl) {
+            return Ok(());
+        }
+        std::fs::write(path, ttl)
+    }
+}
@@ -0,0 +1,145 @@
+//! UI classes and the platforms they are valid on
+//!
+//! An LV2 UI is of a class telling the host what kind of widget it
+//! provides. The native window classes only exist on one platform
+//! each: `X11UI` on Linux and the BSDs, `CocoaUI` on macOS and
+//! `WindowsUI` on Windows. A UI crate supporting several platforms
+//! declares a UI type per class and lets `lv2_ui_descriptors!` export
+//! only the ones valid for the compile target:
+//!
+//! ```ignore
+//! lv2_ui_descriptors!(
+//!     X11UI => AmpX11UI,
+//!     CocoaUI => AmpCocoaUI,
+//!     WindowsUI => AmpWindowsUI,
+//!     UI => AmpExternalUI,
+//! );
+//! ```
+//!
+//! The indices the host selects the UIs by only count the exported
+//! UIs. The UI types of other platforms are not referenced, so they
+//! can be behind the same `#[cfg(…)]` as their platform code.
+//!
+//! `UIClass::manifest_entry()` writes the matching TTL for the
+//! platform of the build, e.g. from a build script generating the
+//! bundle's `manifest.ttl`.
+
+use std::env::consts::{DLL_PREFIX, DLL_SUFFIX};
+
+/// The class of a plugin UI in the `ui:` namespace
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum UIClass {
+    /// A child window of an X11 parent
+    X11UI,
+    /// An `NSView` on macOS
+    CocoaUI,
+    /// An `HWND` on Windows
+    WindowsUI,
+    /// No embeddable widget, e.g. an external UI or one only
+    /// implementing the show interface
+    UI,
+}
+
+impl UIClass {
+    /// All UI classes
+    pub const ALL: [UIClass; 4] = [
+        UIClass::X11UI,
+        UIClass::CocoaUI,
+        UIClass::WindowsUI,
+        UIClass::UI,
+    ];
+
+    /// The name of the class in the `ui:` namespace
+    pub fn name(self) -> &'static str {
+        match self {
+            UIClass::X11UI => "X11UI",
+            UIClass::CocoaUI => "CocoaUI",
+            UIClass::WindowsUI => "WindowsUI",
+            UIClass::UI => "UI",
+        }
+    }
+
+    /// The full URI of the class
+    pub fn uri(self) -> &'static str {
+        match self {
+            UIClass::X11UI => "http://lv2plug.in/ns/extensions/ui#X11UI",
+            UIClass::CocoaUI => "http://lv2plug.in/ns/extensions/ui#CocoaUI",
+            UIClass::WindowsUI => "http://lv2plug.in/ns/extensions/ui#WindowsUI",
+            UIClass::UI => "http://lv2plug.in/ns/extensions/ui#UI",
+        }
+    }
+
+    /// True if UIs of the class can be exported for the compile target
+    ///
+    /// The same conditions are used by `lv2_ui_descriptors!`.
+    pub fn is_valid_for_target(self) -> bool {
+        match self {
+            UIClass::X11UI => cfg!(all(unix, not(target_os = "macos"))),
+            UIClass::CocoaUI => cfg!(target_os = "macos"),
+            UIClass::WindowsUI => cfg!(windows),
+            UIClass::UI => true,
+        }
+    }
+
+    /// The native window class of the compile target
+    pub fn native() -> Self {
+        if cfg!(target_os = "macos") {
+            UIClass::CocoaUI
+        } else if cfg!(windows) {
+            UIClass::WindowsUI
+        } else {
+            UIClass::X11UI
+        }
+    }
+
+    /// The file name of the UI library of the crate `crate_name` on the compile target
+    pub fn binary_name(crate_name: &str) -> std::string::String {
+        format!(
+            "{}{}{}",
+            DLL_PREFIX,
+            crate_name.replace('-', "_"),
+            DLL_SUFFIX
+        )
+    }
+
+    /// The TTL describing the UI `ui_uri` of the class in `binary`
+    ///
+    /// Returns None if the class is not valid for the compile target,
+    /// so a manifest generated for one platform only lists the UIs
+    /// exported there. The `ui:` prefix needs to be declared.
+    pub fn manifest_entry(self, ui_uri: &str, binary: &str) -> Option<std::string::String> {
+        if !self.is_valid_for_target() {
+            return None;
+        }
+        Some(format!(
+            "<{}>\n    a ui:{} ;\n    ui:binary <{}> .\n",
+            ui_uri,
+            self.name(),
+            binary
+        ))
+    }
+}
+
+/// Expands the tokens only if the UI class is valid for the compile target
+///
+/// Used by `lv2_ui_descriptors!`, keep in sync with
+/// `UIClass::is_valid_for_target()`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __lv2_ui_class_cfg {
+    (X11UI, $($tokens:tt)*) => {
+        #[cfg(all(unix, not(target_os = "macos")))]
+        $($tokens)*
+    };
+    (CocoaUI, $($tokens:tt)*) => {
+        #[cfg(target_os = "macos")]
+        $($tokens)*
+    };
+    (WindowsUI, $($tokens:tt)*) => {
+        #[cfg(windows)]
+        $($tokens)*
+    };
+    (UI, $($tokens:tt)*) => {
+        $($tokens)*
+    };
+}
@@ -0,0 +1,166 @@
+//! Formatting and parsing of values with LV2 units
+//!
+//! Plugins declare the unit of a control port by `units:unit` in
+//! their TTL. `Unit` maps those unit URIs to a symbol, formats values
+//! for display and parses text the user typed in. Attached to a
+//! port by `ControlMetadata::unit()`, `UIControlPort::display_value()`
+//! returns e.g. "−6.0 dB" and `UIControlPort::parse("440 Hz")` the
+//! value 440.0, so every toolkit shows values the same way.
+//!
+//! Parsing accepts the symbol of the unit as well as related symbols
+//! with another scale, e.g. "1.2 kHz" for a port in Hz. The symbol
+//! can be omitted.
+
+use urid::Uri;
+
+const UNITS: &str = "http://lv2plug.in/ns/extensions/units#";
+
+// The sign used for negative values, a proper minus rather than a hyphen
+const MINUS: char = '\u{2212}';
+
+/// A unit of the LV2 units extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Unit {
+    Bar,
+    Beat,
+    Bpm,
+    Cent,
+    Coef,
+    Db,
+    Degree,
+    Frame,
+    Hz,
+    KHz,
+    MHz,
+    MidiNote,
+    Min,
+    Ms,
+    Oct,
+    Pc,
+    S,
+    Semitone,
+}
+
+impl Unit {
+    /// Looks up the unit of the URI `uri`
+    pub fn from_uri(uri: &Uri) -> Option<Self> {
+        let name = uri.to_str().ok()?.strip_prefix(UNITS)?;
+        Some(match name {
+            "bar" => Unit::Bar,
+            "beat" => Unit::Beat,
+            "bpm" => Unit::Bpm,
+            "cent" => Unit::Cent,
+            "coef" => Unit::Coef,
+            "db" => Unit::Db,
+            "degree" => Unit::Degree,
+            "frame" => Unit::Frame,
+            "hz" => Unit::Hz,
+            "khz" => Unit::KHz,
+            "mhz" => Unit::MHz,
+            "midiNote" => Unit::MidiNote,
+            "min" => Unit::Min,
+            "ms" => Unit::Ms,
+            "oct" => Unit::Oct,
+            "pc" => Unit::Pc,
+            "s" => Unit::S,
+            "semitone12TET" => Unit::Semitone,
+            _ => return None,
+        })
+    }
+
+    /// The symbol shown after the value
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Bar => "bars",
+            Unit::Beat => "beats",
+            Unit::Bpm => "BPM",
+            Unit::Cent => "ct",
+            Unit::Coef => "×",
+            Unit::Db => "dB",
+            Unit::Degree => "°",
+            Unit::Frame => "frames",
+            Unit::Hz => "Hz",
+            Unit::KHz => "kHz",
+            Unit::MHz => "MHz",
+            Unit::MidiNote => "note",
+            Unit::Min => "min",
+            Unit::Ms => "ms",
+            Unit::Oct => "oct",
+            Unit::Pc => "%",
+            Unit::S => "s",
+            Unit::Semitone => "semi",
+        }
+    }
+
+    /// The number of decimals shown by `format()`
+    fn precision(&self) -> usize {
+        match self {
+            Unit::Bar | Unit::Beat | Unit::Cent | Unit::Frame | Unit::MidiNote | Unit::Pc => 0,
+            Unit::Coef | Unit::S | Unit::KHz | Unit::MHz | Unit::Oct => 2,
+            _ => 1,
+        }
+    }
+
+    /// Symbols accepted by `parse()` with the factor to this unit
+    fn scales(&self) -> &'static [(&'static str, f32)] {
+        match self {
+            Unit::Hz => &[("hz", 1.0), ("khz", 1e3), ("mhz", 1e6)],
+            Unit::KHz => &[("khz", 1.0), ("hz", 1e-3), ("mhz", 1e3)],
+            Unit::MHz => &[("mhz", 1.0), ("khz", 1e-3), ("hz", 1e-6)],
+            Unit::Ms => &[("ms", 1.0), ("s", 1e3), ("min", 6e4)],
+            Unit::S => &[("s", 1.0), ("ms", 1e-3), ("min", 60.0)],
+            Unit::Min => &[("min", 1.0), ("s", 1.0 / 60.0), ("ms", 1.0 / 6e4)],
+            Unit::Semitone => &[("semi", 1.0), ("st", 1.0), ("ct", 0.01)],
+            Unit::Cent => &[("ct", 1.0), ("cent", 1.0), ("semi", 100.0), ("st", 100.0)],
+            Unit::Bpm => &[("bpm", 1.0)],
+            Unit::Coef => &[("×", 1.0), ("x", 1.0)],
+            _ => &[],
+        }
+    }
+
+    /// Formats `value` with the unit symbol, e.g. "−6.0 dB"
+    ///
+    /// Frequencies are shown in kHz from 1000 Hz on.
+    pub fn format(&self, value: f32) -> std::string::String {
+        match self {
+            Unit::Hz if value.abs() >= 1000.0 => Unit::KHz.format(value / 1000.0),
+            _ => format!(
+                "{} {}",
+                format_number(value, self.precision()),
+                self.symbol()
+            ),
+        }
+    }
+
+    /// Parses a value typed in by the user, e.g. "440 Hz" or "−6 dB"
+    ///
+    /// Returns None if the text is no number or has a symbol not
+    /// belonging to the unit.
+    pub fn parse(&self, text: &str) -> Option<f32> {
+        let text = text.trim().replace(MINUS, "-");
+        let end = text
+            .find(|c: char| !(c.is_ascii_digit() || matches!(c, '+' | '-' | '.' | 'e' | 'E')))
+            .unwrap_or(text.len());
+        let (number, suffix) = text.split_at(end);
+        let value: f32 = number.trim().parse().ok()?;
+        let suffix = suffix.trim().to_lowercase();
+        if suffix.is_empty() || suffix == self.symbol().to_lowercase() {
+            return Some(value);
+        }
+        self.scales()
+            .iter()
+            .find(|(symbol, _)| *symbol == suffix)
+            .map(|(_, factor)| value * factor)
+    }
+}
+
+/// Formats `value` with `precision` decimals and a proper minus sign
+pub fn format_number(value: f32, precision: usize) -> std::string::String {
+    let text = format!("{:.*}", precision, value.abs());
+    // No sign for values rounded to zero
+    if value < 0.0 && text.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        format!("{}{}", MINUS, text)
+    } else {
+        text
+    }
+}
@@ -12,3 +12,106 @@ pub struct UpdateRate;
 unsafe impl UriBound for UpdateRate {
     const URI: &'static [u8] = sys::LV2_UI__updateRate;
 }
+
+pub struct PeakProtocol;
+
+unsafe impl UriBound for PeakProtocol {
+    const URI: &'static [u8] = sys::LV2_UI__peakProtocol;
+}
+
+pub struct PatchSet;
+
+unsafe impl UriBound for PatchSet {
+    const URI: &'static [u8] = sys::LV2_PATCH__Set;
+}
+
+pub struct PatchProperty;
+
+unsafe impl UriBound for PatchProperty {
+    const URI: &'static [u8] = sys::LV2_PATCH__property;
+}
+
+pub struct PatchValue;
+
+unsafe impl UriBound for PatchValue {
+    const URI: &'static [u8] = sys::LV2_PATCH__value;
+}
+
+pub struct PatchPut;
+
+unsafe impl UriBound for PatchPut {
+    const URI: &'static [u8] = sys::LV2_PATCH__Put;
+}
+
+pub struct PatchBody;
+
+unsafe impl UriBound for PatchBody {
+    const URI: &'static [u8] = sys::LV2_PATCH__body;
+}
+
+pub struct StateChanged;
+
+unsafe impl UriBound for StateChanged {
+    const URI: &'static [u8] = sys::LV2_STATE__StateChanged;
+}
+
+pub struct MidiEvent;
+
+unsafe impl UriBound for MidiEvent {
+    const URI: &'static [u8] = sys::LV2_MIDI__MidiEvent;
+}
+
+/// The parameter under which `MidiLearn` stores its bindings in the plugin state
+pub struct MidiBindings;
+
+unsafe impl UriBound for MidiBindings {
+    const URI: &'static [u8] = b"https://github.com/johannes-mueller/lv2-ui#midiBindings\0";
+}
+
+pub struct TimePosition;
+
+unsafe impl UriBound for TimePosition {
+    const URI: &'static [u8] = sys::LV2_TIME__Position;
+}
+
+pub struct TimeFrame;
+
+unsafe impl UriBound for TimeFrame {
+    const URI: &'static [u8] = sys::LV2_TIME__frame;
+}
+
+pub struct TimeSpeed;
+
+unsafe impl UriBound for TimeSpeed {
+    const URI: &'static [u8] = sys::LV2_TIME__speed;
+}
+
+pub struct TimeBar;
+
+unsafe impl UriBound for TimeBar {
+    const URI: &'static [u8] = sys::LV2_TIME__bar;
+}
+
+pub struct TimeBarBeat;
+
+unsafe impl UriBound for TimeBarBeat {
+    const URI: &'static [u8] = sys::LV2_TIME__barBeat;
+}
+
+pub struct TimeBeatsPerBar;
+
+unsafe impl UriBound for TimeBeatsPerBar {
+    const URI: &'static [u8] = sys::LV2_TIME__beatsPerBar;
+}
+
+pub struct TimeBeatsPerMinute;
+
+unsafe impl UriBound for TimeBeatsPerMinute {
+    const URI: &'static [u8] = sys::LV2_TIME__beatsPerMinute;
+}
+
+pub struct PatchGet;
+
+unsafe impl UriBound for PatchGet {
+    const URI: &'static [u8] = sys::LV2_PATCH__Get;
+}
@@ -0,0 +1,206 @@
+//! Background work of the UI that ends with the UI instance
+//!
+//! UIs scanning sample folders or fetching presets from the network
+//! do so off the GUI thread. Such work must not outlive the UI
+//! instance, as the host unloads the UI's library after cleanup.
+//! Every instance has a `CancellationToken`, see
+//! `PluginUIInfo::cancellation_token()`, which is cancelled right
+//! before `PluginUI::cleanup()` is called.
+//!
+//! A `UIWorker` runs jobs on a thread of its own, passing them a
+//! child token of the instance's. Dropping the worker, at the latest
+//! together with the UI, cancels the token and waits for the running
+//! job to return:
+//!
+//! ```ignore
+//! // in new()
+//! let worker = UIWorker::new(plugin_ui_info.cancellation_token());
+//! let (sender, receiver) = std::sync::mpsc::channel();
+//! worker.spawn(move |token| {
+//!     for file in scan_folder(&folder) {
+//!         if token.is_cancelled() {
+//!             return;
+//!         }
+//!         let _ = sender.send(file);
+//!     }
+//! });
+//! ```
+//!
+//! Async tasks await `CancellationToken::cancelled()` instead.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Waker};
+use std::thread::JoinHandle;
+
+use crate::logging::ui_log;
+
+#[derive(Default)]
+struct TokenState {
+    cancelled: AtomicBool,
+    wakers: Mutex<Vec<Waker>>,
+    children: Mutex<Vec<Weak<TokenState>>>,
+}
+
+impl TokenState {
+    fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        for waker in self.wakers.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+        let children = std::mem::take(&mut *self.children.lock().unwrap());
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+/// Tells background work that it is to stop
+///
+/// Clones share the state, cancelling one cancels all of them.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    state: Arc<TokenState>,
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A token cancelled together with this one, but which can be cancelled on its own
+    pub fn child(&self) -> Self {
+        let child = Self::new();
+        let mut children = self.state.children.lock().unwrap();
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.state));
+        }
+        child
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::Acquire)
+    }
+
+    /// A future completing when the token is cancelled
+    pub fn cancelled(&self) -> Cancelled {
+        Cancelled {
+            token: self.clone(),
+        }
+    }
+}
+
+/// The future returned by `CancellationToken::cancelled()`
+pub struct Cancelled {
+    token: CancellationToken,
+}
+
+impl Future for Cancelled {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.token.is_cancelled() {
+            return Poll::Ready(());
+        }
+        self.token
+            .state
+            .wakers
+            .lock()
+            .unwrap()
+            .push(cx.waker().clone());
+        // The token may have been cancelled before the waker was registered
+        match self.token.is_cancelled() {
+            true => Poll::Ready(()),
+            false => Poll::Pending,
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce(&CancellationToken) + Send>;
+
+/// Runs jobs one after another on a background thread
+///
+/// The thread ends when the worker is dropped. Queued jobs are
+/// skipped once the token is cancelled.
+pub struct UIWorker {
+    token: CancellationToken,
+    sender: Option<mpsc::Sender<Job>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl UIWorker {
+    /// A worker whose jobs get a child token of `token`
+    pub fn new(token: CancellationToken) -> Self {
+        let token = token.child();
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let job_token = token.clone();
+        let thread = std::thread::Builder::new()
+            .name("lv2-ui worker".into())
+            .spawn(move || {
+                for job in receiver {
+                    if job_token.is_cancelled() {
+                        break;
+                    }
+                    job(&job_token);
+                }
+            })
+            .ok();
+        if thread.is_none() {
+            ui_log!(Error, "failed to start worker thread");
+        }
+        Self {
+            token,
+            sender: Some(sender),
+            thread,
+        }
+    }
+
+    /// Queues `job`
+    ///
+    /// Returns false if the job can't be run, as the worker has no
+    /// thread or its token is cancelled.
+    pub fn spawn(&self, job: impl FnOnce(&CancellationToken) + Send + 'static) -> bool {
+        if self.thread.is_none() || self.token.is_cancelled() {
+            return false;
+        }
+        match &self.sender {
+            Some(sender) => sender.send(Box::new(job)).is_ok(),
+            None => false,
+        }
+    }
+
+    /// The token passed to the jobs
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl Drop for UIWorker {
+    fn drop(&mut self) {
+        self.token.cancel();
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
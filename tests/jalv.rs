@@ -0,0 +1,61 @@
+//! Runs the demo of `examples/jalv_demo.rs` under jalv
+//!
+//! Needs the console jalv and a running JACK server, so the test is
+//! ignored by default:
+//!
+//! ```text
+//! cargo test --features jalv --test jalv -- --ignored
+//! ```
+
+#![cfg(feature = "jalv")]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use lv2_ui::jalv::*;
+
+const PLUGIN_URI: &str = "urn:lv2-ui:jalv-demo";
+const UI_URI: &str = "urn:lv2-ui:jalv-demo#ui";
+
+// Builds the demo and returns the path of its binary
+fn build_demo() -> PathBuf {
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--example", "jalv_demo", "--features", "jalv"])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "failed to build the demo");
+
+    // The test binary is in `target/<profile>/deps`
+    let mut path = std::env::current_exe().unwrap();
+    path.pop();
+    path.pop();
+    path.push("examples");
+    path.push(format!(
+        "{}jalv_demo{}",
+        std::env::consts::DLL_PREFIX,
+        std::env::consts::DLL_SUFFIX
+    ));
+    path
+}
+
+#[test]
+#[ignore]
+fn jalv_sequencing() {
+    let binary = build_demo();
+    let dir = std::env::temp_dir().join(format!("lv2-ui-jalv-demo-{}.lv2", std::process::id()));
+    DemoBundle::new(&dir, PLUGIN_URI, &binary)
+        .ui(UI_URI, &binary)
+        .show_interface()
+        .control_input("gain", 0.0, 1.0, 0.5)
+        .write()
+        .unwrap();
+
+    let trace = Jalv::new(&dir, PLUGIN_URI).program("jalv").arg("-s").run();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let trace = trace.unwrap();
+    trace.check_sequence().unwrap();
+    assert!(trace.count("port_event") > 0);
+    assert!(trace.count("idle") > 0);
+}